@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use authz::{AccessMask, Ace, AceType, Acl, Guid, SecurityDescriptor, Sid, create_private_object_security, SEF_SACL_AUTO_INHERIT};
+use authz::{OWNER_SECURITY_INFORMATION, SACL_SECURITY_INFORMATION, SUCCESSFUL_ACCESS_ACE_FLAG, FAILED_ACCESS_ACE_FLAG, SE_SACL_PRESENT};
+use winldap::error::LdapError;
+use winldap::utils::{get_attr_str, get_attr_strs};
+use crate::delegations::DelegationLocation;
+use crate::engine::Engine;
+use crate::utils::get_attr_sd;
+use crate::directory::SearchScope;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SensitiveTarget {
+    DomainHead,
+    AdminSdHolder,
+    GroupPolicyContainers,
+    GroupPolicy,
+}
+
+// Operations which are commonly abused to take over a domain, and which should be audited
+// (successful attempts) on their target
+const SENSITIVE_OPERATIONS: &[(SensitiveTarget, &str, u32, Option<&str>)] = &[
+    (SensitiveTarget::DomainHead, "modification of its security descriptor", AccessMask::WRITE_DAC.bits(), None),
+    (SensitiveTarget::DomainHead, "change of its owner", AccessMask::WRITE_OWNER.bits(), None),
+    (SensitiveTarget::DomainHead, "DCSync (Replicating Directory Changes)", AccessMask::CONTROL_ACCESS.bits(), Some("1131f6aa-9c07-11d1-f79f-00c04fc2dcd2")),
+    (SensitiveTarget::DomainHead, "DCSync (Replicating Directory Changes All)", AccessMask::CONTROL_ACCESS.bits(), Some("1131f6ad-9c07-11d1-f79f-00c04fc2dcd2")),
+    (SensitiveTarget::DomainHead, "DCSync (Replicating Directory Changes In Filtered Set)", AccessMask::CONTROL_ACCESS.bits(), Some("89e95b76-444d-4c62-991a-0facbeda640c")),
+    (SensitiveTarget::DomainHead, "linking of group policies (gPLink)", AccessMask::WRITE_PROP.bits(), Some("f30e3bbe-9ff0-11d1-b603-0000f80367c1")),
+    (SensitiveTarget::AdminSdHolder, "modification of its security descriptor", AccessMask::WRITE_DAC.bits(), None),
+    (SensitiveTarget::AdminSdHolder, "change of its owner", AccessMask::WRITE_OWNER.bits(), None),
+    (SensitiveTarget::GroupPolicyContainers, "modification of its security descriptor", AccessMask::WRITE_DAC.bits(), None),
+    (SensitiveTarget::GroupPolicyContainers, "creation of group policies", AccessMask::CREATE_CHILD.bits(), Some("f30e3bc2-9ff0-11d1-b603-0000f80367c1")),
+    (SensitiveTarget::GroupPolicy, "modification of its security descriptor", AccessMask::WRITE_DAC.bits(), None),
+    (SensitiveTarget::GroupPolicy, "change of its owner", AccessMask::WRITE_OWNER.bits(), None),
+    (SensitiveTarget::GroupPolicy, "change of its file system path (gPCFileSysPath)", AccessMask::WRITE_PROP.bits(), Some("f30e3bc1-9ff0-11d1-b603-0000f80367c1")),
+];
+
+#[derive(Debug, Clone)]
+pub enum AuditIssue {
+    // The SACL could not be read (reading SACLs requires SeSecurityPrivilege on domain controllers)
+    SaclUnreadable,
+    // A sensitive operation is not audited, successful attempts would go unlogged
+    MissingAudit(&'static str),
+    // An explicit audit ACE is not part of the defaultSecurityDescriptor of the object's class
+    AddedToDefault(Ace),
+    // An audit ACE from the defaultSecurityDescriptor of the object's class has been removed
+    RemovedFromDefault(Ace),
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditFinding {
+    pub(crate) location: DelegationLocation,
+    pub(crate) issue: AuditIssue,
+}
+
+impl<'a> Engine<'a> {
+    // Optional pass which reads SACLs to find sensitive operations which are not audited, and audit
+    // ACEs which differ from the defaultSecurityDescriptor of their object's class
+    pub fn run_audit(&self) -> Result<Vec<AuditFinding>, LdapError> {
+        let mut res = vec![];
+        let default_sacls = self.get_default_sacls()?;
+        let mut naming_contexts = Vec::from(self.directory.get_naming_contexts());
+        naming_contexts.sort();
+
+        for naming_context in naming_contexts {
+            eprintln!(" [.] Analysing audit settings of {} ...", &naming_context);
+            let domain_sid = self.domains.iter().find(|d| d.distinguished_name == naming_context)
+                .map(|d| &d.sid)
+                .unwrap_or(&self.root_domain.sid);
+            let default_sacls = default_sacls.get(domain_sid).expect("naming context without an associated domain");
+            let is_domain = self.domains.iter().any(|d| d.distinguished_name == naming_context);
+            let adminsdholder_dn = format!("CN=AdminSDHolder,CN=System,{}", naming_context);
+            let policies_dn = format!("CN=Policies,CN=System,{}", naming_context);
+
+            let search = self.directory.search(&naming_context, SearchScope::Subtree,
+                Some("(objectClass=*)"),
+                &[
+                    "nTSecurityDescriptor",
+                    "objectClass",
+                ], Some(OWNER_SECURITY_INFORMATION | SACL_SECURITY_INFORMATION));
+
+            for entry in search {
+                let entry = entry?;
+                let sd = match get_attr_sd(&[&entry], &entry.dn, "ntsecuritydescriptor") {
+                    Ok(sd) => sd,
+                    Err(_) => continue, // already reported as a warning by the main pass
+                };
+                if entry.dn.eq_ignore_ascii_case(&naming_context) && (sd.controls & SE_SACL_PRESENT) == 0 {
+                    // Domain controllers silently omit SACLs when we lack the privilege to read them (and
+                    // naming context heads always have one), there is no point in going on with this
+                    // naming context
+                    res.push(AuditFinding {
+                        location: DelegationLocation::Dn(entry.dn),
+                        issue: AuditIssue::SaclUnreadable,
+                    });
+                    break;
+                }
+                let sacl_aces = sd.sacl.map(|acl| acl.aces).unwrap_or_default();
+                let most_specific_class = match get_attr_strs(&[&entry], &entry.dn, "objectclass") {
+                    Ok(mut v) => v.pop().expect("assertion failed: object with an empty objectClass!?").to_ascii_lowercase(),
+                    Err(_) => continue,
+                };
+
+                // Compare explicit audit ACEs with the ones derived from the class defaultSecurityDescriptor
+                if let (Some(default_sd), Some(class_guid), Some(owner)) = (default_sacls.get(&most_specific_class), self.schema.class_guids.get(&most_specific_class), sd.owner.as_ref()) {
+                    let default_aces = create_private_object_security(None, Some(default_sd), Some(class_guid), true, owner, None, SEF_SACL_AUTO_INHERIT)
+                        .sacl
+                        .map(|acl| acl.aces)
+                        .unwrap_or_default();
+                    let explicit_aces: Vec<&Ace> = sacl_aces.iter().filter(|ace| !ace.is_inherited()).collect();
+                    for ace in &explicit_aces {
+                        if !default_aces.contains(ace) {
+                            res.push(AuditFinding {
+                                location: DelegationLocation::Dn(entry.dn.clone()),
+                                issue: AuditIssue::AddedToDefault((*ace).clone()),
+                            });
+                        }
+                    }
+                    for ace in default_aces {
+                        if !explicit_aces.contains(&&ace) {
+                            res.push(AuditFinding {
+                                location: DelegationLocation::Dn(entry.dn.clone()),
+                                issue: AuditIssue::RemovedFromDefault(ace),
+                            });
+                        }
+                    }
+                }
+
+                let target = if is_domain && entry.dn.eq_ignore_ascii_case(&naming_context) {
+                    SensitiveTarget::DomainHead
+                } else if entry.dn.eq_ignore_ascii_case(&adminsdholder_dn) {
+                    SensitiveTarget::AdminSdHolder
+                } else if entry.dn.eq_ignore_ascii_case(&policies_dn) {
+                    SensitiveTarget::GroupPolicyContainers
+                } else if most_specific_class == "grouppolicycontainer" {
+                    SensitiveTarget::GroupPolicy
+                } else {
+                    continue;
+                };
+                for (_, operation, access_mask, object_type) in SENSITIVE_OPERATIONS.iter().filter(|(t, _, _, _)| *t == target) {
+                    let object_type = object_type.map(|s| Guid::try_from(s).expect("invalid sensitive operation GUID"));
+                    if !is_audited(&sacl_aces, *access_mask, object_type.as_ref()) {
+                        res.push(AuditFinding {
+                            location: DelegationLocation::Dn(entry.dn.clone()),
+                            issue: AuditIssue::MissingAudit(operation),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    pub fn describe_audit_issue(&self, issue: &AuditIssue) -> String {
+        match issue {
+            AuditIssue::SaclUnreadable => "SACLs could not be read in this naming context (reading them requires SeSecurityPrivilege), audit coverage was not analysed".to_owned(),
+            AuditIssue::MissingAudit(operation) => format!("No audit of {}, abuses would go unlogged", operation),
+            AuditIssue::AddedToDefault(ace) => format!("Audit ACE added to the class default: {}", self.describe_audit_ace(ace)),
+            AuditIssue::RemovedFromDefault(ace) => format!("Audit ACE removed from the class default: {}", self.describe_audit_ace(ace)),
+        }
+    }
+
+    fn describe_audit_ace(&self, ace: &Ace) -> String {
+        let success = (ace.flags & SUCCESSFUL_ACCESS_ACE_FLAG) != 0;
+        let failure = (ace.flags & FAILED_ACCESS_ACE_FLAG) != 0;
+        format!("{} of {} by {}{}",
+            match (success, failure) {
+                (true, true) => "Success and failure",
+                (true, false) => "Success",
+                (false, true) => "Failure",
+                (false, false) => "Nothing",
+            },
+            self.describe_ace(
+                ace.access_mask,
+                ace.get_object_type(),
+                ace.get_inherited_object_type(),
+                ace.get_container_inherit(),
+                ace.get_inherit_only()
+            ),
+            self.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
+            self.describe_ace_condition(ace))
+    }
+
+    // Result is indexed by (domain SID) -> (class name) -> (defaultSecurityDescriptor with only its SACL)
+    fn get_default_sacls(&self) -> Result<HashMap<Sid, HashMap<String, SecurityDescriptor>>, LdapError> {
+        let mut res: HashMap<Sid, HashMap<String, SecurityDescriptor>> = HashMap::new();
+        let search = self.directory.search(self.directory.get_schema_naming_context(), SearchScope::Subtree,
+            Some("(objectClass=classSchema)"),
+            &[
+                "lDAPDisplayName",
+                "defaultSecurityDescriptor"
+            ], None);
+        for entry in search {
+            let entry = entry?;
+            let class_name = get_attr_str(&[&entry], &entry.dn, "ldapdisplayname")?.to_ascii_lowercase();
+            let sddl = match get_attr_str(&[&entry], &entry.dn, "defaultsecuritydescriptor") {
+                Ok(sddl) => sddl,
+                Err(_) => continue, // already reported as a warning by the main pass
+            };
+            for domain in &self.domains {
+                if let Ok(sd) = SecurityDescriptor::from_str(&sddl, &domain.sid, &self.root_domain.sid) {
+                    res.entry(domain.sid.clone()).or_default().insert(class_name.clone(), SecurityDescriptor {
+                        dacl: None,
+                        sacl: Some(sd.sacl.unwrap_or(Acl { aces: vec![] })),
+                        ..sd
+                    });
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+// Returns true if successful accesses with the given right are audited for everyone by an ACE
+// which applies to the object itself
+fn is_audited(sacl_aces: &[Ace], access_mask: u32, object_type: Option<&Guid>) -> bool {
+    let everyone = Sid::try_from("S-1-1-0").expect("invalid SID");
+    let authenticated_users = Sid::try_from("S-1-5-11").expect("invalid SID");
+    sacl_aces.iter().any(|ace| {
+        if !matches!(ace.type_specific, AceType::Audit | AceType::AuditObject { .. }) {
+            return false; // conditional audit ACEs do not cover all accesses
+        }
+        if ace.get_inherit_only() || (ace.flags & SUCCESSFUL_ACCESS_ACE_FLAG) == 0 {
+            return false;
+        }
+        if ace.trustee != everyone && ace.trustee != authenticated_users {
+            return false;
+        }
+        if let Some(ace_object_type) = ace.get_object_type() {
+            if Some(ace_object_type) != object_type {
+                return false;
+            }
+        }
+        let mask = ace.access_mask.map_generic().bits();
+        (mask & access_mask) == access_mask
+    })
+}
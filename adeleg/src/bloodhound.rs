@@ -0,0 +1,575 @@
+use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use authz::{AccessMask, Ace, Guid, Sid};
+use winldap::error::LdapError;
+use winldap::search::LdapEntry;
+use winldap::utils::{get_attr_str, get_attr_strs};
+use crate::delegations::DelegationLocation;
+use crate::directory::SearchScope;
+use crate::engine::{AdelegResult, Engine, PrincipalType};
+use crate::error::AdelegError;
+use crate::severity::parse_gplink;
+use crate::utils::{Domain, ends_with_case_insensitive, get_attr_guid, get_attr_sid, get_foreign_principal_sid, get_parent_container};
+
+// Version of the SharpHound output format, and collection methods it covers (Group, ACL,
+// Container and ObjectProps)
+const SHARPHOUND_FORMAT_VERSION: u32 = 5;
+const SHARPHOUND_COLLECTION_METHODS: u32 = 1 | 64 | 128 | 512;
+
+const MEMBER_ATTRIBUTE: &str = "bf9679c0-0de6-11d0-a285-00aa003049e2";
+const KEY_CREDENTIAL_LINK_ATTRIBUTE: &str = "5b47d60f-6090-40b2-9f37-2a4de88f3063";
+const ALLOWED_TO_ACT_ATTRIBUTE: &str = "3f78c3e5-f79a-46bd-a0b8-9d18116ddc79";
+const FORCE_CHANGE_PASSWORD: &str = "00299570-246d-11d0-a768-00aa006e0529";
+const GET_CHANGES: &str = "1131f6aa-9c07-11d1-f79f-00c04fc2dcd2";
+const GET_CHANGES_ALL: &str = "1131f6ad-9c07-11d1-f79f-00c04fc2dcd2";
+
+// Trustees which BloodHound does not model: CREATOR OWNER and SELF
+const UNMODELLED_TRUSTEES: &[&str] = &[
+    "S-1-3-0",
+    "S-1-5-10",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum BloodHoundType {
+    User,
+    Computer,
+    Group,
+    #[serde(rename = "OU")]
+    Ou,
+    #[serde(rename = "GPO")]
+    Gpo,
+    Domain,
+    Container,
+    // Principal from another forest, whose type is unknown
+    Base,
+}
+
+impl BloodHoundType {
+    // Name of the SharpHound output file (and "type" in its metadata) for objects of this type
+    fn file_name(&self) -> &'static str {
+        match self {
+            BloodHoundType::User => "users",
+            BloodHoundType::Computer => "computers",
+            BloodHoundType::Group | BloodHoundType::Base => "groups",
+            BloodHoundType::Ou => "ous",
+            BloodHoundType::Gpo => "gpos",
+            BloodHoundType::Domain => "domains",
+            BloodHoundType::Container => "containers",
+        }
+    }
+
+    fn from_classes(classes: &[String]) -> Option<Self> {
+        let has_class = |name: &str| classes.iter().any(|c| c.eq_ignore_ascii_case(name));
+        if has_class("domainDNS") {
+            Some(BloodHoundType::Domain)
+        } else if has_class("groupPolicyContainer") {
+            Some(BloodHoundType::Gpo)
+        } else if has_class("organizationalUnit") {
+            Some(BloodHoundType::Ou)
+        } else if has_class("group") {
+            Some(BloodHoundType::Group)
+        } else if has_class("msDS-GroupManagedServiceAccount") || has_class("msDS-ManagedServiceAccount") {
+            // Managed service accounts are shown as users, as SharpHound does
+            Some(BloodHoundType::User)
+        } else if has_class("computer") {
+            Some(BloodHoundType::Computer)
+        } else if has_class("user") {
+            Some(BloodHoundType::User)
+        } else if has_class("container") {
+            Some(BloodHoundType::Container)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BloodHoundAce {
+    right_name: &'static str,
+    is_inherited: bool,
+    #[serde(rename = "PrincipalSID")]
+    principal_sid: String,
+    principal_type: BloodHoundType,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BloodHoundObject {
+    object_identifier: String,
+    properties: Map<String, Value>,
+    aces: Vec<BloodHoundAce>,
+    #[serde(rename = "IsACLProtected")]
+    is_acl_protected: bool,
+    is_deleted: bool,
+    // Fields which depend on the object type (e.g. Members, ChildObjects, Links)
+    #[serde(flatten)]
+    type_specific: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BloodHoundMeta {
+    methods: u32,
+    #[serde(rename = "type")]
+    data_type: &'static str,
+    count: usize,
+    version: u32,
+}
+
+// One SharpHound output file, holding all objects of a given type
+#[derive(Debug, Clone, Serialize)]
+pub struct BloodHoundFile {
+    data: Vec<BloodHoundObject>,
+    meta: BloodHoundMeta,
+}
+
+impl BloodHoundFile {
+    pub fn get_name(&self) -> &'static str {
+        self.meta.data_type
+    }
+}
+
+// Object of the forest, as collected for the export
+struct CollectedObject<'b> {
+    entry: LdapEntry,
+    domain: &'b Domain,
+    object_type: BloodHoundType,
+    // Lowercase name and GUID of the most specific class of the object
+    class_name: Option<String>,
+    class_guid: Option<Guid>,
+    identifier: String,
+}
+
+impl<'a> Engine<'a> {
+    // Exports users, computers, groups, OUs, GPOs, domains and containers in the format written by
+    // SharpHound, with ACE edges derived from the analysis: ACEs from default security descriptors
+    // and built-in delegations are left out, and so are ACEs of documented delegations unless asked.
+    pub fn export_bloodhound(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, include_documented: bool, show_builtin: bool) -> Result<Vec<BloodHoundFile>, LdapError> {
+        eprintln!(" [.] Collecting objects for BloodHound...");
+        let mut objects = vec![];
+        for domain in &self.domains {
+            let search = self.directory.search(&domain.distinguished_name, SearchScope::Subtree,
+                Some("(|(objectClass=user)(objectClass=group)(objectClass=organizationalUnit)(objectClass=groupPolicyContainer)(objectClass=container)(objectClass=domainDNS))"),
+                &[
+                    "objectClass",
+                    "objectSid",
+                    "objectGUID",
+                    "name",
+                    "sAMAccountName",
+                    "displayName",
+                    "description",
+                    "dNSHostName",
+                    "userAccountControl",
+                    "adminCount",
+                    "primaryGroupID",
+                    "member",
+                    "gPLink",
+                    "gPOptions",
+                    "gPCFileSysPath",
+                ], None);
+            for entry in search {
+                let entry = entry?;
+                // Other naming contexts below this domain (e.g. DNS application partitions) are not part of it
+                if self.naming_contexts.iter().any(|nc| nc.len() > domain.distinguished_name.len() && ends_with_case_insensitive(&entry.dn, nc)) {
+                    continue;
+                }
+                let classes = get_attr_strs(&[&entry], &entry.dn, "objectclass").unwrap_or_default();
+                let object_type = match BloodHoundType::from_classes(&classes) {
+                    Some(t) => t,
+                    None => continue,
+                };
+                let class_name = classes.last().map(|c| c.to_ascii_lowercase());
+                let class_guid = class_name.as_ref().and_then(|c| self.schema.class_guids.get(c)).copied();
+                let identifier = match object_type {
+                    BloodHoundType::Ou | BloodHoundType::Gpo | BloodHoundType::Container => match get_attr_guid(&[&entry], &entry.dn, "objectguid") {
+                        Ok(guid) => guid.to_string(),
+                        Err(_) => continue,
+                    },
+                    _ => match get_attr_sid(&[&entry], &entry.dn, "objectsid") {
+                        Ok(sid) => get_bloodhound_sid(&sid, domain),
+                        Err(_) => continue,
+                    },
+                };
+                objects.push(CollectedObject {
+                    entry,
+                    domain,
+                    object_type,
+                    class_name,
+                    class_guid,
+                    identifier,
+                });
+            }
+        }
+
+        let object_dns: HashMap<String, usize> = objects.iter().enumerate()
+            .map(|(index, object)| (object.entry.dn.to_lowercase(), index))
+            .collect();
+        let types: HashMap<&str, BloodHoundType> = objects.iter()
+            .map(|object| (object.identifier.as_str(), object.object_type))
+            .collect();
+        let results: HashMap<String, &AdelegResult> = res.iter()
+            .filter_map(|(location, res)| match (location, res) {
+                (DelegationLocation::Dn(dn), Ok(res)) => Some((dn.to_lowercase(), res)),
+                _ => None,
+            })
+            .collect();
+        let default_results: HashMap<&str, &AdelegResult> = res.iter()
+            .filter_map(|(location, res)| match (location, res) {
+                (DelegationLocation::DefaultSecurityDescriptor(class_name), Ok(res)) => Some((class_name.as_str(), res)),
+                _ => None,
+            })
+            .collect();
+        let is_protected = |dn: &str| self.protected_objects.borrow().contains(dn) ||
+            results.get(dn).map(|res| res.dacl_protected).unwrap_or(false);
+
+        // Containers list their direct children
+        let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, object) in objects.iter().enumerate() {
+            if let Some(parent) = get_parent_container(&object.entry.dn, &object.domain.distinguished_name) {
+                if let Some(parent_index) = object_dns.get(&parent.to_lowercase()) {
+                    children.entry(*parent_index).or_default().push(index);
+                }
+            }
+        }
+
+        // Well-known principals (e.g. Authenticated Users) have no object in domains, SharpHound
+        // creates one group per domain for each of them
+        let mut well_known_groups: HashMap<String, (Sid, &Domain)> = HashMap::new();
+        let mut files: HashMap<&'static str, Vec<BloodHoundObject>> = HashMap::new();
+        for (index, object) in objects.iter().enumerate() {
+            let dn = object.entry.dn.to_lowercase();
+
+            // Every ACE which applies to this object, whether it is set on it, in the default
+            // security descriptor of its class, or inherited from one of its containers
+            let mut aces: Vec<(&Sid, &'static str, bool)> = vec![];
+            if let Some(res) = results.get(&dn) {
+                if let Some(owner) = &res.owner {
+                    aces.push((owner, "Owns", false));
+                }
+                for ace in self.get_effective_aces(res, include_documented, show_builtin).into_iter().filter(|ace| !ace.get_inherit_only()) {
+                    aces.extend(self.get_bloodhound_rights(ace, object.object_type).into_iter().map(|right| (&ace.trustee, right, false)));
+                }
+            }
+            if let Some(res) = object.class_name.as_ref().and_then(|c| default_results.get(c.as_str())) {
+                for ace in self.get_effective_aces(res, include_documented, show_builtin).into_iter().filter(|ace| !ace.get_inherit_only()) {
+                    aces.extend(self.get_bloodhound_rights(ace, object.object_type).into_iter().map(|right| (&ace.trustee, right, false)));
+                }
+            }
+            if !is_protected(&dn) {
+                let mut cursor = object.entry.dn.as_str();
+                let mut direct_parent = true;
+                while let Some(parent) = get_parent_container(cursor, &object.domain.distinguished_name) {
+                    let parent_dn = parent.to_lowercase();
+                    if let Some(res) = results.get(&parent_dn) {
+                        let inherited = self.get_effective_aces(res, include_documented, show_builtin).into_iter()
+                            .filter(|ace| ace.get_container_inherit() && (direct_parent || !ace.get_no_propagate()))
+                            .filter(|ace| ace.get_inherited_object_type().map(|guid| object.class_guid.as_ref() == Some(guid)).unwrap_or(true));
+                        for ace in inherited {
+                            aces.extend(self.get_bloodhound_rights(ace, object.object_type).into_iter().map(|right| (&ace.trustee, right, true)));
+                        }
+                    }
+                    if is_protected(&parent_dn) {
+                        break;
+                    }
+                    direct_parent = false;
+                    cursor = parent;
+                }
+            }
+            let mut seen = HashSet::new();
+            let mut bloodhound_aces = vec![];
+            for (trustee, right_name, is_inherited) in aces {
+                if UNMODELLED_TRUSTEES.iter().any(|s| Sid::try_from(*s).ok().as_ref() == Some(trustee)) {
+                    continue;
+                }
+                let principal_sid = get_bloodhound_sid(trustee, object.domain);
+                if !trustee.is_domain_specific() && !types.contains_key(principal_sid.as_str()) {
+                    well_known_groups.insert(principal_sid.clone(), (trustee.clone(), object.domain));
+                }
+                if seen.insert((principal_sid.clone(), right_name, is_inherited)) {
+                    bloodhound_aces.push(BloodHoundAce {
+                        right_name,
+                        is_inherited,
+                        principal_type: self.get_bloodhound_principal_type(trustee, &principal_sid, &types),
+                        principal_sid,
+                    });
+                }
+            }
+
+            let child_objects: Vec<Value> = children.get(&index).map(|children| children.iter()
+                .map(|child| json!({
+                    "ObjectIdentifier": objects[*child].identifier,
+                    "ObjectType": objects[*child].object_type,
+                }))
+                .collect())
+                .unwrap_or_default();
+            let exported = BloodHoundObject {
+                object_identifier: object.identifier.clone(),
+                properties: self.get_bloodhound_properties(object),
+                aces: bloodhound_aces,
+                is_acl_protected: is_protected(&dn),
+                is_deleted: false,
+                type_specific: self.get_bloodhound_type_specific(object, child_objects, &object_dns, &objects),
+            };
+            files.entry(object.object_type.file_name()).or_default().push(exported);
+        }
+
+        let mut well_known_groups: Vec<(String, (Sid, &Domain))> = well_known_groups.into_iter().collect();
+        well_known_groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (identifier, (sid, domain)) in well_known_groups {
+            let name = self.resolve_sid(&sid).map(|(name, _)| name).unwrap_or(sid.to_string());
+            let name = name.rsplit('\\').next().unwrap_or(&name).to_uppercase();
+            let mut properties = Map::new();
+            properties.insert("name".to_owned(), json!(format!("{}@{}", name, get_domain_fqdn(domain))));
+            properties.insert("domain".to_owned(), json!(get_domain_fqdn(domain)));
+            properties.insert("domainsid".to_owned(), json!(domain.sid.to_string()));
+            let mut type_specific = Map::new();
+            type_specific.insert("Members".to_owned(), json!([]));
+            files.entry(BloodHoundType::Group.file_name()).or_default().push(BloodHoundObject {
+                object_identifier: identifier,
+                properties,
+                aces: vec![],
+                is_acl_protected: false,
+                is_deleted: false,
+                type_specific,
+            });
+        }
+
+        let mut res = vec![];
+        for object_type in [BloodHoundType::User, BloodHoundType::Computer, BloodHoundType::Group, BloodHoundType::Ou, BloodHoundType::Gpo, BloodHoundType::Domain, BloodHoundType::Container] {
+            let data = files.remove(object_type.file_name()).unwrap_or_default();
+            res.push(BloodHoundFile {
+                meta: BloodHoundMeta {
+                    methods: SHARPHOUND_COLLECTION_METHODS,
+                    data_type: object_type.file_name(),
+                    count: data.len(),
+                    version: SHARPHOUND_FORMAT_VERSION,
+                },
+                data,
+            });
+        }
+        Ok(res)
+    }
+
+    // Allow ACEs reported on a resource, plus ACEs of documented delegations if requested
+    fn get_effective_aces<'r>(&self, res: &'r AdelegResult, include_documented: bool, show_builtin: bool) -> Vec<&'r Ace> {
+        let mut aces: Vec<&Ace> = res.orphan_aces.iter().collect();
+        if include_documented {
+            for (delegation, _, aces_found, _) in &res.delegations {
+                if !delegation.builtin || show_builtin {
+                    aces.extend(aces_found.iter());
+                }
+            }
+        }
+        aces.retain(|ace| ace.grants_access());
+        aces
+    }
+
+    // Names of the BloodHound edges granted by an ACE on an object of the given type
+    fn get_bloodhound_rights(&self, ace: &Ace, object_type: BloodHoundType) -> Vec<&'static str> {
+        let access_mask = ace.access_mask.map_generic();
+        let object_guid = ace.get_object_type();
+        if object_guid.is_none() && access_mask.contains(AccessMask::FULL_CONTROL) {
+            return vec!["GenericAll"];
+        }
+        let is_principal = matches!(object_type, BloodHoundType::User | BloodHoundType::Computer);
+        let mut rights = vec![];
+        if access_mask.contains(AccessMask::WRITE_DAC) {
+            rights.push("WriteDacl");
+        }
+        if access_mask.contains(AccessMask::WRITE_OWNER) {
+            rights.push("WriteOwner");
+        }
+        if access_mask.contains(AccessMask::WRITE_PROP) {
+            if object_guid.is_none() {
+                rights.push("GenericWrite");
+            } else {
+                if object_type == BloodHoundType::Group && self.covers_attribute(object_guid, MEMBER_ATTRIBUTE) {
+                    rights.push("AddMember");
+                }
+                if is_principal && self.covers_attribute(object_guid, KEY_CREDENTIAL_LINK_ATTRIBUTE) {
+                    rights.push("AddKeyCredentialLink");
+                }
+                if object_type == BloodHoundType::Computer && self.covers_attribute(object_guid, ALLOWED_TO_ACT_ATTRIBUTE) {
+                    rights.push("AddAllowedToAct");
+                }
+            }
+        }
+        if access_mask.contains(AccessMask::SELF) && object_type == BloodHoundType::Group && self.covers_attribute(object_guid, MEMBER_ATTRIBUTE) {
+            rights.push("AddSelf");
+        }
+        if access_mask.contains(AccessMask::CONTROL_ACCESS) {
+            let is_right = |s: &str| Guid::try_from(s).ok().as_ref() == object_guid;
+            if object_guid.is_none() {
+                if is_principal || object_type == BloodHoundType::Domain {
+                    rights.push("AllExtendedRights");
+                }
+            } else if is_principal && is_right(FORCE_CHANGE_PASSWORD) {
+                rights.push("ForceChangePassword");
+            } else if object_type == BloodHoundType::Domain && is_right(GET_CHANGES) {
+                rights.push("GetChanges");
+            } else if object_type == BloodHoundType::Domain && is_right(GET_CHANGES_ALL) {
+                rights.push("GetChangesAll");
+            }
+        }
+        rights
+    }
+
+    // Whether writing the given object type (attribute, or property set) includes writing an attribute
+    fn covers_attribute(&self, object_type: Option<&Guid>, attribute: &str) -> bool {
+        let attribute = match Guid::try_from(attribute) {
+            Ok(guid) => guid,
+            Err(_) => return false,
+        };
+        match object_type {
+            None => true,
+            Some(guid) => guid == &attribute || self.schema.attribute_property_sets.get(&attribute) == Some(guid),
+        }
+    }
+
+    fn get_bloodhound_principal_type(&self, sid: &Sid, identifier: &str, types: &HashMap<&str, BloodHoundType>) -> BloodHoundType {
+        if let Some(object_type) = types.get(identifier) {
+            return *object_type;
+        }
+        match self.resolve_sid(sid) {
+            Some((_, PrincipalType::User)) => BloodHoundType::User,
+            Some((_, PrincipalType::Computer)) => BloodHoundType::Computer,
+            Some((_, PrincipalType::Group)) => BloodHoundType::Group,
+            _ if !sid.is_domain_specific() => BloodHoundType::Group,
+            _ => BloodHoundType::Base,
+        }
+    }
+
+    fn get_bloodhound_properties(&self, object: &CollectedObject) -> Map<String, Value> {
+        let entry = &object.entry;
+        let fqdn = get_domain_fqdn(object.domain);
+        let attr = |name: &str| get_attr_str(&[entry], &entry.dn, name).ok();
+        // Relative name of the object, in case its name attribute could not be read
+        let rdn = entry.dn.split_once(',').map(|(rdn, _)| rdn).unwrap_or(&entry.dn);
+        let rdn = rdn.split_once('=').map(|(_, value)| value.to_owned()).unwrap_or_default();
+        let name = match object.object_type {
+            BloodHoundType::Domain => fqdn.clone(),
+            BloodHoundType::Computer => match attr("dnshostname") {
+                Some(hostname) => hostname.to_uppercase(),
+                None => format!("{}.{}", attr("samaccountname").map(|name| name.trim_end_matches('$').to_owned()).unwrap_or(rdn), fqdn).to_uppercase(),
+            },
+            BloodHoundType::User | BloodHoundType::Group => format!("{}@{}", attr("samaccountname").or_else(|| attr("name")).unwrap_or(rdn), fqdn).to_uppercase(),
+            BloodHoundType::Gpo => format!("{}@{}", attr("displayname").or_else(|| attr("name")).unwrap_or(rdn), fqdn).to_uppercase(),
+            _ => format!("{}@{}", attr("name").unwrap_or(rdn), fqdn).to_uppercase(),
+        };
+        let mut properties = Map::new();
+        properties.insert("name".to_owned(), json!(name));
+        properties.insert("domain".to_owned(), json!(fqdn));
+        properties.insert("domainsid".to_owned(), json!(object.domain.sid.to_string()));
+        properties.insert("distinguishedname".to_owned(), json!(entry.dn.to_uppercase()));
+        if let Some(description) = attr("description") {
+            properties.insert("description".to_owned(), json!(description));
+        }
+        if let Some(samaccountname) = attr("samaccountname") {
+            properties.insert("samaccountname".to_owned(), json!(samaccountname));
+        }
+        if matches!(object.object_type, BloodHoundType::User | BloodHoundType::Computer | BloodHoundType::Group) {
+            properties.insert("admincount".to_owned(), json!(attr("admincount").map(|v| v != "0").unwrap_or(false)));
+        }
+        if let Some(uac) = attr("useraccountcontrol").and_then(|v| v.parse::<u32>().ok()) {
+            properties.insert("enabled".to_owned(), json!((uac & 2) == 0)); // ACCOUNTDISABLE
+        }
+        if object.object_type == BloodHoundType::Ou {
+            properties.insert("blocksinheritance".to_owned(), json!(attr("gpoptions").map(|v| v == "1").unwrap_or(false)));
+        }
+        if let Some(path) = attr("gpcfilesyspath") {
+            properties.insert("gpcpath".to_owned(), json!(path.to_uppercase()));
+        }
+        properties
+    }
+
+    fn get_bloodhound_type_specific(&self, object: &CollectedObject, child_objects: Vec<Value>, object_dns: &HashMap<String, usize>, objects: &[CollectedObject]) -> Map<String, Value> {
+        let entry = &object.entry;
+        let not_collected = || json!({ "Results": [], "Collected": false, "FailureReason": null });
+        let primary_group = get_attr_str(&[entry], &entry.dn, "primarygroupid").ok()
+            .and_then(|rid| rid.parse::<u32>().ok())
+            .map(|rid| object.domain.sid.with_rid(rid).to_string());
+        let mut res = Map::new();
+        match object.object_type {
+            BloodHoundType::User => {
+                res.insert("PrimaryGroupSID".to_owned(), json!(primary_group));
+                res.insert("AllowedToDelegate".to_owned(), json!([]));
+                res.insert("HasSIDHistory".to_owned(), json!([]));
+                res.insert("SPNTargets".to_owned(), json!([]));
+            },
+            BloodHoundType::Computer => {
+                res.insert("PrimaryGroupSID".to_owned(), json!(primary_group));
+                res.insert("AllowedToDelegate".to_owned(), json!([]));
+                res.insert("AllowedToAct".to_owned(), json!([]));
+                res.insert("HasSIDHistory".to_owned(), json!([]));
+                for field in ["Sessions", "PrivilegedSessions", "RegistrySessions", "LocalAdmins", "RemoteDesktopUsers", "DcomUsers", "PSRemoteUsers"] {
+                    res.insert(field.to_owned(), not_collected());
+                }
+                res.insert("Status".to_owned(), Value::Null);
+            },
+            BloodHoundType::Group => {
+                let members: Vec<Value> = get_attr_strs(&[entry], &entry.dn, "member").unwrap_or_default().iter()
+                    .filter_map(|member| match object_dns.get(&member.to_lowercase()) {
+                        Some(index) => Some(json!({
+                            "ObjectIdentifier": objects[*index].identifier,
+                            "ObjectType": objects[*index].object_type,
+                        })),
+                        None => get_foreign_principal_sid(member).map(|sid| json!({
+                            "ObjectIdentifier": get_bloodhound_sid(&sid, object.domain),
+                            "ObjectType": if sid.is_domain_specific() { BloodHoundType::Base } else { BloodHoundType::Group },
+                        })),
+                    })
+                    .collect();
+                res.insert("Members".to_owned(), json!(members));
+            },
+            BloodHoundType::Ou | BloodHoundType::Domain => {
+                let links: Vec<Value> = get_attr_str(&[entry], &entry.dn, "gplink").ok()
+                    .map(|gplink| parse_gplink(&gplink))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|(dn, enforced)| object_dns.get(&dn).map(|index| json!({
+                        "IsEnforced": enforced,
+                        "GUID": objects[*index].identifier,
+                    })))
+                    .collect();
+                res.insert("Links".to_owned(), json!(links));
+                res.insert("ChildObjects".to_owned(), json!(child_objects));
+                res.insert("GPOChanges".to_owned(), json!({
+                    "LocalAdmins": [],
+                    "RemoteDesktopUsers": [],
+                    "DcomUsers": [],
+                    "PSRemoteUsers": [],
+                    "AffectedComputers": [],
+                }));
+                if object.object_type == BloodHoundType::Domain {
+                    res.insert("Trusts".to_owned(), json!([]));
+                }
+            },
+            BloodHoundType::Container => {
+                res.insert("ChildObjects".to_owned(), json!(child_objects));
+            },
+            BloodHoundType::Gpo | BloodHoundType::Base => (),
+        }
+        res
+    }
+}
+
+// SIDs which are not specific to a domain (e.g. S-1-5-11 or S-1-5-32-544) are prefixed with the
+// domain they are used in, as SharpHound does
+fn get_bloodhound_sid(sid: &Sid, domain: &Domain) -> String {
+    if sid.is_domain_specific() {
+        sid.to_string()
+    } else {
+        format!("{}-{}", get_domain_fqdn(domain), sid)
+    }
+}
+
+// DNS name of a domain, in uppercase (e.g. DC=example,DC=com becomes EXAMPLE.COM)
+fn get_domain_fqdn(domain: &Domain) -> String {
+    domain.distinguished_name.split(',')
+        .filter_map(|rdn| rdn.split_once('='))
+        .filter(|(attr, _)| attr.eq_ignore_ascii_case("dc"))
+        .map(|(_, value)| value.to_uppercase())
+        .collect::<Vec<String>>()
+        .join(".")
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use authz::{Ace, AceType, Guid, Sid};
+use authz::{CONTAINER_INHERIT_ACE, INHERIT_ONLY_ACE, OBJECT_INHERIT_ACE, NO_PROPAGATE_INHERIT_ACE};
+use crate::delegations::{Delegation, DelegationAce, DelegationLocation, DelegationRights, DelegationTemplate, DelegationTrustee};
+use crate::engine::{AdelegResult, Engine};
+use crate::error::AdelegError;
+use crate::utils::ace_equivalent;
+
+// A template whose ACEs have been found (all of them, or only some) among the orphan ACEs of a trustee
+#[derive(Debug, Clone)]
+pub struct TemplateMatch {
+    pub(crate) location: DelegationLocation,
+    pub(crate) trustee: Sid,
+    pub(crate) template: String,
+    pub(crate) aces_found: Vec<(DelegationLocation, Ace)>,
+    pub(crate) aces_missing: Vec<(DelegationLocation, Ace)>,
+}
+
+impl TemplateMatch {
+    pub fn is_full(&self) -> bool {
+        self.aces_missing.is_empty()
+    }
+}
+
+impl<'a> Engine<'a> {
+    // Matches orphan ACEs of each trustee against every template loaded, at each location where
+    // this trustee has orphan ACEs (or globally, for templates which only use fixed locations)
+    pub fn match_templates(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>) -> Vec<TemplateMatch> {
+        eprintln!(" [.] Matching orphan ACEs against templates...");
+        let orphan_aces = get_orphan_aces(res);
+        let mut templates: Vec<&DelegationTemplate> = self.templates.values().collect();
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut matches = vec![];
+        for (trustee, clusters) in &orphan_aces {
+            let mut locations: Vec<&DelegationLocation> = clusters.keys().collect();
+            locations.sort();
+            for template in &templates {
+                let resources = if template.rights.iter().all(|ace| ace.fixed_location.is_some()) {
+                    vec![&DelegationLocation::Global]
+                } else {
+                    locations.clone()
+                };
+                for resource in resources {
+                    let delegation = Delegation {
+                        trustee: DelegationTrustee::Sid(trustee.clone()),
+                        resource: resource.clone(),
+                        rights: DelegationRights::Template((*template).clone()),
+                        builtin: false,
+                    };
+                    let expected_aces = match delegation.derive_aces(self.directory, &self.root_domain, &self.domains) {
+                        Ok(aces) => aces,
+                        Err(_) => continue,
+                    };
+                    let mut aces_found = vec![];
+                    let mut aces_missing = vec![];
+                    for (location, aces) in expected_aces {
+                        let cluster = clusters.get(&location).map(|v| v.as_slice()).unwrap_or_default();
+                        for ace in aces {
+                            if cluster.iter().any(|found| ace_equivalent(found, &ace)) {
+                                aces_found.push((location.clone(), ace));
+                            } else {
+                                aces_missing.push((location.clone(), ace));
+                            }
+                        }
+                    }
+                    if !aces_found.is_empty() {
+                        matches.push(TemplateMatch {
+                            location: resource.clone(),
+                            trustee: trustee.clone(),
+                            template: template.name.clone(),
+                            aces_found,
+                            aces_missing,
+                        });
+                    }
+                }
+            }
+        }
+        matches.sort_by(|a, b| a.location.cmp(&b.location)
+            .then_with(|| a.trustee.to_string().cmp(&b.trustee.to_string()))
+            .then_with(|| b.is_full().cmp(&a.is_full()))
+            .then_with(|| a.template.cmp(&b.template)));
+        matches
+    }
+
+    pub fn describe_template_match(&self, template_match: &TemplateMatch) -> String {
+        if template_match.is_full() {
+            return format!("Orphan ACEs match template \"{}\", they can be documented as such", template_match.template);
+        }
+        let missing: Vec<String> = template_match.aces_missing.iter().map(|(location, ace)| {
+            let ace = format!("{} {}", if ace.grants_access() { "allow" } else { "deny" }, self.describe_ace(
+                ace.access_mask,
+                ace.get_object_type(),
+                ace.get_inherited_object_type(),
+                ace.get_container_inherit(),
+                ace.get_inherit_only()));
+            if location == &template_match.location { ace } else { format!("{} on {}", ace, location) }
+        }).collect();
+        format!("Orphan ACEs partially match template \"{}\" ({} of {} ACEs found), missing: {}",
+            template_match.template,
+            template_match.aces_found.len(),
+            template_match.aces_found.len() + template_match.aces_missing.len(),
+            missing.join(", "))
+    }
+
+    // Documents the current state as delegations: delegations already loaded, then templates which
+    // fully match orphan ACEs (largest first, each ACE is only documented once), then one raw ACE
+    // delegation for each orphan ACE left.
+    pub fn bootstrap_delegations(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, template_matches: &[TemplateMatch]) -> Vec<Delegation> {
+        let mut delegations: Vec<Delegation> = self.delegations.iter()
+            .filter(|delegation| !delegation.builtin)
+            .map(|delegation| Delegation {
+                rights: match &delegation.rights {
+                    DelegationRights::Template(template) => DelegationRights::TemplateName { template: template.name.clone() },
+                    rights => rights.clone(),
+                },
+                ..delegation.clone()
+            })
+            .collect();
+
+        let mut remaining = get_orphan_aces(res);
+        let mut full_matches: Vec<&TemplateMatch> = template_matches.iter().filter(|m| m.is_full()).collect();
+        full_matches.sort_by_key(|m| std::cmp::Reverse(m.aces_found.len()));
+        for template_match in full_matches {
+            let clusters = match remaining.get_mut(&template_match.trustee) {
+                Some(clusters) => clusters,
+                None => continue,
+            };
+            let still_orphan = template_match.aces_found.iter().all(|(location, ace)| clusters.get(location)
+                .map(|cluster| cluster.iter().any(|found| ace_equivalent(found, ace)))
+                .unwrap_or(false));
+            if !still_orphan {
+                continue;
+            }
+            for (location, ace) in &template_match.aces_found {
+                if let Some(cluster) = clusters.get_mut(location) {
+                    cluster.retain(|found| !ace_equivalent(found, ace));
+                }
+            }
+            delegations.push(Delegation {
+                trustee: DelegationTrustee::Sid(template_match.trustee.clone()),
+                resource: template_match.location.clone(),
+                rights: DelegationRights::TemplateName { template: template_match.template.clone() },
+                builtin: false,
+            });
+        }
+
+        let mut remaining: Vec<(Sid, DelegationLocation, Ace)> = remaining.into_iter()
+            .flat_map(|(trustee, clusters)| clusters.into_iter()
+                .flat_map(move |(location, aces)| {
+                    let trustee = trustee.clone();
+                    aces.into_iter().map(move |ace| (trustee.clone(), location.clone(), ace))
+                }))
+            .collect();
+        remaining.sort_by(|(sid_a, loc_a, _), (sid_b, loc_b, _)| loc_a.cmp(loc_b)
+            .then_with(|| sid_a.to_string().cmp(&sid_b.to_string())));
+        for (trustee, location, ace) in remaining {
+            match self.get_delegation_ace(&ace) {
+                Some(delegation_ace) => delegations.push(Delegation {
+                    trustee: DelegationTrustee::Sid(trustee),
+                    resource: location,
+                    rights: DelegationRights::Ace(delegation_ace),
+                    builtin: false,
+                }),
+                None => eprintln!(" [!] Conditional ACE for {} on {} cannot be documented, it has been left out", trustee, location),
+            }
+        }
+        delegations
+    }
+
+    // Converts an ACE back into its documented form, with object types named after the schema
+    fn get_delegation_ace(&self, ace: &Ace) -> Option<DelegationAce> {
+        let allow = match &ace.type_specific {
+            AceType::AccessAllowed | AceType::AccessAllowedObject { .. } => true,
+            AceType::AccessDenied | AceType::AccessDeniedObject { .. } => false,
+            _ => return None,
+        };
+        let object_type = ace.get_object_type().cloned();
+        let inherited_object_type = ace.get_inherited_object_type().cloned();
+        Some(DelegationAce {
+            fixed_location: None,
+            allow,
+            access_mask: ace.access_mask,
+            object_type_name: object_type.as_ref().map(|guid| self.get_object_type_name(guid)),
+            object_type,
+            inherited_object_type_name: inherited_object_type.as_ref().map(|guid| self.get_class_name(guid)),
+            inherited_object_type,
+            container_inherit: (ace.flags & CONTAINER_INHERIT_ACE) != 0,
+            object_inherit: (ace.flags & OBJECT_INHERIT_ACE) != 0,
+            inherit_only: (ace.flags & INHERIT_ONLY_ACE) != 0,
+            no_propagate: (ace.flags & NO_PROPAGATE_INHERIT_ACE) != 0,
+        })
+    }
+
+    // Names are looked up in the same order as when parsing delegations, GUIDs are kept as is if
+    // they are unknown
+    fn get_object_type_name(&self, guid: &Guid) -> String {
+        if let Some((name, _)) = self.schema.class_guids.iter().find(|(_, class_guid)| *class_guid == guid) {
+            return name.clone();
+        }
+        self.schema.attribute_guids.get(guid)
+            .or_else(|| self.schema.property_set_names.get(guid))
+            .or_else(|| self.schema.validated_write_names.get(guid))
+            .or_else(|| self.schema.control_access_names.get(guid))
+            .cloned()
+            .unwrap_or_else(|| guid.to_string())
+    }
+
+    fn get_class_name(&self, guid: &Guid) -> String {
+        self.schema.class_guids.iter()
+            .find(|(_, class_guid)| *class_guid == guid)
+            .map(|(name, _)| name.clone())
+            .unwrap_or_else(|| guid.to_string())
+    }
+}
+
+// Orphan ACEs of each trustee, by location
+fn get_orphan_aces(res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>) -> HashMap<Sid, HashMap<DelegationLocation, Vec<Ace>>> {
+    let mut orphan_aces: HashMap<Sid, HashMap<DelegationLocation, Vec<Ace>>> = HashMap::new();
+    for (location, res) in res {
+        if let Ok(res) = res {
+            for ace in &res.orphan_aces {
+                orphan_aces.entry(ace.trustee.clone())
+                    .or_default()
+                    .entry(location.clone())
+                    .or_default()
+                    .push(ace.clone());
+            }
+        }
+    }
+    orphan_aces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestForest, ROOT_DOMAIN_DN};
+
+    #[test]
+    fn bootstrapped_delegations_keep_whole_access_masks() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let ou_dn = format!("OU=Staff,{}", ROOT_DOMAIN_DN);
+        // 0x200 has no meaning on Active Directory objects
+        forest.add_object(&ou_dn, "organizationalUnit", &format!("O:DAG:DAD:(A;;GA;;;DA)(A;;0x220;;;{})", helpdesk), &[]);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        let delegations = engine.bootstrap_delegations(&res, &[]);
+        assert_eq!(delegations.len(), 1);
+        match &delegations[0].rights {
+            DelegationRights::Ace(ace) => assert_eq!(ace.access_mask.bits(), 0x220),
+            rights => panic!("unexpected delegation rights {:?}", rights),
+        }
+
+        // Once documented, the ACE is not reported anymore
+        let json = serde_json::to_string(&delegations).expect("unable to serialize delegations");
+        let mut engine = forest.engine();
+        engine.load_delegation_json(&json).expect("unable to load bootstrapped delegations");
+        let res = engine.run().expect("analysis failed");
+        let orphan_aces = match res.get(&DelegationLocation::Dn(ou_dn.clone())) {
+            Some(Ok(result)) => result.orphan_aces.len(),
+            Some(Err(e)) => panic!("analysis of {} failed: {}", ou_dn, e),
+            None => 0,
+        };
+        assert_eq!(orphan_aces, 0);
+    }
+}
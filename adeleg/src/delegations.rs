@@ -1,12 +1,12 @@
-use authz::{Ace, AceType, Guid};
+use authz::{AccessMask, Ace, AceType, Guid};
 use std::collections::HashMap;
 use authz::Sid;
 use serde::{Serialize, Deserialize};
 use crate::{utils::{Domain, replace_suffix_case_insensitive, ends_with_case_insensitive, resolve_samaccountname_to_sid}, schema::Schema, error::AdelegError};
-use winldap::connection::LdapConnection;
-use windows::Win32::Security::{CONTAINER_INHERIT_ACE, INHERIT_ONLY_ACE, OBJECT_INHERIT_ACE, ACE_OBJECT_TYPE_PRESENT};
-use windows::Win32::Security::NO_PROPAGATE_INHERIT_ACE;
-use windows::Win32::Security::ACE_INHERITED_OBJECT_TYPE_PRESENT;
+use crate::directory::DirectorySource;
+use authz::{CONTAINER_INHERIT_ACE, INHERIT_ONLY_ACE, OBJECT_INHERIT_ACE, ACE_OBJECT_TYPE_PRESENT};
+use authz::NO_PROPAGATE_INHERIT_ACE;
+use authz::ACE_INHERITED_OBJECT_TYPE_PRESENT;
 
 fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
@@ -27,7 +27,7 @@ pub enum DelegationTrustee {
     //TODO: UPN(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialOrd)]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, Default)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum DelegationLocation {
@@ -37,15 +37,10 @@ pub enum DelegationLocation {
     Dn(String),
     // Only used for delegations which are not delegated on a particular object
     // and only use fixed_location fields in DelegationAce.
+    #[default]
     Global,
 }
 
-impl Default for DelegationLocation {
-    fn default() -> Self {
-        Self::Global
-    }
-}
-
 impl PartialEq for DelegationLocation {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -72,6 +67,12 @@ impl Ord for DelegationLocation {
     }
 }
 
+impl PartialOrd for DelegationLocation {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl core::fmt::Display for DelegationLocation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -123,7 +124,7 @@ pub struct DelegationAce {
     #[serde(skip_serializing_if = "is_default")]
     #[serde(default = "true_by_default")]
     pub allow: bool,
-    pub access_mask: u32,
+    pub access_mask: AccessMask,
     #[serde(skip)]
     pub object_type: Option<Guid>,
     #[serde(skip_serializing_if = "is_default")]
@@ -184,7 +185,7 @@ pub enum DelegationRights {
 
 impl Delegation {
     pub fn from_json(json: &str, templates: &HashMap<String, DelegationTemplate>, schema: &Schema) -> Result<Vec<Self>, AdelegError> {
-        let mut res: Vec<Self> = match serde_json::from_str(&json) {
+        let mut res: Vec<Self> = match serde_json::from_str(json) {
             Ok(v) => v,
             Err(e) => return Err(AdelegError::JsonParsing(e.to_string())),
         };
@@ -220,7 +221,7 @@ impl Delegation {
         Ok(res)
     }
 
-    pub fn derive_aces(&self, conn: &LdapConnection, root_domain: &Domain, domains: &[Domain]) -> Result<HashMap<DelegationLocation, Vec<Ace>>, AdelegError> {
+    pub fn derive_aces(&self, directory: &dyn DirectorySource, root_domain: &Domain, domains: &[Domain]) -> Result<HashMap<DelegationLocation, Vec<Ace>>, AdelegError> {
         let mut res = HashMap::new();
 
         let delegation_aces = match &self.rights {
@@ -237,18 +238,7 @@ impl Delegation {
                 self.resource.clone()
             };
             let locations = if let DelegationLocation::Dn(dn_or_rdn) = location {
-                if ends_with_case_insensitive(&dn_or_rdn, "cn=configuration,dc=*") {
-                    vec![DelegationLocation::Dn(replace_suffix_case_insensitive(&dn_or_rdn, "cn=configuration,dc=*", conn.get_configuration_naming_context()))]
-                } else if ends_with_case_insensitive(&dn_or_rdn, "cn=schema,dc=*") {
-                    vec![DelegationLocation::Dn(replace_suffix_case_insensitive(&dn_or_rdn, "cn=schema,dc=*", conn.get_schema_naming_context()))]
-                } else if ends_with_case_insensitive(&dn_or_rdn, "dc=domaindnszones,dc=*") ||
-                        ends_with_case_insensitive(&dn_or_rdn, "dc=forestdnszones,dc=*") {
-                    vec![DelegationLocation::Dn(replace_suffix_case_insensitive(&dn_or_rdn, "dc=*", conn.get_root_domain_naming_context()))]
-                } else if ends_with_case_insensitive(&dn_or_rdn, "dc=*") {
-                    domains.iter().map(|d| DelegationLocation::Dn(replace_suffix_case_insensitive(&dn_or_rdn, "dc=*", &d.distinguished_name))).collect()
-                } else {
-                    vec![DelegationLocation::Dn(dn_or_rdn)]
-                }
+                expand_relative_dn(&dn_or_rdn, directory, domains).into_iter().map(DelegationLocation::Dn).collect()
             } else {
                 vec![location]
             };
@@ -267,7 +257,7 @@ impl Delegation {
                                         break;
                                     }
                                 }
-                                vec![domain.unwrap_or(&root_domain).sid.with_rid(*r)]
+                                vec![domain.unwrap_or(root_domain).sid.with_rid(*r)]
                             },
                         }
                     },
@@ -284,10 +274,10 @@ impl Delegation {
                                         break;
                                     }
                                 }
-                                domain.unwrap_or(&root_domain)
+                                domain.unwrap_or(root_domain)
                             },
                         };
-                        if let Ok(sid) = resolve_samaccountname_to_sid(conn, samaccountname, domain) {
+                        if let Ok(sid) = resolve_samaccountname_to_sid(directory, samaccountname, domain) {
                             vec![sid]
                         } else {
                             return Err(AdelegError::UnresolvedSamAccountName(samaccountname.to_owned(), domain.distinguished_name.to_owned()));
@@ -297,27 +287,27 @@ impl Delegation {
 
                 let mut flags = 0;
                 if ace.container_inherit {
-                    flags |= CONTAINER_INHERIT_ACE.0 as u8;
+                    flags |= CONTAINER_INHERIT_ACE;
                 }
                 if ace.object_inherit {
-                    flags |= OBJECT_INHERIT_ACE.0 as u8;
+                    flags |= OBJECT_INHERIT_ACE;
                 }
                 if ace.inherit_only {
-                    flags |= INHERIT_ONLY_ACE.0 as u8;
+                    flags |= INHERIT_ONLY_ACE;
                 }
                 if ace.no_propagate {
-                    flags |= NO_PROPAGATE_INHERIT_ACE.0 as u8;
+                    flags |= NO_PROPAGATE_INHERIT_ACE;
                 }
                 let type_specific = match (ace.allow, ace.object_type, ace.inherited_object_type) {
                     (true, None, None) => AceType::AccessAllowed,
                     (true, object_type, inherited_object_type) => AceType::AccessAllowedObject {
-                        flags: if object_type.is_some() { ACE_OBJECT_TYPE_PRESENT.0 } else { 0 } | if inherited_object_type.is_some() { ACE_INHERITED_OBJECT_TYPE_PRESENT.0 } else { 0 },
+                        flags: if object_type.is_some() { ACE_OBJECT_TYPE_PRESENT } else { 0 } | if inherited_object_type.is_some() { ACE_INHERITED_OBJECT_TYPE_PRESENT } else { 0 },
                         object_type,
                         inherited_object_type,
                     },
                     (false, None, None) => AceType::AccessDenied,
                     (false, object_type, inherited_object_type) => AceType::AccessDeniedObject {
-                        flags: if object_type.is_some() { ACE_OBJECT_TYPE_PRESENT.0 } else { 0 } | if inherited_object_type.is_some() { ACE_INHERITED_OBJECT_TYPE_PRESENT.0 } else { 0 },
+                        flags: if object_type.is_some() { ACE_OBJECT_TYPE_PRESENT } else { 0 } | if inherited_object_type.is_some() { ACE_INHERITED_OBJECT_TYPE_PRESENT } else { 0 },
                         object_type,
                         inherited_object_type,
                     },
@@ -326,7 +316,7 @@ impl Delegation {
                     res.entry(location.clone()).or_insert(vec![]).push(Ace {
                         trustee,
                         flags,
-                        access_mask: ace.access_mask,
+                        access_mask: ace.access_mask.map_generic(),
                         type_specific: type_specific.clone(),
                     });
                 }
@@ -337,28 +327,45 @@ impl Delegation {
     }
 }
 
+// Relative DNs end with "DC=*", which is replaced with the corresponding naming context (or with
+// each domain naming context, for domain partitions)
+pub(crate) fn expand_relative_dn(dn_or_rdn: &str, directory: &dyn DirectorySource, domains: &[Domain]) -> Vec<String> {
+    if ends_with_case_insensitive(dn_or_rdn, "cn=configuration,dc=*") {
+        vec![replace_suffix_case_insensitive(dn_or_rdn, "cn=configuration,dc=*", directory.get_configuration_naming_context())]
+    } else if ends_with_case_insensitive(dn_or_rdn, "cn=schema,dc=*") {
+        vec![replace_suffix_case_insensitive(dn_or_rdn, "cn=schema,dc=*", directory.get_schema_naming_context())]
+    } else if ends_with_case_insensitive(dn_or_rdn, "dc=domaindnszones,dc=*") ||
+            ends_with_case_insensitive(dn_or_rdn, "dc=forestdnszones,dc=*") {
+        vec![replace_suffix_case_insensitive(dn_or_rdn, "dc=*", directory.get_root_domain_naming_context())]
+    } else if ends_with_case_insensitive(dn_or_rdn, "dc=*") {
+        domains.iter().map(|d| replace_suffix_case_insensitive(dn_or_rdn, "dc=*", &d.distinguished_name)).collect()
+    } else {
+        vec![dn_or_rdn.to_owned()]
+    }
+}
+
 fn resolve_object_type(name: &str, schema: &Schema) -> Option<Guid> {
     if let Some(guid) = schema.class_guids.get(&name.to_ascii_lowercase()) {
-        return Some(guid.clone());
+        return Some(*guid);
     }
     for (guid, attr_name) in &schema.attribute_guids {
         if attr_name == name {
-            return Some(guid.clone());
+            return Some(*guid);
         }
     }
     for (guid, propset_name) in &schema.property_set_names {
         if propset_name == name {
-            return Some(guid.clone());
+            return Some(*guid);
         }
     }
     for (guid, validated_write_name) in &schema.validated_write_names {
         if validated_write_name == name {
-            return Some(guid.clone());
+            return Some(*guid);
         }
     }
     for (guid, controlaccess_name) in &schema.control_access_names {
         if controlaccess_name == name {
-            return Some(guid.clone());
+            return Some(*guid);
         }
     }
     if let Ok(guid) = Guid::try_from(name) {
@@ -371,7 +378,7 @@ fn resolve_inherited_object_type(name: &str, schema: &Schema) -> Option<Guid> {
     let name = name.to_ascii_lowercase();
     for (class_name, class_guid) in &schema.class_guids {
         if class_name == &name {
-            return Some(class_guid.clone());
+            return Some(*class_guid);
         }
     }
     if let Ok(guid) = Guid::try_from(name.as_str()) {
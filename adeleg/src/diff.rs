@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+use authz::{Ace, Sid};
+use serde::Serialize;
+use crate::delegations::{Delegation, DelegationLocation};
+use crate::engine::{AdelegResult, Engine};
+use crate::error::AdelegError;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanChange {
+    // An explicit ACE which is not explained by any delegation appeared
+    OrphanAceAdded(Ace),
+    // An explicit ACE which was not explained by any delegation disappeared
+    OrphanAceRemoved(Ace),
+    // The owner flagged on the object changed (None if the owner is not flagged)
+    OwnerChanged {
+        old: Option<Sid>,
+        new: Option<Sid>,
+    },
+    // The DACL now blocks inheritance from its parent container
+    DaclProtected,
+    // The DACL does not block inheritance from its parent container anymore
+    DaclUnprotected,
+    // A documented delegation which was in place (or did not apply here) now misses ACEs
+    DelegationIncomplete {
+        delegation: Delegation,
+        trustee: Sid,
+        missing_aces: Vec<Ace>,
+    },
+    // An ACE appeared for a trustee which does not exist anymore
+    DeletedTrusteeAdded(Ace),
+    // An ACE for a trustee which does not exist anymore has been cleaned up
+    DeletedTrusteeRemoved(Ace),
+    // The location could be analysed, but not anymore (its other changes are unknown)
+    AnalysisFailed(String),
+    // The location could not be analysed, but now can (its findings are reported as new)
+    AnalysisRecovered(String),
+}
+
+impl ScanChange {
+    // Trustee this change is about, if any
+    pub fn get_trustee(&self) -> Option<&Sid> {
+        match self {
+            ScanChange::OrphanAceAdded(ace) |
+            ScanChange::OrphanAceRemoved(ace) |
+            ScanChange::DeletedTrusteeAdded(ace) |
+            ScanChange::DeletedTrusteeRemoved(ace) => Some(&ace.trustee),
+            ScanChange::OwnerChanged { old, new } => new.as_ref().or(old.as_ref()),
+            ScanChange::DelegationIncomplete { trustee, .. } => Some(trustee),
+            ScanChange::DaclProtected | ScanChange::DaclUnprotected |
+            ScanChange::AnalysisFailed(_) | ScanChange::AnalysisRecovered(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanDifference {
+    pub(crate) location: DelegationLocation,
+    pub(crate) change: ScanChange,
+}
+
+// Compares the results of two scans, location by location. Locations which could not be
+// analysed in one of the scans only get a change for the error which appeared or disappeared.
+pub fn diff_results(old: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, new: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>) -> Vec<ScanDifference> {
+    let mut locations: Vec<&DelegationLocation> = old.keys().chain(new.keys())
+        .collect::<HashSet<&DelegationLocation>>()
+        .into_iter()
+        .collect();
+    locations.sort();
+
+    let mut res = vec![];
+    for location in locations {
+        let mut push = |change| res.push(ScanDifference {
+            location: location.clone(),
+            change,
+        });
+        let (old, new) = match (old.get(location), new.get(location)) {
+            (Some(Err(_)), Some(Err(_))) => continue,
+            (_, Some(Err(e))) => {
+                push(ScanChange::AnalysisFailed(e.to_string()));
+                continue;
+            },
+            (Some(Err(e)), new) => {
+                push(ScanChange::AnalysisRecovered(e.to_string()));
+                (None, new.and_then(|r| r.as_ref().ok()))
+            },
+            (Some(Ok(old)), Some(Ok(new))) => (Some(old), Some(new)),
+            (Some(Ok(old)), None) => (Some(old), None),
+            (None, Some(Ok(new))) => (None, Some(new)),
+            (None, None) => continue,
+        };
+
+        let old_owner = old.and_then(|r| r.owner.as_ref());
+        let new_owner = new.and_then(|r| r.owner.as_ref());
+        if old_owner != new_owner {
+            push(ScanChange::OwnerChanged {
+                old: old_owner.cloned(),
+                new: new_owner.cloned(),
+            });
+        }
+
+        let old_protected = old.map(|r| r.dacl_protected).unwrap_or(false);
+        let new_protected = new.map(|r| r.dacl_protected).unwrap_or(false);
+        if !old_protected && new_protected {
+            push(ScanChange::DaclProtected);
+        } else if old_protected && !new_protected {
+            push(ScanChange::DaclUnprotected);
+        }
+
+        let no_aces: &[Ace] = &[];
+        let old_orphans = old.map(|r| &r.orphan_aces[..]).unwrap_or(no_aces);
+        let new_orphans = new.map(|r| &r.orphan_aces[..]).unwrap_or(no_aces);
+        for ace in new_orphans.iter().filter(|ace| !old_orphans.contains(ace)) {
+            push(ScanChange::OrphanAceAdded(ace.clone()));
+        }
+        for ace in old_orphans.iter().filter(|ace| !new_orphans.contains(ace)) {
+            push(ScanChange::OrphanAceRemoved(ace.clone()));
+        }
+
+        let old_deleted = old.map(|r| &r.deleted_trustee[..]).unwrap_or(no_aces);
+        let new_deleted = new.map(|r| &r.deleted_trustee[..]).unwrap_or(no_aces);
+        for ace in new_deleted.iter().filter(|ace| !old_deleted.contains(ace)) {
+            push(ScanChange::DeletedTrusteeAdded(ace.clone()));
+        }
+        for ace in old_deleted.iter().filter(|ace| !new_deleted.contains(ace)) {
+            push(ScanChange::DeletedTrusteeRemoved(ace.clone()));
+        }
+
+        // Delegations are matched by trustee and rights, since their location is already the same
+        let old_incomplete: HashSet<(&Sid, String)> = old.map(|r| r.delegations.iter()
+                .filter(|(_, _, _, missing)| !missing.is_empty())
+                .map(|(delegation, trustee, _, _)| (trustee, get_delegation_key(delegation)))
+                .collect())
+            .unwrap_or_default();
+        for (delegation, trustee, _, missing) in new.map(|r| &r.delegations[..]).unwrap_or(&[]) {
+            if !missing.is_empty() && !old_incomplete.contains(&(trustee, get_delegation_key(delegation))) {
+                push(ScanChange::DelegationIncomplete {
+                    delegation: delegation.clone(),
+                    trustee: trustee.clone(),
+                    missing_aces: missing.clone(),
+                });
+            }
+        }
+    }
+    res
+}
+
+fn get_delegation_key(delegation: &Delegation) -> String {
+    serde_json::to_string(&delegation.rights).unwrap_or_default()
+}
+
+impl<'a> Engine<'a> {
+    pub fn describe_scan_change(&self, change: &ScanChange) -> String {
+        let describe_ace = |ace: &Ace| format!("{} {}{}",
+            if ace.grants_access() { "allow" } else { "deny" },
+            self.describe_ace(
+                ace.access_mask,
+                ace.get_object_type(),
+                ace.get_inherited_object_type(),
+                ace.get_container_inherit(),
+                ace.get_inherit_only()
+            ),
+            self.describe_ace_condition(ace));
+        let describe_owner = |owner: &Option<Sid>| match owner {
+            Some(sid) => self.resolve_sid(sid).map(|(dn, _)| dn).unwrap_or(sid.to_string()),
+            None => "(expected owner)".to_owned(),
+        };
+        match change {
+            ScanChange::OrphanAceAdded(ace) => format!("New ACE: {}", describe_ace(ace)),
+            ScanChange::OrphanAceRemoved(ace) => format!("ACE removed: {}", describe_ace(ace)),
+            ScanChange::OwnerChanged { old, new } => format!("Owner changed from {} to {}", describe_owner(old), describe_owner(new)),
+            ScanChange::DaclProtected => "DACL is now configured to block inheritance of parent container ACEs".to_owned(),
+            ScanChange::DaclUnprotected => "DACL does not block inheritance of parent container ACEs anymore".to_owned(),
+            ScanChange::DelegationIncomplete { delegation, missing_aces, .. } => format!("Delegation is now incomplete ({}), {} ACE(s) missing: {}",
+                self.describe_delegation_rights(&delegation.rights),
+                missing_aces.len(),
+                missing_aces.iter().map(describe_ace).collect::<Vec<String>>().join(", ")),
+            ScanChange::DeletedTrusteeAdded(ace) => format!("New ACE for a trustee which does not exist anymore: {}", describe_ace(ace)),
+            ScanChange::DeletedTrusteeRemoved(ace) => format!("ACE for a trustee which does not exist anymore cleaned up: {}", describe_ace(ace)),
+            ScanChange::AnalysisFailed(error) => format!("Unable to analyse this location anymore: {}", error),
+            ScanChange::AnalysisRecovered(error) => format!("This location can be analysed again (previous error: {})", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use authz::{AccessMask, AceType, Guid};
+
+    fn get_result(orphan_aces: Vec<Ace>) -> Result<AdelegResult, AdelegError> {
+        Ok(AdelegResult {
+            class_guid: Guid::try_from("bf967aa5-0de6-11d0-a285-00aa003049e2").expect("invalid GUID"),
+            dacl_protected: false,
+            owner: None,
+            misplaced_aces: vec![],
+            redundant_aces: vec![],
+            unexpected_inherited_aces: vec![],
+            deleted_trustee: vec![],
+            orphan_aces,
+            delegations: vec![],
+        })
+    }
+
+    #[test]
+    fn analysis_errors_are_reported() {
+        let location = DelegationLocation::Dn("OU=Staff,DC=corp,DC=local".to_owned());
+        let ace = Ace {
+            trustee: Sid::try_from("S-1-5-21-1000-2000-3000-1101").expect("invalid SID"),
+            access_mask: AccessMask::WRITE_PROP,
+            flags: 0,
+            type_specific: AceType::AccessAllowed,
+        };
+        let error = || Err(AdelegError::UnresolvedTemplateName("Reset passwords".to_owned()));
+        let ok = HashMap::from([(location.clone(), get_result(vec![ace.clone()]))]);
+        let failed = HashMap::from([(location.clone(), error())]);
+
+        let changes = diff_results(&ok, &failed);
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0].change, ScanChange::AnalysisFailed(_)));
+
+        let changes = diff_results(&failed, &ok);
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(changes[0].change, ScanChange::AnalysisRecovered(_)));
+        assert!(matches!(&changes[1].change, ScanChange::OrphanAceAdded(added) if added == &ace));
+
+        assert!(diff_results(&failed, &HashMap::from([(location, error())])).is_empty());
+    }
+}
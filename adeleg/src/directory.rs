@@ -0,0 +1,96 @@
+use authz::Sid;
+#[cfg(windows)]
+use windows::Win32::Networking::Ldap::{LDAP_SCOPE_BASE, LDAP_SCOPE_ONELEVEL, LDAP_SCOPE_SUBTREE, LDAP_SERVER_SD_FLAGS_OID};
+#[cfg(windows)]
+use winldap::connection::LdapConnection;
+#[cfg(windows)]
+use winldap::control::{BerVal, BerEncodable, LdapControl};
+use winldap::error::LdapError;
+#[cfg(windows)]
+use winldap::search::LdapSearch;
+use winldap::search::LdapEntry;
+#[cfg(windows)]
+use crate::utils::get_attr_sids;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchScope {
+    Base,
+    OneLevel,
+    Subtree,
+}
+
+pub type DirectorySearch<'a> = Box<dyn Iterator<Item = Result<LdapEntry, LdapError>> + 'a>;
+
+// Everything the engine needs to read from a forest. Attribute names of returned entries are
+// lowercase, as returned by winldap.
+pub trait DirectorySource {
+    fn get_naming_contexts(&self) -> &[String];
+    fn get_root_domain_naming_context(&self) -> &str;
+    fn get_schema_naming_context(&self) -> &str;
+    fn get_configuration_naming_context(&self) -> &str;
+
+    // Searches entries from the given base, only returning the given attributes. If set, sd_flags
+    // selects which parts of nTSecurityDescriptor are returned (OWNER_SECURITY_INFORMATION, etc.)
+    fn search<'a>(&'a self, base: &str, scope: SearchScope, filter: Option<&str>, attributes: &[&str], sd_flags: Option<u32>) -> DirectorySearch<'a>;
+
+    // Looks up the entry with the given objectSid, if any
+    fn get_entry_by_sid(&self, sid: &Sid, attributes: &[&str]) -> Result<Option<LdapEntry>, LdapError>;
+
+    // Returns the SIDs of all groups the given principal is a transitive member of
+    fn get_token_groups(&self, principal: &Sid) -> Result<Vec<Sid>, LdapError>;
+}
+
+#[cfg(windows)]
+impl DirectorySource for LdapConnection {
+    fn get_naming_contexts(&self) -> &[String] {
+        LdapConnection::get_naming_contexts(self)
+    }
+
+    fn get_root_domain_naming_context(&self) -> &str {
+        LdapConnection::get_root_domain_naming_context(self)
+    }
+
+    fn get_schema_naming_context(&self) -> &str {
+        LdapConnection::get_schema_naming_context(self)
+    }
+
+    fn get_configuration_naming_context(&self) -> &str {
+        LdapConnection::get_configuration_naming_context(self)
+    }
+
+    fn search<'a>(&'a self, base: &str, scope: SearchScope, filter: Option<&str>, attributes: &[&str], sd_flags: Option<u32>) -> DirectorySearch<'a> {
+        let scope = match scope {
+            SearchScope::Base => LDAP_SCOPE_BASE,
+            SearchScope::OneLevel => LDAP_SCOPE_ONELEVEL,
+            SearchScope::Subtree => LDAP_SCOPE_SUBTREE,
+        };
+        let mut controls = vec![];
+        if let Some(sd_flags) = sd_flags {
+            let mut sd_control_val = BerVal::new();
+            sd_control_val.append(BerEncodable::Sequence(vec![BerEncodable::Integer(sd_flags.into())]));
+            match LdapControl::new(LDAP_SERVER_SD_FLAGS_OID, &sd_control_val, true) {
+                Ok(control) => controls.push(control),
+                Err(e) => return Box::new(std::iter::once(Err(e))),
+            }
+        }
+        let controls: Vec<&LdapControl> = controls.iter().collect();
+        Box::new(LdapSearch::new(self, Some(base), scope, filter, Some(attributes), &controls))
+    }
+
+    fn get_entry_by_sid(&self, sid: &Sid, attributes: &[&str]) -> Result<Option<LdapEntry>, LdapError> {
+        let base = format!("<SID={}>", sid);
+        let mut res = self.search(&base, SearchScope::Base, Some("(objectClass=*)"), attributes, None)
+            .collect::<Result<Vec<LdapEntry>, LdapError>>()?;
+        Ok(res.pop())
+    }
+
+    fn get_token_groups(&self, principal: &Sid) -> Result<Vec<Sid>, LdapError> {
+        let base = format!("<SID={}>", principal);
+        let res = self.search(&base, SearchScope::Base, None, &["tokenGroups"], None)
+            .collect::<Result<Vec<LdapEntry>, LdapError>>()?;
+        match res.first() {
+            Some(entry) => Ok(get_attr_sids(&[entry], &base, "tokengroups").unwrap_or_default()),
+            None => Ok(vec![]),
+        }
+    }
+}
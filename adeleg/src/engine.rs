@@ -1,33 +1,31 @@
 use core::fmt::Display;
 use core::cell::RefCell;
 use std::collections::{HashMap, HashSet};
-use windows::Win32::Networking::ActiveDirectory::{ADS_RIGHT_WRITE_DAC, ADS_RIGHT_READ_CONTROL, ADS_RIGHT_DS_LIST_OBJECT, ADS_RIGHT_ACCESS_SYSTEM_SECURITY, ADS_RIGHT_DELETE, ADS_RIGHT_DS_WRITE_PROP, ADS_RIGHT_DS_CREATE_CHILD, ADS_RIGHT_DS_DELETE_CHILD, ADS_RIGHT_DS_CONTROL_ACCESS, ADS_RIGHT_DS_SELF, ADS_RIGHT_DS_DELETE_TREE, ADS_RIGHT_ACTRL_DS_LIST, ADS_RIGHT_DS_READ_PROP, ADS_RIGHT_WRITE_OWNER};
-use windows::Win32::Security::{OWNER_SECURITY_INFORMATION, DACL_SECURITY_INFORMATION, SidTypeUser, SidTypeGroup, SidTypeComputer, OBJECT_INHERIT_ACE};
-use windows::Win32::Networking::Ldap::{LDAP_SCOPE_BASE, LDAP_SCOPE_SUBTREE, LDAP_SERVER_SD_FLAGS_OID};
-use windows::Win32::Security::SID_NAME_USE;
-use windows::Win32::Foundation::{PSID, PSTR, PWSTR, BOOL};
-use authz::{SecurityDescriptor, Sid, Ace, Acl};
-use windows::Win32::System::LibraryLoader::{LoadLibraryA, GetProcAddress};
-use windows::Win32::System::SystemServices::SE_DACL_PROTECTED;
-use winldap::connection::LdapConnection;
+use std::rc::Rc;
+use authz::{OWNER_SECURITY_INFORMATION, DACL_SECURITY_INFORMATION, OBJECT_INHERIT_ACE, CONTAINER_INHERIT_ACE};
+use authz::{SecurityDescriptor, Sid, Ace, Acl, AceMove, RedundantAce, create_private_object_security, SEF_DACL_AUTO_INHERIT};
+use authz::SE_DACL_PROTECTED;
 use winldap::utils::{get_attr_strs, get_attr_str};
-use winldap::search::{LdapSearch, LdapEntry};
+use winldap::search::LdapEntry;
 use winldap::error::LdapError;
-use winldap::control::{BerVal, BerEncodable, LdapControl};
 use crate::delegations::{Delegation, DelegationTemplate, DelegationLocation, DelegationRights};
 use crate::error::AdelegError;
-use crate::utils::{Domain, get_ace_derived_by_inheritance_from_schema, get_domains, get_attr_sid, get_attr_sids, get_attr_sd, ends_with_case_insensitive, capitalize, ace_equivalent, get_parent_container};
+use crate::utils::{Domain, get_domains, get_attr_sid, get_attr_sd, ends_with_case_insensitive, capitalize, ace_equivalent, get_parent_container};
 use crate::schema::Schema;
-use authz::Guid;
+use crate::tiers::TierModel;
+use crate::members::MemberCache;
+use crate::directory::{DirectorySource, SearchScope};
+use serde::{Serialize, Deserialize};
+use authz::{AccessMask, Guid, ConditionalExpression, UnaryOperator, BinaryOperator, WellKnownSidKind, lookup_well_known_sid, is_well_known_user_rid};
 
-pub const BUILTIN_ACES: &str = include_str!("..\\builtin_delegations.json");
+pub const BUILTIN_ACES: &str = include_str!("../builtin_delegations.json");
 
-pub const IGNORED_ACCESS_RIGHTS: u32 = (ADS_RIGHT_READ_CONTROL.0 |
-    ADS_RIGHT_ACTRL_DS_LIST.0 |
-    ADS_RIGHT_DS_LIST_OBJECT.0 |
-    ADS_RIGHT_DS_READ_PROP.0) as u32;
+pub const IGNORED_ACCESS_RIGHTS: AccessMask = AccessMask::from_bits_truncate(AccessMask::READ_CONTROL.bits() |
+    AccessMask::LIST_CHILDREN.bits() |
+    AccessMask::LIST_OBJECT.bits() |
+    AccessMask::READ_PROP.bits());
 
-pub const IGNORED_ACE_FLAGS: u8 = OBJECT_INHERIT_ACE.0 as u8; // there is no "object" in Active Directory, only containers
+pub const IGNORED_ACE_FLAGS: u8 = OBJECT_INHERIT_ACE; // there is no "object" in Active Directory, only containers
 
 pub const IGNORED_CONTROL_ACCESSES: &[&str] = &[
     "apply group policy", // applying a group policy does not mean we control it
@@ -46,7 +44,7 @@ pub const IGNORED_BLOCK_DACL_DOMAIN_CONTAINERS: &[&str] = &[
     "CN=SOM,CN=WMIPolicy,CN=System",
 ];
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum PrincipalType {
     User,
     Group,
@@ -65,16 +63,13 @@ impl Display for PrincipalType {
     }
 }
 
-impl From<SID_NAME_USE> for PrincipalType {
-    fn from(siduse: SID_NAME_USE) -> Self {
-        if siduse == SidTypeUser {
-            PrincipalType::User
-        } else if siduse == SidTypeGroup {
-            PrincipalType::Group
-        } else if siduse == SidTypeComputer {
-            PrincipalType::Computer
-        } else {
-            PrincipalType::External
+impl From<WellKnownSidKind> for PrincipalType {
+    fn from(kind: WellKnownSidKind) -> Self {
+        match kind {
+            WellKnownSidKind::BuiltinGroup => PrincipalType::Group,
+            WellKnownSidKind::DomainRelative => PrincipalType::Group,
+            WellKnownSidKind::SpecialIdentity => PrincipalType::External,
+            WellKnownSidKind::ServiceIdentity => PrincipalType::External,
         }
     }
 }
@@ -94,28 +89,46 @@ impl From<&str> for PrincipalType {
     }
 }
 
+// ACEs expected for each trustee at each location, along with the delegation they come from
+type ExpectedAces = HashMap<Sid, HashMap<DelegationLocation, Vec<(Delegation, Vec<Ace>)>>>;
+
+// Results of each default security descriptor, for each domain SID
+type SchemaResults = HashMap<Sid, HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>>;
+
+// Inherited ACEs of an object, along with its DN, class GUID, owner, and whether its DACL is protected
+type InheritedAces = (String, Guid, Sid, bool, Rc<Vec<Ace>>);
+
 pub(crate) struct Engine<'a> {
-    ldap: &'a LdapConnection,
+    pub(crate) directory: &'a dyn DirectorySource,
     pub(crate) domains: Vec<Domain>,
-    root_domain: Domain,
-    schema: Schema,
+    pub(crate) root_domain: Domain,
+    pub(crate) schema: Schema,
     ignored_trustee_sids: HashSet<Sid>,
     pub(crate) naming_contexts: Vec<String>,
     pub(crate) resolve_names: bool,
     pub(crate) templates: HashMap<String, DelegationTemplate>,
     pub(crate) delegations: Vec<Delegation>,
-    expected_aces: HashMap<Sid, HashMap<DelegationLocation, Vec<(Delegation, Vec<Ace>)>>>,
-    resolved_sid_to_dn: RefCell<HashMap<Sid, String>>,
+    expected_aces: ExpectedAces,
+    pub(crate) resolved_sid_to_dn: RefCell<HashMap<Sid, String>>,
     resolved_sid_to_type: RefCell<HashMap<Sid, PrincipalType>>,
-    p_lookupaccountsidlocal: Option<unsafe extern "system" fn(PSID, PWSTR, *mut u32, PWSTR, *mut u32, *mut SID_NAME_USE) -> BOOL>,
+    pub(crate) sensitive_resources: RefCell<HashSet<String>>,
+    pub(crate) tier_model: TierModel,
+    // Lowercase DNs of objects which do not inherit ACEs from their parent container
+    pub(crate) protected_objects: RefCell<HashSet<String>>,
+    // Whether group trustees should be expanded into their effective members in results
+    pub(crate) expand_groups: bool,
+    pub(crate) member_cache: MemberCache,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AdelegResult {
     pub(crate) class_guid: Guid,
     pub(crate) dacl_protected: bool,
     pub(crate) owner: Option<Sid>,
-    pub(crate) non_canonical_ace: Option<Ace>,
+    pub(crate) misplaced_aces: Vec<AceMove>,
+    pub(crate) redundant_aces: Vec<RedundantAce>,
+    // Inherited ACEs which the parent container does not propagate
+    pub(crate) unexpected_inherited_aces: Vec<Ace>,
     pub(crate) deleted_trustee: Vec<Ace>,
     pub(crate) orphan_aces: Vec<Ace>,
     pub(crate) delegations: Vec<(Delegation, Sid, Vec<Ace>, Vec<Ace>)>,
@@ -125,7 +138,9 @@ impl AdelegResult {
     pub(crate) fn needs_to_be_displayed(&self, view_builtin_delegations: bool) -> bool {
         self.dacl_protected ||
             self.owner.is_some() ||
-            self.non_canonical_ace.is_some() ||
+            !self.misplaced_aces.is_empty() ||
+            !self.redundant_aces.is_empty() ||
+            !self.unexpected_inherited_aces.is_empty() ||
             !self.deleted_trustee.is_empty() ||
             !self.orphan_aces.is_empty() ||
             self.delegations.iter().any(|(d, _, _, _)| !d.builtin || view_builtin_delegations)
@@ -133,8 +148,8 @@ impl AdelegResult {
 }
 
 impl<'a> Engine<'a> {
-    pub fn new(ldap: &'a LdapConnection, resolve_names: bool) -> Self { // FIXME: replace with Result<Self, LdapError> and handle them gracefully
-        let domains = match get_domains(&ldap) {
+    pub fn new(directory: &'a dyn DirectorySource, resolve_names: bool) -> Self { // FIXME: replace with Result<Self, LdapError> and handle them gracefully
+        let domains = match get_domains(directory) {
             Ok(v) => v,
             Err(e) => {
                 eprintln!("Unable to list domains: {}", e);
@@ -143,7 +158,7 @@ impl<'a> Engine<'a> {
         };
 
         let root_domain = {
-            let root_nc = ldap.get_root_domain_naming_context();
+            let root_nc = directory.get_root_domain_naming_context();
             let mut res = None;
             for domain in &domains {
                 if domain.distinguished_name == root_nc {
@@ -159,7 +174,7 @@ impl<'a> Engine<'a> {
             }
         };
 
-        let schema = match Schema::query(&ldap) {
+        let schema = match Schema::query(directory) {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("Unable to fetch required information from schema: {}", e);
@@ -186,27 +201,24 @@ impl<'a> Engine<'a> {
             ignored_trustee_sids.insert(domain.sid.with_rid(519));   // Enterprise Admins
         }
 
-        let h_sechost = unsafe { LoadLibraryA(PSTR(b"sechost.dll\x00".as_ptr())) };
-        let p_lookupaccountsidlocal: Option<unsafe extern "system" fn(PSID, PWSTR, *mut u32, PWSTR, *mut u32, *mut SID_NAME_USE) -> BOOL> = if h_sechost.is_invalid() {
-            None
-        } else {
-            unsafe { GetProcAddress(h_sechost, PSTR(b"LookupAccountSidLocalW\x00".as_ptr())).map(|f| core::mem::transmute(f)) }
-        };
-
         Self {
-            ldap,
+            directory,
             domains,
             root_domain,
             schema,
-            naming_contexts: ldap.get_naming_contexts().to_vec(),
+            naming_contexts: directory.get_naming_contexts().to_vec(),
             ignored_trustee_sids,
             resolve_names,
             templates: HashMap::new(),
             delegations: Vec::new(),
             expected_aces: HashMap::new(),
-            p_lookupaccountsidlocal,
             resolved_sid_to_dn: RefCell::new(HashMap::new()),
             resolved_sid_to_type: RefCell::new(HashMap::new()),
+            sensitive_resources: RefCell::new(HashSet::new()),
+            tier_model: TierModel::default(),
+            protected_objects: RefCell::new(HashSet::new()),
+            expand_groups: false,
+            member_cache: MemberCache::default(),
         }
     }
 
@@ -215,7 +227,7 @@ impl<'a> Engine<'a> {
         if json.is_empty() {
             return Ok(()); // empty file are invalid JSON, just skip them
         }
-        let templates = DelegationTemplate::from_json(&json, &self.schema)?;
+        let templates = DelegationTemplate::from_json(json, &self.schema)?;
         for template in templates.into_iter() {
             self.templates.insert(template.name.to_owned(), template);
         }
@@ -228,13 +240,13 @@ impl<'a> Engine<'a> {
             return Ok(()); // empty file are invalid JSON, just skip them
         }
         // Derive expected ACEs from these delegations, and index these ACEs by Sid then Location
-        let mut delegations = Delegation::from_json(&json, &self.templates, &self.schema)?;
+        let mut delegations = Delegation::from_json(json, &self.templates, &self.schema)?;
         for delegation in &delegations {
-            let expected_aces = delegation.derive_aces(&self.ldap, &self.root_domain, &self.domains)?;
+            let expected_aces = delegation.derive_aces(self.directory, &self.root_domain, &self.domains)?;
             for (location, aces) in expected_aces {
-                if let Some(first_ace) = aces.get(0) {
+                if let Some(first_ace) = aces.first() {
                     let sid = first_ace.trustee.clone();
-                    self.expected_aces.entry(sid).or_insert(HashMap::new())
+                    self.expected_aces.entry(sid).or_default()
                         .entry(location).or_insert(vec![])
                         .push((delegation.clone(), aces));
                 }
@@ -245,15 +257,15 @@ impl<'a> Engine<'a> {
     }
 
     // Result is indexed by (domain SID) -> (class name) -> (list of ACEs, with a trustee in each)
-    fn get_schema_aces(&self) -> Result<HashMap<Sid, HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>>, LdapError> {
-        let mut res: HashMap<Sid, HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>> = HashMap::new();
+    fn get_schema_aces(&self) -> Result<SchemaResults, LdapError> {
+        let mut res: SchemaResults = HashMap::new();
 
-        let search = LdapSearch::new(&self.ldap, Some(self.ldap.get_schema_naming_context()), LDAP_SCOPE_SUBTREE,
+        let search = self.directory.search(self.directory.get_schema_naming_context(), SearchScope::Subtree,
             Some("(objectClass=classSchema)"),
-            Some(&[
+            &[
                 "lDAPDisplayName",
                 "defaultSecurityDescriptor"
-            ]), &[]);
+            ], None);
 
         for entry in search {
             let entry = entry?;
@@ -276,7 +288,7 @@ impl<'a> Engine<'a> {
                         continue;
                     },
                 };
-                let dacl_protected = (sd.controls as u32 & SE_DACL_PROTECTED) != 0 &&
+                let dacl_protected = (sd.controls & SE_DACL_PROTECTED) != 0 &&
                     !IGNORED_BLOCK_DACL_CLASSES.contains(&class_name.to_ascii_lowercase().as_str());
                 let dacl = match sd.dacl {
                     Some(acl) => acl,
@@ -288,7 +300,9 @@ impl<'a> Engine<'a> {
                     owner: None,
                     deleted_trustee: vec![],
                     orphan_aces: vec![],
-                    non_canonical_ace: self.check_acl_canonicality(&dacl).err(),
+                    misplaced_aces: self.check_acl_canonicality(&dacl),
+                    redundant_aces: self.check_acl_redundancy(&dacl, &[]),
+                    unexpected_inherited_aces: vec![],
                     delegations: vec![],
                 };
                 for ace in dacl.aces {
@@ -309,41 +323,34 @@ impl<'a> Engine<'a> {
             .unwrap_or(&self.root_domain)
             .distinguished_name;
         let dn = format!("CN=AdminSDHolder,CN=System,{}", nc_holding_object);
-        let mut sd_control_val = BerVal::new();
-        sd_control_val.append(BerEncodable::Sequence(vec![BerEncodable::Integer((DACL_SECURITY_INFORMATION.0).into())]));
-            let sd_control = LdapControl::new(
-            LDAP_SERVER_SD_FLAGS_OID,
-            &sd_control_val,
-            true)?;
-        let search = LdapSearch::new(self.ldap, Some(&dn),LDAP_SCOPE_BASE,
+        let search = self.directory.search(&dn, SearchScope::Base,
         Some("(objectClass=*)"),
-        Some(&["nTSecurityDescriptor"]), &[&sd_control]);
+        &["nTSecurityDescriptor"], Some(DACL_SECURITY_INFORMATION));
         let res = search.collect::<Result<Vec<LdapEntry>, LdapError>>()?;
         let sd = get_attr_sd(&res[..],  &dn, "ntsecuritydescriptor")?;
         Ok(sd.dacl.map(|d| d.aces).unwrap_or(vec![]))
     }
 
     fn get_explicit_aces(&self, naming_context: &str, schema_aces: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>) -> Result<HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, LdapError> {
-        let adminsdholder_aces = self.get_adminsdholder_aces(&naming_context)?;
+        let adminsdholder_aces = self.get_adminsdholder_aces(naming_context)?;
     
-        let mut sd_control_val = BerVal::new();
-        sd_control_val.append(BerEncodable::Sequence(vec![BerEncodable::Integer((OWNER_SECURITY_INFORMATION.0 | DACL_SECURITY_INFORMATION.0).into())]));
-        let sd_control = LdapControl::new(
-            LDAP_SERVER_SD_FLAGS_OID,
-            &sd_control_val,
-            true)?;
-        let search = LdapSearch::new(self.ldap, Some(naming_context), LDAP_SCOPE_SUBTREE,
+        let search = self.directory.search(naming_context, SearchScope::Subtree,
                                  Some("(objectClass=*)"),
-                                 Some(&[
+                                 &[
             "nTSecurityDescriptor",
             "objectClass",
             "objectSID",
             "adminCount",
             "msDS-KrbTgtLinkBl",
             "serverReference",
-        ]), &[&sd_control]);
+        ], Some(OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION));
     
         let mut res: HashMap<DelegationLocation, Result<AdelegResult, AdelegError>> = HashMap::new();
+        // Inherited ACEs can only be checked once the parent container has been read, which may come
+        // after its children. ACE lists are shared, since siblings usually inherit the exact same ACEs.
+        let mut ace_lists: HashSet<Rc<Vec<Ace>>> = HashSet::new();
+        let mut inheritable_aces: HashMap<String, Rc<Vec<Ace>>> = HashMap::new();
+        let mut inherited_aces: Vec<InheritedAces> = vec![];
         for entry in search {
             let entry = entry?;
             let sd = match get_attr_sd(&[&entry], &entry.dn, "ntsecuritydescriptor") {
@@ -364,7 +371,7 @@ impl<'a> Engine<'a> {
             if let Ok(object_sid) = get_attr_sid(&[&entry], &entry.dn, "objectsid") {
                 // Some well-known SIDs will be found this way, in CN=ForeignSecurityPrincipals in each domain.
                 // We prefer these SIDs to be shown as a resolved entry (in a "Global" section), so we first try to look them
-                // up (using LookupAccountSidLocal()) and only use their DN if that fails.
+                // up (in the table of well-known SIDs, see lookup_well_known_sid()) and only use their DN if that fails.
                 if object_sid.is_domain_specific() || self.resolve_sid(&object_sid).is_none() {
                     self.resolved_sid_to_dn.borrow_mut().insert(object_sid.clone(), entry.dn.clone());
                     self.resolved_sid_to_type.borrow_mut().insert(object_sid.clone(), PrincipalType::from(most_specific_class.as_str()));
@@ -373,13 +380,30 @@ impl<'a> Engine<'a> {
             let owner = sd.owner.expect("assertion failed: object without an owner!?");
             let dacl = sd.dacl.expect("assertion failed: object without a DACL!?");
 
+            let inheritable = dacl.aces.iter()
+                .filter(|ace| (ace.flags & (CONTAINER_INHERIT_ACE | OBJECT_INHERIT_ACE)) != 0)
+                .cloned()
+                .collect();
+            inheritable_aces.insert(entry.dn.to_lowercase(), share_ace_list(&mut ace_lists, inheritable));
+            let inherited: Vec<Ace> = dacl.aces.iter()
+                .filter(|ace| ace.is_inherited())
+                .cloned()
+                .collect();
+            if !inherited.is_empty() {
+                inherited_aces.push((entry.dn.clone(), *most_specific_class_guid, owner.clone(),
+                    (sd.controls & SE_DACL_PROTECTED) != 0, share_ace_list(&mut ace_lists, inherited)));
+            }
+
             let (default_aces, default_dacl_protected) = match schema_aces.get(&DelegationLocation::DefaultSecurityDescriptor(most_specific_class.clone())) {
                 Some(Ok(AdelegResult { dacl_protected, orphan_aces, ..  })) => (&orphan_aces[..], *dacl_protected),
                 _ => (&[] as &[Ace], false),
             };
             let admincount = get_attr_str(&[&entry], &entry.dn, "admincount")
                 .unwrap_or("0".to_owned()) != "0";
-            let dacl_protected = ((sd.controls as u32 & SE_DACL_PROTECTED) != 0) &&
+            if (sd.controls & SE_DACL_PROTECTED) != 0 {
+                self.protected_objects.borrow_mut().insert(entry.dn.to_lowercase());
+            }
+            let dacl_protected = ((sd.controls & SE_DACL_PROTECTED) != 0) &&
                 !default_dacl_protected &&
                 !admincount &&
                 !IGNORED_BLOCK_DACL_CLASSES.contains(&most_specific_class.as_str()) &&
@@ -389,9 +413,21 @@ impl<'a> Engine<'a> {
             let reference_server = get_attr_str(&[&entry], &entry.dn, "serverreference").ok();
 
             // Derive ACEs from the defaultSecurityDescriptor of the object's class, and see if the ACE is a default.
-            // These ACEs are not simply memcpy()ed, they are treated as if the object had inherited them from the schema.
+            // These ACEs are not simply memcpy()ed, the defaultSecurityDescriptor is used as the creator descriptor
+            // of the object (CREATOR OWNER gets replaced, generic rights get mapped, etc.)
             let object_type = self.schema.class_guids.get(&most_specific_class).expect("assertion failed: invalid objectClass?!");
-            let default_aces = get_ace_derived_by_inheritance_from_schema(default_aces, &owner, object_type, true);
+            let default_sd = SecurityDescriptor {
+                revision: 1,
+                controls: 0,
+                owner: None,
+                group: None,
+                dacl: Some(Acl { aces: default_aces.to_vec() }),
+                sacl: None,
+            };
+            let default_aces = create_private_object_security(None, Some(&default_sd), Some(object_type), true, &owner, None, SEF_DACL_AUTO_INHERIT)
+                .dacl
+                .map(|acl| acl.aces)
+                .unwrap_or_default();
 
             let owner = if self.ignored_trustee_sids.contains(&owner) {
                 None
@@ -399,10 +435,12 @@ impl<'a> Engine<'a> {
                 Some(owner)
             };
             let mut record = AdelegResult {
-                class_guid: most_specific_class_guid.clone(),
+                class_guid: *most_specific_class_guid,
                 dacl_protected,
                 owner,
-                non_canonical_ace: self.check_acl_canonicality(&dacl).err(),
+                misplaced_aces: self.check_acl_canonicality(&dacl),
+                redundant_aces: self.check_acl_redundancy(&dacl, &default_aces),
+                unexpected_inherited_aces: vec![],
                 deleted_trustee: vec![],
                 orphan_aces: vec![],
                 delegations: vec![],
@@ -414,12 +452,12 @@ impl<'a> Engine<'a> {
                 }
 
                 // RODCs have Change Password + Reset Password on their secondary krbtgt, nothing to say about that.
-                if ace.access_mask == ADS_RIGHT_DS_CONTROL_ACCESS.0 as u32 {
+                if ace.access_mask == AccessMask::CONTROL_ACCESS {
                     if let Some(rodc_dn) = secondary_krbtgt_server.as_ref() {
                         if let Some(guid) = ace.get_object_type() {
                             if let Some(control_access_name) = self.schema.control_access_names.get(guid) {
                                 let control_access_name = control_access_name.to_ascii_lowercase();
-                                if ["change password", "reset password"].contains(&control_access_name.as_str()) && self.resolve_str_to_sid(&rodc_dn).as_ref() == Some(&ace.trustee) {
+                                if ["change password", "reset password"].contains(&control_access_name.as_str()) && self.resolve_str_to_sid(rodc_dn).as_ref() == Some(&ace.trustee) {
                                     continue;
                                 }
                             }
@@ -428,10 +466,10 @@ impl<'a> Engine<'a> {
                 }
 
                 // RODCs can create and delete nTDSConnection objects in their NTDS Settings
-                if most_specific_class == "ntdsdsa" && (ace.access_mask == ADS_RIGHT_DS_CREATE_CHILD.0 as u32 || (ace.access_mask == ADS_RIGHT_DELETE.0 as u32 && ace.get_inherit_only())) {
+                if most_specific_class == "ntdsdsa" && (ace.access_mask == AccessMask::CREATE_CHILD || (ace.access_mask == AccessMask::DELETE && ace.get_inherit_only())) {
                     if let Some(server_dn) = get_parent_container(&entry.dn, naming_context) {
-                        let mut search = LdapSearch::new(self.ldap, Some(server_dn), LDAP_SCOPE_BASE,
-                            Some("(objectClass=server)"), Some(&["serverReference"]), &[]);
+                        let mut search = self.directory.search(server_dn, SearchScope::Base,
+                            Some("(objectClass=server)"), &["serverReference"], None);
                         if let Some(Ok(entry)) = search.next() {
                             if let Ok(rodc_dn) = get_attr_str(&[&entry], &entry.dn, "serverreference") {
                                 if let Some(rodc_sid) = self.resolve_str_to_sid(&rodc_dn) {
@@ -445,13 +483,13 @@ impl<'a> Engine<'a> {
                 }
 
                 // RODCs can write attributes "schedule" and "fromServer" of their nTDSConnection object in their NTDS Settings, nothing to say about that.
-                if most_specific_class == "ntdsconnection" && (ace.access_mask & !(ADS_RIGHT_DS_READ_PROP.0 as u32 | ADS_RIGHT_DS_WRITE_PROP.0 as u32)) == 0 {
-                    if ace.get_object_type() == Some(&Guid::try_from("dd712224-10e4-11d0-a05f-00aa006c33ed").unwrap()) ||
-                            ace.get_object_type() == Some(&Guid::try_from("bf967979-0de6-11d0-a285-00aa003049e2").unwrap()) {
+                if most_specific_class == "ntdsconnection" && (ace.access_mask & !(AccessMask::READ_PROP | AccessMask::WRITE_PROP)).is_empty()
+                    && (ace.get_object_type() == Some(&Guid::try_from("dd712224-10e4-11d0-a05f-00aa006c33ed").unwrap()) ||
+                            ace.get_object_type() == Some(&Guid::try_from("bf967979-0de6-11d0-a285-00aa003049e2").unwrap())) {
                         if let Some(ntds_settings_dn) = get_parent_container(&entry.dn, naming_context) {
                             if let Some(server_dn) = get_parent_container(ntds_settings_dn, naming_context) {
-                                let mut search = LdapSearch::new(self.ldap, Some(server_dn), LDAP_SCOPE_BASE,
-                                                            Some("(objectClass=server)"), Some(&["serverReference"]), &[]);
+                                let mut search = self.directory.search(server_dn, SearchScope::Base,
+                                                            Some("(objectClass=server)"), &["serverReference"], None);
                                 if let Some(Ok(entry)) = search.next() {
                                     if let Ok(rodc_dn) = get_attr_str(&[&entry], &entry.dn, "serverreference") {
                                         if let Some(rodc_sid) = self.resolve_str_to_sid(&rodc_dn) {
@@ -464,13 +502,12 @@ impl<'a> Engine<'a> {
                             }
                         }
                     }
-                }
 
                 // RODCs have a validated write on their server object to update its dnsHostname, nothing to say about that.
-                if most_specific_class == "server" && ace.access_mask == ADS_RIGHT_DS_SELF.0 as u32
+                if most_specific_class == "server" && ace.access_mask == AccessMask::SELF
                         && ace.get_object_type() == Some(&Guid::try_from("72e39547-7b18-11d1-adef-00c04fd8d5cd").unwrap()) {
                     if let Some(rodc_dn) = reference_server.as_deref() {
-                        if let Some(rodc_sid) = self.resolve_str_to_sid(&rodc_dn) {
+                        if let Some(rodc_sid) = self.resolve_str_to_sid(rodc_dn) {
                             if ace.trustee == rodc_sid {
                                 continue;
                             }
@@ -483,6 +520,21 @@ impl<'a> Engine<'a> {
             res.insert(DelegationLocation::Dn(entry.dn), Ok(record));
         }
 
+        for (dn, class_guid, owner, dacl_protected, inherited) in inherited_aces {
+            let parent_aces = if dacl_protected {
+                &[] as &[Ace]
+            } else {
+                match get_parent_container(&dn, naming_context).and_then(|parent| inheritable_aces.get(&parent.to_lowercase())) {
+                    Some(aces) => &aces[..],
+                    None => continue, // naming context head, or parent which could not be read
+                }
+            };
+            let unexpected = self.get_unexpected_inherited_aces(&inherited, parent_aces, &class_guid, &owner, dacl_protected);
+            if let Some(Ok(record)) = res.get_mut(&DelegationLocation::Dn(dn)) {
+                record.unexpected_inherited_aces = unexpected;
+            }
+        }
+
         // Remove any ACE whose trustee is a parent object (parents control their child containers anyway,
         // e.g. computers control their BitLocker recovery information, TPM information, Hyper-V virtual machine objects, etc.)
         // Also do not flag objects owned by a parent object (same cases).
@@ -490,7 +542,7 @@ impl<'a> Engine<'a> {
             if let DelegationLocation::Dn(dn) = location {
                 if let Ok(res) = res {
                     if let Some(sid) = &res.owner {
-                        if let Some(trustee_dn) = self.resolved_sid_to_dn.borrow().get(&sid) { // if any parent object has a SID, it is necessarily in cache
+                        if let Some(trustee_dn) = self.resolved_sid_to_dn.borrow().get(sid) { // if any parent object has a SID, it is necessarily in cache
                             if ends_with_case_insensitive(dn, trustee_dn) {
                                 res.owner = None;
                             }
@@ -510,33 +562,60 @@ impl<'a> Engine<'a> {
         Ok(res)
     }
 
-    pub fn check_acl_canonicality(&self, acl: &Acl) -> Result<(), Ace> {
-        let mut prev_ace: Option<&Ace> = None;
-        for ace in &acl.aces {
-            if let Some(prev_ace) = prev_ace {
-                // Bad patterns we are looking for:
-                // Explicit ACE after an inherited one
-                if !ace.is_inherited() && prev_ace.is_inherited() {
-                    return Err(ace.to_owned());
-                }
-                // Deny ACE after an allow one amongst explicit ACEs
-                // (in inherited ACEs, deny ACEs of a grandparent can occur after allow ones of a parent)
-                if !ace.grants_access() && prev_ace.grants_access() && !ace.is_inherited() && !prev_ace.is_inherited() {
-                    return Err(ace.to_owned());
-                }
-            }
+    // Returns the moves required to put the ACL back in canonical order (explicit deny ACEs, explicit
+    // allow ACEs, then inherited ones), which is empty if it already is
+    pub fn check_acl_canonicality(&self, acl: &Acl) -> Vec<AceMove> {
+        acl.get_reorder_moves()
+    }
 
-            prev_ace = Some(ace);
-        }
-        Ok(())
+    // Returns explicit ACEs which are already covered by an earlier ACE of the ACL (e.g. an explicit
+    // "write member" after a full control ACE for the same trustee), and can be removed. Default
+    // ACEs from the schema are not reported, since they would be added back to new objects anyway.
+    pub fn check_acl_redundancy(&self, acl: &Acl, default_aces: &[Ace]) -> Vec<RedundantAce> {
+        acl.get_redundant_aces(&self.schema.attribute_property_sets)
+            .into_iter()
+            .filter(|r| !r.ace.is_inherited())
+            .filter(|r| !default_aces.iter().any(|default_ace| ace_equivalent(default_ace, &r.ace)))
+            .collect()
+    }
+
+    // Returns inherited ACEs of an object which its parent container does not propagate: stale ones
+    // (e.g. left behind by a propagation which did not complete), or forged ones written with the
+    // inherited flag to go unnoticed. All inherited ACEs are unexpected if the DACL is protected.
+    fn get_unexpected_inherited_aces(&self, inherited_aces: &[Ace], parent_aces: &[Ace], class_guid: &Guid, owner: &Sid, dacl_protected: bool) -> Vec<Ace> {
+        let parent_sd = SecurityDescriptor {
+            revision: 1,
+            controls: 0,
+            owner: None,
+            group: None,
+            dacl: Some(Acl { aces: parent_aces.to_vec() }),
+            sacl: None,
+        };
+        let get_propagated_aces = |owner: &Sid, group: Option<&Sid>| {
+            create_private_object_security(Some(&parent_sd), None, Some(class_guid), true, owner, group, SEF_DACL_AUTO_INHERIT)
+                .dacl
+                .map(|acl| acl.aces)
+                .unwrap_or_default()
+        };
+        let propagated_aces = if dacl_protected { vec![] } else { get_propagated_aces(owner, None) };
+        inherited_aces.iter()
+            .filter(|ace| !self.ignored_trustee_sids.contains(&ace.trustee))
+            .filter(|ace| !propagated_aces.iter().any(|propagated| ace_equivalent(propagated, ace)))
+            // CREATOR OWNER and CREATOR GROUP are replaced when the object is created, its owner
+            // may have changed since then (so ACEs granting exactly what CREATOR OWNER would get
+            // cannot be told apart from forged ones)
+            .filter(|ace| dacl_protected || !get_propagated_aces(&ace.trustee, Some(&ace.trustee)).iter()
+                .any(|propagated| ace_equivalent(propagated, ace)))
+            .cloned()
+            .collect()
     }
 
     pub fn is_ace_interesting(&self, ace: &Ace, admincount: bool, adminsdholder_aces: &[Ace], default_aces: &[Ace]) -> bool {
         if ace.is_inherited() {
             return false; // ignore inherited ACEs
         }
-        let problematic_rights = ace.access_mask & !(IGNORED_ACCESS_RIGHTS);
-        if problematic_rights == 0 {
+        let problematic_rights = ace.access_mask & !IGNORED_ACCESS_RIGHTS;
+        if problematic_rights.is_empty() {
             return false; // ignore read-only ACEs which cannot be abused
         }
 
@@ -548,19 +627,19 @@ impl<'a> Engine<'a> {
 
         let everyone = Sid::try_from("S-1-1-0").expect("invalid SID");
         if ace.trustee == everyone && !ace.grants_access() &&
-                (ace.access_mask & !(ADS_RIGHT_DELETE.0 as u32 | ADS_RIGHT_DS_DELETE_CHILD.0 as u32 | ADS_RIGHT_DS_DELETE_TREE.0 as u32)) == 0 {
+                (ace.access_mask & !(AccessMask::DELETE | AccessMask::DELETE_CHILD | AccessMask::DELETE_TREE)).is_empty() {
             return false; // ignore "delete protection" ACEs
         }
-        if ace.trustee == everyone && !ace.grants_access() && ace.access_mask == ADS_RIGHT_DS_CONTROL_ACCESS.0 as u32 {
+        if ace.trustee == everyone && !ace.grants_access() && ace.access_mask == AccessMask::CONTROL_ACCESS {
             if let Some(guid) = ace.get_object_type() {
                 if let Some(name) = self.schema.control_access_names.get(guid) {
-                    if name.to_ascii_lowercase() == "change password" {
+                    if name.eq_ignore_ascii_case("change password") {
                         return false; // ignore ACEs put by e.g. dsa.msc when setting "Cannot change password" on users
                     }
                 }
             }
         }
-        if admincount && adminsdholder_aces.contains(&ace) {
+        if admincount && adminsdholder_aces.contains(ace) {
             return false; // ignore ACEs from SDProp on objects marked with adminCount=1 (note: ACEs from
             // AdminSDHolder are not inherited, just copied, so comparison here is a simple fast hash
             // lookup.)
@@ -569,8 +648,17 @@ impl<'a> Engine<'a> {
             return false; // these principals are already in control of the resource (either because they
             // are the resource itself, or because they are highly privileged over the entire forest)
         }
+        if ace.grants_access() {
+            if let Ok(Some(condition)) = ace.get_condition() {
+                if self.condition_requires_ignored_membership(&condition) {
+                    return false; // only principals already in control of the resource can satisfy
+                    // this ACE's condition (note: conditions which cannot be parsed are treated as if
+                    // the ACE was unconditional)
+                }
+            }
+        }
         // Some control accesses do not grant any right on the resource itself, they are not a delegation
-        if problematic_rights == ADS_RIGHT_DS_CONTROL_ACCESS.0 as u32 {
+        if problematic_rights == AccessMask::CONTROL_ACCESS {
             if let Some(guid) = ace.get_object_type() {
                 if let Some(name) = self.schema.control_access_names.get(guid) {
                     if IGNORED_CONTROL_ACCESSES.contains(&name.to_lowercase().as_str()) {
@@ -587,9 +675,18 @@ impl<'a> Engine<'a> {
         // Fetch all meaningful ACEs
         eprintln!(" [.] Fetching schema information...");
         let schema_aces = self.get_schema_aces()?;
+
+        // Resources which grant control over a domain are only used to rate findings, results can
+        // still be shown (with lower severities) if they cannot be listed
+        eprintln!(" [.] Listing sensitive resources...");
+        match self.get_sensitive_resources() {
+            Ok(resources) => *self.sensitive_resources.borrow_mut() = resources,
+            Err(e) => eprintln!(" [!] Unable to list sensitive resources, some severities may be underestimated: {}", e),
+        }
+
         let mut res = schema_aces.get(&self.root_domain.sid).cloned().unwrap_or_default();
-        let mut naming_contexts = Vec::from(self.ldap.get_naming_contexts());
-        let config_naming_context = self.ldap.get_configuration_naming_context();
+        let mut naming_contexts = Vec::from(self.directory.get_naming_contexts());
+        let config_naming_context = self.directory.get_configuration_naming_context();
         naming_contexts.sort();
 
         for naming_context in naming_contexts {
@@ -613,7 +710,7 @@ impl<'a> Engine<'a> {
                     }
                 }
                 let keep = if let Ok(record) = record {
-                    record.dacl_protected || !record.delegations.is_empty() || !record.deleted_trustee.is_empty() || record.non_canonical_ace.is_some() || record.owner.is_some() || !record.orphan_aces.is_empty()
+                    record.dacl_protected || !record.delegations.is_empty() || !record.deleted_trustee.is_empty() || !record.misplaced_aces.is_empty() || !record.redundant_aces.is_empty() || !record.unexpected_inherited_aces.is_empty() || record.owner.is_some() || !record.orphan_aces.is_empty()
                 } else {
                     true
                 };
@@ -642,7 +739,7 @@ impl<'a> Engine<'a> {
             // Special handling of root keys for the Key Distribution Service, which must have a protected ACL
             // They are only delegated to tier-0 accounts
             // (c.f. https://docs.microsoft.com/en-us/windows-server/security/group-managed-service-accounts/create-the-key-distribution-services-kds-root-key)
-            if &naming_context == config_naming_context {
+            if naming_context == config_naming_context {
                 let kds_container = format!(",CN=Master Root Keys,CN=Group Key Distribution Service,CN=Services,{}", config_naming_context);
                 for (location, res) in explicit_aces.iter_mut() {
                     if let Ok(res) = res {
@@ -694,13 +791,13 @@ impl<'a> Engine<'a> {
                             if let Some(Ok(schema_aces)) = schema_aces.get(&DelegationLocation::DefaultSecurityDescriptor(cursor_class_name.clone())) {
                                 schema_aces.orphan_aces.iter()
                             } else {
-                                (&[]).iter()
+                                [].iter()
                             }
                         );
                         let mut must_be_member_of = HashSet::new();
                         let mut must_not_be_member_of = HashSet::new();
                         for ace in aces {
-                            if (ace.access_mask & (ADS_RIGHT_DS_CREATE_CHILD.0 as u32)) == 0 {
+                            if !ace.access_mask.contains(AccessMask::CREATE_CHILD) {
                                 continue;
                             }
                             if let Some(class_guid) = ace.get_object_type() {
@@ -756,7 +853,9 @@ impl<'a> Engine<'a> {
                             class_guid: Guid::try_from("bf967a8b-0de6-11d0-a285-00aa003049e2").unwrap(), // container class GUID
                             dacl_protected: false,
                             owner: None,
-                            non_canonical_ace: None,
+                            misplaced_aces: vec![],
+                            redundant_aces: vec![],
+                            unexpected_inherited_aces: vec![],
                             deleted_trustee: vec![],
                             orphan_aces: vec![],
                             delegations: vec![],
@@ -805,15 +904,8 @@ impl<'a> Engine<'a> {
         let mut groups = HashSet::new();
         groups.insert(principal.clone());
         groups.insert(Sid::try_from("S-1-5-11").unwrap());
-        let base = format!("<SID={}>", principal);
-        let search = LdapSearch::new(self.ldap, Some(&base), LDAP_SCOPE_BASE,
-            None, Some(&["tokenGroups"]), &[]);
-        if let Ok(mut res) = search.collect::<Result<Vec<LdapEntry>, LdapError>>() {
-            if let Some(entry) = res.pop() {
-                if let Ok(sids) = get_attr_sids(&[&entry], &base, "tokengroups") {
-                    groups.extend(sids.into_iter());
-                }
-            }
+        if let Ok(sids) = self.directory.get_token_groups(principal) {
+            groups.extend(sids);
         }
         Ok(groups)
     }
@@ -832,14 +924,59 @@ impl<'a> Engine<'a> {
         }
     }
 
+    // Returns whether a conditional expression can only be true for members of one of the principals
+    // we ignore, in which case an allow ACE with this condition cannot be abused by anyone else
+    fn condition_requires_ignored_membership(&self, condition: &ConditionalExpression) -> bool {
+        match condition {
+            ConditionalExpression::Unary { operator: operator @ (UnaryOperator::MemberOf | UnaryOperator::MemberOfAny), operand } => {
+                let sids = operand.get_sids();
+                match operator {
+                    // Member_of requires membership of all SIDs listed, Member_of_Any of one of them
+                    UnaryOperator::MemberOf => sids.iter().any(|sid| self.ignored_trustee_sids.contains(*sid)),
+                    _ => !sids.is_empty() && sids.iter().all(|sid| self.ignored_trustee_sids.contains(*sid)),
+                }
+            },
+            ConditionalExpression::Binary { operator: BinaryOperator::And, left, right } =>
+                self.condition_requires_ignored_membership(left) || self.condition_requires_ignored_membership(right),
+            ConditionalExpression::Binary { operator: BinaryOperator::Or, left, right } =>
+                self.condition_requires_ignored_membership(left) && self.condition_requires_ignored_membership(right),
+            _ => false,
+        }
+    }
+
+    // Describe the condition of a callback ACE, as a suffix to append to describe_ace(), or an empty
+    // string if the ACE is unconditional
+    pub fn describe_ace_condition(&self, ace: &Ace) -> String {
+        match ace.get_condition() {
+            Ok(Some(condition)) => format!(" only if {}", condition),
+            Ok(None) if ace.get_application_data().map(|d| !d.is_empty()).unwrap_or(false) => " only if (unknown callback condition)".to_owned(),
+            Ok(None) => String::new(),
+            Err(_) => " only if (invalid conditional expression)".to_owned(),
+        }
+    }
+
     // Describe this ACE access rights as a string, without mentionning the trustee or the location
-    pub fn describe_ace(&self, access_mask: u32, object_type: Option<&Guid>, inherit_object_type: Option<&Guid>, container_inherit: bool, inherit_only: bool) -> String {
+    pub fn describe_ace(&self, access_mask: AccessMask, object_type: Option<&Guid>, inherit_object_type: Option<&Guid>, container_inherit: bool, inherit_only: bool) -> String {
         let mut res = vec![];
 
-        if !self.resolve_names && (access_mask & ADS_RIGHT_DS_READ_PROP.0 as u32) != 0 {
+        // Generic rights are described as the rights they are mapped to, and full control is
+        // described as a whole instead of listing each right
+        let raw_access_mask = access_mask;
+        let mut access_mask = access_mask.map_generic();
+        if access_mask.contains(AccessMask::FULL_CONTROL) {
+            res.push(if self.resolve_names {
+                "Full control"
+            } else {
+                "FULL_CONTROL"
+            }.to_owned());
+            access_mask.remove(AccessMask::FULL_CONTROL);
+        }
+        let access_mask = access_mask.bits();
+
+        if !self.resolve_names && (access_mask & AccessMask::READ_PROP.bits()) != 0 {
             res.push("READ_PROP".to_owned());
         }
-        if (access_mask & ADS_RIGHT_DS_WRITE_PROP.0 as u32) != 0 {
+        if (access_mask & AccessMask::WRITE_PROP.bits()) != 0 {
             if self.resolve_names {
                 if let Some(guid) = object_type {
                     if let Some(name) = self.schema.attribute_guids.get(guid) {
@@ -858,7 +995,7 @@ impl<'a> Engine<'a> {
                 res.push("WRITE_PROP".to_owned());
             }
         }
-        if (access_mask & ADS_RIGHT_DS_CONTROL_ACCESS.0 as u32) != 0 {
+        if (access_mask & AccessMask::CONTROL_ACCESS.bits()) != 0 {
             if self.resolve_names {
                 if let Some(guid) = object_type {
                     if let Some(name) = self.schema.control_access_names.get(guid) {
@@ -874,7 +1011,7 @@ impl<'a> Engine<'a> {
                 res.push("CONTROL_ACCESS".to_owned());
             }
         }
-        if (access_mask & ADS_RIGHT_DS_CREATE_CHILD.0 as u32) != 0 {
+        if (access_mask & AccessMask::CREATE_CHILD.bits()) != 0 {
             if self.resolve_names {
                 if let Some(guid) = object_type {
                     let mut found = false;
@@ -896,7 +1033,7 @@ impl<'a> Engine<'a> {
                 res.push("CREATE_CHILD".to_owned());
             }
         }
-        if (access_mask & ADS_RIGHT_DS_DELETE_CHILD.0 as u32) != 0 {
+        if (access_mask & AccessMask::DELETE_CHILD.bits()) != 0 {
             if self.resolve_names {
                 if let Some(guid) = object_type {
                     let mut found = false;
@@ -918,44 +1055,44 @@ impl<'a> Engine<'a> {
                 res.push("DELETE_CHILD".to_owned());
             }
         }
-        if !self.resolve_names && (access_mask & ADS_RIGHT_ACTRL_DS_LIST.0 as u32) != 0 {
+        if !self.resolve_names && (access_mask & AccessMask::LIST_CHILDREN.bits()) != 0 {
             res.push("LIST_CHILDREN".to_owned());
         }
-        if !self.resolve_names && (access_mask & ADS_RIGHT_DS_LIST_OBJECT.0 as u32) != 0 {
+        if !self.resolve_names && (access_mask & AccessMask::LIST_OBJECT.bits()) != 0 {
             res.push("LIST_OBJECT".to_owned());
         }
-        if !self.resolve_names && (access_mask & ADS_RIGHT_READ_CONTROL.0 as u32) != 0 {
+        if !self.resolve_names && (access_mask & AccessMask::READ_CONTROL.bits()) != 0 {
             res.push("READ_CONTROL".to_owned());
         }
-        if (access_mask & ADS_RIGHT_WRITE_OWNER.0 as u32) != 0 {
+        if (access_mask & AccessMask::WRITE_OWNER.bits()) != 0 {
             res.push(if self.resolve_names {
                 "Change the owner"
             } else {
                 "WRITE_OWNER"
             }.to_owned());
         }
-        if (access_mask & ADS_RIGHT_WRITE_DAC.0 as u32) != 0 {
+        if (access_mask & AccessMask::WRITE_DAC.bits()) != 0 {
             res.push(if self.resolve_names {
                 "Add/delete delegations"
             } else {
                 "WRITE_DAC"
             }.to_owned());
         }
-        if (access_mask & ADS_RIGHT_DELETE.0 as u32) != 0 {
+        if (access_mask & AccessMask::DELETE.bits()) != 0 {
             res.push(if self.resolve_names {
                 "Delete"
             } else {
                 "DELETE"
             }.to_owned());
         }
-        if (access_mask & ADS_RIGHT_DS_DELETE_TREE.0 as u32) != 0 {
+        if (access_mask & AccessMask::DELETE_TREE.bits()) != 0 {
             res.push(if self.resolve_names {
                 "Delete along with all children"
             } else {
                 "DELETE_TREE"
             }.to_owned());
         }
-        if (access_mask & ADS_RIGHT_DS_SELF.0 as u32) != 0 {
+        if (access_mask & AccessMask::SELF.bits()) != 0 {
             if self.resolve_names {
                 if let Some(guid) = object_type {
                     if let Some(name) = self.schema.validated_write_names.get(guid) {
@@ -971,7 +1108,7 @@ impl<'a> Engine<'a> {
                 res.push("VALIDATED_WRITE/SELF".to_owned());
             }
         }
-        if (access_mask & ADS_RIGHT_ACCESS_SYSTEM_SECURITY.0 as u32) != 0 {
+        if (access_mask & AccessMask::ACCESS_SYSTEM_SECURITY.bits()) != 0 {
             res.push(if self.resolve_names {
                 "Add/delete auditing rules"
             } else {
@@ -1001,7 +1138,7 @@ impl<'a> Engine<'a> {
                 }
             }
         } else {
-            res.push_str(&format!(" (0x{:X})", access_mask));
+            res.push_str(&format!(" (0x{:X})", raw_access_mask.bits()));
             if let Some(guid) = object_type {
                 res.push_str(&format!(" OBJECT_GUID={}", guid));
                 let mut found = false;
@@ -1072,50 +1209,43 @@ impl<'a> Engine<'a> {
             return Some((dn.clone(), self.resolved_sid_to_type.borrow().get(sid).cloned().unwrap_or(PrincipalType::External)));
         }
 
-        // Try to resolve locally, in case it is a well known SID
-        if let Some(p_lookupaccountsidlocal) = self.p_lookupaccountsidlocal {
-            let mut siduse = SID_NAME_USE::default();
-            let mut user_name = vec![0u16; 256];
-            let mut user_name_len = user_name.len() as u32;
-            let mut domain_name = vec![0u16; 256];
-            let mut domain_name_len = domain_name.len() as u32;
-
-            // LookupAccountSidLocalW from sechost.dll is not in windows-rs (yet)
-            let succeeded = unsafe {
-                p_lookupaccountsidlocal(PSID(sid.as_bytes().as_ptr() as isize), PWSTR(user_name.as_mut_ptr()), &mut user_name_len as *mut _, PWSTR(domain_name.as_mut_ptr()), &mut domain_name_len as *mut _, &mut siduse as *mut _)
-            };
-            if succeeded.as_bool() {
-                user_name.truncate(user_name_len as usize);
-                domain_name.truncate(domain_name_len as usize);
-                if !user_name.is_empty() {
-                    let name = if domain_name.is_empty() {
-                        String::from_utf16_lossy(&user_name)
-                    } else {
-                        format!("{}\\{}", String::from_utf16_lossy(&domain_name), String::from_utf16_lossy(&user_name))
-                    };
-                    let ptype = PrincipalType::from(siduse);
-                    self.resolved_sid_to_dn.borrow_mut().insert(sid.clone(), name.clone());
-                    self.resolved_sid_to_type.borrow_mut().insert(sid.clone(), ptype.clone());
-                    return Some((name, ptype));
-                }
+        // Well known SIDs are resolved from a builtin catalog, so that results do not depend on
+        // the language or OS of the machine running the analysis
+        let well_known = lookup_well_known_sid(sid);
+        if let Some((name, kind)) = well_known {
+            if kind != WellKnownSidKind::DomainRelative {
+                let ptype = PrincipalType::from(kind);
+                self.resolved_sid_to_dn.borrow_mut().insert(sid.clone(), name.to_owned());
+                self.resolved_sid_to_type.borrow_mut().insert(sid.clone(), ptype.clone());
+                return Some((name.to_owned(), ptype));
             }
         }
-        
-        let base = format!("<SID={}>", sid);
-        let search = LdapSearch::new(&self.ldap, Some(&base), LDAP_SCOPE_BASE, Some("(objectClass=*)"), Some(&["objectClass"]), &[]);
-        if let Ok(mut res) = search.collect::<Result<Vec<LdapEntry>, LdapError>>() {
-            if let Some(entry) = res.pop() {
-                if let Ok(mut classes) = get_attr_strs(&[&entry], &base, "objectclass") {
-                    let dn = entry.dn;
-                    let most_specific_class = classes.pop().expect("assertion failed: object with empty objectClass!?");
-                    let ptype = PrincipalType::from(most_specific_class.as_str());
-                    self.resolved_sid_to_dn.borrow_mut().insert(sid.clone(), dn.clone());
-                    self.resolved_sid_to_type.borrow_mut().insert(sid.clone(), ptype.clone());
-                    return Some((dn, ptype));
-                }
+
+        if let Ok(Some(entry)) = self.directory.get_entry_by_sid(sid, &["objectClass"]) {
+            if let Ok(mut classes) = get_attr_strs(&[&entry], &entry.dn, "objectclass") {
+                let dn = entry.dn;
+                let most_specific_class = classes.pop().expect("assertion failed: object with empty objectClass!?");
+                let ptype = PrincipalType::from(most_specific_class.as_str());
+                self.resolved_sid_to_dn.borrow_mut().insert(sid.clone(), dn.clone());
+                self.resolved_sid_to_type.borrow_mut().insert(sid.clone(), ptype.clone());
+                return Some((dn, ptype));
             }
         }
 
+        // Domain-relative SIDs are only named from the catalog if they cannot be found in the
+        // directory (e.g. renamed or deleted), prefixed with the domain they belong to
+        if let Some((name, _)) = well_known {
+            let rid = sid.get_rid();
+            let name = match self.domains.iter().find(|d| d.sid.with_rid(rid) == *sid) {
+                Some(domain) => format!("{}\\{}", domain.netbios_name, name),
+                None => name.to_owned(),
+            };
+            let ptype = if is_well_known_user_rid(rid) { PrincipalType::User } else { PrincipalType::Group };
+            self.resolved_sid_to_dn.borrow_mut().insert(sid.clone(), name.clone());
+            self.resolved_sid_to_type.borrow_mut().insert(sid.clone(), ptype.clone());
+            return Some((name, ptype));
+        }
+
         None
     }
 
@@ -1128,7 +1258,7 @@ impl<'a> Engine<'a> {
         if let Some((netbios_name,username)) = trustee.split_once("\\") {
             for domain in &self.domains {
                 if domain.netbios_name.to_lowercase() == netbios_name.to_lowercase() {
-                    let search = LdapSearch::new(&self.ldap, Some(&domain.distinguished_name), LDAP_SCOPE_SUBTREE, Some(&format!("(samAccountName={})", username)), Some(&["objectSid"]), &[]);
+                    let search = self.directory.search(&domain.distinguished_name, SearchScope::Subtree, Some(&format!("(samAccountName={})", username)), &["objectSid"], None);
                     if let Ok(res) = search.collect::<Result<Vec<LdapEntry>, LdapError>>() {
                         if let Ok(sid) = get_attr_sid(&res, &domain.distinguished_name, "objectsid") {
                             return Some(sid);
@@ -1140,9 +1270,9 @@ impl<'a> Engine<'a> {
         }
         for domain in &self.domains {
             if ends_with_case_insensitive(trustee, &domain.distinguished_name) {
-                let search = LdapSearch::new(&self.ldap, Some(&trustee), LDAP_SCOPE_BASE, None, Some(&["objectSid"]), &[]);
+                let search = self.directory.search(trustee, SearchScope::Base, None, &["objectSid"], None);
                 return match search.collect::<Result<Vec<LdapEntry>, LdapError>>() {
-                    Ok(res) => get_attr_sid(&res, &trustee, "objectsid").map(|sid| Some(sid)).unwrap_or(None),
+                    Ok(res) => get_attr_sid(&res, trustee, "objectsid").map(Some).unwrap_or(None),
                     _ => None,
                 };
             }
@@ -1153,3 +1283,186 @@ impl<'a> Engine<'a> {
         None
     }
 }
+
+// Returns the shared copy of an ACE list, adding it if it is the first of its kind
+fn share_ace_list(ace_lists: &mut HashSet<Rc<Vec<Ace>>>, aces: Vec<Ace>) -> Rc<Vec<Ace>> {
+    if let Some(shared) = ace_lists.get(&aces) {
+        return shared.clone();
+    }
+    let shared = Rc::new(aces);
+    ace_lists.insert(shared.clone());
+    shared
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestForest, CONFIGURATION_DN, DEFAULT_SDDL, ROOT_DOMAIN_DN};
+
+    const CHANGE_PASSWORD: &str = "ab721a53-1e2f-11d0-9819-00aa0040529b";
+    const RESET_PASSWORD: &str = "00299570-246d-11d0-a768-00aa006e0529";
+
+    fn get_result<'r>(res: &'r HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, dn: &str) -> Option<&'r AdelegResult> {
+        match res.get(&DelegationLocation::Dn(dn.to_owned())) {
+            Some(Ok(result)) => Some(result),
+            Some(Err(e)) => panic!("analysis of {} failed: {}", dn, e),
+            None => None,
+        }
+    }
+
+    fn get_orphan_aces(res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, dn: &str) -> usize {
+        get_result(res, dn).map(|result| result.orphan_aces.len()).unwrap_or(0)
+    }
+
+    fn add_rodc(forest: &mut TestForest, name: &str, rid: u32) -> (String, Sid) {
+        let dn = format!("CN={},OU=Domain Controllers,{}", name, ROOT_DOMAIN_DN);
+        let sid = forest.add_principal(&dn, "computer", rid, &[]);
+        (dn, sid)
+    }
+
+    fn add_server(forest: &mut TestForest, name: &str, server_reference: Option<&str>, trustee: &Sid) -> String {
+        let server_dn = format!("CN={},CN=Servers,CN=Default-First-Site-Name,CN=Sites,{}", name, CONFIGURATION_DN);
+        let attrs: Vec<(&str, &[u8])> = server_reference.map(|dn| ("serverReference", dn.as_bytes())).into_iter().collect();
+        forest.add_object(&server_dn, "server", &format!("O:DAG:DAD:(A;;GA;;;DA)(OA;;SW;72e39547-7b18-11d1-adef-00c04fd8d5cd;;{})", trustee), &attrs);
+        let ntds_settings_dn = format!("CN=NTDS Settings,{}", server_dn);
+        forest.add_object(&ntds_settings_dn, "nTDSDSA", &format!("O:DAG:DAD:(A;;GA;;;DA)(A;;CC;;;{0})(A;CIIO;SD;;;{0})", trustee), &[]);
+        forest.add_object(&format!("CN=Connection,{}", ntds_settings_dn), "nTDSConnection",
+            &format!("O:DAG:DAD:(A;;GA;;;DA)(OA;;WP;dd712224-10e4-11d0-a05f-00aa006c33ed;;{0})(OA;;WP;bf967979-0de6-11d0-a285-00aa003049e2;;{0})", trustee), &[]);
+        server_dn
+    }
+
+    fn add_sites(forest: &mut TestForest) {
+        forest.add_object(&format!("CN=Sites,{}", CONFIGURATION_DN), "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&format!("CN=Default-First-Site-Name,CN=Sites,{}", CONFIGURATION_DN), "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&format!("CN=Servers,CN=Default-First-Site-Name,CN=Sites,{}", CONFIGURATION_DN), "container", DEFAULT_SDDL, &[]);
+    }
+
+    #[test]
+    fn rodc_rights_are_not_reported() {
+        let mut forest = TestForest::new();
+        forest.add_object(&format!("OU=Domain Controllers,{}", ROOT_DOMAIN_DN), "organizationalUnit", DEFAULT_SDDL, &[]);
+        add_sites(&mut forest);
+        let (rodc_dn, rodc) = add_rodc(&mut forest, "RODC1", 1001);
+        let (_, other) = add_rodc(&mut forest, "RODC2", 1002);
+
+        let password_sddl = |trustee: &Sid| format!("O:DAG:DAD:(A;;GA;;;DA)(OA;;CR;{};;{trustee})(OA;;CR;{};;{trustee})", CHANGE_PASSWORD, RESET_PASSWORD, trustee = trustee);
+        let krbtgt_dn = format!("CN=krbtgt_1001,CN=Users,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&format!("CN=Users,{}", ROOT_DOMAIN_DN), "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&krbtgt_dn, "user", &password_sddl(&rodc), &[("msDS-KrbTgtLinkBl", rodc_dn.as_bytes())]);
+        // Same rights, granted to another RODC than the one which uses this account
+        let other_krbtgt_dn = format!("CN=krbtgt_1002,CN=Users,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&other_krbtgt_dn, "user", &password_sddl(&other), &[("msDS-KrbTgtLinkBl", rodc_dn.as_bytes())]);
+
+        let server_dn = add_server(&mut forest, "RODC1", Some(&rodc_dn), &rodc);
+        // Same rights, on a server object which does not reference this RODC
+        let other_server_dn = add_server(&mut forest, "RODC3", None, &rodc);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        assert_eq!(get_orphan_aces(&res, &krbtgt_dn), 0);
+        assert_eq!(get_orphan_aces(&res, &server_dn), 0);
+        assert_eq!(get_orphan_aces(&res, &format!("CN=NTDS Settings,{}", server_dn)), 0);
+        assert_eq!(get_orphan_aces(&res, &format!("CN=Connection,CN=NTDS Settings,{}", server_dn)), 0);
+        assert_eq!(get_orphan_aces(&res, &other_krbtgt_dn), 2);
+        assert_eq!(get_orphan_aces(&res, &other_server_dn), 1);
+        assert_eq!(get_orphan_aces(&res, &format!("CN=NTDS Settings,{}", other_server_dn)), 2);
+        assert_eq!(get_orphan_aces(&res, &format!("CN=Connection,CN=NTDS Settings,{}", other_server_dn)), 2);
+    }
+
+    #[test]
+    fn kds_root_keys_may_block_inheritance() {
+        let mut forest = TestForest::new();
+        let services_dn = format!("CN=Services,{}", CONFIGURATION_DN);
+        let kds_dn = format!("CN=Group Key Distribution Service,{}", services_dn);
+        let root_keys_dn = format!("CN=Master Root Keys,{}", kds_dn);
+        let root_key_dn = format!("CN=d5e9a1c3-1d2b-4c8a-9f3e-2b7c6a1d0e4f,{}", root_keys_dn);
+        forest.add_object(&services_dn, "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&kds_dn, "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&root_keys_dn, "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&root_key_dn, "msKds-ProvRootKey", "O:DAG:DAD:P(A;;GA;;;DA)", &[]);
+        let ou_dn = format!("OU=Isolated,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&ou_dn, "organizationalUnit", "O:DAG:DAD:P(A;;GA;;;DA)", &[]);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        assert!(!get_result(&res, &root_key_dn).map(|result| result.dacl_protected).unwrap_or(false));
+        assert!(get_result(&res, &ou_dn).expect("protected OU not reported").dacl_protected);
+    }
+
+    #[test]
+    fn owners_allowed_to_create_children_are_tolerated() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let alice = forest.add_principal(&format!("CN=Alice,{}", ROOT_DOMAIN_DN), "user", 1102, &[]);
+        forest.directory.set_token_groups(alice.clone(), vec![helpdesk.clone()]);
+        let workstations_dn = format!("OU=Workstations,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&workstations_dn, "organizationalUnit",
+            &format!("O:DAG:DAD:(A;;GA;;;DA)(OA;;CC;bf967a86-0de6-11d0-a285-00aa003049e2;;{})", helpdesk), &[]);
+        let servers_dn = format!("OU=Servers,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&servers_dn, "organizationalUnit", DEFAULT_SDDL, &[]);
+        // Allowed to create computers, but not groups, in OU=Workstations
+        let workstation_dn = format!("CN=WS1,{}", workstations_dn);
+        forest.add_object(&workstation_dn, "computer", &format!("O:{}G:DAD:(A;;GA;;;DA)", alice), &[]);
+        let group_dn = format!("CN=Local Admins,{}", workstations_dn);
+        forest.add_object(&group_dn, "group", &format!("O:{}G:DAD:(A;;GA;;;DA)", alice), &[]);
+        let server_dn = format!("CN=SRV1,{}", servers_dn);
+        forest.add_object(&server_dn, "computer", &format!("O:{}G:DAD:(A;;GA;;;DA)", alice), &[]);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        assert!(get_result(&res, &workstation_dn).map(|result| result.owner.is_none()).unwrap_or(true));
+        assert_eq!(get_result(&res, &group_dn).and_then(|result| result.owner.as_ref()), Some(&alice));
+        assert_eq!(get_result(&res, &server_dn).and_then(|result| result.owner.as_ref()), Some(&alice));
+    }
+
+    #[test]
+    fn adminsdholder_aces_are_ignored_on_protected_accounts() {
+        let mut forest = TestForest::new();
+        let service = forest.add_principal(&format!("CN=Service,{}", ROOT_DOMAIN_DN), "user", 1101, &[]);
+        let adminsdholder_sddl = format!("O:DAG:DAD:P(A;;GA;;;DA)(A;;RPWP;;;{})", service);
+        forest.set_security_descriptor(&format!("CN=AdminSDHolder,CN=System,{}", ROOT_DOMAIN_DN), &adminsdholder_sddl);
+        let admin_dn = format!("CN=Admin,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&admin_dn, "user", &adminsdholder_sddl, &[("adminCount", b"1")]);
+        let user_dn = format!("CN=Bob,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&user_dn, "user", &adminsdholder_sddl, &[]);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        assert_eq!(get_result(&res, &format!("CN=AdminSDHolder,CN=System,{}", ROOT_DOMAIN_DN)).map(|result| result.dacl_protected), Some(false));
+        assert!(get_result(&res, &admin_dn).map(|result| !result.dacl_protected && result.orphan_aces.is_empty()).unwrap_or(true));
+        let user = get_result(&res, &user_dn).expect("user not reported");
+        assert!(user.dacl_protected);
+        assert_eq!(user.orphan_aces.len(), 1);
+    }
+
+    #[test]
+    fn inherited_aces_not_propagated_are_reported() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let alice = forest.add_principal(&format!("CN=Alice,{}", ROOT_DOMAIN_DN), "user", 1102, &[]);
+        let mallory = forest.add_principal(&format!("CN=Mallory,{}", ROOT_DOMAIN_DN), "user", 1103, &[]);
+        let ou_dn = format!("OU=Staff,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&ou_dn, "organizationalUnit",
+            &format!("O:DAG:DAD:(A;;GA;;;DA)(A;CI;WP;;;{})(A;CIIO;GA;;;CO)", helpdesk), &[]);
+        // Created by Alice, who does not own it anymore
+        let user_dn = format!("CN=Bob,{}", ou_dn);
+        forest.add_object(&user_dn, "user",
+            &format!("O:DAG:DAD:(A;;GA;;;DA)(A;CIID;WP;;;{})(A;ID;GA;;;{})(A;CIIOID;GA;;;CO)", helpdesk, alice), &[]);
+        let forged_dn = format!("CN=Carol,{}", ou_dn);
+        forest.add_object(&forged_dn, "user",
+            &format!("O:DAG:DAD:(A;;GA;;;DA)(A;CIID;WP;;;{})(A;ID;WD;;;{})", helpdesk, mallory), &[]);
+        let protected_dn = format!("CN=Dave,{}", ou_dn);
+        forest.add_object(&protected_dn, "user",
+            &format!("O:DAG:DAD:P(A;;GA;;;DA)(A;CIID;WP;;;{})", helpdesk), &[]);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        assert!(get_result(&res, &user_dn).map(|result| result.unexpected_inherited_aces.is_empty()).unwrap_or(true));
+        let forged = get_result(&res, &forged_dn).expect("forged inherited ACE not reported");
+        assert_eq!(forged.unexpected_inherited_aces.len(), 1);
+        assert_eq!(forged.unexpected_inherited_aces[0].trustee, mallory);
+        let protected = get_result(&res, &protected_dn).expect("inherited ACE on a protected DACL not reported");
+        assert_eq!(protected.unexpected_inherited_aces.len(), 1);
+        assert_eq!(protected.unexpected_inherited_aces[0].trustee, helpdesk);
+    }
+}
@@ -9,6 +9,8 @@ pub enum AdelegError {
     UnresolvedTemplateName(String),
     UnresolvedObjectTypeName(String),
     JsonParsing(String),
+    SnapshotIo(String),
+    UnsupportedSnapshotVersion(u32),
 }
 
 impl core::fmt::Display for AdelegError {
@@ -20,6 +22,8 @@ impl core::fmt::Display for AdelegError {
             AdelegError::UnresolvedTemplateName(template) => f.write_fmt(format_args!("unknown template name \"{}\" referenced", template)),
             AdelegError::UnresolvedObjectTypeName(object_type) => f.write_fmt(format_args!("unknown object type {} referenced", object_type)),
             AdelegError::JsonParsing(e) => f.write_fmt(format_args!("could not parse input as JSON: {}", e)),
+            AdelegError::SnapshotIo(e) => f.write_fmt(format_args!("unable to read or write snapshot file: {}", e)),
+            AdelegError::UnsupportedSnapshotVersion(v) => f.write_fmt(format_args!("snapshot file format version {} is not supported by this version of adeleg (expected {})", v, crate::snapshot::SNAPSHOT_VERSION)),
         }
     }
 }
\ No newline at end of file
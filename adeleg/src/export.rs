@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, HashMap};
+use authz::{Ace, Guid, Sid};
+use serde::Serialize;
+use crate::audit::AuditFinding;
+use crate::bootstrap::TemplateMatch;
+use crate::delegations::DelegationLocation;
+use crate::engine::{AdelegResult, Engine, PrincipalType};
+use crate::error::AdelegError;
+use crate::graph::{ControlNode, ControlPath};
+use crate::severity::Severity;
+use crate::tiers::TierViolation;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonTrustee {
+    sid: Sid,
+    // Distinguished name, or account name for principals outside of the directory
+    name: Option<String>,
+    #[serde(rename = "type")]
+    principal_type: PrincipalType,
+    // Only set for groups, when they are expanded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effective_members: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonAce {
+    trustee: JsonTrustee,
+    allow: bool,
+    access_mask: u32,
+    flags: u8,
+    object_type: Option<Guid>,
+    inherited_object_type: Option<Guid>,
+    container_inherit: bool,
+    inherit_only: bool,
+    condition: Option<String>,
+    description: String,
+    severity: Severity,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonAceMove {
+    from: usize,
+    to: usize,
+    ace: JsonAce,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonRedundantAce {
+    index: usize,
+    covered_by_index: usize,
+    ace: JsonAce,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonDelegation {
+    trustee: JsonTrustee,
+    rights: String,
+    builtin: bool,
+    severity: Severity,
+    aces_found: Vec<JsonAce>,
+    aces_missing: Vec<JsonAce>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonResult {
+    class_guid: Guid,
+    class: Option<String>,
+    // Highest severity of everything reported on this location
+    severity: Severity,
+    owner: Option<JsonTrustee>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner_severity: Option<Severity>,
+    dacl_protected: bool,
+    non_canonical_aces: Vec<JsonAceMove>,
+    redundant_aces: Vec<JsonRedundantAce>,
+    unexpected_inherited_aces: Vec<JsonAce>,
+    deleted_trustee: Vec<JsonAce>,
+    orphan_aces: Vec<JsonAce>,
+    delegations: Vec<JsonDelegation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonLocation {
+    location: DelegationLocation,
+    // Set if this location could not be analysed, in which case there are no results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(flatten)]
+    result: Option<JsonResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonAuditFinding {
+    location: DelegationLocation,
+    description: String,
+    severity: Severity,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonTierViolation {
+    location: DelegationLocation,
+    resource_tier: u8,
+    trustee: JsonTrustee,
+    // Not set for trustees outside of any tier
+    trustee_tier: Option<u8>,
+    description: String,
+    severity: Severity,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonTemplateMatch {
+    location: DelegationLocation,
+    trustee: JsonTrustee,
+    template: String,
+    // Whether every ACE of the template has been found
+    full: bool,
+    description: String,
+    aces_found: Vec<JsonTemplateAce>,
+    aces_missing: Vec<JsonTemplateAce>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonTemplateAce {
+    location: DelegationLocation,
+    #[serde(flatten)]
+    ace: JsonAce,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonControlNode {
+    Principal(JsonTrustee),
+    Resource(DelegationLocation),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonControlStep {
+    description: String,
+    node: JsonControlNode,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonControlPath {
+    source: JsonControlNode,
+    target: JsonControlNode,
+    steps: Vec<JsonControlStep>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonExport {
+    locations: Vec<JsonLocation>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    audit: Vec<JsonAuditFinding>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tiers: Vec<JsonTierViolation>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    templates: Vec<JsonTemplateMatch>,
+    // Effective members of each group trustee (by SID), when groups are expanded
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    group_members: BTreeMap<String, Vec<JsonTrustee>>,
+}
+
+impl<'a> Engine<'a> {
+    // Converts results into a self-describing structure, with trustees resolved and ACEs described
+    pub fn export_json(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, audit_findings: &[AuditFinding], tier_violations: &[TierViolation], template_matches: &[TemplateMatch], show_builtin: bool) -> JsonExport {
+        let mut locations: Vec<(&DelegationLocation, &Result<AdelegResult, AdelegError>)> = res.iter().collect();
+        locations.sort_by_key(|(location, _)| *location);
+        let locations = locations.into_iter().map(|(location, res)| match res {
+            Ok(res) => JsonLocation {
+                location: location.clone(),
+                error: None,
+                result: Some(self.export_result(location, res, show_builtin)),
+            },
+            Err(e) => JsonLocation {
+                location: location.clone(),
+                error: Some(e.to_string()),
+                result: None,
+            },
+        }).collect();
+        let audit = audit_findings.iter().map(|finding| JsonAuditFinding {
+            location: finding.location.clone(),
+            description: self.describe_audit_issue(&finding.issue),
+            severity: self.get_audit_severity(&finding.issue),
+        }).collect();
+        let tiers = tier_violations.iter().map(|violation| JsonTierViolation {
+            location: violation.location.clone(),
+            resource_tier: violation.resource_tier,
+            trustee: self.export_trustee(&violation.trustee),
+            trustee_tier: violation.trustee_tier,
+            description: self.describe_tier_violation(violation),
+            severity: self.get_tier_violation_severity(violation),
+        }).collect();
+        let templates = template_matches.iter().map(|template_match| JsonTemplateMatch {
+            location: template_match.location.clone(),
+            trustee: self.export_trustee(&template_match.trustee),
+            template: template_match.template.clone(),
+            full: template_match.is_full(),
+            description: self.describe_template_match(template_match),
+            aces_found: template_match.aces_found.iter().map(|(location, ace)| JsonTemplateAce {
+                location: location.clone(),
+                ace: self.export_ace(ace, Severity::Info),
+            }).collect(),
+            aces_missing: template_match.aces_missing.iter().map(|(location, ace)| JsonTemplateAce {
+                location: location.clone(),
+                ace: self.export_ace(ace, Severity::Info),
+            }).collect(),
+        }).collect();
+        // Every group trustee exported above has been expanded along the way
+        let group_members = self.get_expanded_groups().into_iter()
+            .map(|(group, members)| (group.to_string(), members.iter().map(|sid| self.export_trustee(sid)).collect()))
+            .collect();
+        JsonExport {
+            locations,
+            audit,
+            tiers,
+            templates,
+            group_members,
+        }
+    }
+
+    fn export_result(&self, location: &DelegationLocation, res: &AdelegResult, show_builtin: bool) -> JsonResult {
+        JsonResult {
+            class_guid: res.class_guid,
+            class: self.schema.class_guids.iter()
+                .find(|(_, guid)| **guid == res.class_guid)
+                .map(|(name, _)| name.clone()),
+            severity: self.get_result_severity(location, res, show_builtin),
+            owner: res.owner.as_ref().map(|sid| self.export_trustee(sid)),
+            owner_severity: res.owner.as_ref().map(|sid| self.get_owner_severity(location, sid)),
+            dacl_protected: res.dacl_protected,
+            non_canonical_aces: res.misplaced_aces.iter().map(|m| JsonAceMove {
+                from: m.from,
+                to: m.to,
+                ace: self.export_ace(&m.ace, Severity::Low),
+            }).collect(),
+            redundant_aces: res.redundant_aces.iter().map(|r| JsonRedundantAce {
+                index: r.index,
+                covered_by_index: r.covered_by_index,
+                ace: self.export_ace(&r.ace, Severity::Low),
+            }).collect(),
+            unexpected_inherited_aces: res.unexpected_inherited_aces.iter().map(|ace| self.export_ace(ace, self.get_ace_severity(location, ace).max(Severity::Low))).collect(),
+            deleted_trustee: res.deleted_trustee.iter().map(|ace| self.export_ace(ace, Severity::Low)).collect(),
+            orphan_aces: res.orphan_aces.iter().map(|ace| self.export_ace(ace, self.get_ace_severity(location, ace))).collect(),
+            delegations: res.delegations.iter()
+                .filter(|(delegation, _, _, _)| show_builtin || !delegation.builtin)
+                .map(|(delegation, trustee, aces_found, aces_missing)| JsonDelegation {
+                    trustee: self.export_trustee(trustee),
+                    rights: self.describe_delegation_rights(&delegation.rights),
+                    builtin: delegation.builtin,
+                    severity: self.get_delegation_severity(location, aces_found, aces_missing),
+                    aces_found: aces_found.iter().map(|ace| self.export_ace(ace, Severity::Info)).collect(),
+                    aces_missing: aces_missing.iter().map(|ace| self.export_ace(ace, Severity::Low)).collect(),
+                }).collect(),
+        }
+    }
+
+    pub fn export_control_paths(&self, paths: &[ControlPath]) -> Vec<JsonControlPath> {
+        paths.iter().map(|path| JsonControlPath {
+            source: self.export_control_node(&path.source),
+            target: self.export_control_node(&path.target),
+            steps: path.steps.iter().map(|(edge, node)| JsonControlStep {
+                description: self.describe_control_edge(edge),
+                node: self.export_control_node(node),
+            }).collect(),
+        }).collect()
+    }
+
+    fn export_control_node(&self, node: &ControlNode) -> JsonControlNode {
+        match node {
+            ControlNode::Principal(sid) => JsonControlNode::Principal(self.export_trustee(sid)),
+            ControlNode::Resource(location) => JsonControlNode::Resource(location.clone()),
+        }
+    }
+
+    pub(crate) fn export_trustee(&self, sid: &Sid) -> JsonTrustee {
+        let (name, principal_type) = match self.resolve_sid(sid) {
+            Some((name, ptype)) => (Some(name), ptype),
+            None => (None, PrincipalType::External),
+        };
+        JsonTrustee {
+            sid: sid.clone(),
+            name,
+            principal_type,
+            effective_members: if self.expand_groups { self.get_effective_members(sid).map(|members| members.len()) } else { None },
+        }
+    }
+
+    fn export_ace(&self, ace: &Ace, severity: Severity) -> JsonAce {
+        JsonAce {
+            trustee: self.export_trustee(&ace.trustee),
+            allow: ace.grants_access(),
+            access_mask: ace.access_mask.bits(),
+            flags: ace.flags,
+            object_type: ace.get_object_type().cloned(),
+            inherited_object_type: ace.get_inherited_object_type().cloned(),
+            container_inherit: ace.get_container_inherit(),
+            inherit_only: ace.get_inherit_only(),
+            condition: match ace.get_condition() {
+                Ok(Some(condition)) => Some(condition.to_string()),
+                _ => None,
+            },
+            description: self.describe_ace(
+                ace.access_mask,
+                ace.get_object_type(),
+                ace.get_inherited_object_type(),
+                ace.get_container_inherit(),
+                ace.get_inherit_only()),
+            severity,
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use authz::{Ace, Guid, Sid};
+use winldap::error::LdapError;
+use winldap::utils::get_attr_strs;
+use crate::delegations::{DelegationLocation, DelegationRights};
+use crate::directory::SearchScope;
+use crate::engine::{AdelegResult, Engine, PrincipalType};
+use crate::error::AdelegError;
+use crate::utils::{ends_with_case_insensitive, get_foreign_principal_sid, get_parent_container};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ControlNode {
+    // Security principal, whether it is an object of the forest or not (e.g. Everyone)
+    Principal(Sid),
+    // Any other resource, without a SID of its own
+    Resource(DelegationLocation),
+}
+
+#[derive(Debug, Clone)]
+pub enum ControlEdge {
+    // Owners can change the DACL of what they own
+    Owner,
+    // ACE which grants enough rights to take over the resource
+    Ace(Ace),
+    // Same, from an inheritable ACE set on a parent container
+    InheritedAce(DelegationLocation, Ace),
+    // Documented delegation with an ACE which grants enough rights to take over the resource
+    Delegation(DelegationRights),
+    // Members of a group get every right granted to it
+    MemberOf,
+    // Controlling a container allows adding inheritable ACEs which apply to its children
+    Container,
+}
+
+// Directed graph where an edge from A to B means that whoever controls A also controls B
+pub struct ControlGraph {
+    nodes: Vec<ControlNode>,
+    node_index: HashMap<ControlNode, usize>,
+    edges: Vec<Vec<(usize, ControlEdge)>>,
+    // Principals which are directly granted control over a resource (owners, ACE and delegation trustees)
+    trustees: HashSet<usize>,
+    // Domain Admins, Enterprise Admins, domain heads and domain controllers
+    pub(crate) default_targets: Vec<ControlNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlPath {
+    pub(crate) source: ControlNode,
+    pub(crate) target: ControlNode,
+    // Each step is an edge followed, along with the node it leads to
+    pub(crate) steps: Vec<(ControlEdge, ControlNode)>,
+}
+
+impl ControlGraph {
+    fn get_or_insert(&mut self, node: ControlNode) -> usize {
+        if let Some(index) = self.node_index.get(&node) {
+            return *index;
+        }
+        self.nodes.push(node.clone());
+        self.edges.push(vec![]);
+        self.node_index.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn add_edge(&mut self, from: ControlNode, to: ControlNode, edge: ControlEdge) {
+        let from = self.get_or_insert(from);
+        let to = self.get_or_insert(to);
+        if from != to {
+            self.edges[from].push((to, edge));
+        }
+    }
+
+    // Shortest paths from each source (by default, each trustee) to each target. Paths which start
+    // with a membership in another trustee are only reported once, from that trustee.
+    pub fn find_paths(&self, targets: &[ControlNode], sources: Option<&[ControlNode]>) -> Vec<ControlPath> {
+        let mut reverse_edges: Vec<Vec<(usize, &ControlEdge)>> = vec![vec![]; self.nodes.len()];
+        for (from, edges) in self.edges.iter().enumerate() {
+            for (to, edge) in edges {
+                reverse_edges[*to].push((from, edge));
+            }
+        }
+        let target_indexes: HashSet<usize> = targets.iter().filter_map(|t| self.node_index.get(t).copied()).collect();
+        let source_indexes: Vec<usize> = match sources {
+            Some(sources) => sources.iter().filter_map(|s| self.node_index.get(s).copied()).collect(),
+            None => {
+                let mut v: Vec<usize> = self.trustees.iter().copied().collect();
+                v.sort_unstable();
+                v
+            },
+        };
+
+        let mut res = vec![];
+        for target in targets {
+            let target_index = match self.node_index.get(target) {
+                Some(i) => *i,
+                None => continue,
+            };
+            // Breadth-first search from the target, following edges backwards
+            let mut next_hop: HashMap<usize, (usize, &ControlEdge)> = HashMap::new();
+            let mut queue = VecDeque::from([target_index]);
+            while let Some(node) = queue.pop_front() {
+                for (from, edge) in &reverse_edges[node] {
+                    if *from != target_index && !next_hop.contains_key(from) {
+                        next_hop.insert(*from, (node, *edge));
+                        queue.push_back(*from);
+                    }
+                }
+            }
+            for source in &source_indexes {
+                if target_indexes.contains(source) || !next_hop.contains_key(source) {
+                    continue;
+                }
+                let (next, edge) = next_hop[source];
+                if sources.is_none() && matches!(edge, ControlEdge::MemberOf) && self.trustees.contains(&next) {
+                    continue;
+                }
+                let mut steps = vec![];
+                let mut cursor = *source;
+                while cursor != target_index {
+                    let (next, edge) = next_hop[&cursor];
+                    steps.push((edge.clone(), self.nodes[next].clone()));
+                    cursor = next;
+                }
+                res.push(ControlPath {
+                    source: self.nodes[*source].clone(),
+                    target: target.clone(),
+                    steps,
+                });
+            }
+        }
+        res
+    }
+}
+
+impl<'a> Engine<'a> {
+    // Builds a graph of who controls what, from ownership, ACEs and delegations found during the
+    // analysis, group memberships, and containers which control their children. Additional nodes
+    // (e.g. sources and targets given by the user) are linked to their groups and containers.
+    pub fn build_control_graph(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, additional_nodes: &[ControlNode]) -> Result<ControlGraph, LdapError> {
+        eprintln!(" [.] Building control graph...");
+        let mut graph = ControlGraph {
+            nodes: vec![],
+            node_index: HashMap::new(),
+            edges: vec![],
+            trustees: HashSet::new(),
+            default_targets: vec![],
+        };
+        let dn_to_sid: HashMap<String, Sid> = self.resolved_sid_to_dn.borrow().iter()
+            .map(|(sid, dn)| (dn.to_lowercase(), sid.clone()))
+            .collect();
+        let node_from_location = |location: &DelegationLocation| match location {
+            DelegationLocation::Dn(dn) => node_from_dn(dn, &dn_to_sid),
+            _ => ControlNode::Resource(location.clone()),
+        };
+
+        // Direct control, from the results of the analysis. Inheritable ACEs are only applied to
+        // children once the whole tree is known.
+        let mut node_classes: HashMap<ControlNode, Guid> = HashMap::new();
+        let mut inheritable: Vec<(&DelegationLocation, &Ace, ControlEdge)> = vec![];
+        let mut trustees: HashSet<ControlNode> = HashSet::new();
+        for (location, res) in res {
+            let res = match res {
+                Ok(res) => res,
+                Err(_) => continue,
+            };
+            let node = node_from_location(location);
+            node_classes.insert(node.clone(), res.class_guid);
+            let mut controls: Vec<(&Ace, ControlEdge)> = res.orphan_aces.iter()
+                .map(|ace| (ace, ControlEdge::Ace(ace.clone())))
+                .collect();
+            for (delegation, _, aces_found, _) in &res.delegations {
+                controls.extend(aces_found.iter().map(|ace| (ace, ControlEdge::Delegation(delegation.rights.clone()))));
+            }
+            for (ace, edge) in controls {
+                if !self.grants_takeover(ace) {
+                    continue;
+                }
+                trustees.insert(ControlNode::Principal(ace.trustee.clone()));
+                if ace.get_container_inherit() && (ace.get_inherit_only() || ace.get_inherited_object_type().is_some()) {
+                    let inherited_edge = match &edge {
+                        ControlEdge::Ace(ace) => ControlEdge::InheritedAce(location.clone(), ace.clone()),
+                        edge => edge.clone(),
+                    };
+                    inheritable.push((location, ace, inherited_edge));
+                    graph.get_or_insert(node.clone());
+                }
+                if !ace.get_inherit_only() {
+                    graph.add_edge(ControlNode::Principal(ace.trustee.clone()), node.clone(), edge);
+                }
+            }
+            if let Some(owner) = &res.owner {
+                trustees.insert(ControlNode::Principal(owner.clone()));
+                graph.add_edge(ControlNode::Principal(owner.clone()), node, ControlEdge::Owner);
+            }
+        }
+        for trustee in trustees {
+            let index = graph.get_or_insert(trustee);
+            graph.trustees.insert(index);
+        }
+
+        // Group memberships, from the member attribute of all groups (to know which principals get
+        // rights through groups), and from token groups of trustees (to include primary groups)
+        for domain in &self.domains {
+            let search = self.directory.search(&domain.distinguished_name, SearchScope::Subtree,
+                Some("(&(objectClass=group)(member=*))"), &["member"], None);
+            for entry in search {
+                let entry = entry?;
+                let group = node_from_dn(&entry.dn, &dn_to_sid);
+                for member in get_attr_strs(&[&entry], &entry.dn, "member").unwrap_or_default() {
+                    graph.add_edge(node_from_dn(&member, &dn_to_sid), group.clone(), ControlEdge::MemberOf);
+                }
+            }
+        }
+        let mut principals: Vec<ControlNode> = graph.trustees.iter().map(|index| graph.nodes[*index].clone()).collect();
+        principals.extend(additional_nodes.iter().cloned());
+        for principal in principals {
+            if let ControlNode::Principal(sid) = &principal {
+                for group in self.fetch_principal_groups(sid)? {
+                    graph.add_edge(principal.clone(), ControlNode::Principal(group), ControlEdge::MemberOf);
+                }
+            }
+        }
+
+        // Default targets, which grant control over a whole domain (or forest)
+        graph.default_targets.push(ControlNode::Principal(self.root_domain.sid.with_rid(519)));
+        for domain in &self.domains {
+            graph.default_targets.push(ControlNode::Principal(domain.sid.with_rid(512)));
+            graph.default_targets.push(node_from_dn(&domain.distinguished_name, &dn_to_sid));
+            let search = self.directory.search(&domain.distinguished_name, SearchScope::Subtree,
+                Some("(|(primaryGroupID=516)(primaryGroupID=521))"), &["primaryGroupID"], None);
+            for entry in search {
+                let entry = entry?;
+                graph.default_targets.push(node_from_dn(&entry.dn, &dn_to_sid));
+            }
+        }
+        for node in graph.default_targets.clone().into_iter().chain(additional_nodes.iter().cloned()) {
+            graph.get_or_insert(node);
+        }
+
+        // Containers control their children, unless these children block inheritance. Only
+        // containers and children which are in the graph matter, so each node is linked to its
+        // closest ancestor in the graph.
+        let mut node_dns: HashMap<String, usize> = HashMap::new();
+        for (index, node) in graph.nodes.iter().enumerate() {
+            if let Some(dn) = self.get_control_node_dn(node) {
+                node_dns.insert(dn.to_lowercase(), index);
+            }
+        }
+        let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (dn, index) in &node_dns {
+            let naming_context = match self.naming_contexts.iter()
+                    .filter(|nc| ends_with_case_insensitive(dn, nc))
+                    .max_by_key(|nc| nc.len()) {
+                Some(nc) => nc,
+                None => continue,
+            };
+            let mut cursor = dn.as_str();
+            while let Some(parent) = get_parent_container(cursor, naming_context) {
+                if let Some(parent_index) = node_dns.get(parent) {
+                    children.entry(*parent_index).or_default().push(*index);
+                    break;
+                }
+                cursor = parent;
+            }
+        }
+        let protected: HashSet<usize> = node_dns.iter()
+            .filter(|(dn, _)| self.protected_objects.borrow().contains(*dn) ||
+                matches!(res.get(&DelegationLocation::Dn(dn.to_string())), Some(Ok(r)) if r.dacl_protected))
+            .map(|(_, index)| *index)
+            .collect();
+        for (parent, children) in &children {
+            for child in children {
+                if !protected.contains(child) {
+                    graph.edges[*parent].push((*child, ControlEdge::Container));
+                }
+            }
+        }
+
+        // Inheritable ACEs which do not apply to the container itself, or only to some classes
+        // of children objects
+        for (location, ace, edge) in inheritable {
+            let start = match graph.node_index.get(&node_from_location(location)) {
+                Some(i) => *i,
+                None => continue,
+            };
+            let trustee = graph.node_index[&ControlNode::Principal(ace.trustee.clone())];
+            let mut stack: Vec<usize> = children.get(&start).cloned().unwrap_or_default();
+            while let Some(child) = stack.pop() {
+                if protected.contains(&child) {
+                    continue;
+                }
+                let applies = match ace.get_inherited_object_type() {
+                    None => true,
+                    Some(class_guid) => self.get_control_node_class(&graph.nodes[child], &node_classes).as_ref() == Some(class_guid),
+                };
+                if applies && trustee != child {
+                    graph.edges[trustee].push((child, edge.clone()));
+                }
+                stack.extend(children.get(&child).cloned().unwrap_or_default());
+            }
+        }
+
+        Ok(graph)
+    }
+
+    fn get_control_node_dn(&self, node: &ControlNode) -> Option<String> {
+        match node {
+            ControlNode::Resource(DelegationLocation::Dn(dn)) => Some(dn.clone()),
+            ControlNode::Resource(_) => None,
+            ControlNode::Principal(sid) => self.resolved_sid_to_dn.borrow().get(sid)
+                .filter(|name| self.naming_contexts.iter().any(|nc| ends_with_case_insensitive(name, nc)))
+                .cloned(),
+        }
+    }
+
+    fn get_control_node_class(&self, node: &ControlNode, node_classes: &HashMap<ControlNode, Guid>) -> Option<Guid> {
+        if let Some(class_guid) = node_classes.get(node) {
+            return Some(*class_guid);
+        }
+        let class_name = match node {
+            ControlNode::Principal(sid) => match self.resolve_sid(sid) {
+                Some((_, PrincipalType::User)) => "user",
+                Some((_, PrincipalType::Group)) => "group",
+                Some((_, PrincipalType::Computer)) => "computer",
+                _ => return None,
+            },
+            ControlNode::Resource(_) => return None,
+        };
+        self.schema.class_guids.get(class_name).copied()
+    }
+
+    // Parses targets and sources given by the user, as a SID, a DN, or a DOMAIN\name
+    pub fn resolve_control_node(&self, name: &str) -> ControlNode {
+        match self.resolve_str_to_sid(name) {
+            Some(sid) => ControlNode::Principal(sid),
+            None => ControlNode::Resource(DelegationLocation::Dn(name.to_owned())),
+        }
+    }
+
+    pub fn describe_control_node(&self, node: &ControlNode) -> String {
+        match node {
+            ControlNode::Principal(sid) => self.resolve_sid(sid).map(|(dn, _)| dn).unwrap_or(sid.to_string()),
+            ControlNode::Resource(location) => location.to_string(),
+        }
+    }
+
+    pub fn describe_control_edge(&self, edge: &ControlEdge) -> String {
+        let describe_ace = |ace: &Ace| format!("{}{}", self.describe_ace(
+            ace.access_mask,
+            ace.get_object_type(),
+            ace.get_inherited_object_type(),
+            ace.get_container_inherit(),
+            ace.get_inherit_only()
+        ), self.describe_ace_condition(ace));
+        match edge {
+            ControlEdge::Owner => "as its owner".to_owned(),
+            ControlEdge::Ace(ace) => format!("through an ACE: {}", describe_ace(ace)),
+            ControlEdge::InheritedAce(location, ace) => format!("through an ACE inherited from {}: {}", location, describe_ace(ace)),
+            ControlEdge::Delegation(rights) => format!("through a documented delegation: {}", self.describe_delegation_rights(rights)),
+            ControlEdge::MemberOf => "as a member".to_owned(),
+            ControlEdge::Container => "through its parent container".to_owned(),
+        }
+    }
+}
+
+// Objects with a SID are principals, including foreign security principals which stand for a
+// principal from another forest (or a well-known one)
+fn node_from_dn(dn: &str, dn_to_sid: &HashMap<String, Sid>) -> ControlNode {
+    if let Some(sid) = dn_to_sid.get(&dn.to_lowercase()) {
+        return ControlNode::Principal(sid.clone());
+    }
+    if let Some(sid) = get_foreign_principal_sid(dn) {
+        return ControlNode::Principal(sid);
+    }
+    ControlNode::Resource(DelegationLocation::Dn(dn.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestForest, DEFAULT_SDDL, ROOT_DOMAIN_DN};
+
+    const MEMBER: &str = "bf9679c0-0de6-11d0-a285-00aa003049e2";
+
+    fn get_steps(path: &ControlPath) -> Vec<String> {
+        path.steps.iter()
+            .map(|(edge, node)| format!("{} -> {:?}", match edge {
+                ControlEdge::Owner => "Owner".to_owned(),
+                ControlEdge::Ace(ace) => format!("Ace({})", ace.trustee),
+                ControlEdge::InheritedAce(location, ace) => format!("InheritedAce({}, {})", location, ace.trustee),
+                ControlEdge::Delegation(_) => "Delegation".to_owned(),
+                ControlEdge::MemberOf => "MemberOf".to_owned(),
+                ControlEdge::Container => "Container".to_owned(),
+            }, node))
+            .collect()
+    }
+
+    fn add_dc(forest: &mut TestForest, dn: &str, sddl: &str) -> ControlNode {
+        forest.add_object(dn, "computer", sddl, &[("primaryGroupID", b"516")]);
+        ControlNode::Resource(DelegationLocation::Dn(dn.to_owned()))
+    }
+
+    #[test]
+    fn broad_group_controls_domain_controller() {
+        let mut forest = TestForest::new();
+        let authenticated_users = Sid::try_from("S-1-5-11").expect("invalid SID");
+        let group_dn = format!("CN=Server Admins,{}", ROOT_DOMAIN_DN);
+        let group = forest.add_principal(&group_dn, "group", 1101, &[]);
+        forest.set_security_descriptor(&group_dn, &format!("O:DAG:DAD:(A;;GA;;;DA)(OA;;WP;{};;AU)", MEMBER));
+        let ou_dn = format!("OU=Servers,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&ou_dn, "organizationalUnit", &format!("O:DAG:DAD:(A;;GA;;;DA)(A;;GA;;;{})", group), &[]);
+        let dc = add_dc(&mut forest, &format!("CN=DC01,{}", ou_dn), DEFAULT_SDDL);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        let graph = engine.build_control_graph(&res, &[]).expect("unable to build control graph");
+        assert!(graph.default_targets.contains(&dc));
+        // The domain head has the SID of its domain
+        assert!(graph.default_targets.contains(&ControlNode::Principal(forest.domain_sid.clone())));
+
+        let paths: Vec<ControlPath> = graph.find_paths(std::slice::from_ref(&dc), None).into_iter()
+            .filter(|path| path.source == ControlNode::Principal(authenticated_users.clone()))
+            .collect();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].target, dc);
+        assert_eq!(get_steps(&paths[0]), vec![
+            format!("Ace({}) -> {:?}", authenticated_users, ControlNode::Principal(group.clone())),
+            format!("Ace({}) -> {:?}", group, ControlNode::Resource(DelegationLocation::Dn(ou_dn.clone()))),
+            format!("Container -> {:?}", dc),
+        ]);
+    }
+
+    #[test]
+    fn inherit_only_aces_reach_children_which_do_not_block_inheritance() {
+        let mut forest = TestForest::new();
+        let group = forest.add_principal(&format!("CN=Server Admins,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let ou_dn = format!("OU=Servers,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&ou_dn, "organizationalUnit", &format!("O:DAG:DAD:(A;;GA;;;DA)(A;CIIO;GA;;;{})", group), &[]);
+        let dc = add_dc(&mut forest, &format!("CN=DC01,{}", ou_dn), DEFAULT_SDDL);
+        let protected_dc = add_dc(&mut forest, &format!("CN=DC02,{}", ou_dn), "O:DAG:DAD:P(A;;GA;;;DA)");
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        let graph = engine.build_control_graph(&res, &[]).expect("unable to build control graph");
+        let sources = [ControlNode::Principal(group.clone())];
+        let ou = ControlNode::Resource(DelegationLocation::Dn(ou_dn.clone()));
+
+        let paths = graph.find_paths(&[dc.clone(), protected_dc, ou], Some(&sources));
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].target, dc);
+        assert_eq!(get_steps(&paths[0]), vec![
+            format!("InheritedAce({}, {}) -> {:?}", ou_dn, group, dc),
+        ]);
+    }
+
+    #[test]
+    fn paths_through_other_trustees_are_reported_once() {
+        let mut forest = TestForest::new();
+        let user_dn = format!("CN=Alice,{}", ROOT_DOMAIN_DN);
+        let user = forest.add_principal(&user_dn, "user", 1102, &[]);
+        let group = forest.add_principal(&format!("CN=Server Admins,{}", ROOT_DOMAIN_DN), "group", 1101, &[("member", user_dn.as_bytes())]);
+        forest.add_object(&format!("OU=Staff,{}", ROOT_DOMAIN_DN), "organizationalUnit", &format!("O:DAG:DAD:(A;;GA;;;DA)(A;;WD;;;{})", user), &[]);
+        let ou_dn = format!("OU=Servers,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&ou_dn, "organizationalUnit", &format!("O:DAG:DAD:(A;;GA;;;DA)(A;;GA;;;{})", group), &[]);
+        let dc = add_dc(&mut forest, &format!("CN=DC01,{}", ou_dn), DEFAULT_SDDL);
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        let graph = engine.build_control_graph(&res, &[]).expect("unable to build control graph");
+
+        // By default, the path from the user is only reported from the group it is a member of
+        let paths = graph.find_paths(std::slice::from_ref(&dc), None);
+        let sources: Vec<&ControlNode> = paths.iter().map(|path| &path.source).collect();
+        assert!(sources.contains(&&ControlNode::Principal(group.clone())));
+        assert!(!sources.contains(&&ControlNode::Principal(user.clone())));
+
+        // Unless that user is explicitly asked for
+        let paths = graph.find_paths(std::slice::from_ref(&dc), Some(&[ControlNode::Principal(user.clone())]));
+        assert_eq!(paths.len(), 1);
+        assert_eq!(get_steps(&paths[0]), vec![
+            format!("MemberOf -> {:?}", ControlNode::Principal(group.clone())),
+            format!("Ace({}) -> {:?}", group, ControlNode::Resource(DelegationLocation::Dn(ou_dn))),
+            format!("Container -> {:?}", dc),
+        ]);
+    }
+}
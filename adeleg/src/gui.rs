@@ -10,6 +10,7 @@ use authz::Sid;
 use crate::delegations::DelegationLocation;
 use crate::engine::{Engine, PrincipalType};
 use crate::error::AdelegError;
+use crate::severity::Severity;
 use crate::AdelegResult;
 use crate::utils::{ends_with_case_insensitive, replace_suffix_case_insensitive};
 
@@ -30,6 +31,8 @@ pub struct BasicApp {
     view_builtin_delegations: RefCell<bool>,
     show_unresolved_access_rights: RefCell<bool>,
     show_unreadable_warnings: RefCell<bool>,
+    sort_by_severity: RefCell<bool>,
+    expand_groups: RefCell<bool>,
     results: Option<RefCell<HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>>>,
 
     #[nwg_resource(initial: 10, size: (16, 16))]
@@ -79,6 +82,10 @@ pub struct BasicApp {
     #[nwg_events( OnMenuItemSelected: [BasicApp::toggle_view_unresolved_access_rights] )]
     menu_view_unresolved_access_rights: nwg::MenuItem,
 
+    #[nwg_control(parent: menu_view, text: "Expand group trustees")]
+    #[nwg_events( OnMenuItemSelected: [BasicApp::toggle_expand_groups] )]
+    menu_view_expand_groups: nwg::MenuItem,
+
     #[nwg_control(parent: menu_view, text: "Index view by...")]
     menu_view_index_by: nwg::Menu,
 
@@ -111,6 +118,7 @@ pub struct BasicApp {
 
     #[nwg_control(list_style: nwg::ListViewStyle::Detailed, ex_flags: nwg::ListViewExFlags::from_bits(nwg::ListViewExFlags::HEADER_DRAG_DROP.bits() | nwg::ListViewExFlags::FULL_ROW_SELECT.bits() | nwg::ListViewExFlags::BORDER_SELECT.bits()).unwrap())]
     #[nwg_layout_item(layout: grid, col: 1, row: 0, col_span: 3)]
+    #[nwg_events(OnListViewColumnClick: [BasicApp::handle_list_column_click(SELF, EVT_DATA)])]
     list: nwg::ListView,
 }
 
@@ -142,6 +150,12 @@ impl BasicApp {
             width: Some((std::cmp::max(width/2, 450) - 55) as i32),
             ..Default::default()
         });
+        self.list.insert_column(nwg::InsertListViewColumn {
+            index: Some(3),
+            text: Some("Severity".to_owned()),
+            width: Some(80),
+            ..Default::default()
+        });
         self.refresh();
     }
 
@@ -185,6 +199,13 @@ impl BasicApp {
         self.redraw();
     }
 
+    fn toggle_expand_groups(&self) {
+        let prev = *(self.expand_groups.borrow());
+
+        *self.expand_groups.borrow_mut() = !prev;
+        self.redraw();
+    }
+
     fn set_view_index_by_resources(&self) {
         *self.view_by_trustee.borrow_mut() = false;
         self.redraw();
@@ -196,7 +217,7 @@ impl BasicApp {
         for (_, result) in results.iter() {
             match result {
                 Ok(res) => {
-                    if res.non_canonical_ace.is_some() {
+                    if !res.misplaced_aces.is_empty() || !res.redundant_aces.is_empty() || !res.unexpected_inherited_aces.is_empty() {
                         warning_count += 1;
                     }
                 },
@@ -389,17 +410,20 @@ impl BasicApp {
         let view_builtin_delegations = *self.view_builtin_delegations.borrow();
         let show_unreadable_warnings = *self.show_unreadable_warnings.borrow();
         let show_unresolved_access_rights = *self.show_unresolved_access_rights.borrow();
+        let expand_groups = *self.expand_groups.borrow();
         self.menu_view_index_by_resources.set_checked(!view_by_trustee);
         self.menu_view_index_by_trustees.set_checked(view_by_trustee);
         self.menu_view_builtin_delegations.set_checked(view_builtin_delegations);
         self.menu_view_unresolved_access_rights.set_checked(show_unresolved_access_rights);
         self.menu_view_unreadable_warnings.set_checked(show_unreadable_warnings);
+        self.menu_view_expand_groups.set_checked(expand_groups);
         self.window.focus();
         self.tree_view.clear();
         let results = self.results.as_ref().unwrap().borrow();
         {
             let mut engine = self.engine.as_ref().unwrap().borrow_mut();
             engine.resolve_names = !show_unresolved_access_rights;
+            engine.expand_groups = expand_groups;
         }
         let engine = self.engine.as_ref().unwrap().borrow();
 
@@ -635,6 +659,12 @@ impl BasicApp {
                             text: Some("This principal owns the object, which implicitly grants them full control over it".to_owned()),
                             image: None,
                         });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 3,
+                            text: Some(engine.get_owner_severity(location, &trustee).to_string()),
+                            image: None,
+                        });
                     }
                     for ace in &result.orphan_aces {
                         if &ace.trustee != &trustee {
@@ -655,13 +685,19 @@ impl BasicApp {
                         self.list.insert_item(nwg::InsertListViewItem {
                             index: Some(0),
                             column_index: 2,
-                            text: Some(engine.describe_ace(
+                            text: Some(format!("{}{}", engine.describe_ace(
                                 ace.access_mask,
                                 ace.get_object_type(),
                                 ace.get_inherited_object_type(),
                                 ace.get_container_inherit(),
                                 ace.get_inherit_only()
-                            )),
+                            ), engine.describe_ace_condition(ace))),
+                            image: None,
+                        });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 3,
+                            text: Some(engine.get_ace_severity(location, ace).to_string()),
                             image: None,
                         });
                     }
@@ -694,6 +730,12 @@ impl BasicApp {
                                 )),
                                 image: None,
                             });
+                            self.list.insert_item(nwg::InsertListViewItem {
+                                index: Some(0),
+                                column_index: 3,
+                                text: Some(Severity::Info.to_string()),
+                                image: None,
+                            });
                         }
                         for ace in aces_missing {
                             self.list.insert_item(nwg::InsertListViewItem {
@@ -720,6 +762,12 @@ impl BasicApp {
                                 )),
                                 image: None,
                             });
+                            self.list.insert_item(nwg::InsertListViewItem {
+                                index: Some(0),
+                                column_index: 3,
+                                text: Some(Severity::Low.to_string()),
+                                image: None,
+                            });
                         }
                         self.list.insert_item(nwg::InsertListViewItem {
                             index: Some(0),
@@ -739,7 +787,69 @@ impl BasicApp {
                             text: Some(engine.describe_delegation_rights(&delegation.rights)),
                             image: None,
                         });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 3,
+                            text: Some(engine.get_delegation_severity(location, aces_found, aces_missing).to_string()),
+                            image: None,
+                        });
+                    }
+                }
+            }
+            // Effective members of group trustees are listed first, rows are inserted bottom-up
+            if engine.expand_groups {
+                if let Some(members) = engine.get_effective_members(&trustee) {
+                    for member in members.iter().rev() {
+                        let (name, ptype) = engine.resolve_sid(member).unwrap_or((member.to_string(), PrincipalType::External));
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 0,
+                            text: Some("    \u{E125} Member".to_owned()),
+                            image: None,
+                        });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 1,
+                            text: Some(name),
+                            image: None,
+                        });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 2,
+                            text: Some(ptype.to_string()),
+                            image: None,
+                        });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 3,
+                            text: Some(Severity::Info.to_string()),
+                            image: None,
+                        });
                     }
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 0,
+                        text: Some("\u{E125} Group".to_owned()),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 1,
+                        text: None,
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 2,
+                        text: engine.describe_effective_members(&trustee),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(Severity::Info.to_string()),
+                        image: None,
+                    });
                 }
             }
         } else {
@@ -767,6 +877,12 @@ impl BasicApp {
                                 text: Some(e.to_string()),
                                 image: None,
                             });
+                            self.list.insert_item(nwg::InsertListViewItem {
+                                index: Some(0),
+                                column_index: 3,
+                                text: Some(Severity::Info.to_string()),
+                                image: None,
+                            });
                         }
                         return;
                     },
@@ -781,7 +897,7 @@ impl BasicApp {
                     self.list.insert_item(nwg::InsertListViewItem {
                         index: Some(0),
                         column_index: 1,
-                        text: Some(engine.resolve_sid(owner).map(|(dn, _)| dn).unwrap_or(owner.to_string())),
+                        text: Some(engine.describe_trustee_members(owner)),
                         image: None,
                     });
                     self.list.insert_item(nwg::InsertListViewItem {
@@ -790,6 +906,12 @@ impl BasicApp {
                         text: Some("This principal owns the object, which implicitly grants them full control over it".to_owned()),
                         image: None,
                     });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(engine.get_owner_severity(&location, owner).to_string()),
+                        image: None,
+                    });
                 }
                 if result.dacl_protected {
                     self.list.insert_item(nwg::InsertListViewItem {
@@ -810,8 +932,15 @@ impl BasicApp {
                         text: Some("DACL is configured to block inheritance of parent container ACEs".to_owned()),
                         image: None,
                     });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(Severity::Low.to_string()),
+                        image: None,
+                    });
                 }
-                if let Some(ace) = &result.non_canonical_ace {
+                for ace_move in &result.misplaced_aces {
+                    let ace = &ace_move.ace;
                     self.list.insert_item(nwg::InsertListViewItem {
                         index: Some(0),
                         column_index: 0,
@@ -827,9 +956,11 @@ impl BasicApp {
                     self.list.insert_item(nwg::InsertListViewItem {
                         index: Some(0),
                         column_index: 2,
-                        text: Some(format!("DACL is not in canonical order, e.g. see {} ACE for {}: {}",
+                        text: Some(format!("DACL is not in canonical order, {} ACE for {} should be moved from position {} to {}: {}",
                             if ace.grants_access() { "allow" } else { "deny" },
                             engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
+                            ace_move.from,
+                            ace_move.to,
                             engine.describe_ace(
                                 ace.access_mask,
                                 ace.get_object_type(),
@@ -839,6 +970,83 @@ impl BasicApp {
                         ))),
                         image: None,
                     });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(Severity::Low.to_string()),
+                        image: None,
+                    });
+                }
+                for redundant in &result.redundant_aces {
+                    let ace = &redundant.ace;
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 0,
+                        text: Some("\u{1f518} Warning".to_owned()),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 1,
+                        text: Some(engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string())),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 2,
+                        text: Some(format!("Redundant {} ACE at position {} is already covered by the ACE at position {}, it can be removed: {}",
+                            if ace.grants_access() { "allow" } else { "deny" },
+                            redundant.index,
+                            redundant.covered_by_index,
+                            engine.describe_ace(
+                                ace.access_mask,
+                                ace.get_object_type(),
+                                ace.get_inherited_object_type(),
+                                ace.get_container_inherit(),
+                                ace.get_inherit_only()
+                        ))),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(Severity::Low.to_string()),
+                        image: None,
+                    });
+                }
+                for ace in &result.unexpected_inherited_aces {
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 0,
+                        text: Some("\u{1f518} Warning".to_owned()),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 1,
+                        text: Some(engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string())),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 2,
+                        text: Some(format!("Inherited {} ACE is not propagated by the parent container, it is either stale or forged: {}",
+                            if ace.grants_access() { "allow" } else { "deny" },
+                            engine.describe_ace(
+                                ace.access_mask,
+                                ace.get_object_type(),
+                                ace.get_inherited_object_type(),
+                                ace.get_container_inherit(),
+                                ace.get_inherit_only()
+                        ))),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(engine.get_ace_severity(&location, ace).max(Severity::Low).to_string()),
+                        image: None,
+                    });
                 }
 
                 for ace in &result.deleted_trustee {
@@ -860,6 +1068,12 @@ impl BasicApp {
                         text: Some(format!("A delegation for trustee {} which does not exist anymore should be cleaned up", ace.trustee)),
                         image: None,
                     });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(Severity::Low.to_string()),
+                        image: None,
+                    });
                 }
 
                 for ace in &result.orphan_aces {
@@ -872,19 +1086,25 @@ impl BasicApp {
                     self.list.insert_item(nwg::InsertListViewItem {
                         index: Some(0),
                         column_index: 1,
-                        text: Some(engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string())),
+                        text: Some(engine.describe_trustee_members(&ace.trustee)),
                         image: None,
                     });
                     self.list.insert_item(nwg::InsertListViewItem {
                         index: Some(0),
                         column_index: 2,
-                        text: Some(engine.describe_ace(
+                        text: Some(format!("{}{}", engine.describe_ace(
                             ace.access_mask,
                             ace.get_object_type(),
                             ace.get_inherited_object_type(),
                             ace.get_container_inherit(),
                             ace.get_inherit_only()
-                        )),
+                        ), engine.describe_ace_condition(ace))),
+                        image: None,
+                    });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(engine.get_ace_severity(&location, ace).to_string()),
                         image: None,
                     });
                 }
@@ -917,6 +1137,12 @@ impl BasicApp {
                             )),
                             image: None,
                         });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 3,
+                            text: Some(Severity::Info.to_string()),
+                            image: None,
+                        });
                     }
                     for ace in aces_missing {
                         self.list.insert_item(nwg::InsertListViewItem {
@@ -943,6 +1169,12 @@ impl BasicApp {
                             )),
                             image: None,
                         });
+                        self.list.insert_item(nwg::InsertListViewItem {
+                            index: Some(0),
+                            column_index: 3,
+                            text: Some(Severity::Low.to_string()),
+                            image: None,
+                        });
                     }
                     self.list.insert_item(nwg::InsertListViewItem {
                         index: Some(0),
@@ -953,7 +1185,7 @@ impl BasicApp {
                     self.list.insert_item(nwg::InsertListViewItem {
                         index: Some(0),
                         column_index: 1,
-                        text: Some(engine.resolve_sid(&trustee).map(|(dn, _)| dn).unwrap_or(trustee.to_string())),
+                        text: Some(engine.describe_trustee_members(&trustee)),
                         image: None,
                     });
                     self.list.insert_item(nwg::InsertListViewItem {
@@ -962,9 +1194,50 @@ impl BasicApp {
                         text: Some(engine.describe_delegation_rights(&delegation.rights)),
                         image: None,
                     });
+                    self.list.insert_item(nwg::InsertListViewItem {
+                        index: Some(0),
+                        column_index: 3,
+                        text: Some(engine.get_delegation_severity(&location, aces_found, aces_missing).to_string()),
+                        image: None,
+                    });
                 }
             }
         }
+        if *self.sort_by_severity.borrow() {
+            self.sort_list_by_severity();
+        }
+    }
+
+    // Clicking on the severity column header toggles between listing findings by decreasing
+    // severity, and in the order they were analysed
+    fn handle_list_column_click(&self, data: &nwg::EventData) {
+        let (_, column_index) = data.on_list_view_item_index();
+        if column_index != 3 {
+            return;
+        }
+        let sort_by_severity = !*self.sort_by_severity.borrow();
+        *self.sort_by_severity.borrow_mut() = sort_by_severity;
+        self.list.set_column_sort_arrow(3, if sort_by_severity { Some(nwg::ListViewColumnSortArrow::Down) } else { None });
+        self.handle_treeview_select();
+    }
+
+    fn sort_list_by_severity(&self) {
+        let severities = [Severity::Critical, Severity::High, Severity::Medium, Severity::Low, Severity::Info].map(|s| s.to_string());
+        let mut rows: Vec<Vec<String>> = (0..self.list.len()).map(|row_index| {
+            (0..4).map(|column_index| self.list.item(row_index, column_index, 1000).map(|item| item.text).unwrap_or_default()).collect()
+        }).collect();
+        rows.sort_by_key(|row| severities.iter().position(|s| s == &row[3]).unwrap_or(severities.len()));
+        self.list.clear();
+        for (row_index, row) in rows.into_iter().enumerate() {
+            for (column_index, text) in row.into_iter().enumerate() {
+                self.list.insert_item(nwg::InsertListViewItem {
+                    index: Some(row_index as i32),
+                    column_index: column_index as i32,
+                    text: Some(text),
+                    image: None,
+                });
+            }
+        }
     }
 
     fn cleanup(&self) {
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use serde_json::json;
+use crate::audit::AuditFinding;
+use crate::bootstrap::TemplateMatch;
+use crate::delegations::DelegationLocation;
+use crate::engine::{AdelegResult, Engine};
+use crate::error::AdelegError;
+use crate::tiers::TierViolation;
+
+pub const REPORT_TEMPLATE: &str = include_str!("../report_template.html");
+
+impl<'a> Engine<'a> {
+    // Generates a single HTML file which can be opened offline: results are embedded as JSON,
+    // and rendered by the page itself
+    pub fn export_html(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, audit_findings: &[AuditFinding], tier_violations: &[TierViolation], template_matches: &[TemplateMatch], show_builtin: bool) -> Result<String, AdelegError> {
+        let data = json!({
+            "naming_contexts": &self.naming_contexts,
+            "results": self.export_json(res, audit_findings, tier_violations, template_matches, show_builtin),
+        });
+        let data = serde_json::to_string(&data).map_err(|e| AdelegError::JsonParsing(e.to_string()))?;
+        // Names come from the directory, they must not be able to close the script tag or open
+        // a comment in it (which would make it swallow the rest of the page). These characters
+        // can only appear in JSON strings, where escaping them keeps the same value.
+        let data = data.replace('<', "\\u003c").replace('>', "\\u003e").replace('&', "\\u0026");
+        Ok(REPORT_TEMPLATE
+            .replace("{{VERSION}}", env!("CARGO_PKG_VERSION"))
+            .replace("{{REPORT_DATA}}", &data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{TestForest, ROOT_DOMAIN_DN};
+
+    #[test]
+    fn names_cannot_break_out_of_report_data() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let ou_dn = format!("OU=<!--<script>&</script>,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&ou_dn, "organizationalUnit", &format!("O:DAG:DAD:(A;;GA;;;DA)(A;;WD;;;{})", helpdesk), &[]);
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        let html = engine.export_html(&res, &[], &[], &[], false).expect("unable to export HTML");
+
+        let prefix = r#"<script id="report-data" type="application/json">"#;
+        let start = html.find(prefix).expect("no report data") + prefix.len();
+        let end = start + html[start..].find("</script>").expect("report data not closed");
+        let data = &html[start..end];
+        assert!(!data.contains(['<', '>', '&']));
+        let data: serde_json::Value = serde_json::from_str(data).expect("invalid report data");
+        assert!(data.to_string().contains(&ou_dn));
+    }
+}
@@ -3,27 +3,50 @@ mod error;
 mod schema;
 mod delegations;
 mod engine;
+mod audit;
+mod severity;
+mod tiers;
+mod members;
+mod graph;
+mod bloodhound;
+mod bootstrap;
+mod directory;
+mod snapshot;
+mod diff;
+mod export;
+mod remediation;
+mod html;
+#[cfg(windows)]
 mod gui;
+#[cfg(test)]
+mod testing;
 
 use std::io::Write;
 use std::collections::HashMap;
 use engine::PrincipalType;
-use windows::Win32::Networking::Ldap::LDAP_PORT;
-use clap::{Command, Arg};
+use clap::{Command, Arg, ArgMatches};
 use authz::Sid;
+#[cfg(windows)]
 use winldap::connection::LdapConnection;
+#[cfg(windows)]
 use crate::gui::run_gui;
 use crate::engine::{Engine, AdelegResult};
 use crate::error::AdelegError;
 use crate::delegations::DelegationLocation;
+use crate::directory::DirectorySource;
+use crate::snapshot::{Snapshot, RecordingDirectory};
+use crate::diff::diff_results;
+use crate::severity::Severity;
+use crate::graph::{ControlNode, ControlPath};
+use crate::remediation::{RemediationKind, RemediationSelection};
 
 fn main() {
+    #[cfg(windows)]
     if std::env::args().count() <= 1 {
         run_gui();
         return;
     }
 
-    let default_port = format!("{}", LDAP_PORT);
     let app = Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
@@ -33,13 +56,15 @@ fn main() {
                 .long("server")
                 .short('s')
                 .number_of_values(1)
+                .global(true)
         )
         .arg(
             Arg::new("port")
                 .help("(explicit server) LDAP port")
                 .long("port")
                 .number_of_values(1)
-                .default_value(&default_port)
+                .default_value("389")
+                .global(true)
         )
         .arg(
             Arg::new("domain")
@@ -48,6 +73,7 @@ fn main() {
                 .short('d')
                 .number_of_values(1)
                 .requires_all(&["username", "password"])
+                .global(true)
         )
         .arg(
             Arg::new("username")
@@ -56,6 +82,7 @@ fn main() {
                 .short('u')
                 .number_of_values(1)
                 .requires_all(&["domain","password"])
+                .global(true)
         )
         .arg(
             Arg::new("password")
@@ -64,6 +91,7 @@ fn main() {
                 .short('p')
                 .number_of_values(1)
                 .requires_all(&["domain","username"])
+                .global(true)
         )
         .arg(
             Arg::new("templates")
@@ -73,6 +101,7 @@ fn main() {
                 .value_name("T.json")
                 .multiple_occurrences(true)
                 .number_of_values(1)
+                .global(true)
         )
         .arg(
             Arg::new("delegations")
@@ -82,13 +111,14 @@ fn main() {
                 .value_name("D.json")
                 .multiple_occurrences(true)
                 .number_of_values(1)
+                .global(true)
         ).arg(
             Arg::new("index")
                 .help("Index view by trustee or resources (default is by resources)")
                 .long("index")
                 .takes_value(true)
                 .default_value("resources")
-                .possible_values(&["resources", "trustees"])
+                .possible_values(["resources", "trustees"])
         ).arg(
             Arg::new("text")
             .help("Output as text (default is GUI if there is no commandline argument")
@@ -100,10 +130,27 @@ fn main() {
                 .takes_value(true)
                 .number_of_values(1)
                 .long("csv")
+        ).arg(
+            Arg::new("json")
+                .help("Write the full results into a JSON file")
+                .takes_value(true)
+                .number_of_values(1)
+                .long("json")
+        ).arg(
+            Arg::new("html")
+                .help("Write a self-contained HTML report")
+                .takes_value(true)
+                .number_of_values(1)
+                .long("html")
         ).arg(
             Arg::new("show_builtin")
             .help("Include built-in delegations in the output")
             .long("show-builtin")
+            .global(true)
+        ).arg(
+            Arg::new("sort_by_severity")
+            .help("Sort results by decreasing severity (default is by resource)")
+            .long("sort-by-severity")
         ).arg(
             Arg::new("show_warning_unreadable")
             .help("Show unreadable security descriptors as warnings")
@@ -112,239 +159,683 @@ fn main() {
             Arg::new("show_raw")
                 .help("Show unresolved ACE contents")
                 .long("show-raw")
+                .global(true)
+        ).arg(
+            Arg::new("expand_groups")
+                .help("Expand group trustees into their effective members (nested groups, primary groups and foreign security principals)")
+                .long("expand-groups")
+                .global(true)
+        ).arg(
+            Arg::new("audit")
+                .help("Also read SACLs and report missing or modified auditing (requires SeSecurityPrivilege)")
+                .long("audit")
+                .global(true)
+        ).arg(
+            Arg::new("tiers")
+                .help("json file with tier definitions (implies --check-tiers)")
+                .long("tiers")
+                .value_name("tiers.json")
+                .multiple_occurrences(true)
+                .number_of_values(1)
+                .global(true)
+        ).arg(
+            Arg::new("check_tiers")
+                .help("Report trustees which control resources of a more privileged tier (built-in tier 0 is always defined)")
+                .long("check-tiers")
+        ).arg(
+            Arg::new("match_templates")
+                .help("Report templates whose ACEs are found (entirely or partially) among ACEs which are not documented yet")
+                .long("match-templates")
+        ).arg(
+            Arg::new("snapshot")
+                .help("Analyse a forest snapshot file instead of connecting to a domain controller")
+                .long("snapshot")
+                .value_name("forest.snap")
+                .number_of_values(1)
+                .conflicts_with_all(&["server", "domain", "username", "password"])
+        ).subcommand(
+            Command::new("capture")
+                .about("Record everything read from the forest into a snapshot file, to analyse it later with --snapshot")
+                .arg(
+                    Arg::new("out")
+                        .help("Path of the snapshot file to write")
+                        .long("out")
+                        .short('o')
+                        .value_name("forest.snap")
+                        .number_of_values(1)
+                        .required(true)
+                )
+        ).subcommand(
+            Command::new("paths")
+                .about("Compute chains of control (ownership, ACEs, delegations, group membership, parent containers) from trustees to sensitive targets")
+                .arg(
+                    Arg::new("to")
+                        .help("Target to compute paths to, as a SID, DN or DOMAIN\\name (default is Enterprise Admins, Domain Admins, domain heads and domain controllers)")
+                        .long("to")
+                        .value_name("target")
+                        .multiple_occurrences(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("from")
+                        .help("Source to compute paths from, as a SID, DN or DOMAIN\\name (default is every trustee found)")
+                        .long("from")
+                        .value_name("source")
+                        .multiple_occurrences(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("csv")
+                        .help("Write paths into a CSV file")
+                        .long("csv")
+                        .takes_value(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Write paths into a JSON file")
+                        .long("json")
+                        .takes_value(true)
+                        .number_of_values(1)
+                )
+        ).subcommand(
+            Command::new("bloodhound")
+                .about("Export objects and the ACE edges found during analysis as SharpHound JSON files, to import them into BloodHound")
+                .arg(
+                    Arg::new("out")
+                        .help("Directory to write users.json, computers.json, groups.json, etc. into")
+                        .long("out")
+                        .short('o')
+                        .value_name("directory")
+                        .number_of_values(1)
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("include_documented")
+                        .help("Also export edges of documented delegations (built-in ones require --show-builtin)")
+                        .long("include-documented")
+                )
+        ).subcommand(
+            Command::new("bootstrap")
+                .about("Write a delegations file documenting every ACE found, using loaded templates where they match")
+                .arg(
+                    Arg::new("out")
+                        .help("Path of the delegations file to write")
+                        .long("out")
+                        .short('o')
+                        .value_name("delegations.json")
+                        .number_of_values(1)
+                        .required(true)
+                )
+        ).subcommand(
+            Command::new("remediate")
+                .about("Generate reviewable changes which fix the selected findings, as a PowerShell script, LDIF records or a JSON plan")
+                .arg(
+                    Arg::new("action")
+                        .help("Kind of finding to fix (default is all of them)")
+                        .long("action")
+                        .multiple_occurrences(true)
+                        .number_of_values(1)
+                        .possible_values(["remove-orphan-aces", "add-missing-aces", "reset-owner", "enable-inheritance"])
+                )
+                .arg(
+                    Arg::new("resource")
+                        .help("Only fix findings on this object and below it, as a DN (default is everywhere)")
+                        .long("resource")
+                        .value_name("DN")
+                        .multiple_occurrences(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("trustee")
+                        .help("Only fix findings for this trustee, as a SID, DN or DOMAIN\\name (default is every trustee)")
+                        .long("trustee")
+                        .value_name("trustee")
+                        .multiple_occurrences(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("min_severity")
+                        .help("Only fix findings of at least this severity")
+                        .long("min-severity")
+                        .default_value("info")
+                        .possible_values(["info", "low", "medium", "high", "critical"])
+                )
+                .arg(
+                    Arg::new("ps1")
+                        .help("Write a PowerShell script using Set-Acl")
+                        .long("ps1")
+                        .value_name("fix.ps1")
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("ldif")
+                        .help("Write LDIF modify records replacing each security descriptor as SDDL")
+                        .long("ldif")
+                        .value_name("fix.ldif")
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("plan")
+                        .help("Write the list of changes into a JSON file")
+                        .long("plan")
+                        .value_name("plan.json")
+                        .number_of_values(1)
+                )
+        ).subcommand(
+            Command::new("diff")
+                .about("Compare the results of two scans, recorded as snapshot files, and only report what changed")
+                .arg(
+                    Arg::new("old")
+                        .help("Snapshot of the previous scan")
+                        .value_name("old.snap")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("new")
+                        .help("Snapshot of the current scan")
+                        .value_name("new.snap")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("csv")
+                        .help("Write changes into a CSV file")
+                        .long("csv")
+                        .takes_value(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Write changes into a JSON file")
+                        .long("json")
+                        .takes_value(true)
+                        .number_of_values(1)
+                )
         );
 
     let args = app.get_matches();
-    let server= args.value_of("server");
-    let port = args.value_of("port").expect("no port set");
-    let port = match port.parse::<u16>() {
-        Ok(n) if n > 0 => n,
-        _ => {
-            eprintln!("Unable to parse \"{}\" as TCP port", port);
+    if let Some(diff_args) = args.subcommand_matches("diff") {
+        run_diff(&args, diff_args);
+        return;
+    }
+
+    let capture_path = args.subcommand_matches("capture").and_then(|m| m.value_of("out"));
+    let snapshot;
+    let conn;
+    let source: &dyn DirectorySource = if let Some(snapshot_path) = args.value_of("snapshot") {
+        snapshot = match Snapshot::load(snapshot_path) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!(" [!] Unable to load snapshot {} : {}", snapshot_path, e);
+                std::process::exit(1);
+            }
+        };
+        &snapshot
+    } else {
+        conn = connect_ldap(&args);
+        conn.as_ref()
+    };
+    let recorder = capture_path.map(|_| RecordingDirectory::new(source));
+    let directory: &dyn DirectorySource = match &recorder {
+        Some(recorder) => recorder,
+        None => source,
+    };
+
+    let mut engine = Engine::new(directory, !args.is_present("show_raw"));
+    engine.expand_groups = args.is_present("expand_groups");
+    engine.load_delegation_json(engine::BUILTIN_ACES).expect("unable to parse builtin delegations");
+    engine.load_tier_json(tiers::BUILTIN_TIERS).expect("unable to parse builtin tiers");
+
+    load_engine_inputs(&mut engine, &args);
+
+    let res = match engine.run() {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!(" [!] Unable to scan for delegations: {}", e);
             std::process::exit(1);
         }
     };
-    let mut password = String::with_capacity(100);
-    let credentials = match (args.value_of("domain"),
-                             args.value_of("username"),
-                             args.value_of("password")) {
-        (Some(d), Some(u), None) | (Some(d), Some(u), Some("*")) => {
-            crate::utils::read_password(&mut password, &format!("Password for {}\\{}", d, u));
-            Some((d, u, password.as_str()))
-        },
-        (Some(d), Some(u), Some(p)) => Some((d, u, p)),
-        _ => None,
+
+    let audit_findings = if args.is_present("audit") {
+        match engine.run_audit() {
+            Ok(findings) => findings,
+            Err(e) => {
+                eprintln!(" [!] Unable to analyse audit settings: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        vec![]
     };
 
-    let (server, port) = if let Some(server) = server {
-        (server.to_owned(), port)
+    // Also check tiers when capturing, so that every lookup they need gets recorded
+    let tier_violations = if args.is_present("check_tiers") || args.is_present("tiers") || capture_path.is_some() {
+        engine.run_tier_check(&res)
     } else {
-        if let Some((server, port)) = utils::get_gc_domain_controller() {
-            (server, port)
-        } else {
-            eprintln!(" [!] Unable to find a domain controller automatically, please specify one manually using --server");
-            std::process::exit(1);
+        vec![]
+    };
+
+    let bootstrap_args = args.subcommand_matches("bootstrap");
+    let template_matches = if args.is_present("match_templates") || bootstrap_args.is_some() {
+        engine.match_templates(&res)
+    } else {
+        vec![]
+    };
+
+    // Also build the control graph when capturing, so that group memberships and domain
+    // controllers get recorded
+    let paths_args = args.subcommand_matches("paths");
+    let path_targets: Vec<ControlNode> = paths_args.and_then(|m| m.values_of("to"))
+        .map(|names| names.map(|name| engine.resolve_control_node(name)).collect())
+        .unwrap_or_default();
+    let path_sources: Option<Vec<ControlNode>> = paths_args.and_then(|m| m.values_of("from"))
+        .map(|names| names.map(|name| engine.resolve_control_node(name)).collect());
+    let graph = if paths_args.is_some() || capture_path.is_some() {
+        let additional_nodes: Vec<ControlNode> = path_targets.iter().chain(path_sources.iter().flatten()).cloned().collect();
+        match engine.build_control_graph(&res, &additional_nodes) {
+            Ok(graph) => Some(graph),
+            Err(e) => {
+                eprintln!(" [!] Unable to build control graph: {}", e);
+                std::process::exit(1);
+            }
         }
+    } else {
+        None
     };
 
-    let conn = match LdapConnection::new(&server, port, credentials) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Unable to establish LDAP connection to \"{}:{}\" : {}", server, port, e);
-            std::process::exit(1);
+    // Objects exported to BloodHound are also collected when capturing, so that they get recorded
+    let bloodhound_args = args.subcommand_matches("bloodhound");
+    let bloodhound_files = if bloodhound_args.is_some() || capture_path.is_some() {
+        let include_documented = bloodhound_args.map(|m| m.is_present("include_documented")).unwrap_or(false);
+        match engine.export_bloodhound(&res, include_documented, args.is_present("show_builtin")) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!(" [!] Unable to collect objects for BloodHound: {}", e);
+                std::process::exit(1);
+            }
         }
+    } else {
+        vec![]
     };
 
-    let mut engine = Engine::new(&conn, !args.is_present("show_raw"));
-    engine.load_delegation_json(engine::BUILTIN_ACES).expect("unable to parse builtin delegations");
+    // Security descriptors of every object with a finding are also read when capturing, so that
+    // remediation can be planned from the snapshot
+    let remediate_args = args.subcommand_matches("remediate");
+    let remediation = if remediate_args.is_some() || capture_path.is_some() {
+        let selection = match remediate_args {
+            Some(m) => get_remediation_selection(&engine, m),
+            None => RemediationSelection {
+                kinds: vec![RemediationKind::RemoveOrphanAces, RemediationKind::AddMissingAces, RemediationKind::ResetOwner, RemediationKind::EnableInheritance],
+                resources: vec![],
+                trustees: vec![],
+                min_severity: Severity::Info,
+            },
+        };
+        engine.plan_remediation(&res, &selection)
+    } else {
+        vec![]
+    };
 
-    if let Some(input_filepaths) = args.values_of("templates") {
-        for input_filepath in input_filepaths.into_iter() {
-            let json = match std::fs::read_to_string(input_filepath) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!(" [!] Unable to open template file {} : {}", input_filepath, e);
-                    std::process::exit(1);
-                }
-            };
-            if let Err(e) = engine.load_template_json(&json) {
-                eprintln!(" [!] Unable to parse template file {} : {}", input_filepath, e);
-                std::process::exit(1);
+    if let (Some(capture_path), Some(recorder)) = (capture_path, &recorder) {
+        // Also record lookups of every principal which could be displayed, so that results can be
+        // resolved to names when analysing the snapshot
+        for res in res.values().flatten() {
+            let trustees = res.owner.iter()
+                .chain(res.misplaced_aces.iter().map(|m| &m.ace.trustee))
+                .chain(res.redundant_aces.iter().map(|r| &r.ace.trustee))
+                .chain(res.unexpected_inherited_aces.iter().map(|ace| &ace.trustee))
+                .chain(res.deleted_trustee.iter().map(|ace| &ace.trustee))
+                .chain(res.orphan_aces.iter().map(|ace| &ace.trustee))
+                .chain(res.delegations.iter().map(|(_, trustee, _, _)| trustee));
+            for trustee in trustees {
+                engine.resolve_sid(trustee);
+                engine.get_effective_members(trustee);
             }
         }
+        if let Err(e) = recorder.to_snapshot().save(capture_path) {
+            eprintln!(" [!] Unable to write snapshot {} : {}", capture_path, e);
+            std::process::exit(1);
+        }
+        eprintln!(" [+] Snapshot written to {}", capture_path);
+        return;
     }
 
-    if let Some(input_files) = args.values_of("delegations") {
-        for input_filepath in input_files.into_iter() {
-            let json = match std::fs::read_to_string(input_filepath) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!(" [!] Unable to open delegation file {} : {}", input_filepath, e);
-                    std::process::exit(1);
-                }
-            };
-            if let Err(e) = engine.load_delegation_json(&json) {
-                eprintln!(" [!] Unable to parse delegation file {} : {}", input_filepath, e);
+    if let Some(out_dir) = bloodhound_args.and_then(|m| m.value_of("out")) {
+        if let Err(e) = std::fs::create_dir_all(out_dir) {
+            eprintln!(" [!] Unable to create directory {} : {}", out_dir, e);
+            std::process::exit(1);
+        }
+        for file in &bloodhound_files {
+            let path = std::path::Path::new(out_dir).join(format!("{}.json", file.get_name()));
+            let path = path.to_string_lossy();
+            if let Err(e) = serde_json::to_writer(open_output(&path, "JSON"), file) {
+                eprintln!(" [!] Unable to write JSON file {} : {}", path, e);
                 std::process::exit(1);
             }
         }
+        eprintln!(" [+] BloodHound files written to {}", out_dir);
+        return;
+    }
+
+    if let Some(out_path) = bootstrap_args.and_then(|m| m.value_of("out")) {
+        let delegations = engine.bootstrap_delegations(&res, &template_matches);
+        if let Err(e) = serde_json::to_writer_pretty(open_output(out_path, "JSON"), &delegations) {
+            eprintln!(" [!] Unable to write JSON file {} : {}", out_path, e);
+            std::process::exit(1);
+        }
+        eprintln!(" [+] {} delegations written to {}", delegations.len(), out_path);
+        return;
     }
 
-    let res = match engine.run() {
-        Ok(res) => res,
-        Err(e) => {
-            eprintln!(" [!] Unable to scan for delegations: {}", e);
+    if let Some(remediate_args) = remediate_args {
+        if !["ps1", "ldif", "plan"].iter().any(|arg| remediate_args.is_present(arg)) {
+            eprintln!(" [!] Nothing to write, use --ps1, --ldif and/or --plan");
             std::process::exit(1);
         }
-    };
+        if let Some(path) = remediate_args.value_of("ps1") {
+            if let Err(e) = open_output(path, "PowerShell").write_all(engine.export_remediation_powershell(&remediation).as_bytes()) {
+                eprintln!(" [!] Unable to write PowerShell script {} : {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        if let Some(path) = remediate_args.value_of("ldif") {
+            if let Err(e) = open_output(path, "LDIF").write_all(engine.export_remediation_ldif(&remediation).as_bytes()) {
+                eprintln!(" [!] Unable to write LDIF file {} : {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        if let Some(path) = remediate_args.value_of("plan") {
+            if let Err(e) = serde_json::to_writer_pretty(open_output(path, "JSON"), &engine.export_remediation_plan(&remediation)) {
+                eprintln!(" [!] Unable to write JSON file {} : {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        eprintln!(" [+] Remediation of {} objects written", remediation.len());
+        return;
+    }
+
+    if let (Some(paths_args), Some(graph)) = (paths_args, &graph) {
+        let targets = if path_targets.is_empty() { &graph.default_targets } else { &path_targets };
+        let paths = graph.find_paths(targets, path_sources.as_deref());
+        print_control_paths(&engine, paths_args, &paths);
+        return;
+    }
 
     let show_builtin = args.is_present("show_builtin");
+    if let Some(json_path) = args.value_of("json") {
+        let export = engine.export_json(&res, &audit_findings, &tier_violations, &template_matches, show_builtin);
+        if let Err(e) = serde_json::to_writer_pretty(open_output(json_path, "JSON"), &export) {
+            eprintln!(" [!] Unable to write JSON file {} : {}", json_path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(html_path) = args.value_of("html") {
+        let html = match engine.export_html(&res, &audit_findings, &tier_violations, &template_matches, show_builtin) {
+            Ok(html) => html,
+            Err(e) => {
+                eprintln!(" [!] Unable to generate HTML report: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = open_output(html_path, "HTML").write_all(html.as_bytes()) {
+            eprintln!(" [!] Unable to write HTML report {} : {}", html_path, e);
+            std::process::exit(1);
+        }
+    }
     let show_warning_unreadable = args.is_present("show_warning_unreadable");
+    let sort_by_severity = args.is_present("sort_by_severity");
     let mut warning_unreadable_count = 0;
     if let Some(csv_path) = args.value_of("csv") {
-        let output: Box<dyn std::io::Write> = if csv_path == "-" {
-            Box::new(std::io::stdout())
-        } else {
-            match std::fs::OpenOptions::new().create(true).truncate(true).write(true).open(csv_path) {
-                Ok(f) => Box::new(f),
-                Err(e) => {
-                    eprintln!(" [!] Unable to open output CSV file {} : {}", csv_path, e);
-                    std::process::exit(1);
-                }
-            }
-        };
-        let mut writer = csv::Writer::from_writer(output);
-        writer.write_record(&[
-            "Resource",
-            "Trustee",
-            "Trustee type",
-            "Category",
-            "Details",
-        ]).expect("unable to write CSV record");
+        // Records are (severity, resource, trustee, trustee type, category, details)
+        let mut records: Vec<(Severity, [String; 5])> = vec![];
+        let mut add_record = |severity: Severity, record: [&str; 5]| records.push((severity, record.map(|s| s.to_owned())));
         for (location, res) in &res {
             let res = match res {
                 Ok(r) => r,
                 Err(e) => {
                     warning_unreadable_count += 1;
                     if show_warning_unreadable {
-                        writer.write_record(&[
+                        add_record(Severity::Info, [
                             location.to_string().as_str(),
                             "Global",
                             "External",
                             "Warning",
                             &e.to_string(),
-                        ]).expect("unable to write CSV record");
+                        ]);
                     }
                     continue;
                 },
             };
             if let Some(owner) = &res.owner {
-                let (dn, ptype) = engine.resolve_sid(&owner).unwrap_or((owner.to_string(), PrincipalType::External));
-                writer.write_record(&[
+                let (dn, ptype) = engine.resolve_sid(owner).unwrap_or((owner.to_string(), PrincipalType::External));
+                add_record(engine.get_owner_severity(location, owner), [
                     location.to_string().as_str(),
                     &dn,
                     &ptype.to_string(),
                     "Owner",
                     "This principal owns the object, which implicitly grants them full control over it",
-                ]).expect("unable to write CSV record");
+                ]);
             }
             if res.dacl_protected {
-                writer.write_record(&[
+                add_record(Severity::Low, [
                     location.to_string().as_str(),
                     "Global",
                     "External",
                     "Warning",
                     "DACL is configured to block inheritance of parent container ACEs",
-                ]).expect("unable to write CSV record");
+                ]);
             }
-            if let Some(ace) = &res.non_canonical_ace {
-                writer.write_record(&[
+            for ace_move in &res.misplaced_aces {
+                let ace = &ace_move.ace;
+                add_record(Severity::Low, [
                     location.to_string().as_str(),
                     "Global",
                     "External",
                     "Warning",
-                    &format!("ACL is not in canonical order, e.g. see {} ACE for {}: {}", 
+                    &format!("ACL is not in canonical order, {} ACE for {} should be moved from position {} to {}: {}",
                         if ace.grants_access() { "allow" } else { "deny" },
                         engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
+                        ace_move.from,
+                        ace_move.to,
+                        engine.describe_ace(
+                            ace.access_mask,
+                            ace.get_object_type(),
+                            ace.get_inherited_object_type(),
+                            ace.get_container_inherit(),
+                            ace.get_inherit_only()))
+                ]);
+            }
+            for redundant in &res.redundant_aces {
+                let ace = &redundant.ace;
+                let (dn, ptype) = engine.resolve_sid(&ace.trustee).unwrap_or((ace.trustee.to_string(), PrincipalType::External));
+                add_record(Severity::Low, [
+                    location.to_string().as_str(),
+                    &dn,
+                    &ptype.to_string(),
+                    "Warning",
+                    &format!("Redundant {} ACE at position {} is already covered by the ACE at position {}, it can be removed: {}",
+                        if ace.grants_access() { "allow" } else { "deny" },
+                        redundant.index,
+                        redundant.covered_by_index,
                         engine.describe_ace(
                             ace.access_mask,
                             ace.get_object_type(),
                             ace.get_inherited_object_type(),
                             ace.get_container_inherit(),
                             ace.get_inherit_only()))
-                ]).expect("unable to write CSV record");
+                ]);
+            }
+            for ace in &res.unexpected_inherited_aces {
+                let (dn, ptype) = engine.resolve_sid(&ace.trustee).unwrap_or((ace.trustee.to_string(), PrincipalType::External));
+                add_record(engine.get_ace_severity(location, ace).max(Severity::Low), [
+                    location.to_string().as_str(),
+                    &dn,
+                    &ptype.to_string(),
+                    "Warning",
+                    &format!("Inherited {} ACE is not propagated by the parent container, it is either stale or forged: {}",
+                        if ace.grants_access() { "allow" } else { "deny" },
+                        engine.describe_ace(
+                            ace.access_mask,
+                            ace.get_object_type(),
+                            ace.get_inherited_object_type(),
+                            ace.get_container_inherit(),
+                            ace.get_inherit_only()))
+                ]);
             }
             for ace in &res.deleted_trustee {
-                writer.write_record(&[
+                add_record(Severity::Low, [
                     location.to_string().as_str(),
                     &ace.trustee.to_string(),
                     "External",
                     "Warning",
                     "The trustee linked to this delegation does not exist anymore, it should be cleaned up",
-                ]).expect("unable to write CSV record");
+                ]);
             }
             for ace in &res.orphan_aces {
                 let (dn, ptype) = engine.resolve_sid(&ace.trustee).unwrap_or((ace.trustee.to_string(), PrincipalType::External));
-                writer.write_record(&[
+                add_record(engine.get_ace_severity(location, ace), [
                     location.to_string().as_str(),
                     &dn,
                     &ptype.to_string(),
                     if ace.grants_access() { "Allow ACE" } else { "Deny ACE" },
-                    engine.describe_ace(
+                    format!("{}{}", engine.describe_ace(
                         ace.access_mask,
                         ace.get_object_type(),
                         ace.get_inherited_object_type(),
                         ace.get_container_inherit(),
                         ace.get_inherit_only()
-                    ).as_str(),
-                ]).expect("unable to write CSV record");
+                    ), engine.describe_ace_condition(ace)).as_str(),
+                ]);
             }
             for (deleg, trustee, aces_found, aces_missing) in &res.delegations {
                 if deleg.builtin && !show_builtin {
                     continue;
                 }
                 let (dn, ptype) = engine.resolve_sid(trustee).unwrap_or((trustee.to_string(), PrincipalType::External));
-                writer.write_record(&[
+                add_record(engine.get_delegation_severity(location, aces_found, aces_missing), [
                     location.to_string().as_str(),
                     &dn,
                     &ptype.to_string(),
                     if deleg.builtin { "Built-in" } else { "Delegation" },
                     engine.describe_delegation_rights(&deleg.rights).as_str(),
-                ]).expect("unable to write CSV record");
+                ]);
 
                 for ace in aces_found {
-                    writer.write_record(&[
+                    add_record(Severity::Info, [
                         location.to_string().as_str(),
                         &dn,
                         &ptype.to_string(),
                         if ace.grants_access() { "Expected allow ACE found" } else { "Expected deny ACE found" },
-                        &format!("In delegation: {}", engine.describe_delegation_rights(&deleg.rights)).as_str(),
-                    ]).expect("unable to write CSV record");
+                        format!("In delegation: {}", engine.describe_delegation_rights(&deleg.rights)).as_str(),
+                    ]);
                 }
                 for ace in aces_missing {
-                    writer.write_record(&[
+                    add_record(Severity::Low, [
                         location.to_string().as_str(),
                         &dn,
                         &ptype.to_string(),
                         if ace.grants_access() { "Expected allow ACE missing" } else { "Expected deny ACE missing" },
-                        &format!("In delegation: {}", engine.describe_delegation_rights(&deleg.rights)).as_str(),
-                    ]).expect("unable to write CSV record");
+                        format!("In delegation: {}", engine.describe_delegation_rights(&deleg.rights)).as_str(),
+                    ]);
                 }
             }
         }
 
+        for finding in &audit_findings {
+            add_record(engine.get_audit_severity(&finding.issue), [
+                finding.location.to_string().as_str(),
+                "Global",
+                "External",
+                "Audit",
+                engine.describe_audit_issue(&finding.issue).as_str(),
+            ]);
+        }
+
+        for violation in &tier_violations {
+            let (dn, ptype) = engine.resolve_sid(&violation.trustee).unwrap_or((violation.trustee.to_string(), PrincipalType::External));
+            add_record(engine.get_tier_violation_severity(violation), [
+                violation.location.to_string().as_str(),
+                &dn,
+                &ptype.to_string(),
+                "Tier",
+                engine.describe_tier_violation(violation).as_str(),
+            ]);
+        }
+
+        for template_match in &template_matches {
+            let (dn, ptype) = engine.resolve_sid(&template_match.trustee).unwrap_or((template_match.trustee.to_string(), PrincipalType::External));
+            add_record(Severity::Info, [
+                template_match.location.to_string().as_str(),
+                &dn,
+                &ptype.to_string(),
+                if template_match.is_full() { "Template match" } else { "Partial template match" },
+                engine.describe_template_match(template_match).as_str(),
+            ]);
+        }
+
+        if sort_by_severity {
+            // Stable sort, records of a same severity stay grouped by resource
+            records.sort_by_key(|(severity, _)| std::cmp::Reverse(*severity));
+        }
+        let mut writer = csv::Writer::from_writer(open_output(csv_path, "CSV"));
+        let mut header = vec![
+            "Resource",
+            "Trustee",
+            "Trustee type",
+            "Category",
+            "Severity",
+            "Details",
+        ];
+        if engine.expand_groups {
+            header.extend(["Effective members", "Members"]);
+        }
+        writer.write_record(&header).expect("unable to write CSV record");
+        // Trustees are only kept by name in records, find their SID back to expand groups
+        let mut members_by_trustee: HashMap<String, [String; 2]> = HashMap::new();
+        for (severity, [resource, trustee, trustee_type, category, details]) in &records {
+            let mut record = vec![
+                resource.to_owned(),
+                trustee.to_owned(),
+                trustee_type.to_owned(),
+                category.to_owned(),
+                severity.to_string(),
+                details.to_owned(),
+            ];
+            if engine.expand_groups {
+                let members = members_by_trustee.entry(trustee.to_owned()).or_insert_with(|| {
+                    let members = engine.resolve_str_to_sid(trustee)
+                        .or_else(|| Sid::try_from(trustee.as_str()).ok())
+                        .and_then(|sid| engine.get_effective_members(&sid));
+                    match members {
+                        Some(members) => [
+                            members.len().to_string(),
+                            members.iter()
+                                .map(|sid| engine.resolve_sid(sid).map(|(dn, _)| dn).unwrap_or(sid.to_string()))
+                                .collect::<Vec<String>>()
+                                .join("; "),
+                        ],
+                        None => [String::new(), String::new()],
+                    }
+                });
+                record.extend(members.iter().cloned());
+            }
+            writer.write_record(&record).expect("unable to write CSV record");
+        }
         drop(writer);
         let _ = std::io::stdout().flush();
         if !show_warning_unreadable && warning_unreadable_count > 0 {
             eprintln!("\n [!] {} security descriptors could not be read, use --show-warning-unreadable to see where", warning_unreadable_count);
         }
     }
+    else if args.value_of("json").is_some() || args.value_of("html").is_some() {
+        // Results have already been written as JSON or HTML, with unreadable security descriptors as errors
+    }
     else if args.value_of("index").unwrap_or("") == "trustees" {
         let mut warning_count = 0;
         let mut reindexed: HashMap<Sid, HashMap<DelegationLocation, AdelegResult>> = HashMap::new();
         for (location, res) in res.into_iter() {
             if let Ok(res) = res {
-                if res.non_canonical_ace.is_some() {
+                if !res.misplaced_aces.is_empty() || !res.redundant_aces.is_empty() || !res.unexpected_inherited_aces.is_empty() {
                     warning_count += 1;
                 }
                 if res.deleted_trustee.is_empty() {
@@ -356,10 +847,12 @@ fn main() {
                         .entry(location.clone())
                         .or_insert_with(|| {
                             AdelegResult {
-                                class_guid: res.class_guid.clone(),
+                                class_guid: res.class_guid,
                                 owner: None,
                                 dacl_protected: false,
-                                non_canonical_ace: None,
+                                misplaced_aces: vec![],
+                                redundant_aces: vec![],
+                                unexpected_inherited_aces: vec![],
                                 deleted_trustee: vec![],
                                 orphan_aces: vec![],
                                 delegations: vec![],
@@ -373,10 +866,12 @@ fn main() {
                         .entry(location.clone())
                         .or_insert_with(|| {
                             AdelegResult {
-                                class_guid: res.class_guid.clone(),
+                                class_guid: res.class_guid,
                                 owner: None,
                                 dacl_protected: false,
-                                non_canonical_ace: None,
+                                misplaced_aces: vec![],
+                                redundant_aces: vec![],
+                                unexpected_inherited_aces: vec![],
                                 deleted_trustee: vec![],
                                 orphan_aces: vec![],
                                 delegations: vec![],
@@ -393,10 +888,12 @@ fn main() {
                         .entry(location.clone())
                         .or_insert_with(|| {
                             AdelegResult {
-                                class_guid: res.class_guid.clone(),
+                                class_guid: res.class_guid,
                                 owner: None,
                                 dacl_protected: false,
-                                non_canonical_ace: None,
+                                misplaced_aces: vec![],
+                                redundant_aces: vec![],
+                                unexpected_inherited_aces: vec![],
                                 deleted_trustee: vec![],
                                 orphan_aces: vec![],
                                 delegations: vec![],
@@ -408,15 +905,30 @@ fn main() {
                 warning_count += 1;
             }
         }
+        let mut reindexed: Vec<(Sid, HashMap<DelegationLocation, AdelegResult>)> = reindexed.into_iter().collect();
+        if sort_by_severity {
+            reindexed.sort_by_cached_key(|(_, locations)| std::cmp::Reverse(locations.iter()
+                .map(|(location, res)| engine.get_result_severity(location, res, show_builtin))
+                .max()));
+        }
         for (trustee, locations) in &reindexed {
             println!("\n=== {}", engine.resolve_sid(trustee).map(|(dn, _)| dn).unwrap_or(trustee.to_string()));
+            if engine.expand_groups {
+                if let Some(members) = engine.get_effective_members(trustee) {
+                    println!("       {} :", engine.describe_effective_members(trustee).unwrap_or_default());
+                    for member in members.iter() {
+                        println!("         {}", engine.resolve_sid(member).map(|(dn, _)| dn).unwrap_or(member.to_string()));
+                    }
+                }
+            }
             for (location, res) in locations.iter() {
                 println!("       {} :", location);
-                if res.owner.as_ref() == Some(trustee) {
-                    println!("            Owner");
+                if let Some(owner) = res.owner.as_ref().filter(|owner| *owner == trustee) {
+                    println!("            [{}] Owner", engine.get_owner_severity(location, owner));
                 }
                 for ace in &res.orphan_aces {
-                    println!("            {} ACE {}",
+                    println!("            [{}] {} ACE {}{}",
+                        engine.get_ace_severity(location, ace),
                         if ace.grants_access() { "Allow" } else { "Deny" },
                         engine.describe_ace(
                             ace.access_mask,
@@ -424,13 +936,15 @@ fn main() {
                             ace.get_inherited_object_type(),
                             ace.get_container_inherit(),
                             ace.get_inherit_only()
-                    ));
+                        ),
+                        engine.describe_ace_condition(ace));
                 }
                 for (delegation, _, aces_found, aces_missing) in &res.delegations {
                     if !show_builtin && delegation.builtin {
                         continue;
                     }
-                    println!("            Documented delegation: {}",
+                    println!("            [{}] Documented delegation: {}",
+                        engine.get_delegation_severity(location, aces_found, aces_missing),
                         engine.describe_delegation_rights(&delegation.rights));
                     
                     for ace in aces_found {
@@ -465,7 +979,13 @@ fn main() {
         }
     } else {
         let mut res: Vec<(&DelegationLocation, &Result<AdelegResult, AdelegError>)> = res.iter().collect();
-        res.sort_by(|(loc_a, _), (loc_b, _)| loc_a.cmp(loc_b));
+        res.sort_by_key(|(loc_a, _)| *loc_a);
+        if sort_by_severity {
+            res.sort_by_key(|(location, res)| std::cmp::Reverse(match res {
+                Ok(res) => engine.get_result_severity(location, res, show_builtin),
+                Err(_) => Severity::Info,
+            }));
+        }
         for (location, res) in res {
             if let Ok(res) = &res {
                 if !res.needs_to_be_displayed(show_builtin) {
@@ -478,31 +998,72 @@ fn main() {
                 }
             }
 
-            println!("\n=== {}", &location);
             let res = match res {
                 Ok(r) => r,
                 Err(e) => {
+                    println!("\n=== {}", &location);
                     println!("       /!\\ {}", e);
                     continue;
                 },
             };
+            println!("\n=== {} [{}]", &location, engine.get_result_severity(location, res, show_builtin));
             if let Some(owner) = &res.owner {
-                println!("       Owner: {}", engine.resolve_sid(owner).map(|(dn, _)| dn).unwrap_or(owner.to_string()));
+                println!("       [{}] Owner: {}", engine.get_owner_severity(location, owner),
+                    engine.describe_trustee_members(owner));
             }
             if res.dacl_protected {
                 println!("       /!\\ ACL is configured to block inheritance of parent container ACEs");
             }
-            if let Some(ace) = &res.non_canonical_ace {
-                println!("       /!\\ ACL is not in canonical order, e.g. see {} ACE for {} : {}",
-                    if ace.grants_access() { "allow" } else { "deny" },
-                    engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
-                    engine.describe_ace(
-                        ace.access_mask,
-                        ace.get_object_type(),
-                        ace.get_inherited_object_type(),
-                        ace.get_container_inherit(),
-                        ace.get_inherit_only()
-                ));
+            if !res.misplaced_aces.is_empty() {
+                println!("       /!\\ ACL is not in canonical order, it can be fixed by moving these ACEs:");
+                for ace_move in &res.misplaced_aces {
+                    let ace = &ace_move.ace;
+                    println!("         From position {} to {}: {} ACE for {} : {}",
+                        ace_move.from,
+                        ace_move.to,
+                        if ace.grants_access() { "allow" } else { "deny" },
+                        engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
+                        engine.describe_ace(
+                            ace.access_mask,
+                            ace.get_object_type(),
+                            ace.get_inherited_object_type(),
+                            ace.get_container_inherit(),
+                            ace.get_inherit_only()
+                    ));
+                }
+            }
+            if !res.redundant_aces.is_empty() {
+                println!("       /!\\ ACL contains ACEs already covered by other ACEs, which can be removed:");
+                for redundant in &res.redundant_aces {
+                    let ace = &redundant.ace;
+                    println!("         Position {} (covered by position {}): {} ACE for {} : {}",
+                        redundant.index,
+                        redundant.covered_by_index,
+                        if ace.grants_access() { "allow" } else { "deny" },
+                        engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
+                        engine.describe_ace(
+                            ace.access_mask,
+                            ace.get_object_type(),
+                            ace.get_inherited_object_type(),
+                            ace.get_container_inherit(),
+                            ace.get_inherit_only()
+                    ));
+                }
+            }
+            if !res.unexpected_inherited_aces.is_empty() {
+                println!("       /!\\ ACL contains inherited ACEs which are not propagated by the parent container, they are either stale or forged:");
+                for ace in &res.unexpected_inherited_aces {
+                    println!("         {} ACE for {} : {}",
+                        if ace.grants_access() { "Allow" } else { "Deny" },
+                        engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
+                        engine.describe_ace(
+                            ace.access_mask,
+                            ace.get_object_type(),
+                            ace.get_inherited_object_type(),
+                            ace.get_container_inherit(),
+                            ace.get_inherit_only()
+                    ));
+                }
             }
             if !res.deleted_trustee.is_empty() {
                 println!("       /!\\ ACEs for trustees which do not exist anymore and should be cleaned up:");
@@ -513,16 +1074,18 @@ fn main() {
             if !res.orphan_aces.is_empty() {
                 println!("       ACEs found:");
                 for ace in &res.orphan_aces {
-                    println!("         {} {} : {}",
+                    println!("         [{}] {} {} : {}{}",
+                        engine.get_ace_severity(location, ace),
                         if ace.grants_access() { "Allow" } else { "Deny" },
-                        engine.resolve_sid(&ace.trustee).map(|(dn, _)| dn).unwrap_or(ace.trustee.to_string()),
+                        engine.describe_trustee_members(&ace.trustee),
                         engine.describe_ace(
                             ace.access_mask,
                             ace.get_object_type(),
                             ace.get_inherited_object_type(),
                             ace.get_container_inherit(),
                             ace.get_inherit_only()
-                        ));
+                        ),
+                        engine.describe_ace_condition(ace));
                 }
             }
             if res.delegations.iter().any(|(d, _, _, _)| !d.builtin) ||
@@ -532,7 +1095,8 @@ fn main() {
                     if !show_builtin && delegation.builtin {
                         continue;
                     }
-                    println!("         {} : {}", engine.resolve_sid(&trustee).map(|(dn, _)| dn).unwrap_or(trustee.to_string()),
+                    println!("         [{}] {} : {}", engine.get_delegation_severity(location, aces_found, aces_missing),
+                        engine.describe_trustee_members(trustee),
                         engine.describe_delegation_rights(&delegation.rights));
                     for ace in aces_found {
                         println!("           [+] {} ACE found: {}",
@@ -565,4 +1129,302 @@ fn main() {
             eprintln!("\n [!] {} security descriptors could not be read, use --show-warning-unreadable to see where", warning_unreadable_count);
         }
     }
-}
\ No newline at end of file
+
+    if args.value_of("csv").is_none() && args.value_of("json").is_none() && args.value_of("html").is_none() && !audit_findings.is_empty() {
+        println!("\n=== Audit coverage");
+        for finding in &audit_findings {
+            println!("       [{}] {} : {}", engine.get_audit_severity(&finding.issue), finding.location, engine.describe_audit_issue(&finding.issue));
+        }
+    }
+    if args.value_of("csv").is_none() && args.value_of("json").is_none() && args.value_of("html").is_none() && !tier_violations.is_empty() {
+        println!("\n=== Tier model");
+        for violation in &tier_violations {
+            println!("       [{}] {} : {} : {}", engine.get_tier_violation_severity(violation), violation.location,
+                engine.resolve_sid(&violation.trustee).map(|(dn, _)| dn).unwrap_or(violation.trustee.to_string()),
+                engine.describe_tier_violation(violation));
+        }
+    }
+    if args.value_of("csv").is_none() && args.value_of("json").is_none() && args.value_of("html").is_none() && !template_matches.is_empty() {
+        println!("\n=== Template matches");
+        for template_match in &template_matches {
+            println!("       {} : {} : {}", template_match.location,
+                engine.resolve_sid(&template_match.trustee).map(|(dn, _)| dn).unwrap_or(template_match.trustee.to_string()),
+                engine.describe_template_match(template_match));
+        }
+    }
+}
+
+// Connects to the given server, or to a global catalog of the current domain
+#[cfg(windows)]
+fn connect_ldap(args: &ArgMatches) -> Box<dyn DirectorySource> {
+    let server= args.value_of("server");
+    let port = args.value_of("port").expect("no port set");
+    let port = match port.parse::<u16>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            eprintln!("Unable to parse \"{}\" as TCP port", port);
+            std::process::exit(1);
+        }
+    };
+    let mut password = String::with_capacity(100);
+    let credentials = match (args.value_of("domain"),
+                             args.value_of("username"),
+                             args.value_of("password")) {
+        (Some(d), Some(u), None) | (Some(d), Some(u), Some("*")) => {
+            crate::utils::read_password(&mut password, &format!("Password for {}\\{}", d, u));
+            Some((d, u, password.as_str()))
+        },
+        (Some(d), Some(u), Some(p)) => Some((d, u, p)),
+        _ => None,
+    };
+
+    let (server, port) = if let Some(server) = server {
+        (server.to_owned(), port)
+    } else {
+        if let Some((server, port)) = utils::get_gc_domain_controller() {
+            (server, port)
+        } else {
+            eprintln!(" [!] Unable to find a domain controller automatically, please specify one manually using --server");
+            std::process::exit(1);
+        }
+    };
+
+    match LdapConnection::new(&server, port, credentials) {
+        Ok(c) => Box::new(c),
+        Err(e) => {
+            eprintln!("Unable to establish LDAP connection to \"{}:{}\" : {}", server, port, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// LDAP connections rely on the Windows LDAP client, only snapshots can be analysed elsewhere
+#[cfg(not(windows))]
+fn connect_ldap(_args: &ArgMatches) -> Box<dyn DirectorySource> {
+    eprintln!(" [!] Connecting to a domain controller is only supported on Windows, please analyse a snapshot using --snapshot");
+    std::process::exit(1);
+}
+
+fn get_remediation_selection(engine: &Engine, remediate_args: &ArgMatches) -> RemediationSelection {
+    let kinds = match remediate_args.values_of("action") {
+        Some(actions) => actions.map(|action| match action {
+            "remove-orphan-aces" => RemediationKind::RemoveOrphanAces,
+            "add-missing-aces" => RemediationKind::AddMissingAces,
+            "reset-owner" => RemediationKind::ResetOwner,
+            _ => RemediationKind::EnableInheritance,
+        }).collect(),
+        None => vec![RemediationKind::RemoveOrphanAces, RemediationKind::AddMissingAces, RemediationKind::ResetOwner, RemediationKind::EnableInheritance],
+    };
+    let trustees = remediate_args.values_of("trustee").map(|names| names.map(|name| match engine.resolve_str_to_sid(name) {
+        Some(sid) => sid,
+        None => {
+            eprintln!(" [!] Unable to find a trustee named {}", name);
+            std::process::exit(1);
+        }
+    }).collect()).unwrap_or_default();
+    let min_severity = match remediate_args.value_of("min_severity").unwrap_or("info") {
+        "low" => Severity::Low,
+        "medium" => Severity::Medium,
+        "high" => Severity::High,
+        "critical" => Severity::Critical,
+        _ => Severity::Info,
+    };
+    RemediationSelection {
+        kinds,
+        resources: remediate_args.values_of("resource").map(|dns| dns.map(|dn| dn.to_owned()).collect()).unwrap_or_default(),
+        trustees,
+        min_severity,
+    }
+}
+
+// Loads delegation templates, delegations and tier definitions given on the command line into the engine
+fn load_engine_inputs(engine: &mut Engine, args: &ArgMatches) {
+    if let Some(input_filepaths) = args.values_of("templates") {
+        for input_filepath in input_filepaths.into_iter() {
+            let json = match std::fs::read_to_string(input_filepath) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!(" [!] Unable to open template file {} : {}", input_filepath, e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = engine.load_template_json(&json) {
+                eprintln!(" [!] Unable to parse template file {} : {}", input_filepath, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(input_files) = args.values_of("delegations") {
+        for input_filepath in input_files.into_iter() {
+            let json = match std::fs::read_to_string(input_filepath) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!(" [!] Unable to open delegation file {} : {}", input_filepath, e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = engine.load_delegation_json(&json) {
+                eprintln!(" [!] Unable to parse delegation file {} : {}", input_filepath, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(input_files) = args.values_of("tiers") {
+        for input_filepath in input_files.into_iter() {
+            let json = match std::fs::read_to_string(input_filepath) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!(" [!] Unable to open tier file {} : {}", input_filepath, e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = engine.load_tier_json(&json) {
+                eprintln!(" [!] Unable to parse tier file {} : {}", input_filepath, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn open_output(path: &str, description: &str) -> Box<dyn std::io::Write> {
+    if path == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        match std::fs::OpenOptions::new().create(true).truncate(true).write(true).open(path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!(" [!] Unable to open output {} file {} : {}", description, path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn print_control_paths(engine: &Engine, paths_args: &ArgMatches, paths: &[ControlPath]) {
+    if let Some(json_path) = paths_args.value_of("json") {
+        let export = engine.export_control_paths(paths);
+        if let Err(e) = serde_json::to_writer_pretty(open_output(json_path, "JSON"), &export) {
+            eprintln!(" [!] Unable to write JSON file {} : {}", json_path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(csv_path) = paths_args.value_of("csv") {
+        let mut writer = csv::Writer::from_writer(open_output(csv_path, "CSV"));
+        writer.write_record([
+            "Source",
+            "Target",
+            "Length",
+            "Path",
+        ]).expect("unable to write CSV record");
+        for path in paths {
+            let steps: Vec<String> = path.steps.iter()
+                .map(|(edge, node)| format!("{} -> {}", engine.describe_control_edge(edge), engine.describe_control_node(node)))
+                .collect();
+            writer.write_record([
+                engine.describe_control_node(&path.source).as_str(),
+                &engine.describe_control_node(&path.target),
+                &path.steps.len().to_string(),
+                &steps.join(" ; "),
+            ]).expect("unable to write CSV record");
+        }
+    }
+    if paths_args.value_of("json").is_none() && paths_args.value_of("csv").is_none() {
+        let mut previous_target = None;
+        for path in paths {
+            if previous_target != Some(&path.target) {
+                println!("\n=== Paths to {}", engine.describe_control_node(&path.target));
+                previous_target = Some(&path.target);
+            }
+            println!("       {}", engine.describe_control_node(&path.source));
+            for (edge, node) in &path.steps {
+                println!("         -> {} : {}", engine.describe_control_edge(edge), engine.describe_control_node(node));
+            }
+        }
+        if paths.is_empty() {
+            println!("No control path found to these targets");
+        }
+    }
+    let _ = std::io::stdout().flush();
+}
+
+fn run_diff(args: &ArgMatches, diff_args: &ArgMatches) {
+    let load = |path: &str| match Snapshot::load(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!(" [!] Unable to load snapshot {} : {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let old_snapshot = load(diff_args.value_of("old").expect("no old snapshot set"));
+    let new_snapshot = load(diff_args.value_of("new").expect("no new snapshot set"));
+
+    let mut engines = vec![];
+    let mut results = vec![];
+    for snapshot in [&old_snapshot, &new_snapshot] {
+        let mut engine = Engine::new(snapshot, !args.is_present("show_raw"));
+        engine.load_delegation_json(engine::BUILTIN_ACES).expect("unable to parse builtin delegations");
+        load_engine_inputs(&mut engine, args);
+        match engine.run() {
+            Ok(res) => results.push(res),
+            Err(e) => {
+                eprintln!(" [!] Unable to scan for delegations: {}", e);
+                std::process::exit(1);
+            }
+        }
+        engines.push(engine);
+    }
+    let (old_engine, engine) = (&engines[0], &engines[1]);
+    let changes = diff_results(&results[0], &results[1]);
+    // Trustees which disappeared can only be resolved using the previous scan
+    let resolve_sid = |sid: &Sid| engine.resolve_sid(sid)
+        .or_else(|| old_engine.resolve_sid(sid))
+        .unwrap_or((sid.to_string(), PrincipalType::External));
+
+    if let Some(json_path) = diff_args.value_of("json") {
+        let output = open_output(json_path, "JSON");
+        if let Err(e) = serde_json::to_writer_pretty(output, &changes) {
+            eprintln!(" [!] Unable to write JSON file {} : {}", json_path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(csv_path) = diff_args.value_of("csv") {
+        let mut writer = csv::Writer::from_writer(open_output(csv_path, "CSV"));
+        writer.write_record([
+            "Resource",
+            "Trustee",
+            "Trustee type",
+            "Details",
+        ]).expect("unable to write CSV record");
+        for change in &changes {
+            let (dn, ptype) = match change.change.get_trustee() {
+                Some(sid) => resolve_sid(sid),
+                None => ("Global".to_owned(), PrincipalType::External),
+            };
+            writer.write_record([
+                change.location.to_string().as_str(),
+                &dn,
+                &ptype.to_string(),
+                &engine.describe_scan_change(&change.change),
+            ]).expect("unable to write CSV record");
+        }
+    }
+    if diff_args.value_of("json").is_none() && diff_args.value_of("csv").is_none() {
+        let mut previous_location = None;
+        for change in &changes {
+            if previous_location != Some(&change.location) {
+                println!("\n=== {}", &change.location);
+                previous_location = Some(&change.location);
+            }
+            match change.change.get_trustee() {
+                Some(sid) => println!("       {} : {}", resolve_sid(sid).0, engine.describe_scan_change(&change.change)),
+                None => println!("       {}", engine.describe_scan_change(&change.change)),
+            }
+        }
+        if changes.is_empty() {
+            println!("No change found between these two scans");
+        }
+    }
+    let _ = std::io::stdout().flush();
+}
@@ -0,0 +1,141 @@
+use core::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use authz::Sid;
+use winldap::error::LdapError;
+use winldap::search::LdapEntry;
+use crate::directory::SearchScope;
+use crate::engine::{Engine, PrincipalType};
+use crate::utils::{get_attr_sid, get_attr_sids, get_foreign_principal_sid};
+use winldap::utils::get_attr_strs;
+
+// Group memberships fetched so far, and effective members of each trustee expanded so far
+#[derive(Debug, Default)]
+pub(crate) struct MemberCache {
+    // Members of each group, as listed in its member attribute or with it as their primary group
+    direct_members: RefCell<HashMap<Sid, Vec<Sid>>>,
+    // Not set for trustees which are not groups
+    effective_members: RefCell<HashMap<Sid, Option<Rc<Vec<Sid>>>>>,
+    // Lowercase DN of each principal of the forest, built from SIDs resolved during the analysis
+    dn_to_sid: RefCell<Option<HashMap<String, Sid>>>,
+}
+
+impl<'a> Engine<'a> {
+    // Every user, computer, or principal which cannot be expanded (e.g. from another forest, or
+    // Authenticated Users) which is a member of a group trustee: directly, through nested groups from
+    // any domain of the forest, through foreign security principals, or through its primary group.
+    // Returns None for trustees which are not groups.
+    pub fn get_effective_members(&self, trustee: &Sid) -> Option<Rc<Vec<Sid>>> {
+        if let Some(members) = self.member_cache.effective_members.borrow().get(trustee) {
+            return members.clone();
+        }
+        if !self.is_group(trustee) {
+            self.member_cache.effective_members.borrow_mut().insert(trustee.clone(), None);
+            return None;
+        }
+        let mut members = vec![];
+        let mut visited = HashSet::from([trustee.clone()]);
+        let mut queue = vec![trustee.clone()];
+        while let Some(principal) = queue.pop() {
+            if &principal != trustee && !self.is_group(&principal) {
+                members.push(principal);
+                continue;
+            }
+            let direct_members = match self.fetch_direct_members(&principal) {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!(" [!] Unable to fetch members of {} : {}", self.resolve_sid(&principal).map(|(dn, _)| dn).unwrap_or(principal.to_string()), e);
+                    continue;
+                }
+            };
+            for member in direct_members {
+                if visited.insert(member.clone()) {
+                    queue.push(member);
+                }
+            }
+        }
+        members.sort_by_cached_key(|sid| self.resolve_sid(sid).map(|(dn, _)| dn).unwrap_or(sid.to_string()).to_lowercase());
+        let members = Some(Rc::new(members));
+        self.member_cache.effective_members.borrow_mut().insert(trustee.clone(), members.clone());
+        members
+    }
+
+    pub fn describe_effective_members(&self, trustee: &Sid) -> Option<String> {
+        self.get_effective_members(trustee).map(|members| match members.len() {
+            1 => "1 effective member".to_owned(),
+            n => format!("{} effective members", n),
+        })
+    }
+
+    // Name of a trustee, followed by its number of effective members if groups are expanded
+    pub fn describe_trustee_members(&self, trustee: &Sid) -> String {
+        let name = self.resolve_sid(trustee).map(|(dn, _)| dn).unwrap_or(trustee.to_string());
+        if !self.expand_groups {
+            return name;
+        }
+        match self.describe_effective_members(trustee) {
+            Some(members) => format!("{} ({})", name, members),
+            None => name,
+        }
+    }
+
+    // Groups expanded so far, along with their effective members
+    pub(crate) fn get_expanded_groups(&self) -> Vec<(Sid, Rc<Vec<Sid>>)> {
+        if !self.expand_groups {
+            return vec![];
+        }
+        self.member_cache.effective_members.borrow().iter()
+            .filter_map(|(group, members)| members.as_ref().map(|members| (group.clone(), members.clone())))
+            .collect()
+    }
+
+    fn is_group(&self, principal: &Sid) -> bool {
+        matches!(self.resolve_sid(principal), Some((_, PrincipalType::Group)))
+    }
+
+    fn fetch_direct_members(&self, group: &Sid) -> Result<Vec<Sid>, LdapError> {
+        if let Some(members) = self.member_cache.direct_members.borrow().get(group) {
+            return Ok(members.clone());
+        }
+        let mut members = vec![];
+        if let Some(entry) = self.directory.get_entry_by_sid(group, &["member"])? {
+            for dn in get_attr_strs(&[&entry], &entry.dn, "member").unwrap_or_default() {
+                if let Some(sid) = self.get_member_sid(&dn)? {
+                    members.push(sid);
+                }
+            }
+        }
+        // Primary group memberships are not listed in the member attribute
+        if let Some(domain) = self.domains.iter().find(|d| d.sid.with_rid(group.get_rid()) == *group) {
+            let search = self.directory.search(&domain.distinguished_name, SearchScope::Subtree,
+                Some(&format!("(primaryGroupID={})", group.get_rid())), &["objectSid"], None);
+            for entry in search {
+                let entry = entry?;
+                if let Ok(sid) = get_attr_sid(&[&entry], &entry.dn, "objectsid") {
+                    members.push(sid);
+                }
+            }
+        }
+        self.member_cache.direct_members.borrow_mut().insert(group.clone(), members.clone());
+        Ok(members)
+    }
+
+    fn get_member_sid(&self, dn: &str) -> Result<Option<Sid>, LdapError> {
+        if let Some(sid) = get_foreign_principal_sid(dn) {
+            return Ok(Some(sid));
+        }
+        if self.member_cache.dn_to_sid.borrow().is_none() {
+            let dn_to_sid = self.resolved_sid_to_dn.borrow().iter()
+                .map(|(sid, dn)| (dn.to_lowercase(), sid.clone()))
+                .collect();
+            *self.member_cache.dn_to_sid.borrow_mut() = Some(dn_to_sid);
+        }
+        if let Some(sid) = self.member_cache.dn_to_sid.borrow().as_ref().and_then(|m| m.get(&dn.to_lowercase())) {
+            return Ok(Some(sid.clone()));
+        }
+        // Principals which have not been seen during the analysis (e.g. built-in groups)
+        let res = self.directory.search(dn, SearchScope::Base, None, &["objectSid"], None)
+            .collect::<Result<Vec<LdapEntry>, LdapError>>()?;
+        Ok(get_attr_sids(&res, dn, "objectsid").ok().and_then(|sids| sids.into_iter().next()))
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use authz::{Ace, SecurityDescriptor, Sid};
+use serde::Serialize;
+use winldap::error::LdapError;
+use winldap::search::LdapEntry;
+use authz::{OWNER_SECURITY_INFORMATION, DACL_SECURITY_INFORMATION, INHERITED_ACE, SE_DACL_PROTECTED, SE_SACL_PRESENT};
+use crate::delegations::{DelegationLocation, DelegationRights};
+use crate::directory::SearchScope;
+use crate::engine::{AdelegResult, Engine};
+use crate::error::AdelegError;
+use crate::export::JsonTrustee;
+use crate::severity::Severity;
+use crate::utils::{ace_equivalent, ends_with_case_insensitive, get_attr_sd};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemediationKind {
+    RemoveOrphanAces,
+    AddMissingAces,
+    ResetOwner,
+    EnableInheritance,
+}
+
+#[derive(Debug, Clone)]
+pub enum RemediationAction {
+    RemoveAce(Ace),
+    // Missing ACE of a documented delegation
+    AddAce(DelegationRights, Ace),
+    // Previous owner, new owner
+    SetOwner(Sid, Sid),
+    EnableInheritance,
+}
+
+#[derive(Debug, Clone)]
+pub struct RemediationStep {
+    pub(crate) action: RemediationAction,
+    pub(crate) severity: Severity,
+}
+
+// Which findings should be fixed: empty resource and trustee lists select everything
+#[derive(Debug, Clone)]
+pub struct RemediationSelection {
+    pub(crate) kinds: Vec<RemediationKind>,
+    // DNs of subtrees to fix
+    pub(crate) resources: Vec<String>,
+    pub(crate) trustees: Vec<Sid>,
+    pub(crate) min_severity: Severity,
+}
+
+// Security descriptor of an object (owner and DACL only) as currently found, and as it would be
+// once every step has been applied
+#[derive(Debug, Clone)]
+pub struct RemediationObject {
+    pub(crate) dn: String,
+    pub(crate) current: SecurityDescriptor,
+    pub(crate) remediated: SecurityDescriptor,
+    pub(crate) steps: Vec<RemediationStep>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonRemediationAction {
+    RemoveAce,
+    AddAce,
+    SetOwner,
+    EnableInheritance,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonRemediationStep {
+    action: JsonRemediationAction,
+    severity: Severity,
+    description: String,
+    // Trustee of the ACE, or new owner
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trustee: Option<JsonTrustee>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ace: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonRemediationObject {
+    dn: String,
+    current_sddl: String,
+    remediated_sddl: String,
+    steps: Vec<JsonRemediationStep>,
+}
+
+impl<'a> Engine<'a> {
+    // Lists changes which fix the selected findings, object by object. Findings on default
+    // security descriptors are left out, since changing the schema needs more care than this.
+    pub fn plan_remediation(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>, selection: &RemediationSelection) -> Vec<RemediationObject> {
+        eprintln!(" [.] Planning remediation...");
+        let mut locations: Vec<(&String, &AdelegResult)> = res.iter()
+            .filter_map(|(location, res)| match (location, res) {
+                (DelegationLocation::Dn(dn), Ok(res)) => Some((dn, res)),
+                _ => None,
+            })
+            .filter(|(dn, _)| selection.resources.is_empty() || selection.resources.iter()
+                .any(|base| dn.eq_ignore_ascii_case(base) || ends_with_case_insensitive(dn, &format!(",{}", base))))
+            .collect();
+        locations.sort_by_key(|(dn, _)| DelegationLocation::Dn(dn.to_string()));
+
+        let mut objects = vec![];
+        for (dn, res) in locations {
+            let location = DelegationLocation::Dn(dn.clone());
+            let mut steps = vec![];
+            if selection.kinds.contains(&RemediationKind::RemoveOrphanAces) {
+                for ace in &res.orphan_aces {
+                    steps.push((Some(&ace.trustee), RemediationStep {
+                        action: RemediationAction::RemoveAce(ace.clone()),
+                        severity: self.get_ace_severity(&location, ace),
+                    }));
+                }
+            }
+            if selection.kinds.contains(&RemediationKind::AddMissingAces) {
+                for (delegation, trustee, _, aces_missing) in res.delegations.iter().filter(|(delegation, _, _, _)| !delegation.builtin) {
+                    for ace in aces_missing {
+                        steps.push((Some(trustee), RemediationStep {
+                            action: RemediationAction::AddAce(delegation.rights.clone(), ace.clone()),
+                            severity: Severity::Low,
+                        }));
+                    }
+                }
+            }
+            if selection.kinds.contains(&RemediationKind::ResetOwner) {
+                if let Some(owner) = &res.owner {
+                    steps.push((Some(owner), RemediationStep {
+                        action: RemediationAction::SetOwner(owner.clone(), self.get_domain_admins(dn)),
+                        severity: self.get_owner_severity(&location, owner),
+                    }));
+                }
+            }
+            if selection.kinds.contains(&RemediationKind::EnableInheritance) && res.dacl_protected {
+                steps.push((None, RemediationStep {
+                    action: RemediationAction::EnableInheritance,
+                    severity: Severity::Low,
+                }));
+            }
+            let steps: Vec<RemediationStep> = steps.into_iter()
+                .filter(|(trustee, step)| step.severity >= selection.min_severity &&
+                    (selection.trustees.is_empty() || trustee.map(|t| selection.trustees.contains(t)).unwrap_or(false)))
+                .map(|(_, step)| step)
+                .collect();
+            if steps.is_empty() {
+                continue;
+            }
+
+            let current = match self.fetch_owner_and_dacl(dn) {
+                Ok(sd) => sd,
+                Err(e) => {
+                    eprintln!(" [!] Unable to fetch security descriptor of {} , it will not be remediated: {}", dn, e);
+                    continue;
+                }
+            };
+            let remediated = apply_remediation_steps(&current, &steps);
+            objects.push(RemediationObject {
+                dn: dn.clone(),
+                current,
+                remediated,
+                steps,
+            });
+        }
+        objects
+    }
+
+    pub fn describe_remediation_step(&self, step: &RemediationStep) -> String {
+        let trustee_name = |sid: &Sid| self.resolve_sid(sid).map(|(dn, _)| dn).unwrap_or(sid.to_string());
+        let describe_ace = |ace: &Ace| format!("{}{}", self.describe_ace(
+            ace.access_mask,
+            ace.get_object_type(),
+            ace.get_inherited_object_type(),
+            ace.get_container_inherit(),
+            ace.get_inherit_only()
+        ), self.describe_ace_condition(ace));
+        match &step.action {
+            RemediationAction::RemoveAce(ace) => format!("Remove {} ACE for {}: {}",
+                if ace.grants_access() { "allow" } else { "deny" }, trustee_name(&ace.trustee), describe_ace(ace)),
+            RemediationAction::AddAce(rights, ace) => format!("Add missing {} ACE for {} from delegation \"{}\": {}",
+                if ace.grants_access() { "allow" } else { "deny" }, trustee_name(&ace.trustee), self.describe_delegation_rights(rights), describe_ace(ace)),
+            RemediationAction::SetOwner(previous, owner) => format!("Change owner from {} to {}", trustee_name(previous), trustee_name(owner)),
+            RemediationAction::EnableInheritance => "Re-enable inheritance of ACEs from the parent container".to_owned(),
+        }
+    }
+
+    pub fn export_remediation_plan(&self, objects: &[RemediationObject]) -> Vec<JsonRemediationObject> {
+        objects.iter().map(|object| JsonRemediationObject {
+            dn: object.dn.clone(),
+            current_sddl: object.current.to_sddl(),
+            remediated_sddl: object.remediated.to_sddl(),
+            steps: object.steps.iter().map(|step| {
+                let (action, trustee, ace) = match &step.action {
+                    RemediationAction::RemoveAce(ace) => (JsonRemediationAction::RemoveAce, Some(&ace.trustee), Some(ace.to_sddl())),
+                    RemediationAction::AddAce(_, ace) => (JsonRemediationAction::AddAce, Some(&ace.trustee), Some(ace.to_sddl())),
+                    RemediationAction::SetOwner(_, owner) => (JsonRemediationAction::SetOwner, Some(owner), None),
+                    RemediationAction::EnableInheritance => (JsonRemediationAction::EnableInheritance, None, None),
+                };
+                JsonRemediationStep {
+                    action,
+                    severity: step.severity,
+                    description: self.describe_remediation_step(step),
+                    trustee: trustee.map(|sid| self.export_trustee(sid)),
+                    ace,
+                }
+            }).collect(),
+        }).collect()
+    }
+
+    // Script which replaces the owner and DACL of each object, using the ActiveDirectory module
+    pub fn export_remediation_powershell(&self, objects: &[RemediationObject]) -> String {
+        // Windows PowerShell reads scripts without a byte order mark as ANSI, which breaks non-ASCII DNs
+        let mut script = String::from("\u{FEFF}");
+        script.push_str("# Remediation generated by adeleg: review every change before running this script\r\n");
+        script.push_str("# Each object gets its owner and DACL replaced, the previous ones are listed for rollback\r\n");
+        script.push_str("Import-Module ActiveDirectory\r\n");
+        script.push_str("$ErrorActionPreference = 'Stop'\r\n");
+        for object in objects {
+            script.push_str(&format!("\r\n# {}\r\n", object.dn));
+            for step in &object.steps {
+                script.push_str(&format!("#   [{}] {}\r\n", step.severity, self.describe_remediation_step(step)));
+            }
+            script.push_str(&format!("#   Previous owner and DACL: {}\r\n", object.current.to_sddl()));
+            let path = format!("AD:\\{}", object.dn).replace('\'', "''");
+            script.push_str(&format!("$acl = Get-Acl -LiteralPath '{}'\r\n", path));
+            script.push_str(&format!("$acl.SetSecurityDescriptorSddlForm('{}', 'Access, Owner')\r\n", object.remediated.to_sddl()));
+            script.push_str(&format!("Set-Acl -LiteralPath '{}' -AclObject $acl\r\n", path));
+        }
+        script
+    }
+
+    // LDIF modify records replacing the security descriptor of each object, as SDDL
+    pub fn export_remediation_ldif(&self, objects: &[RemediationObject]) -> String {
+        let mut ldif = String::new();
+        ldif.push_str("# Remediation generated by adeleg: review every change before importing this file\r\n");
+        ldif.push_str("version: 1\r\n");
+        for object in objects {
+            ldif.push_str(&format!("\r\n# {}\r\n", object.dn));
+            for step in &object.steps {
+                ldif.push_str(&format!("#   [{}] {}\r\n", step.severity, self.describe_remediation_step(step)));
+            }
+            ldif.push_str(&format!("#   Previous owner and DACL: {}\r\n", object.current.to_sddl()));
+            ldif.push_str(&format_ldif_value("dn", &object.dn));
+            ldif.push_str("changetype: modify\r\n");
+            ldif.push_str("replace: nTSecurityDescriptor\r\n");
+            ldif.push_str(&format_ldif_value("nTSecurityDescriptor", &object.remediated.to_sddl()));
+            ldif.push_str("-\r\n");
+        }
+        ldif
+    }
+
+    fn fetch_owner_and_dacl(&self, dn: &str) -> Result<SecurityDescriptor, LdapError> {
+        let search = self.directory.search(dn, SearchScope::Base, Some("(objectClass=*)"),
+            &["nTSecurityDescriptor"], Some(OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION));
+        let res = search.collect::<Result<Vec<LdapEntry>, LdapError>>()?;
+        let mut sd = get_attr_sd(&res[..], dn, "ntsecuritydescriptor")?;
+        // Only the owner and DACL are replaced
+        sd.group = None;
+        sd.sacl = None;
+        sd.controls &= !SE_SACL_PRESENT;
+        Ok(sd)
+    }
+
+    // Domain Admins of the domain holding an object, or of the forest root domain for objects
+    // outside of domain partitions
+    fn get_domain_admins(&self, dn: &str) -> Sid {
+        self.domains.iter()
+            .filter(|domain| ends_with_case_insensitive(dn, &domain.distinguished_name))
+            .max_by_key(|domain| domain.distinguished_name.len())
+            .unwrap_or(&self.root_domain)
+            .sid.with_rid(512)
+    }
+}
+
+fn apply_remediation_steps(current: &SecurityDescriptor, steps: &[RemediationStep]) -> SecurityDescriptor {
+    let mut sd = current.clone();
+    let mut aces = sd.dacl.take().map(|dacl| dacl.aces).unwrap_or_default();
+    let is_inherited = |ace: &Ace| (ace.flags & INHERITED_ACE) != 0;
+    for step in steps {
+        match &step.action {
+            RemediationAction::RemoveAce(ace) => aces.retain(|existing| is_inherited(existing) || !ace_equivalent(existing, ace)),
+            // Deny ACEs go first, allow ACEs go after other explicit ACEs, to keep the DACL canonical
+            RemediationAction::AddAce(_, ace) if !ace.grants_access() => aces.insert(0, ace.clone()),
+            RemediationAction::AddAce(_, ace) => {
+                let position = aces.iter().position(is_inherited).unwrap_or(aces.len());
+                aces.insert(position, ace.clone());
+            },
+            RemediationAction::SetOwner(_, owner) => sd.owner = Some(owner.clone()),
+            RemediationAction::EnableInheritance => sd.controls &= !SE_DACL_PROTECTED,
+        }
+    }
+    sd.dacl = Some(authz::Acl { aces });
+    sd
+}
+
+// Values which are not safe to write as is (non-ASCII, or starting with a space, colon or '<')
+// are written base64-encoded
+fn format_ldif_value(attribute: &str, value: &str) -> String {
+    let safe = value.bytes().all(|b| (0x20..0x7F).contains(&b)) &&
+        !value.starts_with([' ', ':', '<']) && !value.ends_with(' ');
+    if safe {
+        format!("{}: {}\r\n", attribute, value)
+    } else {
+        format!("{}:: {}\r\n", attribute, base64_encode(value.as_bytes()))
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestForest, ROOT_DOMAIN_DN};
+
+    #[test]
+    fn resources_are_matched_on_rdn_boundaries() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let sddl = format!("O:DAG:DAD:(A;;GA;;;DA)(A;;WP;;;{})", helpdesk);
+        let staff_dn = format!("OU=Staff,{}", ROOT_DOMAIN_DN);
+        let user_dn = format!("CN=Bob,{}", staff_dn);
+        // Its DN ends with the selected one, but it is not in that subtree
+        let former_staff_dn = format!("OU=Former OU=Staff,{}", ROOT_DOMAIN_DN);
+        for dn in [&staff_dn, &user_dn, &former_staff_dn] {
+            forest.add_object(dn, if dn == &user_dn { "user" } else { "organizationalUnit" }, &sddl, &[]);
+        }
+
+        let engine = forest.engine();
+        let res = engine.run().expect("analysis failed");
+        let selection = RemediationSelection {
+            kinds: vec![RemediationKind::RemoveOrphanAces],
+            resources: vec![staff_dn.to_uppercase()],
+            trustees: vec![],
+            min_severity: Severity::Info,
+        };
+        let dns: Vec<String> = engine.plan_remediation(&res, &selection).into_iter().map(|object| object.dn).collect();
+        assert_eq!(dns, vec![staff_dn, user_dn]);
+    }
+}
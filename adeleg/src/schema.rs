@@ -1,17 +1,17 @@
-use winldap::connection::LdapConnection;
 use winldap::error::LdapError;
-use winldap::search::LdapSearch;
 use winldap::utils::get_attr_str;
-use windows::Win32::Networking::Ldap::LDAP_SCOPE_SUBTREE;
 use authz::Guid;
 use std::collections::HashMap;
 use crate::utils::get_attr_guid;
+use crate::directory::{DirectorySource, SearchScope};
 
 pub struct Schema {
     // Mapping from class GUID to lowercase class name
     pub(crate) class_guids: HashMap<String, Guid>,
     // Mapping from attribute GUID to attribute name
     pub(crate) attribute_guids: HashMap<Guid, String>,
+    // Mapping from attribute GUID to the GUID of the property set it belongs to
+    pub(crate) attribute_property_sets: HashMap<Guid, Guid>,
     // Mapping from property set GUIDs to property set names
     pub(crate) property_set_names: HashMap<Guid, String>,
     // Mapping from validated write GUIDs to validated write names
@@ -21,21 +21,22 @@ pub struct Schema {
 }
 
 impl Schema {
-    pub fn query(conn: &LdapConnection) -> Result<Self, LdapError> {
+    pub fn query(directory: &dyn DirectorySource) -> Result<Self, LdapError> {
         let mut class_guids = HashMap::new();
         let mut attribute_guids = HashMap::new();
+        let mut attribute_property_sets = HashMap::new();
         let mut property_set_names = HashMap::new();
         let mut validated_write_names = HashMap::new();
         let mut control_access_names = HashMap::new();
 
         // Fetch classes
-        let search = LdapSearch::new(&conn, Some(conn.get_schema_naming_context()), LDAP_SCOPE_SUBTREE,
+        let search = directory.search(directory.get_schema_naming_context(), SearchScope::Subtree,
                                     Some("(objectClass=classSchema)"),
-                                    Some(&[
+                                    &[
                                         "schemaIDGUID",
                                         "lDAPDisplayName",
                                         "defaultSecurityDescriptor"
-                                    ]), &[]);
+                                    ], None);
         for entry in search {
             let entry = entry?;
             let guid = get_attr_guid(&[&entry], &entry.dn, "schemaidguid")?;
@@ -44,26 +45,31 @@ impl Schema {
         }
 
         // Fetch attribute types
-        let search = LdapSearch::new(&conn, Some(conn.get_schema_naming_context()), LDAP_SCOPE_SUBTREE,
+        let search = directory.search(directory.get_schema_naming_context(), SearchScope::Subtree,
                                     Some("(objectClass=attributeSchema)"),
-                                    Some(&[
+                                    &[
                                         "schemaIDGUID",
                                         "lDAPDisplayName",
-                                    ]), &[]);
+                                        "attributeSecurityGUID",
+                                    ], None);
         for entry in search {
             let entry = entry?;
             let guid = get_attr_guid(&[&entry], &entry.dn, "schemaidguid")?;
             let name = get_attr_str(&[&entry], &entry.dn, "ldapdisplayname")?;
+            // Most attributes are not part of any property set
+            if let Ok(property_set) = get_attr_guid(&[&entry], &entry.dn, "attributesecurityguid") {
+                attribute_property_sets.insert(guid, property_set);
+            }
             attribute_guids.insert(guid, name);
         }
 
         // Fetch property sets (validAccesses = READ_PROP | WRITE_PROP)
-        let search = LdapSearch::new(&conn, Some(conn.get_configuration_naming_context()), LDAP_SCOPE_SUBTREE,
+        let search = directory.search(directory.get_configuration_naming_context(), SearchScope::Subtree,
                                     Some("(&(objectClass=controlAccessRight)(validAccesses=48)(rightsGuid=*))"),
-                                    Some(&[
+                                    &[
                                         "rightsGuid",
                                         "displayName",
-                                    ]), &[]);
+                                    ], None);
         for entry in search {
             let entry = entry?;
             let guid = get_attr_str(&[&entry], &entry.dn, "rightsguid")?;
@@ -73,12 +79,12 @@ impl Schema {
         }
 
         // Fetch validated writes (validAccesses = SELF)
-        let search = LdapSearch::new(&conn, Some(conn.get_configuration_naming_context()), LDAP_SCOPE_SUBTREE,
+        let search = directory.search(directory.get_configuration_naming_context(), SearchScope::Subtree,
         Some("(&(objectClass=controlAccessRight)(validAccesses=8)(rightsGuid=*))"),
-        Some(&[
+        &[
             "rightsGuid",
             "displayName",
-        ]), &[]);
+        ], None);
         for entry in search {
             let entry = entry?;
             let guid = get_attr_str(&[&entry], &entry.dn, "rightsguid")?;
@@ -88,12 +94,12 @@ impl Schema {
         }
 
         // Fetch control access rights (validAccesses = CONTROL_ACCESS)
-        let search = LdapSearch::new(&conn, Some(conn.get_configuration_naming_context()), LDAP_SCOPE_SUBTREE,
+        let search = directory.search(directory.get_configuration_naming_context(), SearchScope::Subtree,
                                     Some("(&(objectClass=controlAccessRight)(validAccesses=256)(rightsGuid=*))"),
-                                    Some(&[
+                                    &[
                                         "rightsGuid",
                                         "displayName",
-                                    ]), &[]);
+                                    ], None);
         for entry in search {
             let entry = entry?;
             let guid = get_attr_str(&[&entry], &entry.dn, "rightsguid")?;
@@ -105,6 +111,7 @@ impl Schema {
         Ok(Self {
             class_guids,
             attribute_guids,
+            attribute_property_sets,
             property_set_names,
             validated_write_names,
             control_access_names,
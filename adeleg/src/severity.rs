@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+use core::fmt::Display;
+use serde::Serialize;
+use authz::{AccessMask, Ace, Guid, Sid};
+use winldap::error::LdapError;
+use winldap::utils::get_attr_str;
+use crate::audit::AuditIssue;
+use crate::delegations::DelegationLocation;
+use crate::directory::SearchScope;
+use crate::engine::{AdelegResult, Engine, IGNORED_ACCESS_RIGHTS};
+use crate::tiers::{TierControl, TierViolation};
+use crate::utils::get_parent_container;
+
+// Attributes whose write access is enough to take over their object (or every object the object
+// applies to, in the case of GPOs and their links)
+const TAKEOVER_ATTRIBUTES: &[&str] = &[
+    "bf9679c0-0de6-11d0-a285-00aa003049e2", // member
+    "5b47d60f-6090-40b2-9f37-2a4de88f3063", // msDS-KeyCredentialLink
+    "3f78c3e5-f79a-46bd-a0b8-9d18116ddc79", // msDS-AllowedToActOnBehalfOfOtherIdentity (RBCD)
+    "f30e3bbe-9ff0-11d1-b603-0000f80367c1", // gPLink
+    "f30e3bc1-9ff0-11d1-b603-0000f80367c1", // gPCFileSysPath
+];
+
+// Control accesses which are enough to take over their object (or the whole domain)
+const TAKEOVER_CONTROL_ACCESSES: &[&str] = &[
+    "1131f6ad-9c07-11d1-f79f-00c04fc2dcd2", // DS-Replication-Get-Changes-All
+    "00299570-246d-11d0-a768-00aa006e0529", // User-Force-Change-Password
+];
+
+// Trustees which (almost) every account in the forest is a member of
+const BROAD_TRUSTEES: &[&str] = &[
+    "S-1-1-0",      // Everyone
+    "S-1-5-7",      // Anonymous
+    "S-1-5-11",     // Authenticated Users
+    "S-1-5-32-545", // Users
+    "S-1-5-32-546", // Guests
+    "S-1-5-32-554", // Pre-Windows 2000 Compatible Access
+];
+const BROAD_DOMAIN_RIDS: &[u32] = &[
+    513, // Domain Users
+    514, // Domain Guests
+    515, // Domain Computers
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    fn from_score(score: u8) -> Self {
+        match score {
+            0 => Severity::Info,
+            1 => Severity::Low,
+            2 => Severity::Medium,
+            3 => Severity::High,
+            _ => Severity::Critical,
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => f.write_str("Info"),
+            Severity::Low => f.write_str("Low"),
+            Severity::Medium => f.write_str("Medium"),
+            Severity::High => f.write_str("High"),
+            Severity::Critical => f.write_str("Critical"),
+        }
+    }
+}
+
+impl<'a> Engine<'a> {
+    // Lists (lowercase) DNs of resources which grant control over a whole domain: naming context
+    // heads, AdminSDHolder, principals it protects, domain controllers, the containers holding them
+    // and group policies linked to these containers or above them.
+    pub(crate) fn get_sensitive_resources(&self) -> Result<HashSet<String>, LdapError> {
+        let mut res: HashSet<String> = self.naming_contexts.iter().map(|nc| nc.to_lowercase()).collect();
+        for domain in &self.domains {
+            let naming_context = &domain.distinguished_name;
+            res.insert(format!("CN=AdminSDHolder,CN=System,{}", naming_context).to_lowercase());
+
+            let search = self.directory.search(naming_context, SearchScope::Subtree,
+                Some("(|(adminCount=1)(primaryGroupID=516)(primaryGroupID=521)(gPLink=*))"),
+                &[
+                    "adminCount",
+                    "primaryGroupID",
+                    "gPLink",
+                ], None);
+            let mut domain_controllers = vec![];
+            let mut gplinks = vec![];
+            for entry in search {
+                let entry = entry?;
+                if get_attr_str(&[&entry], &entry.dn, "admincount").map(|v| v != "0").unwrap_or(false) {
+                    res.insert(entry.dn.to_lowercase());
+                }
+                if let Ok(primary_group) = get_attr_str(&[&entry], &entry.dn, "primarygroupid") {
+                    if primary_group == "516" || primary_group == "521" {
+                        res.insert(entry.dn.to_lowercase());
+                        domain_controllers.push(entry.dn.clone());
+                    }
+                }
+                if let Ok(gplink) = get_attr_str(&[&entry], &entry.dn, "gplink") {
+                    gplinks.push((entry.dn.to_lowercase(), gplink));
+                }
+            }
+
+            // Group policies apply to every object below the container they are linked to
+            let mut dc_ancestors = HashSet::new();
+            for dn in &domain_controllers {
+                let mut cursor = dn.as_str();
+                while let Some(parent) = get_parent_container(cursor, naming_context) {
+                    if cursor == dn {
+                        res.insert(parent.to_lowercase());
+                    }
+                    dc_ancestors.insert(parent.to_lowercase());
+                    cursor = parent;
+                }
+            }
+            for (dn, gplink) in gplinks {
+                if dc_ancestors.contains(&dn) {
+                    res.extend(parse_gplink(&gplink).into_iter().map(|(dn, _)| dn));
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    // Severity of an ACE found (or expected) on a resource, depending on the resource, the rights
+    // it grants, and how many principals it grants them to
+    pub fn get_ace_severity(&self, location: &DelegationLocation, ace: &Ace) -> Severity {
+        if !ace.grants_access() {
+            return Severity::Info;
+        }
+        // A condition restricts which of the trustee's members actually get these rights (and
+        // callback allow ACEs whose condition cannot be evaluated grant nothing), so a broad
+        // trustee behind a condition does not count as broad
+        let trustee_score = if is_conditional(ace) { 0 } else { self.get_trustee_score(&ace.trustee) };
+        Severity::from_score(self.get_resource_score(location) +
+            self.get_rights_score(ace) +
+            trustee_score)
+    }
+
+    // Owners implicitly have the right to modify the DACL of what they own
+    pub fn get_owner_severity(&self, location: &DelegationLocation, owner: &Sid) -> Severity {
+        Severity::from_score(self.get_resource_score(location) + 2 + self.get_trustee_score(owner))
+    }
+
+    // A documented delegation is as severe as the most severe access it grants
+    pub fn get_delegation_severity(&self, location: &DelegationLocation, aces_found: &[Ace], aces_missing: &[Ace]) -> Severity {
+        aces_found.iter()
+            .chain(aces_missing.iter())
+            .map(|ace| self.get_ace_severity(location, ace))
+            .max()
+            .unwrap_or(Severity::Info)
+    }
+
+    // Highest severity of everything which would be displayed for this resource
+    pub fn get_result_severity(&self, location: &DelegationLocation, res: &AdelegResult, show_builtin: bool) -> Severity {
+        let mut severity = Severity::Info;
+        if res.dacl_protected || !res.misplaced_aces.is_empty() || !res.redundant_aces.is_empty() || !res.unexpected_inherited_aces.is_empty() || !res.deleted_trustee.is_empty() {
+            severity = Severity::Low;
+        }
+        if let Some(owner) = &res.owner {
+            severity = severity.max(self.get_owner_severity(location, owner));
+        }
+        for ace in res.orphan_aces.iter().chain(res.unexpected_inherited_aces.iter()) {
+            severity = severity.max(self.get_ace_severity(location, ace));
+        }
+        for (delegation, _, aces_found, aces_missing) in &res.delegations {
+            if delegation.builtin && !show_builtin {
+                continue;
+            }
+            severity = severity.max(self.get_delegation_severity(location, aces_found, aces_missing));
+            if !aces_missing.is_empty() {
+                severity = severity.max(Severity::Low);
+            }
+        }
+        severity
+    }
+
+    pub fn get_audit_severity(&self, issue: &AuditIssue) -> Severity {
+        match issue {
+            AuditIssue::SaclUnreadable => Severity::Info,
+            AuditIssue::MissingAudit(_) => Severity::Medium,
+            AuditIssue::AddedToDefault(_) => Severity::Info,
+            AuditIssue::RemovedFromDefault(_) => Severity::Low,
+        }
+    }
+
+    // Control of a resource by a less privileged tier is at least as severe as the control itself,
+    // and breaks the whole tier model when it happens on tier 0
+    pub fn get_tier_violation_severity(&self, violation: &TierViolation) -> Severity {
+        let location = &violation.location;
+        let severity = match &violation.control {
+            TierControl::Owner => self.get_owner_severity(location, &violation.trustee),
+            TierControl::Ace(ace) => self.get_ace_severity(location, ace),
+            TierControl::Delegation(_, aces) => self.get_delegation_severity(location, aces, &[]),
+        };
+        severity.max(if violation.resource_tier == 0 { Severity::High } else { Severity::Medium })
+    }
+
+    // Whether an ACE grants enough rights to take over the resource it applies to
+    pub(crate) fn grants_takeover(&self, ace: &Ace) -> bool {
+        ace.grants_access() && self.get_rights_score(ace) == 2
+    }
+
+    // 2 for resources which grant control over a domain, 1 for those which apply to many objects
+    fn get_resource_score(&self, location: &DelegationLocation) -> u8 {
+        match location {
+            DelegationLocation::Dn(dn) if self.sensitive_resources.borrow().contains(&dn.to_lowercase()) => 2,
+            DelegationLocation::Dn(_) => 0,
+            DelegationLocation::DefaultSecurityDescriptor(_) | DelegationLocation::Global => 1,
+        }
+    }
+
+    // 2 for rights which are enough to take over the resource, 1 for other modifications
+    fn get_rights_score(&self, ace: &Ace) -> u8 {
+        let access_mask = ace.access_mask.map_generic() & !(IGNORED_ACCESS_RIGHTS | AccessMask::UNDEFINED);
+        if access_mask.is_empty() {
+            return 0;
+        }
+        if access_mask.intersects(AccessMask::WRITE_DAC | AccessMask::WRITE_OWNER) {
+            return 2;
+        }
+        let object_type = ace.get_object_type();
+        if access_mask.intersects(AccessMask::WRITE_PROP | AccessMask::SELF) && self.is_takeover_attribute(object_type) {
+            return 2;
+        }
+        if access_mask.contains(AccessMask::CONTROL_ACCESS) {
+            match object_type {
+                None => return 2,
+                Some(guid) if TAKEOVER_CONTROL_ACCESSES.iter().any(|s| Guid::try_from(*s).ok().as_ref() == Some(guid)) => return 2,
+                _ => (),
+            }
+        }
+        1
+    }
+
+    // Writing all attributes, or a property set which contains one of the takeover attributes,
+    // includes writing that attribute
+    fn is_takeover_attribute(&self, object_type: Option<&Guid>) -> bool {
+        let guid = match object_type {
+            Some(guid) => guid,
+            None => return true,
+        };
+        TAKEOVER_ATTRIBUTES.iter()
+            .filter_map(|s| Guid::try_from(*s).ok())
+            .any(|attribute| &attribute == guid || self.schema.attribute_property_sets.get(&attribute) == Some(guid))
+    }
+
+    fn get_trustee_score(&self, trustee: &Sid) -> u8 {
+        let broad = BROAD_TRUSTEES.iter().any(|s| Sid::try_from(*s).ok().as_ref() == Some(trustee)) ||
+            self.domains.iter().any(|domain| BROAD_DOMAIN_RIDS.iter().any(|rid| &domain.sid.with_rid(*rid) == trustee));
+        if broad { 1 } else { 0 }
+    }
+}
+
+fn is_conditional(ace: &Ace) -> bool {
+    ace.get_application_data().map(|data| !data.is_empty()).unwrap_or(false)
+}
+
+// Returns the (lowercase) DNs of group policies enabled in a gPLink value, along with whether they
+// are enforced. Values are formatted as [LDAP://cn={GUID},cn=policies,cn=system,DC=example,DC=com;0][LDAP://...;2]
+pub(crate) fn parse_gplink(gplink: &str) -> Vec<(String, bool)> {
+    gplink.split(['[', ']'])
+        .filter_map(|link| link.split_once(';'))
+        .map(|(path, options)| (path, options.parse::<u32>().unwrap_or(0)))
+        .filter(|(_, options)| (options & 1) == 0)
+        .filter_map(|(path, options)| {
+            let path = path.to_lowercase();
+            path.strip_prefix("ldap://").map(|dn| (dn.to_owned(), (options & 2) != 0))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use authz::SecurityDescriptor;
+    use crate::testing::{TestForest, DEFAULT_SDDL, ROOT_DOMAIN_DN};
+
+    const MEMBERSHIP: &str = "bc0ac240-79a9-11d0-9020-00c04fc2d4cf";
+    const PERSONAL_INFORMATION: &str = "77b5b886-944a-11d1-aebd-0000f80367c1";
+    const DS_REPLICATION_GET_CHANGES_ALL: &str = "1131f6ad-9c07-11d1-f79f-00c04fc2dcd2";
+
+    fn get_severity(forest: &TestForest, dn: &str, ace_sddl: &str) -> Severity {
+        let engine = forest.engine();
+        engine.run().expect("analysis failed");
+        let sd = SecurityDescriptor::from_str(&format!("D:{}", ace_sddl), &forest.domain_sid, &forest.domain_sid).expect("invalid SDDL");
+        let ace = &sd.dacl.expect("no DACL").aces[0];
+        engine.get_ace_severity(&DelegationLocation::Dn(dn.to_owned()), ace)
+    }
+
+    #[test]
+    fn takeover_attributes_through_property_sets() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let group_dn = format!("CN=Server Admins,{}", ROOT_DOMAIN_DN);
+        forest.add_principal(&group_dn, "group", 1102, &[]);
+
+        // Writing the property set which contains member is as bad as writing member itself
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;WP;{};;{})", MEMBERSHIP, helpdesk)), Severity::Medium);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;WP;bf9679c0-0de6-11d0-a285-00aa003049e2;;{})", helpdesk)), Severity::Medium);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(A;;WP;;;{})", helpdesk)), Severity::Medium);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;WP;{};;{})", PERSONAL_INFORMATION, helpdesk)), Severity::Low);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;RP;{};;{})", MEMBERSHIP, helpdesk)), Severity::Info);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OD;;WP;{};;{})", MEMBERSHIP, helpdesk)), Severity::Info);
+    }
+
+    #[test]
+    fn dcsync_rights_on_domain_heads() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        assert_eq!(get_severity(&forest, ROOT_DOMAIN_DN, &format!("(OA;;CR;{};;{})", DS_REPLICATION_GET_CHANGES_ALL, helpdesk)), Severity::Critical);
+        // Other control accesses on the domain head only allow modifications
+        assert_eq!(get_severity(&forest, ROOT_DOMAIN_DN, &format!("(OA;;CR;ab721a53-1e2f-11d0-9819-00aa0040529b;;{})", helpdesk)), Severity::High);
+        let ou_dn = format!("OU=Staff,{}", ROOT_DOMAIN_DN);
+        forest.add_object(&ou_dn, "organizationalUnit", DEFAULT_SDDL, &[]);
+        assert_eq!(get_severity(&forest, &ou_dn, &format!("(OA;;CR;{};;{})", DS_REPLICATION_GET_CHANGES_ALL, helpdesk)), Severity::Medium);
+    }
+
+    #[test]
+    fn broad_trustees_raise_severity_unless_restricted_by_a_condition() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        let group_dn = format!("CN=Server Admins,{}", ROOT_DOMAIN_DN);
+        forest.add_principal(&group_dn, "group", 1102, &[]);
+        let domain_users = forest.domain_sid.with_rid(513);
+
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;WP;{};;{})", MEMBERSHIP, helpdesk)), Severity::Medium);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;WP;{};;AU)", MEMBERSHIP)), Severity::High);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;WP;{};;WD)", MEMBERSHIP)), Severity::High);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(OA;;WP;{};;{})", MEMBERSHIP, domain_users)), Severity::High);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(ZA;;WP;{};;AU;(Member_of {{SID({})}}))", MEMBERSHIP, helpdesk)), Severity::Medium);
+        assert_eq!(get_severity(&forest, &group_dn, &format!("(XA;;WP;;;{};(Member_of {{SID({})}}))", domain_users, helpdesk)), Severity::Medium);
+    }
+}
@@ -0,0 +1,331 @@
+use core::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use authz::Sid;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Serialize, Deserialize};
+use winldap::error::LdapError;
+use winldap::search::LdapEntry;
+use crate::directory::{DirectorySource, DirectorySearch, SearchScope};
+use crate::error::AdelegError;
+
+// Bumped each time the format changes in a way older versions cannot read
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// Written before the compressed contents, so that other files are rejected early
+const SNAPSHOT_MAGIC: &[u8; 8] = b"ADELEGSN";
+
+// LDAP_NO_SUCH_OBJECT result code, returned for searches which were not recorded
+const LDAP_NO_SUCH_OBJECT: u32 = 0x20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct SearchKey {
+    base: String,
+    scope: u8,
+    filter: Option<String>,
+    attributes: Vec<String>,
+    sd_flags: Option<u32>,
+}
+
+impl SearchKey {
+    fn new(base: &str, scope: SearchScope, filter: Option<&str>, attributes: &[&str], sd_flags: Option<u32>) -> Self {
+        Self {
+            base: base.to_lowercase(),
+            scope: match scope {
+                SearchScope::Base => 0,
+                SearchScope::OneLevel => 1,
+                SearchScope::Subtree => 2,
+            },
+            filter: filter.map(|f| f.to_owned()),
+            attributes: attributes.iter().map(|a| a.to_lowercase()).collect(),
+            sd_flags,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotEntry {
+    dn: String,
+    attrs: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl From<&LdapEntry> for SnapshotEntry {
+    fn from(entry: &LdapEntry) -> Self {
+        Self {
+            dn: entry.dn.clone(),
+            attrs: entry.attrs.clone(),
+        }
+    }
+}
+
+impl From<&SnapshotEntry> for LdapEntry {
+    fn from(entry: &SnapshotEntry) -> Self {
+        Self {
+            dn: entry.dn.clone(),
+            attrs: entry.attrs.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedSearch {
+    key: SearchKey,
+    entries: Vec<SnapshotEntry>,
+    // Set if the search failed after returning these entries, with the LDAP error code if any
+    failed: Option<u32>,
+}
+
+// Everything read from a forest during one analysis, which can be replayed later without any
+// domain controller. Searches are replayed as they were recorded: analysing a snapshot with
+// delegation files referencing principals not looked up during capture will not resolve them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    version: u32,
+    naming_contexts: Vec<String>,
+    root_domain_naming_context: String,
+    schema_naming_context: String,
+    configuration_naming_context: String,
+    searches: Vec<RecordedSearch>,
+    entries_by_sid: Vec<(Sid, Option<SnapshotEntry>)>,
+    token_groups: Vec<(Sid, Vec<Sid>)>,
+    #[serde(skip)]
+    search_index: HashMap<SearchKey, usize>,
+}
+
+impl Snapshot {
+    pub fn load(path: &str) -> Result<Self, AdelegError> {
+        let file = std::fs::File::open(path).map_err(|e| AdelegError::SnapshotIo(e.to_string()))?;
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(|e| AdelegError::SnapshotIo(e.to_string()))?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(AdelegError::SnapshotIo("not an adeleg snapshot file".to_owned()));
+        }
+        let mut snapshot: Snapshot = serde_json::from_reader(GzDecoder::new(reader))
+            .map_err(|e| AdelegError::JsonParsing(e.to_string()))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(AdelegError::UnsupportedSnapshotVersion(snapshot.version));
+        }
+        snapshot.search_index = snapshot.searches.iter()
+            .enumerate()
+            .map(|(i, search)| (search.key.clone(), i))
+            .collect();
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), AdelegError> {
+        let file = std::fs::OpenOptions::new().create(true).truncate(true).write(true).open(path)
+            .map_err(|e| AdelegError::SnapshotIo(e.to_string()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(SNAPSHOT_MAGIC).map_err(|e| AdelegError::SnapshotIo(e.to_string()))?;
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        serde_json::to_writer(&mut encoder, self).map_err(|e| AdelegError::JsonParsing(e.to_string()))?;
+        encoder.finish()
+            .and_then(|mut writer| writer.flush())
+            .map_err(|e| AdelegError::SnapshotIo(e.to_string()))
+    }
+}
+
+impl DirectorySource for Snapshot {
+    fn get_naming_contexts(&self) -> &[String] {
+        &self.naming_contexts
+    }
+
+    fn get_root_domain_naming_context(&self) -> &str {
+        &self.root_domain_naming_context
+    }
+
+    fn get_schema_naming_context(&self) -> &str {
+        &self.schema_naming_context
+    }
+
+    fn get_configuration_naming_context(&self) -> &str {
+        &self.configuration_naming_context
+    }
+
+    fn search<'a>(&'a self, base: &str, scope: SearchScope, filter: Option<&str>, attributes: &[&str], sd_flags: Option<u32>) -> DirectorySearch<'a> {
+        let key = SearchKey::new(base, scope, filter, attributes, sd_flags);
+        let error = |code| LdapError::SearchFailed {
+            base: Some(base.to_owned()),
+            filter: filter.map(|f| f.to_owned()),
+            only_attributes: Some(attributes.iter().map(|a| a.to_string()).collect()),
+            code,
+        };
+        let recorded = match self.search_index.get(&key) {
+            Some(i) => &self.searches[*i],
+            None => return Box::new(std::iter::once(Err(error(LDAP_NO_SUCH_OBJECT)))),
+        };
+        let failure = recorded.failed.map(|code| Err(error(code)));
+        Box::new(recorded.entries.iter()
+            .map(|entry| Ok(LdapEntry::from(entry)))
+            .chain(failure))
+    }
+
+    fn get_entry_by_sid(&self, sid: &Sid, attributes: &[&str]) -> Result<Option<LdapEntry>, LdapError> {
+        let entry = self.entries_by_sid.iter()
+            .find(|(recorded_sid, _)| recorded_sid == sid)
+            .and_then(|(_, entry)| entry.as_ref());
+        Ok(entry.map(|entry| {
+            let attrs = attributes.iter()
+                .filter_map(|name| entry.attrs.get_key_value(&name.to_lowercase()))
+                .map(|(name, values)| (name.clone(), values.clone()))
+                .collect();
+            LdapEntry {
+                dn: entry.dn.clone(),
+                attrs,
+            }
+        }))
+    }
+
+    fn get_token_groups(&self, principal: &Sid) -> Result<Vec<Sid>, LdapError> {
+        Ok(self.token_groups.iter()
+            .find(|(sid, _)| sid == principal)
+            .map(|(_, groups)| groups.clone())
+            .unwrap_or_default())
+    }
+}
+
+// Forwards every request to another directory, and keeps a copy of every result so that they
+// can be saved as a snapshot
+pub struct RecordingDirectory<'a> {
+    inner: &'a dyn DirectorySource,
+    searches: RefCell<Vec<RecordedSearch>>,
+    search_index: RefCell<HashMap<SearchKey, usize>>,
+    entries_by_sid: RefCell<HashMap<Sid, Option<SnapshotEntry>>>,
+    token_groups: RefCell<HashMap<Sid, Vec<Sid>>>,
+}
+
+impl<'a> RecordingDirectory<'a> {
+    pub fn new(inner: &'a dyn DirectorySource) -> Self {
+        Self {
+            inner,
+            searches: RefCell::new(vec![]),
+            search_index: RefCell::new(HashMap::new()),
+            entries_by_sid: RefCell::new(HashMap::new()),
+            token_groups: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn to_snapshot(&self) -> Snapshot {
+        let searches = self.searches.borrow().clone();
+        let search_index = self.search_index.borrow().clone();
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            naming_contexts: self.inner.get_naming_contexts().to_vec(),
+            root_domain_naming_context: self.inner.get_root_domain_naming_context().to_owned(),
+            schema_naming_context: self.inner.get_schema_naming_context().to_owned(),
+            configuration_naming_context: self.inner.get_configuration_naming_context().to_owned(),
+            searches,
+            entries_by_sid: self.entries_by_sid.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            token_groups: self.token_groups.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            search_index,
+        }
+    }
+}
+
+impl<'a> DirectorySource for RecordingDirectory<'a> {
+    fn get_naming_contexts(&self) -> &[String] {
+        self.inner.get_naming_contexts()
+    }
+
+    fn get_root_domain_naming_context(&self) -> &str {
+        self.inner.get_root_domain_naming_context()
+    }
+
+    fn get_schema_naming_context(&self) -> &str {
+        self.inner.get_schema_naming_context()
+    }
+
+    fn get_configuration_naming_context(&self) -> &str {
+        self.inner.get_configuration_naming_context()
+    }
+
+    fn search<'b>(&'b self, base: &str, scope: SearchScope, filter: Option<&str>, attributes: &[&str], sd_flags: Option<u32>) -> DirectorySearch<'b> {
+        let key = SearchKey::new(base, scope, filter, attributes, sd_flags);
+        // The same search run twice replaces its previous recording, instead of appending to it
+        let index = {
+            let mut searches = self.searches.borrow_mut();
+            let mut search_index = self.search_index.borrow_mut();
+            let recorded = RecordedSearch {
+                key: key.clone(),
+                entries: vec![],
+                failed: None,
+            };
+            match search_index.get(&key) {
+                Some(i) => {
+                    searches[*i] = recorded;
+                    *i
+                },
+                None => {
+                    searches.push(recorded);
+                    search_index.insert(key, searches.len() - 1);
+                    searches.len() - 1
+                },
+            }
+        };
+        Box::new(self.inner.search(base, scope, filter, attributes, sd_flags).inspect(move |res| {
+            let mut searches = self.searches.borrow_mut();
+            match res {
+                Ok(entry) => searches[index].entries.push(SnapshotEntry::from(entry)),
+                Err(LdapError::SearchFailed { code, .. }) => searches[index].failed = Some(*code),
+                Err(_) => searches[index].failed = Some(0),
+            }
+        }))
+    }
+
+    fn get_entry_by_sid(&self, sid: &Sid, attributes: &[&str]) -> Result<Option<LdapEntry>, LdapError> {
+        let res = self.inner.get_entry_by_sid(sid, attributes)?;
+        let mut entries_by_sid = self.entries_by_sid.borrow_mut();
+        let recorded = entries_by_sid.entry(sid.clone()).or_insert(None);
+        if let Some(entry) = &res {
+            // Merge attributes, since the same principal may be looked up for different ones
+            let recorded = recorded.get_or_insert_with(|| SnapshotEntry {
+                dn: entry.dn.clone(),
+                attrs: HashMap::new(),
+            });
+            recorded.attrs.extend(entry.attrs.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        Ok(res)
+    }
+
+    fn get_token_groups(&self, principal: &Sid) -> Result<Vec<Sid>, LdapError> {
+        let groups = self.inner.get_token_groups(principal)?;
+        self.token_groups.borrow_mut().insert(principal.clone(), groups.clone());
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::testing::{TestForest, ROOT_DOMAIN_DN};
+
+    #[test]
+    fn replayed_snapshots_give_the_same_results() {
+        let mut forest = TestForest::new();
+        let helpdesk = forest.add_principal(&format!("CN=Helpdesk,{}", ROOT_DOMAIN_DN), "group", 1101, &[]);
+        forest.add_object(&format!("OU=Workstations,{}", ROOT_DOMAIN_DN), "organizationalUnit",
+            &format!("O:DAG:DAD:P(A;;GA;;;DA)(A;;WP;;;{})", helpdesk), &[]);
+
+        let recorder = RecordingDirectory::new(&forest.directory);
+        let recorded = Engine::new(&recorder, true).run().expect("analysis failed");
+        let path = std::env::temp_dir().join(format!("adeleg-test-{}.snapshot", std::process::id()));
+        let path = path.to_str().expect("invalid temporary path");
+        recorder.to_snapshot().save(path).expect("unable to save snapshot");
+        let snapshot = Snapshot::load(path);
+        std::fs::remove_file(path).expect("unable to remove snapshot");
+        let snapshot = snapshot.expect("unable to load snapshot");
+        let replayed = Engine::new(&snapshot, true).run().expect("replay failed");
+
+        let describe = |res: &HashMap<_, Result<_, AdelegError>>| {
+            let mut res: Vec<String> = res.iter().map(|(location, res)| format!("{:?} {:?}", location, res)).collect();
+            res.sort();
+            res
+        };
+        assert!(recorded.values().any(|res| res.as_ref().map(|res| !res.orphan_aces.is_empty()).unwrap_or(false)));
+        assert_eq!(describe(&recorded), describe(&replayed));
+    }
+}
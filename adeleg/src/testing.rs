@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use authz::{Guid, SecurityDescriptor, Sid};
+use authz::{OWNER_SECURITY_INFORMATION, GROUP_SECURITY_INFORMATION, DACL_SECURITY_INFORMATION, SACL_SECURITY_INFORMATION};
+use winldap::error::LdapError;
+use winldap::search::LdapEntry;
+use crate::directory::{DirectorySearch, DirectorySource, SearchScope};
+use crate::engine::Engine;
+use crate::utils::ends_with_case_insensitive;
+
+pub(crate) const ROOT_DOMAIN_DN: &str = "DC=corp,DC=local";
+pub(crate) const CONFIGURATION_DN: &str = "CN=Configuration,DC=corp,DC=local";
+pub(crate) const SCHEMA_DN: &str = "CN=Schema,CN=Configuration,DC=corp,DC=local";
+
+// Owner and DACL of objects which should not show up in results
+pub(crate) const DEFAULT_SDDL: &str = "O:DAG:DAD:(A;;GA;;;DA)";
+
+// Classes known to the synthetic schema, along with their schemaIDGUID
+const CLASSES: &[(&str, &str)] = &[
+    ("attributeSchema", "bf967a80-0de6-11d0-a285-00aa003049e2"),
+    ("classSchema", "bf967a83-0de6-11d0-a285-00aa003049e2"),
+    ("computer", "bf967a86-0de6-11d0-a285-00aa003049e2"),
+    ("configuration", "bf967a87-0de6-11d0-a285-00aa003049e2"),
+    ("container", "bf967a8b-0de6-11d0-a285-00aa003049e2"),
+    ("controlAccessRight", "8297931e-86d3-11d0-afda-00c04fd930c9"),
+    ("crossRef", "bf967a8d-0de6-11d0-a285-00aa003049e2"),
+    ("dMD", "bf967a8f-0de6-11d0-a285-00aa003049e2"),
+    ("domainDNS", "19195a5b-6da0-11d0-afd3-00c04fd930c9"),
+    ("group", "bf967a9c-0de6-11d0-a285-00aa003049e2"),
+    ("msKds-ProvRootKey", "aa02fd41-17e0-4f18-8687-b2239649736b"),
+    ("nTDSConnection", "19195a60-6da0-11d0-afd3-00c04fd930c9"),
+    ("nTDSDSA", "f0f8ffab-1191-11d0-a060-00aa006c33ed"),
+    ("organizationalUnit", "bf967aa5-0de6-11d0-a285-00aa003049e2"),
+    ("server", "bf967a92-0de6-11d0-a285-00aa003049e2"),
+    ("user", "bf967aba-0de6-11d0-a285-00aa003049e2"),
+];
+
+// Control access rights known to the synthetic forest: name, rightsGuid, validAccesses
+const CONTROL_ACCESS_RIGHTS: &[(&str, &str, &str)] = &[
+    ("Change Password", "ab721a53-1e2f-11d0-9819-00aa0040529b", "256"),
+    ("Reset Password", "00299570-246d-11d0-a768-00aa006e0529", "256"),
+    ("Validated write to DNS host name", "72e39547-7b18-11d1-adef-00c04fd8d5cd", "8"),
+    ("Personal Information", "77b5b886-944a-11d1-aebd-0000f80367c1", "48"),
+    ("Membership", "bc0ac240-79a9-11d0-9020-00c04fc2d4cf", "48"),
+];
+
+// Attributes known to the synthetic schema: name, schemaIDGUID, and property set if any
+const ATTRIBUTES: &[(&str, &str, Option<&str>)] = &[
+    ("schedule", "dd712224-10e4-11d0-a05f-00aa006c33ed", None),
+    ("fromServer", "bf967979-0de6-11d0-a285-00aa003049e2", None),
+    ("telephoneNumber", "bf967a49-0de6-11d0-a285-00aa003049e2", Some("77b5b886-944a-11d1-aebd-0000f80367c1")),
+    ("member", "bf9679c0-0de6-11d0-a285-00aa003049e2", Some("bc0ac240-79a9-11d0-9020-00c04fc2d4cf")),
+];
+
+// Forest with a single domain, its configuration and schema partitions, and the few classes,
+// attributes and control access rights the engine needs
+pub(crate) struct TestForest {
+    pub(crate) directory: InMemoryDirectory,
+    pub(crate) domain_sid: Sid,
+}
+
+impl TestForest {
+    pub(crate) fn new() -> Self {
+        let mut forest = Self {
+            directory: InMemoryDirectory::new(ROOT_DOMAIN_DN),
+            domain_sid: Sid::try_from("S-1-5-21-1000-2000-3000").expect("invalid SID"),
+        };
+        let domain_sid = forest.domain_sid.to_bytes();
+        forest.add_object(ROOT_DOMAIN_DN, "domainDNS", DEFAULT_SDDL, &[("objectSid", &domain_sid)]);
+        forest.add_object(&format!("CN=System,{}", ROOT_DOMAIN_DN), "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&format!("CN=AdminSDHolder,CN=System,{}", ROOT_DOMAIN_DN), "container", "O:DAG:DAD:P(A;;GA;;;DA)", &[]);
+        forest.add_object(CONFIGURATION_DN, "configuration", DEFAULT_SDDL, &[]);
+        forest.add_object(&format!("CN=Partitions,{}", CONFIGURATION_DN), "container", DEFAULT_SDDL, &[]);
+        forest.add_object(&format!("CN=CORP,CN=Partitions,{}", CONFIGURATION_DN), "crossRef", DEFAULT_SDDL, &[
+            ("nCName", ROOT_DOMAIN_DN.as_bytes()),
+            ("nETBIOSName", b"CORP"),
+        ]);
+        forest.add_object(&format!("CN=Extended-Rights,{}", CONFIGURATION_DN), "container", DEFAULT_SDDL, &[]);
+        for (name, guid, valid_accesses) in CONTROL_ACCESS_RIGHTS {
+            forest.add_object(&format!("CN={},CN=Extended-Rights,{}", name, CONFIGURATION_DN), "controlAccessRight", DEFAULT_SDDL, &[
+                ("displayName", name.as_bytes()),
+                ("rightsGuid", guid.as_bytes()),
+                ("validAccesses", valid_accesses.as_bytes()),
+            ]);
+        }
+        forest.add_object(SCHEMA_DN, "dMD", DEFAULT_SDDL, &[]);
+        for (name, guid) in CLASSES {
+            let guid = Guid::try_from(*guid).expect("invalid GUID").to_bytes();
+            forest.add_object(&format!("CN={},{}", name, SCHEMA_DN), "classSchema", DEFAULT_SDDL, &[
+                ("lDAPDisplayName", name.as_bytes()),
+                ("schemaIDGUID", &guid),
+            ]);
+        }
+        for (name, guid, property_set) in ATTRIBUTES {
+            let guid = Guid::try_from(*guid).expect("invalid GUID").to_bytes();
+            let property_set = property_set.map(|g| Guid::try_from(g).expect("invalid GUID").to_bytes());
+            let mut attrs: Vec<(&str, &[u8])> = vec![("lDAPDisplayName", name.as_bytes()), ("schemaIDGUID", &guid)];
+            if let Some(property_set) = &property_set {
+                attrs.push(("attributeSecurityGUID", property_set));
+            }
+            forest.add_object(&format!("CN={},{}", name, SCHEMA_DN), "attributeSchema", DEFAULT_SDDL, &attrs);
+        }
+        forest
+    }
+
+    // Security descriptor in binary form, with domain-relative aliases (e.g. DA) resolved in the domain
+    pub(crate) fn sd(&self, sddl: &str) -> Vec<u8> {
+        SecurityDescriptor::from_str(sddl, &self.domain_sid, &self.domain_sid)
+            .expect("invalid SDDL")
+            .to_bytes()
+    }
+
+    pub(crate) fn add_object(&mut self, dn: &str, class: &str, sddl: &str, attrs: &[(&str, &[u8])]) {
+        let mut entry = LdapEntry {
+            dn: dn.to_owned(),
+            attrs: HashMap::new(),
+        };
+        entry.attrs.insert("objectClass".to_owned(), vec![b"top".to_vec(), class.as_bytes().to_vec()]);
+        entry.attrs.insert("nTSecurityDescriptor".to_owned(), vec![self.sd(sddl)]);
+        for (name, value) in attrs {
+            entry.attrs.entry(name.to_string()).or_default().push(value.to_vec());
+        }
+        self.directory.add_entry(entry);
+    }
+
+    pub(crate) fn set_security_descriptor(&mut self, dn: &str, sddl: &str) {
+        let sd = self.sd(sddl);
+        let entry = self.directory.entries.iter_mut()
+            .find(|entry| entry.dn.eq_ignore_ascii_case(dn))
+            .expect("object not found");
+        entry.attrs.insert("ntsecuritydescriptor".to_owned(), vec![sd]);
+    }
+
+    // Adds a user, computer or group with the given RID in the domain, and returns its SID
+    pub(crate) fn add_principal(&mut self, dn: &str, class: &str, rid: u32, attrs: &[(&str, &[u8])]) -> Sid {
+        let sid = self.domain_sid.with_rid(rid);
+        let sid_bytes = sid.to_bytes();
+        let mut attrs = attrs.to_vec();
+        attrs.push(("objectSid", &sid_bytes));
+        self.add_object(dn, class, DEFAULT_SDDL, &attrs);
+        sid
+    }
+
+    pub(crate) fn engine(&self) -> Engine<'_> {
+        Engine::new(&self.directory, true)
+    }
+}
+
+// Directory held entirely in memory, used to run the engine against synthetic forests
+#[derive(Debug, Clone)]
+pub struct InMemoryDirectory {
+    naming_contexts: Vec<String>,
+    root_domain_naming_context: String,
+    schema_naming_context: String,
+    configuration_naming_context: String,
+    entries: Vec<LdapEntry>,
+    token_groups: HashMap<Sid, Vec<Sid>>,
+}
+
+impl InMemoryDirectory {
+    // Creates an empty forest with the given root domain, and its configuration and schema
+    // partitions at their usual places
+    pub fn new(root_domain_naming_context: &str) -> Self {
+        let configuration_naming_context = format!("CN=Configuration,{}", root_domain_naming_context);
+        let schema_naming_context = format!("CN=Schema,{}", configuration_naming_context);
+        Self {
+            naming_contexts: vec![
+                root_domain_naming_context.to_owned(),
+                configuration_naming_context.clone(),
+                schema_naming_context.clone(),
+            ],
+            root_domain_naming_context: root_domain_naming_context.to_owned(),
+            schema_naming_context,
+            configuration_naming_context,
+            entries: vec![],
+            token_groups: HashMap::new(),
+        }
+    }
+
+    pub fn add_entry(&mut self, entry: LdapEntry) {
+        let attrs = entry.attrs.into_iter()
+            .map(|(name, values)| (name.to_lowercase(), values))
+            .collect();
+        self.entries.push(LdapEntry {
+            dn: entry.dn,
+            attrs,
+        });
+    }
+
+    pub fn set_token_groups(&mut self, principal: Sid, groups: Vec<Sid>) {
+        self.token_groups.insert(principal, groups);
+    }
+
+    // Most specific naming context holding the given DN
+    fn get_naming_context(&self, dn: &str) -> Option<&str> {
+        self.naming_contexts.iter()
+            .filter(|nc| dn.eq_ignore_ascii_case(nc) || ends_with_case_insensitive(dn, &format!(",{}", nc)))
+            .max_by_key(|nc| nc.len())
+            .map(|nc| nc.as_str())
+    }
+
+    fn get_projection(entry: &LdapEntry, attributes: &[&str], sd_flags: Option<u32>) -> LdapEntry {
+        let mut attrs = HashMap::new();
+        for name in attributes {
+            let name = name.to_lowercase();
+            if let Some(values) = entry.attrs.get(&name) {
+                let values = match (name.as_str(), sd_flags) {
+                    ("ntsecuritydescriptor", Some(sd_flags)) => values.iter().map(|v| filter_security_descriptor(v, sd_flags)).collect(),
+                    _ => values.clone(),
+                };
+                attrs.insert(name, values);
+            }
+        }
+        LdapEntry {
+            dn: entry.dn.clone(),
+            attrs,
+        }
+    }
+}
+
+impl DirectorySource for InMemoryDirectory {
+    fn get_naming_contexts(&self) -> &[String] {
+        &self.naming_contexts
+    }
+
+    fn get_root_domain_naming_context(&self) -> &str {
+        &self.root_domain_naming_context
+    }
+
+    fn get_schema_naming_context(&self) -> &str {
+        &self.schema_naming_context
+    }
+
+    fn get_configuration_naming_context(&self) -> &str {
+        &self.configuration_naming_context
+    }
+
+    fn search<'a>(&'a self, base: &str, scope: SearchScope, filter: Option<&str>, attributes: &[&str], sd_flags: Option<u32>) -> DirectorySearch<'a> {
+        let filter = match filter.map(Filter::parse) {
+            Some(Some(filter)) => Some(filter),
+            Some(None) => return Box::new(std::iter::once(Err(LdapError::SearchFailed {
+                base: Some(base.to_owned()),
+                filter: filter.map(|f| f.to_owned()),
+                only_attributes: Some(attributes.iter().map(|a| a.to_string()).collect()),
+                code: LDAP_FILTER_ERROR,
+            }))),
+            None => None,
+        };
+        // Like domain controllers, do not return entries from naming contexts below the base
+        let base_naming_context = self.get_naming_context(base);
+        let base = base.to_owned();
+        let attributes: Vec<String> = attributes.iter().map(|a| a.to_string()).collect();
+        Box::new(self.entries.iter()
+            .filter(move |entry| is_in_scope(&entry.dn, &base, scope))
+            .filter(move |entry| self.get_naming_context(&entry.dn) == base_naming_context)
+            .filter(move |entry| filter.as_ref().map(|f| f.matches(entry)).unwrap_or(true))
+            .map(move |entry| {
+                let attributes: Vec<&str> = attributes.iter().map(|a| a.as_str()).collect();
+                Ok(Self::get_projection(entry, &attributes, sd_flags))
+            }))
+    }
+
+    fn get_entry_by_sid(&self, sid: &Sid, attributes: &[&str]) -> Result<Option<LdapEntry>, LdapError> {
+        let sid_bytes = sid.to_bytes();
+        Ok(self.entries.iter()
+            .find(|entry| entry.attrs.get("objectsid").map(|v| v.contains(&sid_bytes)).unwrap_or(false))
+            .map(|entry| Self::get_projection(entry, attributes, None)))
+    }
+
+    fn get_token_groups(&self, principal: &Sid) -> Result<Vec<Sid>, LdapError> {
+        Ok(self.token_groups.get(principal).cloned().unwrap_or_default())
+    }
+}
+
+// LDAP_FILTER_ERROR result code, returned for filters the in-memory directory cannot parse
+const LDAP_FILTER_ERROR: u32 = 0x57;
+
+fn is_in_scope(dn: &str, base: &str, scope: SearchScope) -> bool {
+    if dn.eq_ignore_ascii_case(base) {
+        return scope != SearchScope::OneLevel;
+    }
+    match scope {
+        SearchScope::Base => false,
+        SearchScope::OneLevel => dn.split_once(',').map(|(_, parent)| parent.eq_ignore_ascii_case(base)).unwrap_or(false),
+        SearchScope::Subtree => ends_with_case_insensitive(dn, &format!(",{}", base)),
+    }
+}
+
+// Only keeps the parts of a security descriptor which would have been returned by a domain
+// controller given these SD flags
+fn filter_security_descriptor(bytes: &[u8], sd_flags: u32) -> Vec<u8> {
+    let sd = match SecurityDescriptor::from_bytes(bytes) {
+        Ok(sd) => sd,
+        Err(_) => return bytes.to_vec(),
+    };
+    let has_flag = |flag: u32| (sd_flags & flag) != 0;
+    SecurityDescriptor {
+        owner: if has_flag(OWNER_SECURITY_INFORMATION) { sd.owner } else { None },
+        group: if has_flag(GROUP_SECURITY_INFORMATION) { sd.group } else { None },
+        dacl: if has_flag(DACL_SECURITY_INFORMATION) { sd.dacl } else { None },
+        sacl: if has_flag(SACL_SECURITY_INFORMATION) { sd.sacl } else { None },
+        ..sd
+    }.to_bytes()
+}
+
+// Subset of LDAP filters (RFC 4515) supported by the in-memory directory: and, or, not,
+// presence and case-insensitive equality
+#[derive(Debug, Clone)]
+enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Present(String),
+    Equals(String, String),
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Option<Self> {
+        match Self::parse_prefix(filter.trim()) {
+            Some((filter, "")) => Some(filter),
+            _ => None,
+        }
+    }
+
+    // Parses one parenthesized filter at the start of the given string, returns it with the rest
+    fn parse_prefix(s: &str) -> Option<(Self, &str)> {
+        let s = s.strip_prefix('(')?;
+        let (operator, mut rest) = s.split_at(s.chars().next()?.len_utf8());
+        match operator {
+            "&" | "|" => {
+                let mut filters = vec![];
+                while !rest.starts_with(')') {
+                    let (filter, next) = Self::parse_prefix(rest)?;
+                    filters.push(filter);
+                    rest = next;
+                }
+                let filter = if operator == "&" { Filter::And(filters) } else { Filter::Or(filters) };
+                Some((filter, &rest[1..]))
+            },
+            "!" => {
+                let (filter, rest) = Self::parse_prefix(rest)?;
+                Some((Filter::Not(Box::new(filter)), rest.strip_prefix(')')?))
+            },
+            _ => {
+                let end = s.find(')')?;
+                let (name, value) = s[..end].split_once('=')?;
+                let filter = if value == "*" {
+                    Filter::Present(name.to_lowercase())
+                } else {
+                    Filter::Equals(name.to_lowercase(), value.to_owned())
+                };
+                Some((filter, &s[end + 1..]))
+            },
+        }
+    }
+
+    fn matches(&self, entry: &LdapEntry) -> bool {
+        match self {
+            Filter::And(filters) => filters.iter().all(|f| f.matches(entry)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(entry)),
+            Filter::Not(filter) => !filter.matches(entry),
+            Filter::Present(name) => entry.attrs.get(name).map(|v| !v.is_empty()).unwrap_or(false),
+            Filter::Equals(name, value) => entry.attrs.get(name)
+                .map(|values| values.iter().any(|v| String::from_utf8_lossy(v).eq_ignore_ascii_case(value)))
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_dns(directory: &InMemoryDirectory, base: &str, scope: SearchScope, filter: Option<&str>) -> Vec<String> {
+        directory.search(base, scope, filter, &["cn"], None)
+            .map(|entry| entry.expect("search failed").dn)
+            .collect()
+    }
+
+    #[test]
+    fn search_scopes() {
+        let forest = TestForest::new();
+        let system = format!("CN=System,{}", ROOT_DOMAIN_DN);
+        assert_eq!(get_dns(&forest.directory, &system, SearchScope::Base, None), vec![system.clone()]);
+        assert_eq!(get_dns(&forest.directory, &system.to_uppercase(), SearchScope::OneLevel, None),
+            vec![format!("CN=AdminSDHolder,{}", system)]);
+        let subtree = get_dns(&forest.directory, ROOT_DOMAIN_DN, SearchScope::Subtree, None);
+        assert!(subtree.contains(&ROOT_DOMAIN_DN.to_owned()));
+        assert!(!subtree.contains(&CONFIGURATION_DN.to_owned()));
+        let subtree = get_dns(&forest.directory, CONFIGURATION_DN, SearchScope::Subtree, None);
+        assert!(subtree.contains(&CONFIGURATION_DN.to_owned()));
+        assert!(!subtree.contains(&SCHEMA_DN.to_owned()));
+        assert!(get_dns(&forest.directory, "DC=other,DC=local", SearchScope::Subtree, None).is_empty());
+    }
+
+    #[test]
+    fn search_filters() {
+        let forest = TestForest::new();
+        let rights = get_dns(&forest.directory, CONFIGURATION_DN, SearchScope::Subtree,
+            Some("(&(objectClass=controlAccessRight)(validAccesses=256)(rightsGuid=*))"));
+        assert_eq!(rights.len(), 2);
+        let rights = get_dns(&forest.directory, CONFIGURATION_DN, SearchScope::Subtree,
+            Some("(&(objectClass=CONTROLACCESSRIGHT)(|(validAccesses=8)(validAccesses=48)))"));
+        assert_eq!(rights.len(), 3);
+        let others = get_dns(&forest.directory, CONFIGURATION_DN, SearchScope::Subtree,
+            Some("(!(objectClass=controlAccessRight))"));
+        assert!(!others.is_empty() && others.iter().all(|dn| !dn.contains("Extended-Rights,") || dn.starts_with("CN=Extended-Rights")));
+
+        let res: Vec<_> = forest.directory.search(ROOT_DOMAIN_DN, SearchScope::Base, Some("(objectClass=*"), &[], None).collect();
+        assert!(matches!(res.as_slice(), [Err(LdapError::SearchFailed { .. })]));
+    }
+
+    #[test]
+    fn security_descriptor_flags() {
+        let forest = TestForest::new();
+        let mut search = forest.directory.search(ROOT_DOMAIN_DN, SearchScope::Base, None, &["nTSecurityDescriptor"], Some(DACL_SECURITY_INFORMATION));
+        let entry = search.next().expect("domain head not found").expect("search failed");
+        let sd = SecurityDescriptor::from_bytes(&entry.attrs["ntsecuritydescriptor"][0]).expect("invalid security descriptor");
+        assert!(sd.owner.is_none());
+        assert!(sd.group.is_none());
+        assert!(sd.dacl.is_some());
+    }
+
+    #[test]
+    fn entries_by_sid() {
+        let mut forest = TestForest::new();
+        let bob = forest.add_principal(&format!("CN=Bob,{}", ROOT_DOMAIN_DN), "user", 1103, &[]);
+        let entry = forest.directory.get_entry_by_sid(&bob, &["objectClass"]).expect("lookup failed").expect("principal not found");
+        assert_eq!(entry.dn, format!("CN=Bob,{}", ROOT_DOMAIN_DN));
+        assert!(forest.directory.get_entry_by_sid(&forest.domain_sid.with_rid(1104), &["objectClass"]).expect("lookup failed").is_none());
+    }
+}
@@ -0,0 +1,227 @@
+use core::cell::RefCell;
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use authz::{Ace, Guid, Sid};
+use crate::delegations::{DelegationLocation, DelegationRights, DelegationTrustee, expand_relative_dn};
+use crate::engine::{AdelegResult, Engine};
+use crate::error::AdelegError;
+use crate::utils::{ends_with_case_insensitive, resolve_samaccountname_to_sid};
+
+pub const BUILTIN_TIERS: &str = include_str!("../builtin_tiers.json");
+
+// One tier of an administrative tier model, 0 being the most privileged one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TierDefinition {
+    pub(crate) tier: u8,
+    // Absolute or relative (ending with DC=*) DNs of containers whose whole subtree belongs to this tier
+    #[serde(default)]
+    pub(crate) containers: Vec<String>,
+    // Principals which belong to this tier, along with all their (possibly nested) members
+    #[serde(default)]
+    pub(crate) principals: Vec<DelegationTrustee>,
+    // Object classes whose instances belong to this tier, wherever they are
+    #[serde(default)]
+    pub(crate) classes: Vec<String>,
+    #[serde(default)]
+    pub(crate) builtin: bool,
+}
+
+// Tier definitions resolved against the forest, with the tier of each trustee seen so far
+#[derive(Debug, Default)]
+pub(crate) struct TierModel {
+    containers: Vec<(String, u8)>,
+    principals: HashMap<Sid, u8>,
+    classes: HashMap<Guid, u8>,
+    trustee_tiers: RefCell<HashMap<Sid, Option<u8>>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TierControl {
+    Owner,
+    Ace(Ace),
+    // Rights of the delegation, along with the ACEs it expects
+    Delegation(DelegationRights, Vec<Ace>),
+}
+
+// A trustee of a lower tier (or outside of any tier) which controls a resource of a higher tier
+#[derive(Debug, Clone)]
+pub struct TierViolation {
+    pub(crate) location: DelegationLocation,
+    pub(crate) resource_tier: u8,
+    pub(crate) trustee: Sid,
+    pub(crate) trustee_tier: Option<u8>,
+    pub(crate) control: TierControl,
+}
+
+impl<'a> Engine<'a> {
+    pub fn load_tier_json(&mut self, json: &str) -> Result<(), AdelegError> {
+        let json = json.trim();
+        if json.is_empty() {
+            return Ok(()); // empty file are invalid JSON, just skip them
+        }
+        let definitions: Vec<TierDefinition> = match serde_json::from_str(json) {
+            Ok(v) => v,
+            Err(e) => return Err(AdelegError::JsonParsing(e.to_string())),
+        };
+        for definition in definitions {
+            let tier = definition.tier;
+            for container in &definition.containers {
+                for dn in expand_relative_dn(container, self.directory, &self.domains) {
+                    self.tier_model.containers.push((dn.to_lowercase(), tier));
+                }
+            }
+            for principal in &definition.principals {
+                let sids = match principal {
+                    DelegationTrustee::Sid(sid) => vec![sid.clone()],
+                    DelegationTrustee::DomainRid(rid) => self.domains.iter().map(|d| d.sid.with_rid(*rid)).collect(),
+                    DelegationTrustee::RootDomainRid(rid) => vec![self.root_domain.sid.with_rid(*rid)],
+                    DelegationTrustee::SamAccountName(samaccountname) => {
+                        let sids: Vec<Sid> = self.domains.iter()
+                            .filter_map(|d| resolve_samaccountname_to_sid(self.directory, samaccountname, d).ok())
+                            .collect();
+                        if sids.is_empty() && !definition.builtin {
+                            return Err(AdelegError::UnresolvedSamAccountName(samaccountname.to_owned(), self.root_domain.distinguished_name.to_owned()));
+                        }
+                        sids
+                    },
+                };
+                for sid in sids {
+                    let entry = self.tier_model.principals.entry(sid).or_insert(tier);
+                    *entry = (*entry).min(tier);
+                }
+            }
+            for class_name in &definition.classes {
+                if let Some(guid) = self.schema.class_guids.get(&class_name.to_ascii_lowercase()) {
+                    let entry = self.tier_model.classes.entry(*guid).or_insert(tier);
+                    *entry = (*entry).min(tier);
+                } else if !definition.builtin { // only ignore missing classes in built-in tiers (e.g. older schemas)
+                    return Err(AdelegError::UnresolvedObjectTypeName(class_name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Lists every owner, ACE and documented delegation which grants a trustee control over a
+    // resource of a more privileged tier. Trustees outside of any tier are treated as the least
+    // privileged ones, resources outside of any tier are not checked.
+    pub fn run_tier_check(&self, res: &HashMap<DelegationLocation, Result<AdelegResult, AdelegError>>) -> Vec<TierViolation> {
+        eprintln!(" [.] Checking tier model...");
+        let dn_to_sid: HashMap<String, Sid> = self.resolved_sid_to_dn.borrow().iter()
+            .map(|(sid, dn)| (dn.to_lowercase(), sid.clone()))
+            .collect();
+        let mut locations: Vec<(&DelegationLocation, &AdelegResult)> = res.iter()
+            .filter_map(|(location, res)| res.as_ref().ok().map(|res| (location, res)))
+            .collect();
+        locations.sort_by_key(|(location, _)| *location);
+
+        let mut violations = vec![];
+        for (location, res) in locations {
+            let mut controls = vec![];
+            if let Some(owner) = &res.owner {
+                controls.push((owner, TierControl::Owner));
+            }
+            for ace in res.orphan_aces.iter().filter(|ace| ace.grants_access()) {
+                controls.push((&ace.trustee, TierControl::Ace(ace.clone())));
+            }
+            for (delegation, trustee, aces_found, aces_missing) in &res.delegations {
+                // Built-in delegations are part of how Active Directory works, there is nothing to act upon
+                if !delegation.builtin && aces_found.iter().chain(aces_missing.iter()).any(|ace| ace.grants_access()) {
+                    let aces = aces_found.iter().chain(aces_missing.iter()).cloned().collect();
+                    controls.push((trustee, TierControl::Delegation(delegation.rights.clone(), aces)));
+                }
+            }
+            if controls.is_empty() {
+                continue;
+            }
+            let resource_tier = match self.get_resource_tier(location, res, &dn_to_sid) {
+                Some(tier) => tier,
+                None => continue,
+            };
+            for (trustee, control) in controls {
+                let trustee_tier = self.get_trustee_tier(trustee);
+                if trustee_tier.map(|tier| tier > resource_tier).unwrap_or(true) {
+                    violations.push(TierViolation {
+                        location: location.clone(),
+                        resource_tier,
+                        trustee: trustee.clone(),
+                        trustee_tier,
+                        control,
+                    });
+                }
+            }
+        }
+        violations
+    }
+
+    // Most privileged tier of a principal: built-in tier 0 principals (protected by AdminSDHolder
+    // or domain controllers), principals declared in a tier or member of one of them, and principals
+    // located in a tier container
+    pub fn get_trustee_tier(&self, trustee: &Sid) -> Option<u8> {
+        if let Some(tier) = self.tier_model.trustee_tiers.borrow().get(trustee) {
+            return *tier;
+        }
+        let groups = self.fetch_principal_groups(trustee).unwrap_or_default();
+        let mut tier = groups.iter()
+            .filter_map(|sid| self.tier_model.principals.get(sid))
+            .min()
+            .copied();
+        if let Some((dn, _)) = self.resolve_sid(trustee) {
+            tier = min_tier(tier, self.get_dn_tier(&dn));
+        }
+        self.tier_model.trustee_tiers.borrow_mut().insert(trustee.clone(), tier);
+        tier
+    }
+
+    fn get_resource_tier(&self, location: &DelegationLocation, res: &AdelegResult, dn_to_sid: &HashMap<String, Sid>) -> Option<u8> {
+        let dn = match location {
+            DelegationLocation::Dn(dn) => dn,
+            // Default security descriptors are stored in the schema partition, and global locations
+            // apply to the whole forest
+            DelegationLocation::DefaultSecurityDescriptor(_) | DelegationLocation::Global => return Some(0),
+        };
+        let mut tier = min_tier(self.get_dn_tier(dn), self.tier_model.classes.get(&res.class_guid).copied());
+        // Principals are as privileged as the tier they belong to
+        if let Some(sid) = dn_to_sid.get(&dn.to_lowercase()) {
+            tier = min_tier(tier, self.get_trustee_tier(sid));
+        }
+        tier
+    }
+
+    fn get_dn_tier(&self, dn: &str) -> Option<u8> {
+        if self.sensitive_resources.borrow().contains(&dn.to_lowercase()) {
+            return Some(0);
+        }
+        self.tier_model.containers.iter()
+            .filter(|(container, _)| dn.eq_ignore_ascii_case(container) || ends_with_case_insensitive(dn, &format!(",{}", container)))
+            .map(|(_, tier)| *tier)
+            .min()
+    }
+
+    pub fn describe_tier_violation(&self, violation: &TierViolation) -> String {
+        let trustee_tier = match violation.trustee_tier {
+            Some(tier) => format!("Tier {} trustee", tier),
+            None => "Trustee outside of any tier".to_owned(),
+        };
+        let control = match &violation.control {
+            TierControl::Owner => "as its owner".to_owned(),
+            TierControl::Ace(ace) => format!("through an ACE: {}{}", self.describe_ace(
+                ace.access_mask,
+                ace.get_object_type(),
+                ace.get_inherited_object_type(),
+                ace.get_container_inherit(),
+                ace.get_inherit_only()
+            ), self.describe_ace_condition(ace)),
+            TierControl::Delegation(rights, _) => format!("through a documented delegation: {}", self.describe_delegation_rights(rights)),
+        };
+        format!("{} controls this tier {} resource {}", trustee_tier, violation.resource_tier, control)
+    }
+}
+
+fn min_tier(a: Option<u8>, b: Option<u8>) -> Option<u8> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
@@ -1,18 +1,23 @@
-use windows::Win32::Security::{CONTAINER_INHERIT_ACE, NO_PROPAGATE_INHERIT_ACE, OBJECT_INHERIT_ACE, INHERIT_ONLY_ACE};
-use windows::Win32::Networking::Ldap::{LDAP_SCOPE_BASE, LDAP_SCOPE_SUBTREE, LDAP_SCOPE_ONELEVEL};
+#[cfg(windows)]
 use windows::Win32::NetworkManagement::NetManagement::NetApiBufferFree;
+#[cfg(windows)]
 use windows::Win32::Networking::ActiveDirectory::{DsGetDcNameW, DS_GC_SERVER_REQUIRED, DS_DIRECTORY_SERVICE_REQUIRED, DS_RETURN_DNS_NAME, DOMAIN_CONTROLLER_INFOW};
+#[cfg(windows)]
 use windows::Win32::System::Console::{GetConsoleMode, CONSOLE_MODE, SetConsoleMode, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT};
+#[cfg(windows)]
 use windows::Win32::Foundation::ERROR_SUCCESS;
+#[cfg(windows)]
 use std::os::windows::prelude::AsRawHandle;
+#[cfg(windows)]
 use std::io::{Write, BufRead};
 use core::borrow::Borrow;
+#[cfg(windows)]
 use core::ptr::null_mut;
 use authz::{Ace, Sid, SecurityDescriptor, Guid};
-use winldap::connection::LdapConnection;
-use winldap::search::{LdapSearch, LdapEntry};
+use winldap::search::LdapEntry;
 use winldap::error::LdapError;
 use winldap::utils::get_attr_str;
+use crate::directory::{DirectorySource, SearchScope};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Domain {
@@ -21,6 +26,7 @@ pub struct Domain {
     pub netbios_name: String,
 }
 
+#[cfg(windows)]
 pub(crate) fn read_password(out: &mut String, prompt: &str) {
     let stdout = std::io::stdout();
     let stdin = std::io::stdin();
@@ -56,6 +62,7 @@ pub(crate) fn read_password(out: &mut String, prompt: &str) {
     }
 }
 
+#[cfg(windows)]
 pub(crate) fn get_gc_domain_controller() -> Option<(String, u16)> {
     let mut dc_info_ptr: *mut DOMAIN_CONTROLLER_INFOW = null_mut();
     let res = unsafe { DsGetDcNameW(None, None, null_mut(), None, DS_GC_SERVER_REQUIRED | DS_DIRECTORY_SERVICE_REQUIRED | DS_RETURN_DNS_NAME, &mut dc_info_ptr as *mut _) };
@@ -72,6 +79,7 @@ pub(crate) fn get_gc_domain_controller() -> Option<(String, u16)> {
     })
 }
 
+#[cfg(windows)]
 pub(crate) fn pwstr_to_str(ptr: *const u16) -> String {
     let mut len = 0;
     unsafe {
@@ -86,7 +94,7 @@ pub(crate) fn pwstr_to_str(ptr: *const u16) -> String {
 pub(crate) fn get_attr_sids<T: Borrow<LdapEntry>>(search_results: &[T], base: &str, attr_name: &str) -> Result<Vec<Sid>, LdapError> {
     let attrs = if search_results.len() > 1 {
         return Err(LdapError::RequiredObjectCollision { dn: base.to_owned() });
-    } else if search_results.len() == 0 {
+    } else if search_results.is_empty() {
         return Err(LdapError::RequiredObjectMissing { dn: base.to_owned() });
     } else {
         &search_results[0].borrow().attrs
@@ -102,17 +110,17 @@ pub(crate) fn get_attr_sids<T: Borrow<LdapEntry>>(search_results: &[T], base: &s
 pub(crate) fn get_attr_sid<T: Borrow<LdapEntry>>(search_results: &[T], base: &str, attr_name: &str) -> Result<Sid, LdapError> {
     let attrs = if search_results.len() > 1 {
         return Err(LdapError::RequiredObjectCollision { dn: base.to_owned() });
-    } else if search_results.len() == 0 {
+    } else if search_results.is_empty() {
         return Err(LdapError::RequiredObjectMissing { dn: base.to_owned() });
     } else {
         &search_results[0].borrow().attrs
     };
 
     if let Some(vals) = attrs.get(attr_name) {
-        if vals.len() == 0 {
-            return Err(LdapError::RequiredAttributeMissing { dn: base.to_owned(), name: attr_name.to_owned() });
+        if vals.is_empty() {
+            Err(LdapError::RequiredAttributeMissing { dn: base.to_owned(), name: attr_name.to_owned() })
         } else if vals.len() > 1 {
-            return Err(LdapError::AttributeValuesCollision { dn: base.to_owned(), name: attr_name.to_owned(), val1: format!("{:?}", vals[0]), val2: format!("{:?}", vals[1]) });
+            Err(LdapError::AttributeValuesCollision { dn: base.to_owned(), name: attr_name.to_owned(), val1: format!("{:?}", vals[0]), val2: format!("{:?}", vals[1]) })
         } else {
             Ok(Sid::from_bytes(&vals[0]).unwrap())
         }
@@ -124,23 +132,23 @@ pub(crate) fn get_attr_sid<T: Borrow<LdapEntry>>(search_results: &[T], base: &st
 pub(crate) fn get_attr_sd<T: Borrow<LdapEntry>>(search_results: &[T], base: &str, attr_name: &str) -> Result<SecurityDescriptor, LdapError> {
     let (dn, attrs) = if search_results.len() > 1 {
         return Err(LdapError::RequiredObjectCollision { dn: base.to_owned() });
-    } else if search_results.len() == 0 {
+    } else if search_results.is_empty() {
         return Err(LdapError::RequiredObjectMissing { dn: base.to_owned() });
     } else {
         (&search_results[0].borrow().dn, &search_results[0].borrow().attrs)
     };
 
     if let Some(vals) = attrs.get(attr_name) {
-        if vals.len() == 0 {
-            return Err(LdapError::RequiredAttributeMissing { dn: dn.to_owned(), name: attr_name.to_owned() });
+        if vals.is_empty() {
+            Err(LdapError::RequiredAttributeMissing { dn: dn.to_owned(), name: attr_name.to_owned() })
         } else if vals.len() > 1 {
-            return Err(LdapError::AttributeValuesCollision { dn: dn.to_owned(), name: attr_name.to_owned(), val1: format!("{:?}", vals[0]), val2: format!("{:?}", vals[1]) });
+            Err(LdapError::AttributeValuesCollision { dn: dn.to_owned(), name: attr_name.to_owned(), val1: format!("{:?}", vals[0]), val2: format!("{:?}", vals[1]) })
         } else {
             match SecurityDescriptor::from_bytes(&vals[0]) {
                 Ok(sd) => Ok(sd),
                 Err(e) => {
                     eprintln!(" [!] Unable to parse security descriptor at {} : {}", &dn, e);
-                    return Err(LdapError::RequiredAttributeMissing { dn: dn.to_owned(), name: attr_name.to_owned() });
+                    Err(LdapError::RequiredAttributeMissing { dn: dn.to_owned(), name: attr_name.to_owned() })
                 }
             }
         }
@@ -152,17 +160,17 @@ pub(crate) fn get_attr_sd<T: Borrow<LdapEntry>>(search_results: &[T], base: &str
 pub(crate) fn get_attr_guid<T: Borrow<LdapEntry>>(search_results: &[T], base: &str, attr_name: &str) -> Result<Guid, LdapError> {
     let attrs = if search_results.len() > 1 {
         return Err(LdapError::RequiredObjectCollision { dn: base.to_owned() });
-    } else if search_results.len() == 0 {
+    } else if search_results.is_empty() {
         return Err(LdapError::RequiredObjectMissing { dn: base.to_owned() });
     } else {
         &search_results[0].borrow().attrs
     };
 
     if let Some(vals) = attrs.get(attr_name) {
-        if vals.len() == 0 {
-            return Err(LdapError::RequiredAttributeMissing { dn: base.to_owned(), name: attr_name.to_owned() });
+        if vals.is_empty() {
+            Err(LdapError::RequiredAttributeMissing { dn: base.to_owned(), name: attr_name.to_owned() })
         } else if vals.len() > 1 {
-            return Err(LdapError::AttributeValuesCollision { dn: base.to_owned(), name: attr_name.to_owned(), val1: format!("{:?}", vals[0]), val2: format!("{:?}", vals[1]) });
+            Err(LdapError::AttributeValuesCollision { dn: base.to_owned(), name: attr_name.to_owned(), val1: format!("{:?}", vals[0]), val2: format!("{:?}", vals[1]) })
         } else {
             let bytes = &vals[0];
             if bytes.len() != 16 {
@@ -179,9 +187,9 @@ pub(crate) fn get_attr_guid<T: Borrow<LdapEntry>>(search_results: &[T], base: &s
     }
 }
 
-pub(crate) fn get_domains(conn: &LdapConnection) -> Result<Vec<Domain>, LdapError> {
-    let partitions_dn = format!("CN=Partitions,{}", conn.get_configuration_naming_context());
-    let search = LdapSearch::new(&conn, Some(&partitions_dn), LDAP_SCOPE_ONELEVEL, Some("(&(nCName=*)(nETBIOSName=*))"), Some(&["nCName", "nETBIOSName"]), &[]);
+pub(crate) fn get_domains(directory: &dyn DirectorySource) -> Result<Vec<Domain>, LdapError> {
+    let partitions_dn = format!("CN=Partitions,{}", directory.get_configuration_naming_context());
+    let search = directory.search(&partitions_dn, SearchScope::OneLevel, Some("(&(nCName=*)(nETBIOSName=*))"), &["nCName", "nETBIOSName"], None);
     let partitions = search.collect::<Result<Vec<LdapEntry>, LdapError>>()?;
 
     let mut v = vec![];
@@ -189,7 +197,7 @@ pub(crate) fn get_domains(conn: &LdapConnection) -> Result<Vec<Domain>, LdapErro
         let nc = get_attr_str(&[partition], &partition.dn, "ncname")?;
         let netbios_name = get_attr_str(&[partition], &partition.dn, "netbiosname")?;
 
-        let mut search = LdapSearch::new(&conn, Some(&nc), LDAP_SCOPE_BASE, Some("(objectSid=*)"), Some(&["objectSid"]), &[]);
+        let mut search = directory.search(&nc, SearchScope::Base, Some("(objectSid=*)"), &["objectSid"], None);
         if let Some(Ok(entry)) = search.next() {
             let sid= get_attr_sid(&[entry], &nc, "objectsid")?;
             v.push(Domain {
@@ -202,49 +210,6 @@ pub(crate) fn get_domains(conn: &LdapConnection) -> Result<Vec<Domain>, LdapErro
     Ok(v)
 }
 
-pub(crate) fn get_ace_derived_by_inheritance_from_schema(parent_aces: &[Ace], child_owner: &Sid, child_object_type: &Guid, force_inheritance: bool) -> Vec<Ace> {
-    let mut res = vec![];
-    for parent_ace in parent_aces {
-        if !force_inheritance && (parent_ace.flags & CONTAINER_INHERIT_ACE.0 as u8) == 0 {
-            continue;
-        }
-        if let Some(guid) = parent_ace.get_inherited_object_type() {
-            if guid != child_object_type && (parent_ace.flags & NO_PROPAGATE_INHERIT_ACE.0 as u8) != 0 {
-                continue;
-            }
-        }
-
-        let mut flags = parent_ace.flags;
-        if (parent_ace.flags & NO_PROPAGATE_INHERIT_ACE.0 as u8) != 0 {
-            flags &= !(CONTAINER_INHERIT_ACE.0 as u8 | OBJECT_INHERIT_ACE.0 as u8);
-        }
-
-        // Creator Owner SID gets replaced by the current owner
-        if parent_ace.trustee == Sid::try_from("S-1-3-0").unwrap() {
-            res.push(Ace {
-                trustee: child_owner.clone(),
-                access_mask: parent_ace.access_mask,
-                flags: flags & !(CONTAINER_INHERIT_ACE.0 as u8 | OBJECT_INHERIT_ACE.0 as u8),
-                type_specific: parent_ace.type_specific.clone(),
-            });
-            res.push(Ace {
-                trustee: parent_ace.trustee.clone(),
-                access_mask: parent_ace.access_mask,
-                flags: flags | (INHERIT_ONLY_ACE.0 as u8),
-                type_specific: parent_ace.type_specific.clone(),
-            });
-        } else {
-            res.push(Ace {
-                trustee: parent_ace.trustee.clone(),
-                access_mask: parent_ace.access_mask,
-                flags,
-                type_specific: parent_ace.type_specific.clone(),
-            });
-        }
-    }
-    res
-}
-
 pub(crate) fn capitalize(s: &str) -> String {
     let mut c = s.chars();
     match c.next() {
@@ -253,7 +218,7 @@ pub(crate) fn capitalize(s: &str) -> String {
     }
 }
 
-pub(crate) fn replace_suffix_case_insensitive<'a>(haystack: &'a str, suffix: &str, replacement: &str) -> String {
+pub(crate) fn replace_suffix_case_insensitive(haystack: &str, suffix: &str, replacement: &str) -> String {
     if haystack.to_lowercase().ends_with(&suffix.to_lowercase()) && haystack.is_char_boundary(haystack.len() - suffix.len()) {
         format!("{}{}", &haystack[..haystack.len() - suffix.len()], replacement)
     } else {
@@ -274,8 +239,17 @@ pub(crate) fn get_parent_container<'a>(dn: &'a str, naming_context: &str) -> Opt
     None
 }
 
-pub(crate) fn resolve_samaccountname_to_sid(ldap: &LdapConnection, samaccountname: &str, domain: &Domain) -> Result<Sid, LdapError> {
-    let search = LdapSearch::new(&ldap, Some(&domain.distinguished_name), LDAP_SCOPE_SUBTREE, Some(&format!("(samAccountName={})", samaccountname)), Some(&["objectSid"]), &[]);
+// Members from other forests (or well-known principals) are stored as CN=<SID>,CN=ForeignSecurityPrincipals,...
+pub(crate) fn get_foreign_principal_sid(dn: &str) -> Option<Sid> {
+    let (rdn, parent) = dn.split_once(',')?;
+    if !parent.to_lowercase().starts_with("cn=foreignsecurityprincipals,") {
+        return None;
+    }
+    Sid::try_from(rdn.get(3..)?).ok()
+}
+
+pub(crate) fn resolve_samaccountname_to_sid(directory: &dyn DirectorySource, samaccountname: &str, domain: &Domain) -> Result<Sid, LdapError> {
+    let search = directory.search(&domain.distinguished_name, SearchScope::Subtree, Some(&format!("(samAccountName={})", samaccountname)), &["objectSid"], None);
     let res = search.collect::<Result<Vec<LdapEntry>, LdapError>>()?;
     get_attr_sid(&res, &domain.distinguished_name, "objectsid")
 }
@@ -288,10 +262,11 @@ pub(crate) fn ace_equivalent(a: &Ace, b: &Ace) -> bool {
     let mut a = a.clone();
     let mut b = b.clone();
 
-    a.access_mask = a.access_mask & !(crate::engine::IGNORED_ACCESS_RIGHTS);
-    b.access_mask = b.access_mask & !(crate::engine::IGNORED_ACCESS_RIGHTS);
-    a.flags = a.flags & !(crate::engine::IGNORED_ACE_FLAGS);
-    b.flags = b.flags & !(crate::engine::IGNORED_ACE_FLAGS);
+    // Generic rights are compared once mapped, e.g. GENERIC_ALL is equivalent to full control
+    a.access_mask = a.access_mask.map_generic() & !crate::engine::IGNORED_ACCESS_RIGHTS;
+    b.access_mask = b.access_mask.map_generic() & !crate::engine::IGNORED_ACCESS_RIGHTS;
+    a.flags &= !(crate::engine::IGNORED_ACE_FLAGS);
+    b.flags &= !(crate::engine::IGNORED_ACE_FLAGS);
 
     a == b
 }
@@ -0,0 +1,318 @@
+use std::convert::TryFrom;
+use crate::error::AuthzError;
+use crate::{Ace, AceType, Guid, SecurityDescriptor, Sid};
+
+pub(crate) const READ_CONTROL: u32 = 0x0002_0000;
+pub(crate) const WRITE_DAC: u32 = 0x0004_0000;
+pub(crate) const GENERIC_ALL: u32 = 0x1000_0000;
+pub(crate) const GENERIC_EXECUTE: u32 = 0x2000_0000;
+pub(crate) const GENERIC_WRITE: u32 = 0x4000_0000;
+pub(crate) const GENERIC_READ: u32 = 0x8000_0000;
+
+// Generic rights mapping of Active Directory objects
+pub(crate) const DS_GENERIC_READ: u32 = 0x0002_0094;
+pub(crate) const DS_GENERIC_WRITE: u32 = 0x0002_0028;
+pub(crate) const DS_GENERIC_EXECUTE: u32 = 0x0002_0004;
+pub(crate) const DS_GENERIC_ALL: u32 = 0x000F_01FF;
+
+const OWNER_RIGHTS_SID: &str = "S-1-3-4";
+
+// One entry of a flattened object type tree, same semantics as OBJECT_TYPE_LIST: the first
+// entry is the object class (level 0), followed by property sets (level 1), each followed by
+// the attributes they contain (level 2), etc.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ObjectTypeNode {
+    pub level: u16,
+    pub guid: Guid,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AccessCheckResult {
+    pub desired_access: u32,
+    // Access granted on the object as a whole (i.e. on the root of the object type tree)
+    pub granted_access: u32,
+    // Access granted on each object type, in the same order as the object type tree given
+    pub object_type_granted_access: Vec<u32>,
+}
+
+impl AccessCheckResult {
+    pub fn is_granted(&self) -> bool {
+        self.granted_access == self.desired_access
+    }
+}
+
+pub(crate) fn map_generic_rights(mask: u32) -> u32 {
+    let mut res = mask & !(GENERIC_ALL | GENERIC_EXECUTE | GENERIC_WRITE | GENERIC_READ);
+    if (mask & GENERIC_READ) != 0 {
+        res |= DS_GENERIC_READ;
+    }
+    if (mask & GENERIC_WRITE) != 0 {
+        res |= DS_GENERIC_WRITE;
+    }
+    if (mask & GENERIC_EXECUTE) != 0 {
+        res |= DS_GENERIC_EXECUTE;
+    }
+    if (mask & GENERIC_ALL) != 0 {
+        res |= DS_GENERIC_ALL;
+    }
+    res
+}
+
+// Evaluates which of the desired rights are granted to a token with the given SIDs (user, groups,
+// and any other SID like PRINCIPAL_SELF if relevant), the same way AccessCheckByTypeResultList()
+// does on an Active Directory object.
+pub fn access_check(sd: &SecurityDescriptor, token_sids: &[Sid], object_type_tree: &[ObjectTypeNode], desired_mask: u32) -> Result<AccessCheckResult, AuthzError> {
+    let desired_mask = map_generic_rights(desired_mask);
+    let mut tree = ObjectTypeTree::new(object_type_tree)?;
+
+    let dacl = match &sd.dacl {
+        Some(dacl) => dacl,
+        // No DACL (or a NULL DACL) grants full access to everyone
+        None => return Ok(AccessCheckResult {
+            desired_access: desired_mask,
+            granted_access: desired_mask,
+            object_type_granted_access: vec![desired_mask; object_type_tree.len()],
+        }),
+    };
+
+    let owner_rights = Sid::try_from(OWNER_RIGHTS_SID).expect("invalid OWNER RIGHTS SID");
+    let is_owner = match &sd.owner {
+        Some(owner) => token_sids.contains(owner),
+        None => false,
+    };
+    // Owners are implicitly granted READ_CONTROL and WRITE_DAC, unless the DACL contains ACEs
+    // for OWNER RIGHTS, in which case these ACEs replace the implicit grant
+    if is_owner && !dacl.aces.iter().any(|ace| !ace.get_inherit_only() && ace.trustee == owner_rights) {
+        tree.grant(0, READ_CONTROL | WRITE_DAC);
+    }
+
+    for ace in &dacl.aces {
+        if ace.get_inherit_only() {
+            continue;
+        }
+        let applies_to_token = token_sids.contains(&ace.trustee) ||
+            (is_owner && ace.trustee == owner_rights);
+        if !applies_to_token {
+            continue;
+        }
+        // Note: inherited object types only matter when ACEs get inherited, they do not
+        // restrict ACEs which apply to the object itself.
+        let mask = ace.access_mask.map_generic().bits();
+        match &ace.type_specific {
+            AceType::AccessAllowed => tree.grant(0, mask),
+            AceType::AccessDenied => tree.deny(0, mask),
+            // Conditional expressions cannot be evaluated without claims, which makes them
+            // evaluate to UNKNOWN: allow ACEs are not applied, deny ACEs are.
+            AceType::AccessAllowedCallback { .. } |
+            AceType::AccessAllowedCallbackObject { .. } => (),
+            AceType::AccessDeniedCallback { .. } => tree.deny(0, mask),
+            AceType::AccessAllowedObject { .. } => {
+                if let Some(node) = tree.locate(ace) {
+                    tree.grant(node, mask);
+                }
+            },
+            AceType::AccessDeniedObject { .. } |
+            AceType::AccessDeniedCallbackObject { .. } => {
+                if let Some(node) = tree.locate(ace) {
+                    tree.deny(node, mask);
+                }
+            },
+            AceType::Audit |
+            AceType::AuditCallback { .. } |
+            AceType::AuditObject { .. } |
+            AceType::AuditCallbackObject { .. } |
+            AceType::MandatoryLabel |
+            AceType::ResourceAttribute { .. } |
+            AceType::ScopedPolicyId => (),
+        }
+    }
+
+    let object_type_granted_access: Vec<u32> = tree.granted.iter().map(|granted| granted & desired_mask).collect();
+    Ok(AccessCheckResult {
+        desired_access: desired_mask,
+        granted_access: tree.granted[0] & desired_mask,
+        object_type_granted_access: if object_type_tree.is_empty() { vec![] } else { object_type_granted_access },
+    })
+}
+
+struct ObjectTypeTree<'a> {
+    nodes: &'a [ObjectTypeNode],
+    parents: Vec<Option<usize>>,
+    granted: Vec<u32>,
+    denied: Vec<u32>,
+}
+
+impl<'a> ObjectTypeTree<'a> {
+    fn new(nodes: &'a [ObjectTypeNode]) -> Result<Self, AuthzError> {
+        // Without any object type, the tree is made of a single anonymous root node
+        let mut parents = vec![None];
+        let mut stack: Vec<usize> = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            if (i == 0) != (node.level == 0) {
+                return Err(AuthzError::InvalidObjectTypeList(nodes.to_vec()));
+            }
+            while let Some(&top) = stack.last() {
+                if nodes[top].level < node.level {
+                    break;
+                }
+                stack.pop();
+            }
+            if let Some(&parent) = stack.last() {
+                if nodes[parent].level + 1 != node.level {
+                    return Err(AuthzError::InvalidObjectTypeList(nodes.to_vec()));
+                }
+            }
+            if i > 0 {
+                parents.push(stack.last().copied());
+            }
+            stack.push(i);
+        }
+        let count = parents.len();
+        Ok(Self {
+            nodes,
+            parents,
+            granted: vec![0; count],
+            denied: vec![0; count],
+        })
+    }
+
+    // Returns the node targeted by an object ACE, the root if it has no object type, or None
+    // if its object type is not part of the tree (in which case the ACE does not apply)
+    fn locate(&self, ace: &Ace) -> Option<usize> {
+        match ace.get_object_type() {
+            None => Some(0),
+            Some(guid) => self.nodes.iter().position(|node| &node.guid == guid),
+        }
+    }
+
+    fn is_descendant_or_self(&self, mut node: usize, ancestor: usize) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            match self.parents[node] {
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    // Grants access to a node and all its descendants (except for rights already denied), then
+    // to each ancestor whose children have all been granted these rights
+    fn grant(&mut self, node: usize, mask: u32) {
+        for i in 0..self.granted.len() {
+            if self.is_descendant_or_self(i, node) {
+                self.granted[i] |= mask & !self.denied[i];
+            }
+        }
+        let mut current = node;
+        while let Some(parent) = self.parents[current] {
+            let children_granted = (0..self.granted.len())
+                .filter(|&i| self.parents[i] == Some(parent))
+                .fold(u32::MAX, |acc, i| acc & self.granted[i]);
+            self.granted[parent] |= children_granted & !self.denied[parent];
+            current = parent;
+        }
+    }
+
+    // Denies access (except for rights already granted) to a node, all its descendants, and
+    // all its ancestors: denying a right on a part of the object denies it on the object
+    fn deny(&mut self, node: usize, mask: u32) {
+        for i in 0..self.denied.len() {
+            if self.is_descendant_or_self(i, node) || self.is_descendant_or_self(node, i) {
+                self.denied[i] |= mask & !self.granted[i];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = "S-1-5-21-1-2-3-1000";
+    const GROUP: &str = "S-1-5-21-1-2-3-1001";
+    const WRITE_PROP: u32 = 0x20;
+    const CLASS: &str = "bf967aba-0de6-11d0-a285-00aa003049e2";
+    const PROPERTY_SET: &str = "bc0ac240-79a9-11d0-9020-00c04fc2d4cf";
+    const ATTRIBUTE: &str = "bf9679c0-0de6-11d0-a285-00aa003049e2";
+    const OTHER_ATTRIBUTE: &str = "bf967991-0de6-11d0-a285-00aa003049e2";
+
+    fn check(sddl: &str, object_type_tree: &[ObjectTypeNode], desired_mask: u32) -> AccessCheckResult {
+        let domain_sid = Sid::try_from("S-1-5-21-1-2-3").expect("invalid SID");
+        let sd = SecurityDescriptor::from_str(sddl, &domain_sid, &domain_sid).expect("unable to parse SDDL");
+        let token_sids = [Sid::try_from(USER).expect("invalid SID"), Sid::try_from(GROUP).expect("invalid SID")];
+        access_check(&sd, &token_sids, object_type_tree, desired_mask).expect("access check failed")
+    }
+
+    // Object class, with one property set containing two attributes
+    fn get_object_type_tree() -> Vec<ObjectTypeNode> {
+        [(0, CLASS), (1, PROPERTY_SET), (2, ATTRIBUTE), (2, OTHER_ATTRIBUTE)].iter()
+            .map(|(level, guid)| ObjectTypeNode {
+                level: *level,
+                guid: Guid::try_from(*guid).expect("invalid GUID"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aces_are_evaluated_in_order() {
+        let res = check(&format!("D:(D;;WP;;;{})(A;;WP;;;{})", GROUP, USER), &[], WRITE_PROP);
+        assert_eq!(res.granted_access, 0);
+        assert!(!res.is_granted());
+        let res = check(&format!("D:(A;;WP;;;{})(D;;WP;;;{})", USER, GROUP), &[], WRITE_PROP);
+        assert_eq!(res.granted_access, WRITE_PROP);
+        assert!(res.is_granted());
+        // ACEs for other trustees do not apply
+        let res = check("D:(A;;WP;;;S-1-5-21-1-2-3-1002)", &[], WRITE_PROP);
+        assert_eq!(res.granted_access, 0);
+    }
+
+    #[test]
+    fn property_set_grants_reach_attributes_and_root() {
+        let tree = get_object_type_tree();
+        let res = check(&format!("D:(OA;;WP;{};;{})", PROPERTY_SET, USER), &tree, WRITE_PROP);
+        assert_eq!(res.object_type_granted_access, vec![WRITE_PROP; 4]);
+        assert_eq!(res.granted_access, WRITE_PROP);
+        // Granting one attribute of the property set is not enough for the set, nor the root
+        let res = check(&format!("D:(OA;;WP;{};;{})", ATTRIBUTE, USER), &tree, WRITE_PROP);
+        assert_eq!(res.object_type_granted_access, vec![0, 0, WRITE_PROP, 0]);
+        assert_eq!(res.granted_access, 0);
+    }
+
+    #[test]
+    fn attribute_denies_block_property_set_and_root() {
+        let tree = get_object_type_tree();
+        let res = check(&format!("D:(OD;;WP;{};;{})(A;;WP;;;{})", ATTRIBUTE, GROUP, USER), &tree, WRITE_PROP);
+        assert_eq!(res.object_type_granted_access, vec![0, 0, 0, WRITE_PROP]);
+        assert_eq!(res.granted_access, 0);
+    }
+
+    #[test]
+    fn owner_rights_replace_implicit_owner_rights() {
+        let res = check(&format!("O:{}D:(A;;WP;;;{})", USER, GROUP), &[], READ_CONTROL | WRITE_DAC);
+        assert_eq!(res.granted_access, READ_CONTROL | WRITE_DAC);
+        let res = check(&format!("O:{}D:(A;;RC;;;S-1-3-4)", USER), &[], READ_CONTROL | WRITE_DAC);
+        assert_eq!(res.granted_access, READ_CONTROL);
+        // Inherit-only OWNER RIGHTS ACEs do not apply to the object itself
+        let res = check(&format!("O:{}D:(A;CIIO;RC;;;S-1-3-4)", USER), &[], READ_CONTROL | WRITE_DAC);
+        assert_eq!(res.granted_access, READ_CONTROL | WRITE_DAC);
+    }
+
+    #[test]
+    fn deny_aces_do_not_remove_implicit_owner_rights() {
+        let res = check(&format!("O:{}D:(D;;RCWD;;;{})", USER, GROUP), &[], READ_CONTROL | WRITE_DAC);
+        assert_eq!(res.granted_access, READ_CONTROL | WRITE_DAC);
+    }
+
+    #[test]
+    fn conditional_aces_are_evaluated_as_unknown() {
+        let res = check(&format!("D:(XA;;WP;;;{};(Member_of {{SID(BA)}}))", USER), &[], WRITE_PROP);
+        assert_eq!(res.granted_access, 0);
+        let res = check(&format!("D:(XD;;WP;;;{};(Member_of {{SID(BA)}}))(A;;WP;;;{})", GROUP, USER), &[], WRITE_PROP);
+        assert_eq!(res.granted_access, 0);
+        let tree = get_object_type_tree();
+        let res = check(&format!("D:(ZA;;WP;{};;{};(Member_of {{SID(BA)}}))", PROPERTY_SET, USER), &tree, WRITE_PROP);
+        assert_eq!(res.object_type_granted_access, vec![0; 4]);
+    }
+}
@@ -0,0 +1,140 @@
+use core::fmt::{Display, Formatter};
+use std::convert::TryFrom;
+use bitflags::bitflags;
+use crate::access_check::map_generic_rights;
+use crate::error::AuthzError;
+
+bitflags! {
+    // Access rights of Active Directory objects, as stored in ACE access masks
+    pub struct AccessMask: u32 {
+        // Directory service specific rights
+        const CREATE_CHILD = 0x0000_0001;
+        const DELETE_CHILD = 0x0000_0002;
+        const LIST_CHILDREN = 0x0000_0004;
+        const SELF = 0x0000_0008; // validated write
+        const READ_PROP = 0x0000_0010;
+        const WRITE_PROP = 0x0000_0020;
+        const DELETE_TREE = 0x0000_0040;
+        const LIST_OBJECT = 0x0000_0080;
+        const CONTROL_ACCESS = 0x0000_0100;
+
+        // Standard rights
+        const DELETE = 0x0001_0000;
+        const READ_CONTROL = 0x0002_0000;
+        const WRITE_DAC = 0x0004_0000;
+        const WRITE_OWNER = 0x0008_0000;
+        const SYNCHRONIZE = 0x0010_0000;
+        const ACCESS_SYSTEM_SECURITY = 0x0100_0000;
+        const MAXIMUM_ALLOWED = 0x0200_0000;
+
+        // Generic rights, mapped to the composites below when ACEs are applied to objects
+        const GENERIC_ALL = 0x1000_0000;
+        const GENERIC_EXECUTE = 0x2000_0000;
+        const GENERIC_WRITE = 0x4000_0000;
+        const GENERIC_READ = 0x8000_0000;
+
+        // Composites used by the generic rights mapping of Active Directory objects
+        const DS_GENERIC_READ = 0x0002_0094;
+        const DS_GENERIC_WRITE = 0x0002_0028;
+        const DS_GENERIC_EXECUTE = 0x0002_0004;
+        const FULL_CONTROL = 0x000F_01FF;
+
+        // Bits with no meaning on Active Directory objects, kept as is so that any mask found in
+        // an ACE can be represented
+        const UNDEFINED = 0x0CE0_FE00;
+    }
+}
+
+// Names used to parse and format access masks. Composites come first so that they are used
+// instead of the rights they contain when formatting.
+const ACCESS_MASK_NAMES: &[(&str, AccessMask)] = &[
+    ("FULL_CONTROL", AccessMask::FULL_CONTROL),
+    ("CREATE_CHILD", AccessMask::CREATE_CHILD),
+    ("DELETE_CHILD", AccessMask::DELETE_CHILD),
+    ("LIST_CHILDREN", AccessMask::LIST_CHILDREN),
+    ("SELF", AccessMask::SELF),
+    ("READ_PROP", AccessMask::READ_PROP),
+    ("WRITE_PROP", AccessMask::WRITE_PROP),
+    ("DELETE_TREE", AccessMask::DELETE_TREE),
+    ("LIST_OBJECT", AccessMask::LIST_OBJECT),
+    ("CONTROL_ACCESS", AccessMask::CONTROL_ACCESS),
+    ("DELETE", AccessMask::DELETE),
+    ("READ_CONTROL", AccessMask::READ_CONTROL),
+    ("WRITE_DAC", AccessMask::WRITE_DAC),
+    ("WRITE_OWNER", AccessMask::WRITE_OWNER),
+    ("SYNCHRONIZE", AccessMask::SYNCHRONIZE),
+    ("ACCESS_SYSTEM_SECURITY", AccessMask::ACCESS_SYSTEM_SECURITY),
+    ("MAXIMUM_ALLOWED", AccessMask::MAXIMUM_ALLOWED),
+    ("GENERIC_ALL", AccessMask::GENERIC_ALL),
+    ("GENERIC_EXECUTE", AccessMask::GENERIC_EXECUTE),
+    ("GENERIC_WRITE", AccessMask::GENERIC_WRITE),
+    ("GENERIC_READ", AccessMask::GENERIC_READ),
+];
+
+impl AccessMask {
+    // Replaces generic rights with the Active Directory rights they are mapped to
+    pub fn map_generic(&self) -> Self {
+        Self::from_bits_truncate(map_generic_rights(self.bits()))
+    }
+
+    // Returns true if all standard and DS-specific rights are granted, directly or through
+    // GENERIC_ALL
+    pub fn is_full_control(&self) -> bool {
+        self.map_generic().contains(Self::FULL_CONTROL)
+    }
+
+    // Returns the names of rights in this mask, using composites where possible
+    pub fn get_names(&self) -> Vec<&'static str> {
+        let mut res = vec![];
+        let mut left = *self;
+        for (name, mask) in ACCESS_MASK_NAMES {
+            if left.contains(*mask) {
+                res.push(*name);
+                left.remove(*mask);
+            }
+        }
+        res
+    }
+}
+
+// Formatted as rights separated by " | ", e.g. "FULL_CONTROL | ACCESS_SYSTEM_SECURITY", with
+// undefined bits as a hexadecimal number
+impl Display for AccessMask {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "0");
+        }
+        let mut names: Vec<String> = self.get_names().into_iter().map(str::to_owned).collect();
+        if self.intersects(Self::UNDEFINED) {
+            names.push(format!("0x{:X}", (*self & Self::UNDEFINED).bits()));
+        }
+        write!(f, "{}", names.join(" | "))
+    }
+}
+
+// Parses rights separated by "|", each being a name (e.g. GENERIC_ALL, case insensitive) or
+// a decimal or hexadecimal (0x prefix) number. Unknown names are rejected.
+impl TryFrom<&str> for AccessMask {
+    type Error = AuthzError;
+
+    fn try_from(str: &str) -> Result<Self, Self::Error> {
+        let mut res = AccessMask::empty();
+        for part in str.split('|') {
+            let part = part.trim();
+            let mask = if let Some(hex) = part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")) {
+                u32::from_str_radix(hex, 16).ok().and_then(AccessMask::from_bits)
+            } else if !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()) {
+                part.parse::<u32>().ok().and_then(AccessMask::from_bits)
+            } else {
+                ACCESS_MASK_NAMES.iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(part))
+                    .map(|(_, mask)| *mask)
+            };
+            match mask {
+                Some(mask) => res |= mask,
+                None => return Err(AuthzError::InvalidAccessMask(str.to_owned())),
+            }
+        }
+        Ok(res)
+    }
+}
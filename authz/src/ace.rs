@@ -1,19 +1,48 @@
 use core::fmt::Display;
+use std::convert::TryInto;
 use crate::error::AuthzError;
-use windows::Win32::Security::{ACE_HEADER, ACCESS_ALLOWED_ACE, ACCESS_DENIED_ACE, ACCESS_ALLOWED_CALLBACK_ACE, ACCESS_ALLOWED_CALLBACK_OBJECT_ACE, ACE_OBJECT_TYPE_PRESENT, ACE_INHERITED_OBJECT_TYPE_PRESENT, ACCESS_DENIED_CALLBACK_ACE, ACCESS_DENIED_CALLBACK_OBJECT_ACE, ACCESS_ALLOWED_OBJECT_ACE, ACCESS_DENIED_OBJECT_ACE, SYSTEM_AUDIT_ACE, SYSTEM_AUDIT_OBJECT_ACE, SYSTEM_AUDIT_CALLBACK_OBJECT_ACE, SYSTEM_AUDIT_CALLBACK_ACE, SYSTEM_MANDATORY_LABEL_ACE, INHERITED_ACE, CONTAINER_INHERIT_ACE, OBJECT_INHERIT_ACE, INHERIT_ONLY_ACE, NO_PROPAGATE_INHERIT_ACE};
-use windows::Win32::System::SystemServices::{ACCESS_ALLOWED_ACE_TYPE, ACCESS_ALLOWED_OBJECT_ACE_TYPE, ACCESS_ALLOWED_CALLBACK_ACE_TYPE, ACCESS_DENIED_ACE_TYPE, ACCESS_DENIED_OBJECT_ACE_TYPE, ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE, ACCESS_DENIED_CALLBACK_ACE_TYPE, ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE, SYSTEM_AUDIT_ACE_TYPE, SYSTEM_AUDIT_CALLBACK_ACE_TYPE, SYSTEM_AUDIT_OBJECT_ACE_TYPE, SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE, SYSTEM_MANDATORY_LABEL_ACE_TYPE};
-use crate::{Sid, Guid};
-use windows::Win32::Foundation::PSID;
+use crate::utils::read_u32;
+use crate::{AccessMask, Sid, Guid, ConditionalExpression, ClaimSecurityAttribute, IntegrityLevel, MandatoryPolicy};
+
+pub(crate) const ACCESS_ALLOWED_ACE_TYPE: u8 = 0x00;
+pub(crate) const ACCESS_DENIED_ACE_TYPE: u8 = 0x01;
+pub(crate) const SYSTEM_AUDIT_ACE_TYPE: u8 = 0x02;
+pub(crate) const ACCESS_ALLOWED_OBJECT_ACE_TYPE: u8 = 0x05;
+pub(crate) const ACCESS_DENIED_OBJECT_ACE_TYPE: u8 = 0x06;
+pub(crate) const SYSTEM_AUDIT_OBJECT_ACE_TYPE: u8 = 0x07;
+pub(crate) const ACCESS_ALLOWED_CALLBACK_ACE_TYPE: u8 = 0x09;
+pub(crate) const ACCESS_DENIED_CALLBACK_ACE_TYPE: u8 = 0x0A;
+pub(crate) const ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE: u8 = 0x0B;
+pub(crate) const ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE: u8 = 0x0C;
+pub(crate) const SYSTEM_AUDIT_CALLBACK_ACE_TYPE: u8 = 0x0D;
+pub(crate) const SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE: u8 = 0x0F;
+pub(crate) const SYSTEM_MANDATORY_LABEL_ACE_TYPE: u8 = 0x11;
+pub(crate) const SYSTEM_RESOURCE_ATTRIBUTE_ACE_TYPE: u8 = 0x12;
+pub(crate) const SYSTEM_SCOPED_POLICY_ID_ACE_TYPE: u8 = 0x13;
+
+pub const OBJECT_INHERIT_ACE: u8 = 0x01;
+pub const CONTAINER_INHERIT_ACE: u8 = 0x02;
+pub const NO_PROPAGATE_INHERIT_ACE: u8 = 0x04;
+pub const INHERIT_ONLY_ACE: u8 = 0x08;
+pub const INHERITED_ACE: u8 = 0x10;
+pub const SUCCESSFUL_ACCESS_ACE_FLAG: u8 = 0x40;
+pub const FAILED_ACCESS_ACE_FLAG: u8 = 0x80;
+
+pub const ACE_OBJECT_TYPE_PRESENT: u32 = 0x1;
+pub const ACE_INHERITED_OBJECT_TYPE_PRESENT: u32 = 0x2;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ace {
     pub trustee: Sid,
-    pub access_mask: u32,
+    pub access_mask: AccessMask,
     pub flags: u8,
     pub type_specific: AceType,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serial", serde(tag = "type", rename_all = "snake_case"))]
 pub enum AceType {
     // Discretionnary access ACEs
     AccessAllowed,
@@ -22,11 +51,14 @@ pub enum AceType {
         object_type: Option<Guid>,
         inherited_object_type: Option<Guid>,
     },
-    AccessAllowedCallback,
+    AccessAllowedCallback {
+        application_data: Vec<u8>,
+    },
     AccessAllowedCallbackObject {
         flags: u32,
         object_type: Option<Guid>,
         inherited_object_type: Option<Guid>,
+        application_data: Vec<u8>,
     },
     AccessDenied,
     AccessDeniedObject {
@@ -34,15 +66,20 @@ pub enum AceType {
         object_type: Option<Guid>,
         inherited_object_type: Option<Guid>,
     },
-    AccessDeniedCallback,
+    AccessDeniedCallback {
+        application_data: Vec<u8>,
+    },
     AccessDeniedCallbackObject {
         flags: u32,
         object_type: Option<Guid>,
         inherited_object_type: Option<Guid>,
+        application_data: Vec<u8>,
     },
     // System ACEs
     Audit,
-    AuditCallback,
+    AuditCallback {
+        application_data: Vec<u8>,
+    },
     AuditObject {
         flags: u32,
         object_type: Option<Guid>,
@@ -52,250 +89,325 @@ pub enum AceType {
         flags: u32,
         object_type: Option<Guid>,
         inherited_object_type: Option<Guid>,
+        application_data: Vec<u8>,
     },
+    // Integrity level (trustee) and policy (access mask) of the object
     MandatoryLabel,
+    ResourceAttribute {
+        attribute: ClaimSecurityAttribute,
+    },
+    // Central access policy ID (trustee) which applies to the object
+    ScopedPolicyId,
+}
+
+impl AceType {
+    pub(crate) fn get_type_code(&self) -> u8 {
+        match self {
+            AceType::AccessAllowed => ACCESS_ALLOWED_ACE_TYPE,
+            AceType::AccessAllowedObject { .. } => ACCESS_ALLOWED_OBJECT_ACE_TYPE,
+            AceType::AccessAllowedCallback { .. } => ACCESS_ALLOWED_CALLBACK_ACE_TYPE,
+            AceType::AccessAllowedCallbackObject { .. } => ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE,
+            AceType::AccessDenied => ACCESS_DENIED_ACE_TYPE,
+            AceType::AccessDeniedObject { .. } => ACCESS_DENIED_OBJECT_ACE_TYPE,
+            AceType::AccessDeniedCallback { .. } => ACCESS_DENIED_CALLBACK_ACE_TYPE,
+            AceType::AccessDeniedCallbackObject { .. } => ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE,
+            AceType::Audit => SYSTEM_AUDIT_ACE_TYPE,
+            AceType::AuditCallback { .. } => SYSTEM_AUDIT_CALLBACK_ACE_TYPE,
+            AceType::AuditObject { .. } => SYSTEM_AUDIT_OBJECT_ACE_TYPE,
+            AceType::AuditCallbackObject { .. } => SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE,
+            AceType::MandatoryLabel => SYSTEM_MANDATORY_LABEL_ACE_TYPE,
+            AceType::ResourceAttribute { .. } => SYSTEM_RESOURCE_ATTRIBUTE_ACE_TYPE,
+            AceType::ScopedPolicyId => SYSTEM_SCOPED_POLICY_ID_ACE_TYPE,
+        }
+    }
+
+    fn get_application_data_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match self {
+            AceType::AccessAllowedCallback { application_data } |
+            AceType::AccessAllowedCallbackObject { application_data, .. } |
+            AceType::AccessDeniedCallback { application_data } |
+            AceType::AccessDeniedCallbackObject { application_data, .. } |
+            AceType::AuditCallback { application_data } |
+            AceType::AuditCallbackObject { application_data, .. } => Some(application_data),
+            _ => None,
+        }
+    }
+
+    // Returns the flags of object ACEs, or None for ACE types without object GUIDs
+    pub(crate) fn get_object_flags(&self) -> Option<u32> {
+        match self {
+            AceType::AccessAllowedObject { flags, .. } |
+            AceType::AccessAllowedCallbackObject { flags, .. } |
+            AceType::AccessDeniedObject { flags, .. } |
+            AceType::AccessDeniedCallbackObject { flags, .. } |
+            AceType::AuditObject { flags, .. } |
+            AceType::AuditCallbackObject { flags, .. } => Some(*flags),
+            _ => None,
+        }
+    }
 }
 
 impl Ace {
     pub fn from_bytes(slice: &[u8]) -> Result<Self, AuthzError> {
-        let header = unsafe { *(slice.as_ptr() as *const ACE_HEADER) };
-        let acetype = u32::from(header.AceType);
-        // Note: we copy the header locally here, but we cannot do that for type-specific
-        // ACE structs, since their last variable-size SidStart field would only partially
-        // be copied.
-        // Note: parsing is tolerant in this case for other data appended after SIDs, but this
-        // is actually a good thing since this possibility is explicitly allowed by specifications.
-        let (trustee, access_mask, type_specific) = if acetype == ACCESS_ALLOWED_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_ALLOWED_ACE;
-            let sid = unsafe { Sid::from_ptr(PSID(&(*ace).SidStart as *const _ as isize))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessAllowed)
-        } else if acetype == ACCESS_ALLOWED_OBJECT_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_ALLOWED_OBJECT_ACE;
-            let (object_type, inherited_object_type, sid) = unsafe {
-                if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 && ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), Some(Guid::from((*ace).InheritedObjectType)), &(*ace).SidStart as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), None, &(*ace).InheritedObjectType as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (None, Some(Guid::from((*ace).ObjectType)), &(*ace).InheritedObjectType as *const _ as isize)
-                } else {
-                    (None, None, &(*ace).ObjectType as *const _ as isize)
-                }
-            };
-            let sid = unsafe { Sid::from_ptr(PSID(sid))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessAllowedObject {
-                flags: unsafe { (*ace).Flags.0 },
-                object_type,
-                inherited_object_type,
-            })
-        } else if acetype == ACCESS_ALLOWED_CALLBACK_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_ALLOWED_CALLBACK_ACE;
-            let sid = unsafe { Sid::from_ptr(PSID(&(*ace).SidStart as *const _ as isize))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessAllowedCallback)
-        } else if acetype == ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_ALLOWED_CALLBACK_OBJECT_ACE;
-            let (object_type, inherited_object_type, sid) = unsafe {
-                if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 && ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), Some(Guid::from((*ace).InheritedObjectType)), &(*ace).SidStart as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), None, &(*ace).InheritedObjectType as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (None, Some(Guid::from((*ace).ObjectType)), &(*ace).InheritedObjectType as *const _ as isize)
-                } else {
-                    (None, None, &(*ace).ObjectType as *const _ as isize)
-                }
-            };
-            let sid = unsafe { Sid::from_ptr(PSID(sid))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessAllowedCallbackObject {
-                flags: unsafe { (*ace).Flags.0 },
-                object_type,
-                inherited_object_type,
-            })
-        } else if acetype == ACCESS_DENIED_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_DENIED_ACE;
-            let sid = unsafe { Sid::from_ptr(PSID(&(*ace).SidStart as *const _ as isize))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessDenied)
-        } else if acetype == ACCESS_DENIED_OBJECT_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_DENIED_OBJECT_ACE;
-            let (object_type, inherited_object_type, sid) = unsafe {
-                if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 && ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), Some(Guid::from((*ace).InheritedObjectType)), &(*ace).SidStart as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), None, &(*ace).InheritedObjectType as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (None, Some(Guid::from((*ace).ObjectType)), &(*ace).InheritedObjectType as *const _ as isize)
-                } else {
-                    (None, None, &(*ace).ObjectType as *const _ as isize)
-                }
-            };
-            let sid = unsafe { Sid::from_ptr(PSID(sid))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessDeniedObject {
-                flags: unsafe { (*ace).Flags.0 },
-                object_type,
-                inherited_object_type,
-            })
-        } else if acetype == ACCESS_DENIED_CALLBACK_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_DENIED_CALLBACK_ACE;
-            let sid = unsafe { Sid::from_ptr(PSID(&(*ace).SidStart as *const _ as isize))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessDeniedCallback)
-        } else if acetype == ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE {
-            let ace = slice.as_ptr() as *const ACCESS_DENIED_CALLBACK_OBJECT_ACE;
-            let (object_type, inherited_object_type, sid) = unsafe {
-                if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 && ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), Some(Guid::from((*ace).InheritedObjectType)), &(*ace).SidStart as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), None, &(*ace).InheritedObjectType as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (None, Some(Guid::from((*ace).ObjectType)), &(*ace).InheritedObjectType as *const _ as isize)
-                } else {
-                    (None, None, &(*ace).ObjectType as *const _ as isize)
-                }
-            };
-            let sid = unsafe { Sid::from_ptr(PSID(sid))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AccessDeniedCallbackObject {
-                flags: unsafe { (*ace).Flags.0 },
-                object_type,
-                inherited_object_type,
-            })
-        } else if acetype == SYSTEM_AUDIT_ACE_TYPE {
-            let ace = slice.as_ptr() as *const SYSTEM_AUDIT_ACE;
-            let sid = unsafe { Sid::from_ptr(PSID(&(*ace).SidStart as *const _ as isize))? };
-            (sid, unsafe { (*ace).Mask }, AceType::Audit)
-        } else if acetype == SYSTEM_AUDIT_CALLBACK_ACE_TYPE {
-            let ace = slice.as_ptr() as *const SYSTEM_AUDIT_CALLBACK_ACE;
-            let sid = unsafe { Sid::from_ptr(PSID(&(*ace).SidStart as *const _ as isize))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AuditCallback)
-        } else if acetype == SYSTEM_AUDIT_OBJECT_ACE_TYPE {
-            let ace = slice.as_ptr() as *const SYSTEM_AUDIT_OBJECT_ACE;
-            let (object_type, inherited_object_type, sid) = unsafe {
-                if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 && ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), Some(Guid::from((*ace).InheritedObjectType)), &(*ace).SidStart as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), None, &(*ace).InheritedObjectType as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (None, Some(Guid::from((*ace).ObjectType)), &(*ace).InheritedObjectType as *const _ as isize)
-                } else {
-                    (None, None, &(*ace).ObjectType as *const _ as isize)
-                }
-            };
-            let sid = unsafe { Sid::from_ptr(PSID(sid))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AuditObject {
-                flags: unsafe { (*ace).Flags.0 },
-                object_type,
-                inherited_object_type,
-            })
-        } else if acetype == SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE {
-            let ace = slice.as_ptr() as *const SYSTEM_AUDIT_CALLBACK_OBJECT_ACE;
-            let (object_type, inherited_object_type, sid) = unsafe {
-                if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 && ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), Some(Guid::from((*ace).InheritedObjectType)), &(*ace).SidStart as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (Some(Guid::from((*ace).ObjectType)), None, &(*ace).InheritedObjectType as *const _ as isize)
-                } else if ((*ace).Flags.0 & ACE_INHERITED_OBJECT_TYPE_PRESENT.0) != 0 {
-                    (None, Some(Guid::from((*ace).ObjectType)), &(*ace).InheritedObjectType as *const _ as isize)
-                } else {
-                    (None, None, &(*ace).ObjectType as *const _ as isize)
-                }
-            };
-            let sid = unsafe { Sid::from_ptr(PSID(sid))? };
-            (sid, unsafe { (*ace).Mask }, AceType::AuditCallbackObject {
-                flags: unsafe { (*ace).Flags.0 },
-                object_type,
-                inherited_object_type,
-            })
-        } else if acetype == SYSTEM_MANDATORY_LABEL_ACE_TYPE {
-            let ace = slice.as_ptr() as *const SYSTEM_MANDATORY_LABEL_ACE;
-            let sid = unsafe { Sid::from_ptr(PSID(&(*ace).SidStart as *const _ as isize))? };
-            (sid, unsafe { (*ace).Mask }, AceType::MandatoryLabel)
-        } else {
-            unimplemented!("unsupported ACE type {}, please contact project maintainers with the following debug information: {:?}", acetype, slice);
+        // Header: AceType (u8), AceFlags (u8), AceSize (u16), then the access mask (u32)
+        // which is common to all ACE types we support.
+        if slice.len() < 8 {
+            return Err(AuthzError::InvalidAce(slice.to_vec()));
+        }
+        let acetype = slice[0];
+        let flags = slice[1];
+        let access_mask = AccessMask::from_bits_truncate(read_u32(slice, 4).expect("assertion failed: ACE shorter than its header"));
+        let (mut type_specific, sid_offset) = match acetype {
+            ACCESS_ALLOWED_ACE_TYPE => (AceType::AccessAllowed, 8),
+            ACCESS_DENIED_ACE_TYPE => (AceType::AccessDenied, 8),
+            SYSTEM_AUDIT_ACE_TYPE => (AceType::Audit, 8),
+            ACCESS_ALLOWED_CALLBACK_ACE_TYPE => (AceType::AccessAllowedCallback { application_data: vec![] }, 8),
+            ACCESS_DENIED_CALLBACK_ACE_TYPE => (AceType::AccessDeniedCallback { application_data: vec![] }, 8),
+            SYSTEM_AUDIT_CALLBACK_ACE_TYPE => (AceType::AuditCallback { application_data: vec![] }, 8),
+            SYSTEM_MANDATORY_LABEL_ACE_TYPE => (AceType::MandatoryLabel, 8),
+            SYSTEM_SCOPED_POLICY_ID_ACE_TYPE => (AceType::ScopedPolicyId, 8),
+            SYSTEM_RESOURCE_ATTRIBUTE_ACE_TYPE => {
+                let trustee = Sid::from_bytes_prefix(&slice[8..])?;
+                let attribute = ClaimSecurityAttribute::from_bytes(&slice[8 + trustee.as_bytes().len()..])?;
+                (AceType::ResourceAttribute { attribute }, 8)
+            },
+            ACCESS_ALLOWED_OBJECT_ACE_TYPE |
+            ACCESS_DENIED_OBJECT_ACE_TYPE |
+            SYSTEM_AUDIT_OBJECT_ACE_TYPE |
+            ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE |
+            ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE |
+            SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE => {
+                let (flags, object_type, inherited_object_type, sid_offset) = Self::parse_object_fields(slice)?;
+                (match acetype {
+                    ACCESS_ALLOWED_OBJECT_ACE_TYPE => AceType::AccessAllowedObject { flags, object_type, inherited_object_type },
+                    ACCESS_DENIED_OBJECT_ACE_TYPE => AceType::AccessDeniedObject { flags, object_type, inherited_object_type },
+                    SYSTEM_AUDIT_OBJECT_ACE_TYPE => AceType::AuditObject { flags, object_type, inherited_object_type },
+                    ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE => AceType::AccessAllowedCallbackObject { flags, object_type, inherited_object_type, application_data: vec![] },
+                    ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE => AceType::AccessDeniedCallbackObject { flags, object_type, inherited_object_type, application_data: vec![] },
+                    _ => AceType::AuditCallbackObject { flags, object_type, inherited_object_type, application_data: vec![] },
+                }, sid_offset)
+            },
+            _ => return Err(AuthzError::UnsupportedAceType { bytes: slice.to_vec(), ace_type: acetype }),
         };
+        let trustee = Sid::from_bytes_prefix(&slice[sid_offset..])?;
+        // Callback ACEs store application data (e.g. a conditional expression) after their SID.
+        // Note: parsing is tolerant for other data appended after SIDs in other ACE types, but this
+        // is actually a good thing since this possibility is explicitly allowed by specifications.
+        if let Some(application_data) = type_specific.get_application_data_mut() {
+            *application_data = slice[sid_offset + trustee.as_bytes().len()..].to_vec();
+        }
         Ok(Self {
             trustee,
             access_mask,
-            flags: header.AceFlags,
+            flags,
             type_specific,
         })
     }
 
+    // Object ACEs have a u32 of flags after their access mask, then up to two GUIDs depending
+    // on these flags. Returns these fields, along with the offset at which the trustee SID starts.
+    fn parse_object_fields(slice: &[u8]) -> Result<(u32, Option<Guid>, Option<Guid>, usize), AuthzError> {
+        let object_flags = match read_u32(slice, 8) {
+            Some(f) => f,
+            None => return Err(AuthzError::InvalidAce(slice.to_vec())),
+        };
+        let mut offset = 12;
+        let read_guid = |offset: &mut usize| -> Result<Guid, AuthzError> {
+            let bytes = match slice.get(*offset..*offset + 16) {
+                Some(b) => b,
+                None => return Err(AuthzError::InvalidAce(slice.to_vec())),
+            };
+            *offset += 16;
+            Ok(Guid::from_bytes(bytes.try_into().expect("assertion failed: GUID slice of invalid length")))
+        };
+        let object_type = if (object_flags & ACE_OBJECT_TYPE_PRESENT) != 0 {
+            Some(read_guid(&mut offset)?)
+        } else {
+            None
+        };
+        let inherited_object_type = if (object_flags & ACE_INHERITED_OBJECT_TYPE_PRESENT) != 0 {
+            Some(read_guid(&mut offset)?)
+        } else {
+            None
+        };
+        Ok((object_flags, object_type, inherited_object_type, offset))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = vec![self.type_specific.get_type_code(), self.flags, 0, 0];
+        res.extend_from_slice(&self.access_mask.bits().to_le_bytes());
+        if let Some(object_flags) = self.type_specific.get_object_flags() {
+            // Presence flags are recomputed from the GUIDs actually stored, so that they are
+            // always consistent with what follows
+            let object_type = self.get_object_type();
+            let inherited_object_type = self.get_inherited_object_type();
+            let mut object_flags = object_flags & !(ACE_OBJECT_TYPE_PRESENT | ACE_INHERITED_OBJECT_TYPE_PRESENT);
+            if object_type.is_some() {
+                object_flags |= ACE_OBJECT_TYPE_PRESENT;
+            }
+            if inherited_object_type.is_some() {
+                object_flags |= ACE_INHERITED_OBJECT_TYPE_PRESENT;
+            }
+            res.extend_from_slice(&object_flags.to_le_bytes());
+            if let Some(guid) = object_type {
+                res.extend_from_slice(&guid.to_bytes());
+            }
+            if let Some(guid) = inherited_object_type {
+                res.extend_from_slice(&guid.to_bytes());
+            }
+        }
+        res.extend_from_slice(self.trustee.as_bytes());
+        if let Some(application_data) = self.get_application_data() {
+            res.extend_from_slice(application_data);
+            // ACEs must be DWORD-aligned
+            res.resize(res.len().div_ceil(4) * 4, 0);
+        } else if let AceType::ResourceAttribute { attribute } = &self.type_specific {
+            res.extend_from_slice(&attribute.to_bytes());
+            res.resize(res.len().div_ceil(4) * 4, 0);
+        }
+        let size = res.len() as u16;
+        res[2..4].copy_from_slice(&size.to_le_bytes());
+        res
+    }
+
     pub fn is_inherited(&self) -> bool {
-        (self.flags & (INHERITED_ACE.0 as u8)) != 0
+        (self.flags & INHERITED_ACE) != 0
     }
 
     pub fn get_container_inherit(&self) -> bool {
-        (self.flags & (CONTAINER_INHERIT_ACE.0 as u8)) != 0
+        (self.flags & CONTAINER_INHERIT_ACE) != 0
     }
 
     pub fn get_object_inherit(&self) -> bool {
-        (self.flags & (OBJECT_INHERIT_ACE.0 as u8)) != 0
+        (self.flags & OBJECT_INHERIT_ACE) != 0
     }
 
     pub fn get_inherit_only(&self) -> bool {
-        (self.flags & (INHERIT_ONLY_ACE.0 as u8)) != 0
+        (self.flags & INHERIT_ONLY_ACE) != 0
     }
 
     pub fn get_no_propagate(&self) -> bool {
-        (self.flags & (NO_PROPAGATE_INHERIT_ACE.0 as u8)) != 0
+        (self.flags & NO_PROPAGATE_INHERIT_ACE) != 0
     }
 
     pub fn get_object_type(&self) -> Option<&Guid> {
         match &self.type_specific {
-            AceType::AccessAllowed { .. } => None,
+            AceType::AccessAllowed => None,
             AceType::AccessAllowedObject { object_type, .. } => object_type.as_ref(),
             AceType::AccessAllowedCallback { .. } => None,
             AceType::AccessAllowedCallbackObject { object_type, .. } => object_type.as_ref(),
-            AceType::AccessDenied {  .. } => None,
+            AceType::AccessDenied => None,
             AceType::AccessDeniedObject { object_type, .. } => object_type.as_ref(),
             AceType::AccessDeniedCallback { .. } => None,
             AceType::AccessDeniedCallbackObject { object_type, .. } => object_type.as_ref(),
-            AceType::Audit { .. } => None,
+            AceType::Audit => None,
             AceType::AuditCallback { .. } => None,
             AceType::AuditObject { object_type, .. } => object_type.as_ref(),
             AceType::AuditCallbackObject { object_type, .. } => object_type.as_ref(),
-            AceType::MandatoryLabel { .. } => None,
+            AceType::MandatoryLabel => None,
+            AceType::ResourceAttribute { .. } => None,
+            AceType::ScopedPolicyId => None,
         }
     }
 
     pub fn get_inherited_object_type(&self) -> Option<&Guid> {
         match &self.type_specific {
-            AceType::AccessAllowed { .. } => None,
+            AceType::AccessAllowed => None,
             AceType::AccessAllowedObject { inherited_object_type, .. } => inherited_object_type.as_ref(),
             AceType::AccessAllowedCallback { .. } => None,
             AceType::AccessAllowedCallbackObject { inherited_object_type, .. } => inherited_object_type.as_ref(),
-            AceType::AccessDenied {  .. } => None,
+            AceType::AccessDenied => None,
             AceType::AccessDeniedObject { inherited_object_type, .. } => inherited_object_type.as_ref(),
             AceType::AccessDeniedCallback { .. } => None,
             AceType::AccessDeniedCallbackObject { inherited_object_type, .. } => inherited_object_type.as_ref(),
-            AceType::Audit { .. } => None,
+            AceType::Audit => None,
             AceType::AuditCallback { .. } => None,
             AceType::AuditObject { inherited_object_type, .. } => inherited_object_type.as_ref(),
             AceType::AuditCallbackObject { inherited_object_type, .. } => inherited_object_type.as_ref(),
-            AceType::MandatoryLabel { .. } => None,
+            AceType::MandatoryLabel => None,
+            AceType::ResourceAttribute { .. } => None,
+            AceType::ScopedPolicyId => None,
+        }
+    }
+
+    // Returns the application data of callback ACEs, or None for other ACE types
+    pub fn get_application_data(&self) -> Option<&[u8]> {
+        match &self.type_specific {
+            AceType::AccessAllowedCallback { application_data } |
+            AceType::AccessAllowedCallbackObject { application_data, .. } |
+            AceType::AccessDeniedCallback { application_data } |
+            AceType::AccessDeniedCallbackObject { application_data, .. } |
+            AceType::AuditCallback { application_data } |
+            AceType::AuditCallbackObject { application_data, .. } => Some(application_data),
+            _ => None,
+        }
+    }
+
+    // Returns the conditional expression of callback ACEs, or None if this ACE has none (not a
+    // callback ACE, or application data in another format than conditional expressions)
+    pub fn get_condition(&self) -> Result<Option<ConditionalExpression>, AuthzError> {
+        match self.get_application_data() {
+            Some(data) if ConditionalExpression::is_conditional_expression(data) => Ok(Some(ConditionalExpression::from_bytes(data)?)),
+            _ => Ok(None),
+        }
+    }
+
+    // Returns the integrity level of mandatory label ACEs
+    pub fn get_integrity_level(&self) -> Option<IntegrityLevel> {
+        match &self.type_specific {
+            AceType::MandatoryLabel => IntegrityLevel::from_sid(&self.trustee),
+            _ => None,
+        }
+    }
+
+    // Returns the access policy of mandatory label ACEs (which accesses are denied to lower
+    // integrity levels)
+    pub fn get_mandatory_policy(&self) -> Option<MandatoryPolicy> {
+        match &self.type_specific {
+            AceType::MandatoryLabel => Some(MandatoryPolicy::from_access_mask(self.access_mask.bits())),
+            _ => None,
+        }
+    }
+
+    pub fn get_resource_attribute(&self) -> Option<&ClaimSecurityAttribute> {
+        match &self.type_specific {
+            AceType::ResourceAttribute { attribute } => Some(attribute),
+            _ => None,
+        }
+    }
+
+    // Returns the ID of the central access policy applied by scoped policy ID ACEs
+    pub fn get_central_access_policy_id(&self) -> Option<&Sid> {
+        match &self.type_specific {
+            AceType::ScopedPolicyId => Some(&self.trustee),
+            _ => None,
         }
     }
 
     pub fn grants_access(&self) -> bool {
         match &self.type_specific {
-            AceType::AccessAllowed { .. } => true,
+            AceType::AccessAllowed => true,
             AceType::AccessAllowedObject { .. } => true,
             AceType::AccessAllowedCallback { .. } => true,
             AceType::AccessAllowedCallbackObject { .. } => true,
-            AceType::AccessDenied { .. } => false,
+            AceType::AccessDenied => false,
             AceType::AccessDeniedObject { .. }=> false,
             AceType::AccessDeniedCallback { .. } => false,
             AceType::AccessDeniedCallbackObject { .. } => false,
-            AceType::Audit { .. } => false,
+            AceType::Audit => false,
             AceType::AuditCallback { .. } => false,
             AceType::AuditObject { .. } => false,
             AceType::AuditCallbackObject { .. } => false,
-            AceType::MandatoryLabel { .. } => false,
+            AceType::MandatoryLabel => false,
+            AceType::ResourceAttribute { .. } => false,
+            AceType::ScopedPolicyId => false,
         }
     }
 }
 
 impl Display for Ace {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
-        write!(f, "{} access mask 0x{:X}", &self.trustee, &self.access_mask)?;
+        write!(f, "{} access mask 0x{:X}", &self.trustee, self.access_mask.bits())?;
         if self.get_no_propagate() {
             write!(f, " no_propagate")?;
         }
@@ -314,6 +426,132 @@ impl Display for Ace {
         if let AceType::AccessAllowedObject { inherited_object_type: Some(guid), .. } = &self.type_specific {
             write!(f, " inh_obj_type={}", guid)?;
         }
+        if let Some(level) = self.get_integrity_level() {
+            write!(f, " integrity_level={}", level)?;
+        }
+        if let Some(policy) = self.get_mandatory_policy() {
+            write!(f, " {}", policy)?;
+        }
+        if let Some(attribute) = self.get_resource_attribute() {
+            write!(f, " resource_attribute={}", attribute)?;
+        }
+        if let Some(capid) = self.get_central_access_policy_id() {
+            write!(f, " central_access_policy={}", capid)?;
+        }
+        if let Ok(Some(condition)) = self.get_condition() {
+            write!(f, " condition={}", condition)?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::{ClaimValues, ConditionalExpression, UnaryOperator};
+    use crate::label::SYSTEM_MANDATORY_LABEL_NO_WRITE_UP;
+
+    fn sid(str: &str) -> Sid {
+        Sid::try_from(str).expect("invalid SID string")
+    }
+
+    fn guid(str: &str) -> Guid {
+        Guid::try_from(str).expect("invalid GUID string")
+    }
+
+    fn attribute(values: ClaimValues) -> AceType {
+        AceType::ResourceAttribute {
+            attribute: ClaimSecurityAttribute {
+                name: "Project".to_owned(),
+                flags: 0,
+                values,
+            },
+        }
+    }
+
+    // One ACE of each type we support, with and without their optional fields
+    pub(crate) fn sample_aces() -> Vec<Ace> {
+        let user = guid("bf967aba-0de6-11d0-a285-00aa003049e2");
+        let reset_password = guid("00299570-246d-11d0-a768-00aa006e0529");
+        let condition = ConditionalExpression::Unary {
+            operator: UnaryOperator::MemberOf,
+            operand: Box::new(ConditionalExpression::Composite(vec![ConditionalExpression::Sid(sid("S-1-5-32-544"))])),
+        }.to_bytes();
+        let ace = |type_specific: AceType| Ace {
+            trustee: sid("S-1-5-21-1-2-3-1104"),
+            access_mask: AccessMask::DS_GENERIC_READ,
+            flags: CONTAINER_INHERIT_ACE | INHERIT_ONLY_ACE,
+            type_specific,
+        };
+        vec![
+            ace(AceType::AccessAllowed),
+            ace(AceType::AccessDenied),
+            ace(AceType::Audit),
+            ace(AceType::AccessAllowedObject { flags: ACE_OBJECT_TYPE_PRESENT | ACE_INHERITED_OBJECT_TYPE_PRESENT, object_type: Some(reset_password), inherited_object_type: Some(user) }),
+            ace(AceType::AccessAllowedObject { flags: ACE_INHERITED_OBJECT_TYPE_PRESENT, object_type: None, inherited_object_type: Some(user) }),
+            ace(AceType::AccessDeniedObject { flags: ACE_OBJECT_TYPE_PRESENT, object_type: Some(reset_password), inherited_object_type: None }),
+            ace(AceType::AuditObject { flags: 0, object_type: None, inherited_object_type: None }),
+            ace(AceType::AccessAllowedCallback { application_data: condition.clone() }),
+            ace(AceType::AccessDeniedCallback { application_data: vec![] }),
+            ace(AceType::AuditCallback { application_data: vec![1, 2, 3, 4] }),
+            ace(AceType::AccessAllowedCallbackObject { flags: ACE_OBJECT_TYPE_PRESENT, object_type: Some(reset_password), inherited_object_type: None, application_data: condition.clone() }),
+            ace(AceType::AccessDeniedCallbackObject { flags: ACE_INHERITED_OBJECT_TYPE_PRESENT, object_type: None, inherited_object_type: Some(user), application_data: condition }),
+            ace(AceType::AuditCallbackObject { flags: 0, object_type: None, inherited_object_type: None, application_data: vec![0xff; 8] }),
+            Ace {
+                trustee: sid("S-1-16-8192"),
+                access_mask: AccessMask::from_bits_truncate(SYSTEM_MANDATORY_LABEL_NO_WRITE_UP),
+                flags: 0,
+                type_specific: AceType::MandatoryLabel,
+            },
+            Ace {
+                trustee: sid("S-1-17-1"),
+                access_mask: AccessMask::empty(),
+                flags: 0,
+                type_specific: AceType::ScopedPolicyId,
+            },
+            Ace { trustee: sid("S-1-1-0"), access_mask: AccessMask::empty(), flags: 0, type_specific: attribute(ClaimValues::Int64(vec![-1, 0, i64::MAX])) },
+            Ace { trustee: sid("S-1-1-0"), access_mask: AccessMask::empty(), flags: 0, type_specific: attribute(ClaimValues::Uint64(vec![u64::MAX])) },
+            Ace { trustee: sid("S-1-1-0"), access_mask: AccessMask::empty(), flags: 0, type_specific: attribute(ClaimValues::String(vec!["Secret".to_owned(), "".to_owned()])) },
+            Ace { trustee: sid("S-1-1-0"), access_mask: AccessMask::empty(), flags: 0, type_specific: attribute(ClaimValues::Sid(vec![sid("S-1-5-32-544")])) },
+            Ace { trustee: sid("S-1-1-0"), access_mask: AccessMask::empty(), flags: 0, type_specific: attribute(ClaimValues::Boolean(vec![true, false])) },
+            Ace { trustee: sid("S-1-1-0"), access_mask: AccessMask::empty(), flags: 0, type_specific: attribute(ClaimValues::OctetString(vec![vec![0xde, 0xad], vec![]])) },
+        ]
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        for ace in sample_aces() {
+            let bytes = ace.to_bytes();
+            assert_eq!(bytes.len() % 4, 0, "{:?} is not DWORD-aligned", ace);
+            assert_eq!(u16::from_le_bytes([bytes[2], bytes[3]]) as usize, bytes.len());
+            let parsed = Ace::from_bytes(&bytes).expect("unable to parse ACE");
+            assert_eq!(parsed, ace);
+            assert_eq!(parsed.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn known_bytes_are_parsed() {
+        // (A;CI;GA;;;WD)
+        let bytes = [0x00, 0x02, 0x14, 0x00, 0x00, 0x00, 0x00, 0x10, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+        let ace = Ace::from_bytes(&bytes).expect("unable to parse ACE");
+        assert_eq!(ace, Ace {
+            trustee: sid("S-1-1-0"),
+            access_mask: AccessMask::GENERIC_ALL,
+            flags: CONTAINER_INHERIT_ACE,
+            type_specific: AceType::AccessAllowed,
+        });
+        assert_eq!(ace.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn invalid_bytes_are_rejected() {
+        let bytes = sample_aces()[3].to_bytes();
+        for len in [0, 7, 12, 28, 44, bytes.len() - 1] {
+            assert!(Ace::from_bytes(&bytes[..len]).is_err(), "truncated to {} bytes", len);
+        }
+        let mut bytes = sample_aces()[0].to_bytes();
+        bytes[0] = 0x42;
+        assert!(matches!(Ace::from_bytes(&bytes), Err(AuthzError::UnsupportedAceType { ace_type: 0x42, .. })));
+    }
+}
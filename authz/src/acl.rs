@@ -1,54 +1,68 @@
-use core::fmt::Display;
+use core::fmt::{Display, Formatter};
 use crate::error::AuthzError;
-use crate::utils::get_last_error;
-use windows::Win32::Security::{ACL_SIZE_INFORMATION, GetAclInformation, AclSizeInformation, ACL, GetAce, ACE_HEADER};
-use windows::core::alloc::fmt::Formatter;
+use crate::utils::read_u16;
 use crate::Ace;
-use std::ptr::null;
 
-#[derive(Debug)]
+pub(crate) const ACL_REVISION: u8 = 2;
+pub(crate) const ACL_REVISION_DS: u8 = 4;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
 pub struct Acl {
     pub aces: Vec<Ace>,
 }
 
 impl Acl {
     pub fn from(slice: &[u8]) -> Result<Self, AuthzError> {
-        let mut info = ACL_SIZE_INFORMATION {
-            AceCount: 0,
-            AclBytesInUse: 0,
-            AclBytesFree: 0
-        };
-        let succeeded = unsafe { GetAclInformation(slice.as_ptr() as *mut ACL, &mut info as *mut _ as *mut _, std::mem::size_of_val(&info) as u32, AclSizeInformation) };
-        if !succeeded.as_bool() {
-            return Err(AuthzError::GetAclInformationFailed { bytes: slice.to_vec(), code: get_last_error() });
+        // Header: AclRevision (u8), Sbz1 (u8), AclSize (u16), AceCount (u16), Sbz2 (u16)
+        if slice.len() < 8 || (slice[0] != ACL_REVISION && slice[0] != ACL_REVISION_DS) {
+            return Err(AuthzError::InvalidAcl(slice.to_vec()));
         }
-        let expected_size = (info.AclBytesInUse + info.AclBytesFree) as usize;
+        let expected_size = read_u16(slice, 2).expect("assertion failed: ACL shorter than its header") as usize;
         if expected_size != slice.len() {
-            return Err(AuthzError::UnexpectedAclSize { bytes: slice.to_vec(), expected_size: expected_size as usize });
+            return Err(AuthzError::UnexpectedAclSize { bytes: slice.to_vec(), expected_size });
         }
-        let mut aces = Vec::new();
-        for ace_index in 0..info.AceCount {
-            let mut ace: *const ACE_HEADER = null() as *const _;
-            let succeeded = unsafe { GetAce(slice.as_ptr() as *const _, ace_index, &mut ace as *mut _ as *mut _) };
-            if !succeeded.as_bool() || (ace as usize) < (slice.as_ptr() as usize) || (ace as usize + std::mem::size_of::<ACE_HEADER>()) > (slice.as_ptr() as usize + slice.len())  {
-                return Err(AuthzError::GetAceFailed { bytes: slice.to_vec(), ace_index, code: get_last_error() });
-            }
-            let expected_size = unsafe { (*ace).AceSize } as usize;
-            if (ace as usize + expected_size) > (slice.as_ptr() as usize + slice.len()) {
+        let ace_count = read_u16(slice, 4).expect("assertion failed: ACL shorter than its header");
+        let mut aces = Vec::with_capacity(ace_count as usize);
+        let mut offset = 8;
+        for ace_index in 0..(ace_count as u32) {
+            let expected_size = match read_u16(slice, offset + 2) {
+                Some(size) => size as usize,
+                None => return Err(AuthzError::UnexpectedAceSize { bytes: slice.to_vec(), ace_index, expected_size: 0 }),
+            };
+            if expected_size < 4 || (offset + expected_size) > slice.len() {
                 return Err(AuthzError::UnexpectedAceSize { bytes: slice.to_vec(), ace_index, expected_size });
             }
-            let offset = (ace as usize) - (slice.as_ptr() as usize);
             aces.push(Ace::from_bytes(&slice[offset..offset+expected_size])?);
+            offset += expected_size;
         }
 
         Ok(Self {
             aces,
         })
     }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Object ACEs can only be stored in ACLs with the DS revision
+        let revision = if self.aces.iter().any(|ace| ace.type_specific.get_object_flags().is_some()) {
+            ACL_REVISION_DS
+        } else {
+            ACL_REVISION
+        };
+        let mut res = vec![revision, 0, 0, 0];
+        res.extend_from_slice(&(self.aces.len() as u16).to_le_bytes());
+        res.extend_from_slice(&[0, 0]);
+        for ace in &self.aces {
+            res.extend_from_slice(&ace.to_bytes());
+        }
+        let size = res.len() as u16;
+        res[2..4].copy_from_slice(&size.to_le_bytes());
+        res
+    }
 }
 
 impl Display for Acl {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "ACL={:?}", self)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,202 @@
+use crate::{Ace, Acl};
+
+// One step to reorder an ACL: remove the ACE at index `from`, then insert it at index `to`
+// (both indexes are relative to the ACL as it is right before this step)
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
+pub struct AceMove {
+    pub ace: Ace,
+    pub from: usize,
+    pub to: usize,
+}
+
+impl Acl {
+    pub fn is_canonical(&self) -> bool {
+        self.aces.windows(2).all(|pair| get_canonical_rank(&pair[0]) <= get_canonical_rank(&pair[1]))
+    }
+
+    // Returns the same ACEs in canonical order: explicit deny ACEs, explicit allow ACEs, then
+    // inherited ACEs. The relative order of ACEs within each group is preserved (inherited ACEs
+    // from a grandparent can legitimately come after those of a parent, whatever their type).
+    pub fn get_canonical_form(&self) -> Acl {
+        Acl {
+            aces: self.get_canonical_order().into_iter().map(|i| self.aces[i].clone()).collect(),
+        }
+    }
+
+    // Returns the ACEs which are not in their canonical place, i.e. those which need to be moved
+    // by get_reorder_moves()
+    pub fn get_misplaced_aces(&self) -> Vec<&Ace> {
+        let in_place = self.get_aces_in_place();
+        (0..self.aces.len())
+            .filter(|i| !in_place[*i])
+            .map(|i| &self.aces[i])
+            .collect()
+    }
+
+    // Returns a minimal list of moves which transforms this ACL into its canonical form: the
+    // longest subsequence of ACEs already in canonical order stays in place, every other ACE is
+    // moved once.
+    pub fn get_reorder_moves(&self) -> Vec<AceMove> {
+        let order = self.get_canonical_order();
+        let in_place = self.get_aces_in_place();
+        let mut current: Vec<usize> = (0..self.aces.len()).collect();
+        let mut moves = vec![];
+        for (target_pos, &ace_index) in order.iter().enumerate() {
+            if in_place[ace_index] {
+                continue;
+            }
+            let from = current.iter().position(|&i| i == ace_index).expect("assertion failed: ACE lost while reordering");
+            current.remove(from);
+            // Insert the ACE right after the one which precedes it in canonical order: since
+            // we process ACEs in canonical order, that one is already at its final place.
+            let to = if target_pos == 0 {
+                0
+            } else {
+                current.iter().position(|&i| i == order[target_pos - 1]).expect("assertion failed: ACE lost while reordering") + 1
+            };
+            current.insert(to, ace_index);
+            moves.push(AceMove {
+                ace: self.aces[ace_index].clone(),
+                from,
+                to,
+            });
+        }
+        moves
+    }
+
+    // Returns the indexes of ACEs, sorted in canonical order
+    fn get_canonical_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.aces.len()).collect();
+        order.sort_by_key(|&i| get_canonical_rank(&self.aces[i])); // stable sort
+        order
+    }
+
+    // Returns, for each ACE, whether it is part of the longest subsequence of ACEs already in
+    // canonical order (which do not need to be moved)
+    fn get_aces_in_place(&self) -> Vec<bool> {
+        let order = self.get_canonical_order();
+        let mut target_positions = vec![0; self.aces.len()];
+        for (target_pos, &ace_index) in order.iter().enumerate() {
+            target_positions[ace_index] = target_pos;
+        }
+        // Longest increasing subsequence of target positions, in O(n log n)
+        let mut tails: Vec<usize> = vec![]; // index of the ACE ending the best subsequence of each length
+        let mut predecessors: Vec<Option<usize>> = vec![None; self.aces.len()];
+        for ace_index in 0..self.aces.len() {
+            let pos = tails.partition_point(|&i| target_positions[i] < target_positions[ace_index]);
+            predecessors[ace_index] = if pos > 0 { Some(tails[pos - 1]) } else { None };
+            if pos == tails.len() {
+                tails.push(ace_index);
+            } else {
+                tails[pos] = ace_index;
+            }
+        }
+        let mut in_place = vec![false; self.aces.len()];
+        let mut cursor = tails.last().copied();
+        while let Some(ace_index) = cursor {
+            in_place[ace_index] = true;
+            cursor = predecessors[ace_index];
+        }
+        in_place
+    }
+}
+
+fn get_canonical_rank(ace: &Ace) -> u8 {
+    if ace.is_inherited() {
+        2
+    } else if ace.grants_access() {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use crate::{SecurityDescriptor, Sid};
+
+    // Builds an ACL from one letter per ACE: D/A for explicit deny/allow, d/a for inherited
+    // deny/allow. Each ACE gets its own trustee, so that they can all be told apart.
+    fn get_acl(aces: &str) -> Acl {
+        let domain_sid = Sid::try_from("S-1-5-21-1-2-3").expect("invalid SID");
+        let mut sddl = "D:".to_owned();
+        for (rid, kind) in aces.chars().enumerate() {
+            let (ace_type, flags) = match kind {
+                'D' => ("D", ""),
+                'A' => ("A", ""),
+                'd' => ("D", "ID"),
+                'a' => ("A", "ID"),
+                _ => panic!("unknown ACE kind {}", kind),
+            };
+            sddl.push_str(&format!("({};{};WP;;;S-1-5-21-1-2-3-{})", ace_type, flags, 1000 + rid));
+        }
+        let sd = SecurityDescriptor::from_str(&sddl, &domain_sid, &domain_sid).expect("unable to parse SDDL");
+        sd.dacl.expect("no DACL")
+    }
+
+    fn apply_moves(acl: &Acl, moves: &[AceMove]) -> Acl {
+        let mut aces = acl.aces.clone();
+        for ace_move in moves {
+            let ace = aces.remove(ace_move.from);
+            assert_eq!(ace, ace_move.ace);
+            aces.insert(ace_move.to, ace);
+        }
+        Acl { aces }
+    }
+
+    // Quadratic longest increasing subsequence, to check the one computed by the ACL
+    fn get_lis_length(acl: &Acl) -> usize {
+        let order = acl.get_canonical_order();
+        let mut target_positions = vec![0; order.len()];
+        for (target_pos, &ace_index) in order.iter().enumerate() {
+            target_positions[ace_index] = target_pos;
+        }
+        let mut lengths: Vec<usize> = vec![];
+        for i in 0..target_positions.len() {
+            let best = (0..i)
+                .filter(|&j| target_positions[j] < target_positions[i])
+                .map(|j| lengths[j])
+                .max()
+                .unwrap_or(0);
+            lengths.push(best + 1);
+        }
+        lengths.into_iter().max().unwrap_or(0)
+    }
+
+    #[test]
+    fn moves_produce_canonical_form() {
+        for aces in ["AD", "aAD", "DaDAdA", "AaAdDDA", "daDAAdDa", "aAaDAdDDAa"] {
+            let acl = get_acl(aces);
+            let moves = acl.get_reorder_moves();
+            let reordered = apply_moves(&acl, &moves);
+            assert!(reordered.is_canonical(), "{} is not canonical once reordered", aces);
+            assert_eq!(reordered, acl.get_canonical_form(), "{} reordered differently", aces);
+            assert_eq!(moves.len(), acl.aces.len() - get_lis_length(&acl), "{} not reordered in a minimal number of moves", aces);
+            assert_eq!(moves.len(), acl.get_misplaced_aces().len());
+        }
+    }
+
+    #[test]
+    fn canonical_acls_need_no_moves() {
+        for aces in ["", "D", "A", "DDAA", "DAdaad", "Aad"] {
+            let acl = get_acl(aces);
+            assert!(acl.is_canonical(), "{} should be canonical", aces);
+            assert_eq!(acl.get_reorder_moves(), vec![]);
+            assert!(acl.get_misplaced_aces().is_empty());
+            assert_eq!(acl.get_canonical_form(), acl);
+        }
+    }
+
+    #[test]
+    fn inherited_aces_keep_their_order() {
+        let acl = get_acl("adAdaD");
+        let canonical = acl.get_canonical_form();
+        // Inherited ACEs come last, in their original order whatever their type
+        let expected: Vec<&Ace> = [5, 2, 0, 1, 3, 4].iter().map(|&i| &acl.aces[i]).collect();
+        assert_eq!(canonical.aces.iter().collect::<Vec<&Ace>>(), expected);
+        assert_eq!(apply_moves(&acl, &acl.get_reorder_moves()), canonical);
+    }
+}
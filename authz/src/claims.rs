@@ -0,0 +1,236 @@
+use core::fmt::{Display, Formatter};
+use crate::error::AuthzError;
+use crate::sddl::sid_to_sddl;
+use crate::utils::{read_u16, read_u32};
+use crate::Sid;
+
+const CLAIM_SECURITY_ATTRIBUTE_TYPE_INT64: u16 = 0x0001;
+const CLAIM_SECURITY_ATTRIBUTE_TYPE_UINT64: u16 = 0x0002;
+const CLAIM_SECURITY_ATTRIBUTE_TYPE_STRING: u16 = 0x0003;
+const CLAIM_SECURITY_ATTRIBUTE_TYPE_SID: u16 = 0x0005;
+const CLAIM_SECURITY_ATTRIBUTE_TYPE_BOOLEAN: u16 = 0x0006;
+const CLAIM_SECURITY_ATTRIBUTE_TYPE_OCTET_STRING: u16 = 0x0010;
+
+const CLAIM_SECURITY_ATTRIBUTE_NON_INHERITABLE: u32 = 0x0001;
+const CLAIM_SECURITY_ATTRIBUTE_VALUE_CASE_SENSITIVE: u32 = 0x0002;
+const CLAIM_SECURITY_ATTRIBUTE_DISABLED: u32 = 0x0010;
+
+// Values of a claim security attribute, which all have the same type. Fully qualified binary
+// names are not listed since they cannot be used in resource attribute ACEs.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serial", serde(tag = "type", content = "values", rename_all = "snake_case"))]
+pub enum ClaimValues {
+    Int64(Vec<i64>),
+    Uint64(Vec<u64>),
+    String(Vec<String>),
+    Sid(Vec<Sid>),
+    Boolean(Vec<bool>),
+    OctetString(Vec<Vec<u8>>),
+}
+
+// Resource attribute stored in SYSTEM_RESOURCE_ATTRIBUTE_ACEs, in the same format as
+// CLAIM_SECURITY_ATTRIBUTE_RELATIVE_V1
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClaimSecurityAttribute {
+    pub name: String,
+    pub flags: u32,
+    pub values: ClaimValues,
+}
+
+impl ClaimValues {
+    pub fn len(&self) -> usize {
+        match self {
+            ClaimValues::Int64(v) => v.len(),
+            ClaimValues::Uint64(v) => v.len(),
+            ClaimValues::String(v) => v.len(),
+            ClaimValues::Sid(v) => v.len(),
+            ClaimValues::Boolean(v) => v.len(),
+            ClaimValues::OctetString(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get_type_code(&self) -> u16 {
+        match self {
+            ClaimValues::Int64(_) => CLAIM_SECURITY_ATTRIBUTE_TYPE_INT64,
+            ClaimValues::Uint64(_) => CLAIM_SECURITY_ATTRIBUTE_TYPE_UINT64,
+            ClaimValues::String(_) => CLAIM_SECURITY_ATTRIBUTE_TYPE_STRING,
+            ClaimValues::Sid(_) => CLAIM_SECURITY_ATTRIBUTE_TYPE_SID,
+            ClaimValues::Boolean(_) => CLAIM_SECURITY_ATTRIBUTE_TYPE_BOOLEAN,
+            ClaimValues::OctetString(_) => CLAIM_SECURITY_ATTRIBUTE_TYPE_OCTET_STRING,
+        }
+    }
+
+    // Two-letter type used in SDDL resource attributes
+    pub(crate) fn get_sddl_type(&self) -> &'static str {
+        match self {
+            ClaimValues::Int64(_) => "TI",
+            ClaimValues::Uint64(_) => "TU",
+            ClaimValues::String(_) => "TS",
+            ClaimValues::Sid(_) => "TD",
+            ClaimValues::Boolean(_) => "TB",
+            ClaimValues::OctetString(_) => "TX",
+        }
+    }
+
+    fn to_sddl_values(&self) -> Vec<String> {
+        match self {
+            ClaimValues::Int64(v) => v.iter().map(|i| i.to_string()).collect(),
+            ClaimValues::Uint64(v) => v.iter().map(|i| i.to_string()).collect(),
+            ClaimValues::String(v) => v.iter().map(|s| format!("\"{}\"", s)).collect(),
+            ClaimValues::Sid(v) => v.iter().map(|sid| format!("SID({})", sid_to_sddl(sid))).collect(),
+            ClaimValues::Boolean(v) => v.iter().map(|b| if *b { "1".to_owned() } else { "0".to_owned() }).collect(),
+            ClaimValues::OctetString(v) => v.iter().map(|bytes| bytes.iter().map(|b| format!("{:02x}", b)).collect()).collect(),
+        }
+    }
+}
+
+impl ClaimSecurityAttribute {
+    pub fn from_bytes(slice: &[u8]) -> Result<Self, AuthzError> {
+        Self::parse(slice).ok_or_else(|| AuthzError::InvalidClaimSecurityAttribute(slice.to_vec()))
+    }
+
+    fn parse(slice: &[u8]) -> Option<Self> {
+        // Header: name offset (u32), value type (u16), reserved (u16), flags (u32), value count
+        // (u32), then one u32 offset per value. All offsets are relative to the start of the header.
+        let name = read_utf16_nul_terminated(slice, read_u32(slice, 0)? as usize)?;
+        let value_type = read_u16(slice, 4)?;
+        let flags = read_u32(slice, 8)?;
+        let value_count = read_u32(slice, 12)? as usize;
+        let mut offsets = vec![];
+        for i in 0..value_count {
+            offsets.push(read_u32(slice, 16usize.checked_add(i.checked_mul(4)?)?)? as usize);
+        }
+        let values = match value_type {
+            CLAIM_SECURITY_ATTRIBUTE_TYPE_INT64 => ClaimValues::Int64(offsets.iter()
+                .map(|o| read_u64(slice, *o).map(|v| v as i64))
+                .collect::<Option<Vec<i64>>>()?),
+            CLAIM_SECURITY_ATTRIBUTE_TYPE_UINT64 => ClaimValues::Uint64(offsets.iter()
+                .map(|o| read_u64(slice, *o))
+                .collect::<Option<Vec<u64>>>()?),
+            CLAIM_SECURITY_ATTRIBUTE_TYPE_STRING => ClaimValues::String(offsets.iter()
+                .map(|o| read_utf16_nul_terminated(slice, *o))
+                .collect::<Option<Vec<String>>>()?),
+            CLAIM_SECURITY_ATTRIBUTE_TYPE_SID => ClaimValues::Sid(offsets.iter()
+                .map(|o| read_octet_string(slice, *o).and_then(|bytes| Sid::from_bytes(bytes).ok()))
+                .collect::<Option<Vec<Sid>>>()?),
+            CLAIM_SECURITY_ATTRIBUTE_TYPE_BOOLEAN => ClaimValues::Boolean(offsets.iter()
+                .map(|o| read_u64(slice, *o).map(|v| v != 0))
+                .collect::<Option<Vec<bool>>>()?),
+            CLAIM_SECURITY_ATTRIBUTE_TYPE_OCTET_STRING => ClaimValues::OctetString(offsets.iter()
+                .map(|o| read_octet_string(slice, *o).map(|bytes| bytes.to_vec()))
+                .collect::<Option<Vec<Vec<u8>>>>()?),
+            _ => return None,
+        };
+        Some(Self {
+            name,
+            flags,
+            values,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let value_count = self.values.len();
+        let header_size = 16 + 4 * value_count;
+        let mut data = vec![];
+        let mut offsets = vec![];
+        write_utf16_nul_terminated(&self.name, &mut data);
+        let mut push_value = |bytes: &[u8], data: &mut Vec<u8>| {
+            offsets.push((header_size + data.len()) as u32);
+            data.extend_from_slice(bytes);
+        };
+        match &self.values {
+            ClaimValues::Int64(v) => v.iter().for_each(|i| push_value(&i.to_le_bytes(), &mut data)),
+            ClaimValues::Uint64(v) => v.iter().for_each(|i| push_value(&i.to_le_bytes(), &mut data)),
+            ClaimValues::Boolean(v) => v.iter().for_each(|b| push_value(&(*b as u64).to_le_bytes(), &mut data)),
+            ClaimValues::String(v) => v.iter().for_each(|s| {
+                let mut bytes = vec![];
+                write_utf16_nul_terminated(s, &mut bytes);
+                push_value(&bytes, &mut data);
+            }),
+            ClaimValues::Sid(v) => v.iter().for_each(|sid| {
+                let mut bytes = (sid.as_bytes().len() as u32).to_le_bytes().to_vec();
+                bytes.extend_from_slice(sid.as_bytes());
+                push_value(&bytes, &mut data);
+            }),
+            ClaimValues::OctetString(v) => v.iter().for_each(|octets| {
+                let mut bytes = (octets.len() as u32).to_le_bytes().to_vec();
+                bytes.extend_from_slice(octets);
+                push_value(&bytes, &mut data);
+            }),
+        }
+
+        let mut res = Vec::with_capacity(header_size + data.len());
+        res.extend_from_slice(&(header_size as u32).to_le_bytes()); // name comes first
+        res.extend_from_slice(&self.values.get_type_code().to_le_bytes());
+        res.extend_from_slice(&0u16.to_le_bytes());
+        res.extend_from_slice(&self.flags.to_le_bytes());
+        res.extend_from_slice(&(value_count as u32).to_le_bytes());
+        for offset in offsets {
+            res.extend_from_slice(&offset.to_le_bytes());
+        }
+        res.extend_from_slice(&data);
+        res
+    }
+
+    pub fn is_case_sensitive(&self) -> bool {
+        (self.flags & CLAIM_SECURITY_ATTRIBUTE_VALUE_CASE_SENSITIVE) != 0
+    }
+
+    pub fn is_inheritable(&self) -> bool {
+        (self.flags & CLAIM_SECURITY_ATTRIBUTE_NON_INHERITABLE) == 0
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        (self.flags & CLAIM_SECURITY_ATTRIBUTE_DISABLED) != 0
+    }
+}
+
+// Displayed as in the last field of SDDL resource attribute ACEs: ("name",type,flags,value,...)
+impl Display for ClaimSecurityAttribute {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "(\"{}\",{},0x{:X}", self.name, self.values.get_sddl_type(), self.flags)?;
+        for value in self.values.to_sddl_values() {
+            write!(f, ",{}", value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+fn read_u64(slice: &[u8], offset: usize) -> Option<u64> {
+    let bytes = slice.get(offset..offset.checked_add(8)?)?;
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes);
+    Some(u64::from_le_bytes(value))
+}
+
+fn read_octet_string(slice: &[u8], offset: usize) -> Option<&[u8]> {
+    let len = read_u32(slice, offset)? as usize;
+    let start = offset.checked_add(4)?;
+    slice.get(start..start.checked_add(len)?)
+}
+
+fn read_utf16_nul_terminated(slice: &[u8], offset: usize) -> Option<String> {
+    let mut utf16 = vec![];
+    let mut pos = offset;
+    loop {
+        let c = read_u16(slice, pos)?;
+        if c == 0 {
+            break;
+        }
+        utf16.push(c);
+        pos += 2;
+    }
+    String::from_utf16(&utf16).ok()
+}
+
+fn write_utf16_nul_terminated(s: &str, res: &mut Vec<u8>) {
+    for c in s.encode_utf16().chain(std::iter::once(0)) {
+        res.extend_from_slice(&c.to_le_bytes());
+    }
+}
@@ -0,0 +1,520 @@
+use core::fmt::{Display, Formatter};
+use std::convert::TryInto;
+use crate::error::AuthzError;
+use crate::sddl::sid_to_sddl;
+use crate::utils::read_u32;
+use crate::Sid;
+
+// Conditional expressions are stored in callback ACEs as a bytecode, starting with this magic
+// and followed by tokens in postfix notation (see MS-DTYP 2.4.4.17)
+const CONDITIONAL_EXPRESSION_MAGIC: &[u8] = b"artx";
+
+// Expressions (and composite literals) nested deeper than this are rejected, so that they can be
+// displayed, compared and dropped without exhausting the stack
+pub(crate) const MAX_NESTING_DEPTH: usize = 256;
+
+const TOKEN_PADDING: u8 = 0x00;
+const TOKEN_INT8: u8 = 0x01;
+const TOKEN_INT16: u8 = 0x02;
+const TOKEN_INT32: u8 = 0x03;
+const TOKEN_INT64: u8 = 0x04;
+const TOKEN_UNICODE_STRING: u8 = 0x10;
+const TOKEN_OCTET_STRING: u8 = 0x18;
+const TOKEN_COMPOSITE: u8 = 0x50;
+const TOKEN_SID: u8 = 0x51;
+const TOKEN_LOCAL_ATTRIBUTE: u8 = 0xF8;
+const TOKEN_USER_ATTRIBUTE: u8 = 0xF9;
+const TOKEN_RESOURCE_ATTRIBUTE: u8 = 0xFA;
+const TOKEN_DEVICE_ATTRIBUTE: u8 = 0xFB;
+
+pub(crate) const UNARY_OPERATORS: &[(u8, UnaryOperator)] = &[
+    (0x87, UnaryOperator::Exists),
+    (0x89, UnaryOperator::MemberOf),
+    (0x8A, UnaryOperator::DeviceMemberOf),
+    (0x8B, UnaryOperator::MemberOfAny),
+    (0x8C, UnaryOperator::DeviceMemberOfAny),
+    (0x8D, UnaryOperator::NotExists),
+    (0x90, UnaryOperator::NotMemberOf),
+    (0x91, UnaryOperator::NotDeviceMemberOf),
+    (0x92, UnaryOperator::NotMemberOfAny),
+    (0x93, UnaryOperator::NotDeviceMemberOfAny),
+    (0xA2, UnaryOperator::Not),
+];
+
+pub(crate) const BINARY_OPERATORS: &[(u8, BinaryOperator)] = &[
+    (0x80, BinaryOperator::Equals),
+    (0x81, BinaryOperator::NotEquals),
+    (0x82, BinaryOperator::LessThan),
+    (0x83, BinaryOperator::LessThanOrEqual),
+    (0x84, BinaryOperator::GreaterThan),
+    (0x85, BinaryOperator::GreaterThanOrEqual),
+    (0x86, BinaryOperator::Contains),
+    (0x88, BinaryOperator::AnyOf),
+    (0x8E, BinaryOperator::NotContains),
+    (0x8F, BinaryOperator::NotAnyOf),
+    (0xA0, BinaryOperator::And),
+    (0xA1, BinaryOperator::Or),
+];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum AttributeScope {
+    Local,
+    User,
+    Resource,
+    Device,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum IntegerSign {
+    Positive,
+    Negative,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum IntegerBase {
+    Octal,
+    Decimal,
+    Hexadecimal,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum UnaryOperator {
+    Exists,
+    NotExists,
+    MemberOf,
+    NotMemberOf,
+    DeviceMemberOf,
+    NotDeviceMemberOf,
+    MemberOfAny,
+    NotMemberOfAny,
+    DeviceMemberOfAny,
+    NotDeviceMemberOfAny,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum BinaryOperator {
+    Equals,
+    NotEquals,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Contains,
+    NotContains,
+    AnyOf,
+    NotAnyOf,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ConditionalExpression {
+    Attribute {
+        scope: AttributeScope,
+        name: String,
+    },
+    Integer {
+        value: i64,
+        sign: IntegerSign,
+        base: IntegerBase,
+        // Size of the integer in bytes, as stored in the bytecode (1, 2, 4 or 8)
+        size: u8,
+    },
+    String(String),
+    OctetString(Vec<u8>),
+    Sid(Sid),
+    Composite(Vec<ConditionalExpression>),
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<ConditionalExpression>,
+    },
+    Binary {
+        operator: BinaryOperator,
+        left: Box<ConditionalExpression>,
+        right: Box<ConditionalExpression>,
+    },
+}
+
+impl UnaryOperator {
+    fn get_token(&self) -> u8 {
+        UNARY_OPERATORS.iter().find(|(_, op)| op == self).map(|(token, _)| *token).expect("assertion failed: unary operator without token")
+    }
+
+    pub(crate) fn get_name(&self) -> &'static str {
+        match self {
+            UnaryOperator::Exists => "Exists",
+            UnaryOperator::NotExists => "Not_Exists",
+            UnaryOperator::MemberOf => "Member_of",
+            UnaryOperator::NotMemberOf => "Not_Member_of",
+            UnaryOperator::DeviceMemberOf => "Device_Member_of",
+            UnaryOperator::NotDeviceMemberOf => "Not_Device_Member_of",
+            UnaryOperator::MemberOfAny => "Member_of_Any",
+            UnaryOperator::NotMemberOfAny => "Not_Member_of_Any",
+            UnaryOperator::DeviceMemberOfAny => "Device_Member_of_Any",
+            UnaryOperator::NotDeviceMemberOfAny => "Not_Device_Member_of_Any",
+            UnaryOperator::Not => "!",
+        }
+    }
+}
+
+impl BinaryOperator {
+    fn get_token(&self) -> u8 {
+        BINARY_OPERATORS.iter().find(|(_, op)| op == self).map(|(token, _)| *token).expect("assertion failed: binary operator without token")
+    }
+
+    pub(crate) fn get_name(&self) -> &'static str {
+        match self {
+            BinaryOperator::Equals => "==",
+            BinaryOperator::NotEquals => "!=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::LessThanOrEqual => "<=",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::GreaterThanOrEqual => ">=",
+            BinaryOperator::Contains => "Contains",
+            BinaryOperator::NotContains => "Not_Contains",
+            BinaryOperator::AnyOf => "Any_of",
+            BinaryOperator::NotAnyOf => "Not_Any_of",
+            BinaryOperator::And => "&&",
+            BinaryOperator::Or => "||",
+        }
+    }
+}
+
+impl ConditionalExpression {
+    // Returns whether the application data of a callback ACE is a conditional expression
+    pub fn is_conditional_expression(application_data: &[u8]) -> bool {
+        application_data.starts_with(CONDITIONAL_EXPRESSION_MAGIC)
+    }
+
+    pub fn from_bytes(slice: &[u8]) -> Result<Self, AuthzError> {
+        if !Self::is_conditional_expression(slice) {
+            return Err(AuthzError::InvalidConditionalExpression(slice.to_vec()));
+        }
+        // Each node is kept along with its depth
+        let mut stack: Vec<(ConditionalExpression, usize)> = vec![];
+        let mut offset = CONDITIONAL_EXPRESSION_MAGIC.len();
+        while offset < slice.len() {
+            let token = slice[offset];
+            if token == TOKEN_PADDING {
+                offset += 1;
+                continue;
+            }
+            if let Some((_, operator)) = UNARY_OPERATORS.iter().find(|(t, _)| *t == token) {
+                let (operand, depth) = stack.pop().ok_or_else(|| AuthzError::InvalidConditionalExpression(slice.to_vec()))?;
+                if depth >= MAX_NESTING_DEPTH {
+                    return Err(AuthzError::InvalidConditionalExpression(slice.to_vec()));
+                }
+                stack.push((ConditionalExpression::Unary { operator: *operator, operand: Box::new(operand) }, depth + 1));
+                offset += 1;
+            } else if let Some((_, operator)) = BINARY_OPERATORS.iter().find(|(t, _)| *t == token) {
+                let (right, right_depth) = stack.pop().ok_or_else(|| AuthzError::InvalidConditionalExpression(slice.to_vec()))?;
+                let (left, left_depth) = stack.pop().ok_or_else(|| AuthzError::InvalidConditionalExpression(slice.to_vec()))?;
+                let depth = core::cmp::max(left_depth, right_depth);
+                if depth >= MAX_NESTING_DEPTH {
+                    return Err(AuthzError::InvalidConditionalExpression(slice.to_vec()));
+                }
+                stack.push((ConditionalExpression::Binary { operator: *operator, left: Box::new(left), right: Box::new(right) }, depth + 1));
+                offset += 1;
+            } else {
+                let (operand, size) = match parse_operand(&slice[offset..], 0) {
+                    Some(res) => res,
+                    None => return Err(AuthzError::InvalidConditionalExpression(slice.to_vec())),
+                };
+                stack.push((operand, 0));
+                offset += size;
+            }
+        }
+        // A well-formed expression reduces to exactly one node
+        match (stack.pop(), stack.is_empty()) {
+            (Some((expr, _)), true) => Ok(expr),
+            _ => Err(AuthzError::InvalidConditionalExpression(slice.to_vec())),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = CONDITIONAL_EXPRESSION_MAGIC.to_vec();
+        self.write_tokens(&mut res);
+        // Application data of ACEs is padded to a multiple of 4 bytes
+        res.resize(res.len().div_ceil(4) * 4, TOKEN_PADDING);
+        res
+    }
+
+    fn write_tokens(&self, res: &mut Vec<u8>) {
+        match self {
+            ConditionalExpression::Attribute { scope, name } => {
+                res.push(match scope {
+                    AttributeScope::Local => TOKEN_LOCAL_ATTRIBUTE,
+                    AttributeScope::User => TOKEN_USER_ATTRIBUTE,
+                    AttributeScope::Resource => TOKEN_RESOURCE_ATTRIBUTE,
+                    AttributeScope::Device => TOKEN_DEVICE_ATTRIBUTE,
+                });
+                write_unicode_string(name, res);
+            },
+            ConditionalExpression::Integer { value, sign, base, size } => {
+                res.push(match size {
+                    1 => TOKEN_INT8,
+                    2 => TOKEN_INT16,
+                    4 => TOKEN_INT32,
+                    _ => TOKEN_INT64,
+                });
+                res.extend_from_slice(&value.to_le_bytes());
+                res.push(match sign {
+                    IntegerSign::Positive => 1,
+                    IntegerSign::Negative => 2,
+                    IntegerSign::None => 3,
+                });
+                res.push(match base {
+                    IntegerBase::Octal => 1,
+                    IntegerBase::Decimal => 2,
+                    IntegerBase::Hexadecimal => 3,
+                });
+            },
+            ConditionalExpression::String(str) => {
+                res.push(TOKEN_UNICODE_STRING);
+                write_unicode_string(str, res);
+            },
+            ConditionalExpression::OctetString(bytes) => {
+                res.push(TOKEN_OCTET_STRING);
+                res.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                res.extend_from_slice(bytes);
+            },
+            ConditionalExpression::Sid(sid) => {
+                res.push(TOKEN_SID);
+                res.extend_from_slice(&(sid.as_bytes().len() as u32).to_le_bytes());
+                res.extend_from_slice(sid.as_bytes());
+            },
+            ConditionalExpression::Composite(items) => {
+                let mut contents = vec![];
+                for item in items {
+                    item.write_tokens(&mut contents);
+                }
+                res.push(TOKEN_COMPOSITE);
+                res.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+                res.extend_from_slice(&contents);
+            },
+            ConditionalExpression::Unary { operator, operand } => {
+                operand.write_tokens(res);
+                res.push(operator.get_token());
+            },
+            ConditionalExpression::Binary { operator, left, right } => {
+                left.write_tokens(res);
+                right.write_tokens(res);
+                res.push(operator.get_token());
+            },
+        }
+    }
+
+    // Returns all SIDs referenced in this expression (e.g. groups in Member_of operators)
+    pub fn get_sids(&self) -> Vec<&Sid> {
+        match self {
+            ConditionalExpression::Sid(sid) => vec![sid],
+            ConditionalExpression::Composite(items) => items.iter().flat_map(|item| item.get_sids()).collect(),
+            ConditionalExpression::Unary { operand, .. } => operand.get_sids(),
+            ConditionalExpression::Binary { left, right, .. } => {
+                let mut res = left.get_sids();
+                res.extend(right.get_sids());
+                res
+            },
+            _ => vec![],
+        }
+    }
+
+    // Returns all attributes referenced in this expression
+    pub fn get_attributes(&self) -> Vec<(AttributeScope, &str)> {
+        match self {
+            ConditionalExpression::Attribute { scope, name } => vec![(*scope, name.as_str())],
+            ConditionalExpression::Composite(items) => items.iter().flat_map(|item| item.get_attributes()).collect(),
+            ConditionalExpression::Unary { operand, .. } => operand.get_attributes(),
+            ConditionalExpression::Binary { left, right, .. } => {
+                let mut res = left.get_attributes();
+                res.extend(right.get_attributes());
+                res
+            },
+            _ => vec![],
+        }
+    }
+
+    // Returns the expression as written in the last field of an SDDL ACE, which always needs
+    // to be enclosed in parenthesis (operators already are)
+    pub(crate) fn to_sddl_condition(&self) -> String {
+        match self {
+            ConditionalExpression::Unary { .. } |
+            ConditionalExpression::Binary { .. } => self.to_string(),
+            _ => format!("({})", self),
+        }
+    }
+}
+
+// Parses a literal or attribute token at the start of the given buffer, returns it along with
+// the number of bytes it spans
+fn parse_operand(slice: &[u8], depth: usize) -> Option<(ConditionalExpression, usize)> {
+    let token = *slice.first()?;
+    match token {
+        TOKEN_INT8 | TOKEN_INT16 | TOKEN_INT32 | TOKEN_INT64 => {
+            let value = i64::from_le_bytes(slice.get(1..9)?.try_into().ok()?);
+            let sign = match slice.get(9)? {
+                1 => IntegerSign::Positive,
+                2 => IntegerSign::Negative,
+                3 => IntegerSign::None,
+                _ => return None,
+            };
+            let base = match slice.get(10)? {
+                1 => IntegerBase::Octal,
+                2 => IntegerBase::Decimal,
+                3 => IntegerBase::Hexadecimal,
+                _ => return None,
+            };
+            let size = match token {
+                TOKEN_INT8 => 1,
+                TOKEN_INT16 => 2,
+                TOKEN_INT32 => 4,
+                _ => 8,
+            };
+            Some((ConditionalExpression::Integer { value, sign, base, size }, 11))
+        },
+        _ => {
+            // All other tokens are followed by the length of their contents in bytes
+            let len = read_u32(slice, 1)? as usize;
+            let contents = slice.get(5..5usize.checked_add(len)?)?;
+            let expr = match token {
+                TOKEN_UNICODE_STRING => ConditionalExpression::String(parse_unicode_string(contents)?),
+                TOKEN_OCTET_STRING => ConditionalExpression::OctetString(contents.to_vec()),
+                TOKEN_SID => ConditionalExpression::Sid(Sid::from_bytes(contents).ok()?),
+                TOKEN_COMPOSITE => {
+                    if depth >= MAX_NESTING_DEPTH {
+                        return None;
+                    }
+                    let mut items = vec![];
+                    let mut offset = 0;
+                    while offset < contents.len() {
+                        let (item, size) = parse_operand(&contents[offset..], depth + 1)?;
+                        items.push(item);
+                        offset += size;
+                    }
+                    ConditionalExpression::Composite(items)
+                },
+                TOKEN_LOCAL_ATTRIBUTE => ConditionalExpression::Attribute { scope: AttributeScope::Local, name: parse_unicode_string(contents)? },
+                TOKEN_USER_ATTRIBUTE => ConditionalExpression::Attribute { scope: AttributeScope::User, name: parse_unicode_string(contents)? },
+                TOKEN_RESOURCE_ATTRIBUTE => ConditionalExpression::Attribute { scope: AttributeScope::Resource, name: parse_unicode_string(contents)? },
+                TOKEN_DEVICE_ATTRIBUTE => ConditionalExpression::Attribute { scope: AttributeScope::Device, name: parse_unicode_string(contents)? },
+                _ => return None,
+            };
+            Some((expr, 5 + len))
+        },
+    }
+}
+
+fn parse_unicode_string(bytes: &[u8]) -> Option<String> {
+    if bytes.len() % 2 == 1 {
+        return None;
+    }
+    let utf16: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    String::from_utf16(&utf16).ok()
+}
+
+fn write_unicode_string(str: &str, res: &mut Vec<u8>) {
+    let utf16: Vec<u8> = str.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+    res.extend_from_slice(&(utf16.len() as u32).to_le_bytes());
+    res.extend_from_slice(&utf16);
+}
+
+impl Display for ConditionalExpression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ConditionalExpression::Attribute { scope: AttributeScope::Local, name } => write!(f, "{}", name),
+            ConditionalExpression::Attribute { scope: AttributeScope::User, name } => write!(f, "@User.{}", name),
+            ConditionalExpression::Attribute { scope: AttributeScope::Resource, name } => write!(f, "@Resource.{}", name),
+            ConditionalExpression::Attribute { scope: AttributeScope::Device, name } => write!(f, "@Device.{}", name),
+            ConditionalExpression::Integer { value, sign, base, .. } => {
+                let prefix = match (sign, *value < 0) {
+                    (_, true) => "-",
+                    (IntegerSign::Positive, false) => "+",
+                    _ => "",
+                };
+                let abs = value.unsigned_abs();
+                match base {
+                    IntegerBase::Octal => write!(f, "{}0{:o}", prefix, abs),
+                    IntegerBase::Decimal => write!(f, "{}{}", prefix, abs),
+                    IntegerBase::Hexadecimal => write!(f, "{}0x{:X}", prefix, abs),
+                }
+            },
+            ConditionalExpression::String(str) => write!(f, "\"{}\"", str),
+            ConditionalExpression::OctetString(bytes) => {
+                write!(f, "#")?;
+                for b in bytes {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            },
+            ConditionalExpression::Sid(sid) => write!(f, "SID({})", sid_to_sddl(sid)),
+            ConditionalExpression::Composite(items) => {
+                write!(f, "{{")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "}}")
+            },
+            ConditionalExpression::Unary { operator: UnaryOperator::Not, operand } => write!(f, "(!{})", operand),
+            ConditionalExpression::Unary { operator, operand } => write!(f, "({} {})", operator.get_name(), operand),
+            ConditionalExpression::Binary { operator, left, right } => write!(f, "({} {} {})", left, operator.get_name(), right),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_nested(inner: &[u8], operator: u8, count: usize) -> Result<ConditionalExpression, AuthzError> {
+        let mut bytes = CONDITIONAL_EXPRESSION_MAGIC.to_vec();
+        bytes.extend_from_slice(inner);
+        bytes.resize(bytes.len() + count, operator);
+        ConditionalExpression::from_bytes(&bytes)
+    }
+
+    fn composite(depth: usize) -> Vec<u8> {
+        let mut res = vec![TOKEN_UNICODE_STRING];
+        write_unicode_string("x", &mut res);
+        for _ in 0..depth {
+            let mut outer = vec![TOKEN_COMPOSITE];
+            outer.extend_from_slice(&(res.len() as u32).to_le_bytes());
+            outer.extend_from_slice(&res);
+            res = outer;
+        }
+        res
+    }
+
+    #[test]
+    fn deeply_nested_operators_are_rejected() {
+        let attribute = {
+            let mut res = vec![TOKEN_USER_ATTRIBUTE];
+            write_unicode_string("x", &mut res);
+            res
+        };
+        let expr = parse_nested(&attribute, 0xA2, MAX_NESTING_DEPTH).expect("unable to parse expression");
+        assert_eq!(ConditionalExpression::from_bytes(&expr.to_bytes()).expect("unable to parse expression"), expr);
+        assert!(parse_nested(&attribute, 0xA2, MAX_NESTING_DEPTH + 1).is_err());
+        assert!(parse_nested(&attribute, 0xA2, 60000).is_err());
+
+        let mut operands = vec![];
+        for _ in 0..=MAX_NESTING_DEPTH {
+            operands.extend_from_slice(&attribute);
+        }
+        assert!(parse_nested(&operands, 0xA0, MAX_NESTING_DEPTH).is_ok());
+        operands.extend_from_slice(&attribute);
+        assert!(parse_nested(&operands, 0xA0, MAX_NESTING_DEPTH + 1).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_composites_are_rejected() {
+        let expr = parse_nested(&composite(MAX_NESTING_DEPTH), 0x87, 1).expect("unable to parse expression");
+        assert_eq!(ConditionalExpression::from_bytes(&expr.to_bytes()).expect("unable to parse expression"), expr);
+        assert!(parse_nested(&composite(MAX_NESTING_DEPTH + 1), 0x87, 1).is_err());
+        assert!(parse_nested(&composite(10000), 0x87, 1).is_err());
+    }
+}
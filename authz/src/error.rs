@@ -1,15 +1,17 @@
-use core::fmt::Display;
-use windows::core::alloc::fmt::Formatter;
+use core::fmt::{Display, Formatter};
+use crate::ObjectTypeNode;
 
 #[derive(Debug, Clone)]
 pub enum AuthzError {
     InvalidSecurityDescriptor(Vec<u8>),
-    InvalidStringSecurityDescriptor { str: String, code: u32 },
+    InvalidStringSecurityDescriptor { str: String, reason: String },
     InvalidSidBytes(Vec<u8>),
-    InvalidSidPointer(*const u8),
-    InvalidSidString {
-        str: String,
-        code: u32,
+    InvalidSidString(String),
+    InvalidAcl(Vec<u8>),
+    InvalidAce(Vec<u8>),
+    UnsupportedAceType {
+        bytes: Vec<u8>,
+        ace_type: u8,
     },
     UnexpectedSecurityDescriptorSize {
         bytes: Vec<u8>,
@@ -28,53 +30,27 @@ pub enum AuthzError {
         bytes: Vec<u8>,
         expected_size: usize,
     },
-    GetSecurityDescriptorControlFailed {
-        bytes: Vec<u8>,
-        code: u32,
-    },
-    MakeAbsoluteSDFailed {
-        bytes: Vec<u8>,
-        code: u32,
-    },
-    GetSecurityDescriptorOwnerFailed {
-        bytes: Vec<u8>,
-        ptr: *const u8,
-        code: u32,
-    },
-    GetSecurityDescriptorGroupFailed {
-        bytes: Vec<u8>,
-        ptr: *const u8,
-        code: u32,
-    },
-    GetSecurityDescriptorDaclFailed {
+    SecurityDescriptorOffsetOutOfBounds {
         bytes: Vec<u8>,
-        ptr: *const u8,
-        code: u32,
-    },
-    GetSecurityDescriptorSaclFailed {
-        bytes: Vec<u8>,
-        ptr: *const u8,
-        code: u32,
-    },
-    GetAclInformationFailed {
-        bytes: Vec<u8>,
-        code: u32,
-    },
-    GetAceFailed {
-        bytes: Vec<u8>,
-        ace_index: u32,
-        code: u32,
+        field: &'static str,
+        offset: usize,
     },
+    InvalidObjectTypeList(Vec<ObjectTypeNode>),
+    InvalidConditionalExpression(Vec<u8>),
+    InvalidClaimSecurityAttribute(Vec<u8>),
+    InvalidAccessMask(String),
 }
 
 impl Display for AuthzError {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::InvalidSecurityDescriptor(bytes) => write!(f, "invalid security descriptor {:?}", bytes),
-            Self::InvalidStringSecurityDescriptor { str, code } => write!(f, "invalid security descriptor string \"{}\" (code {})", str, code),
+            Self::InvalidStringSecurityDescriptor { str, reason } => write!(f, "invalid security descriptor string \"{}\" ({})", str, reason),
             Self::InvalidSidBytes(bytes) => write!(f, "invalid SID {:?}", bytes),
-            Self::InvalidSidPointer(ptr) => write!(f, "invalid SID {:?}", ptr),
-            Self::InvalidSidString { str, code } => write!(f, "invalid SID string \"{}\" (code {})", str, code),
+            Self::InvalidSidString(str) => write!(f, "invalid SID string \"{}\"", str),
+            Self::InvalidAcl(bytes) => write!(f, "invalid ACL {:?}", bytes),
+            Self::InvalidAce(bytes) => write!(f, "invalid ACE {:?}", bytes),
+            Self::UnsupportedAceType { bytes, ace_type } => write!(f, "unsupported ACE type {} in {:?}", ace_type, bytes),
             Self::UnexpectedSecurityDescriptorSize { bytes, expected_size} if *expected_size > bytes.len() => write!(f, "{} leftover bytes after security descriptor {:?}", expected_size - bytes.len(), bytes),
             Self::UnexpectedSecurityDescriptorSize { bytes, expected_size} => write!(f, "{} bytes truncated from security descriptor {:?}", bytes.len() - expected_size, bytes),
             Self::UnexpectedSidSize { bytes, expected_size} if *expected_size > bytes.len() => write!(f, "{} leftover bytes after SID {:?}", expected_size - bytes.len(), bytes),
@@ -82,14 +58,11 @@ impl Display for AuthzError {
             Self::UnexpectedAclSize { bytes, expected_size} if *expected_size > bytes.len() => write!(f, "{} leftover bytes after ACL {:?}", expected_size - bytes.len(), bytes),
             Self::UnexpectedAclSize { bytes, expected_size } => write!(f, "{} bytes truncated from ACL {:?}", bytes.len() - expected_size, bytes),
             Self::UnexpectedAceSize { bytes, ace_index, expected_size } => write!(f, "ACE #{} of {} bytes is out of bounds from ACL {:?}", ace_index, expected_size, bytes),
-            Self::GetSecurityDescriptorControlFailed { bytes, code } => write!(f, "GetSecurityDescriptorControl({:?}) failed with code {}", bytes, code),
-            Self::MakeAbsoluteSDFailed { bytes, code } => write!(f, "MakeAbsoluteSD({:?}) failed with code {}", bytes, code),
-            Self::GetSecurityDescriptorOwnerFailed { bytes, ptr, code } => write!(f, "GetSecurityDescriptorOwner({:?}) failed with code {}, produced {:?}", bytes, code, ptr),
-            Self::GetSecurityDescriptorGroupFailed { bytes, ptr, code } => write!(f, "GetSecurityDescriptorGroup({:?}) failed with code {}, produced {:?}", bytes, code, ptr),
-            Self::GetSecurityDescriptorDaclFailed { bytes, ptr, code } => write!(f, "GetSecurityDescriptorDacl({:?}) failed with code {}, produced {:?}", bytes, code, ptr),
-            Self::GetSecurityDescriptorSaclFailed { bytes, ptr, code } => write!(f, "GetSecurityDescriptorSacl({:?}) failed with code {}, produced {:?}", bytes, code, ptr),
-            Self::GetAclInformationFailed { bytes, code } => write!(f, "GetAclInformation({:?}) failed with code {}", bytes, code),
-            Self::GetAceFailed { bytes, ace_index, code } => write!(f, "GetAce({:?}, index={}) failed with code {}", bytes, ace_index, code),
+            Self::SecurityDescriptorOffsetOutOfBounds { bytes, field, offset } => write!(f, "{} offset {} is out of bounds from security descriptor {:?}", field, offset, bytes),
+            Self::InvalidConditionalExpression(bytes) => write!(f, "invalid conditional expression {:?}", bytes),
+            Self::InvalidClaimSecurityAttribute(bytes) => write!(f, "invalid claim security attribute {:?}", bytes),
+            Self::InvalidAccessMask(str) => write!(f, "invalid access mask \"{}\"", str),
+            Self::InvalidObjectTypeList(nodes) => write!(f, "invalid object type list {:?}", nodes),
         }
     }
-}
\ No newline at end of file
+}
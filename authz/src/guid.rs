@@ -1,10 +1,6 @@
 use core::convert::TryFrom;
-use core::fmt::{Debug, Display};
-use windows::core::alloc::fmt::Formatter;
-use windows::core::GUID;
+use core::fmt::{Debug, Display, Formatter};
 
-// Simple in-between type exposed in our API, so that our
-// dependency to windows-rs is transparent to our users
 #[derive(PartialEq, Eq, Hash, Copy, Clone)]
 pub struct Guid {
     data1: u32,
@@ -30,11 +26,24 @@ impl Guid {
     pub fn from_values(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Self {
         Self { data1, data2, data3, data4 }
     }
-}
 
-impl From<GUID> for Guid {
-    fn from(g: GUID) -> Self {
-        Self::from_values(g.data1, g.data2, g.data3, g.data4)
+    // Parses the binary form found in ACEs and in attributes like schemaIDGUID (first three fields
+    // are little-endian, the last 8 bytes are stored as-is)
+    pub fn from_bytes(bytes: &[u8; 16]) -> Self {
+        Self::from_values(
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            u16::from_le_bytes([bytes[4], bytes[5]]),
+            u16::from_le_bytes([bytes[6], bytes[7]]),
+            [bytes[8], bytes[9], bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15]])
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut res = [0u8; 16];
+        res[0..4].copy_from_slice(&self.data1.to_le_bytes());
+        res[4..6].copy_from_slice(&self.data2.to_le_bytes());
+        res[6..8].copy_from_slice(&self.data3.to_le_bytes());
+        res[8..16].copy_from_slice(&self.data4);
+        res
     }
 }
 
@@ -0,0 +1,251 @@
+use std::convert::TryFrom;
+use crate::ace::{OBJECT_INHERIT_ACE, CONTAINER_INHERIT_ACE, NO_PROPAGATE_INHERIT_ACE, INHERIT_ONLY_ACE, INHERITED_ACE};
+use crate::security_descriptor::{
+    SECURITY_DESCRIPTOR_REVISION, SE_SELF_RELATIVE, SE_DACL_PRESENT, SE_SACL_PRESENT, SE_DACL_DEFAULTED,
+    SE_SACL_DEFAULTED, SE_DACL_AUTO_INHERITED, SE_SACL_AUTO_INHERITED, SE_DACL_PROTECTED, SE_SACL_PROTECTED,
+};
+use crate::{Ace, Acl, Guid, SecurityDescriptor, Sid};
+
+pub const SEF_DACL_AUTO_INHERIT: u32 = 0x01;
+pub const SEF_SACL_AUTO_INHERIT: u32 = 0x02;
+pub const SEF_DEFAULT_OWNER_FROM_PARENT: u32 = 0x20;
+pub const SEF_DEFAULT_GROUP_FROM_PARENT: u32 = 0x40;
+
+const CREATOR_OWNER_SID: &str = "S-1-3-0";
+const CREATOR_GROUP_SID: &str = "S-1-3-1";
+
+struct AclControlBits {
+    present: u16,
+    defaulted: u16,
+    protected: u16,
+    auto_inherited: u16,
+    auto_inherit_flag: u32,
+}
+
+const DACL_CONTROL_BITS: AclControlBits = AclControlBits {
+    present: SE_DACL_PRESENT,
+    defaulted: SE_DACL_DEFAULTED,
+    protected: SE_DACL_PROTECTED,
+    auto_inherited: SE_DACL_AUTO_INHERITED,
+    auto_inherit_flag: SEF_DACL_AUTO_INHERIT,
+};
+
+const SACL_CONTROL_BITS: AclControlBits = AclControlBits {
+    present: SE_SACL_PRESENT,
+    defaulted: SE_SACL_DEFAULTED,
+    protected: SE_SACL_PROTECTED,
+    auto_inherited: SE_SACL_AUTO_INHERITED,
+    auto_inherit_flag: SEF_SACL_AUTO_INHERIT,
+};
+
+// Computes the security descriptor of a new object the same way CreatePrivateObjectSecurityEx()
+// does. In Active Directory, the creator descriptor is the one supplied in the LDAP add request,
+// or the defaultSecurityDescriptor of the object's class if none was supplied, all objects are
+// containers, and both SEF_DACL_AUTO_INHERIT and SEF_SACL_AUTO_INHERIT are set.
+pub fn create_private_object_security(parent: Option<&SecurityDescriptor>, creator: Option<&SecurityDescriptor>, object_type: Option<&Guid>, is_container: bool, owner: &Sid, group: Option<&Sid>, auto_inherit_flags: u32) -> SecurityDescriptor {
+    let owner_from_parent = if (auto_inherit_flags & SEF_DEFAULT_OWNER_FROM_PARENT) != 0 {
+        parent.and_then(|sd| sd.owner.clone())
+    } else {
+        None
+    };
+    let owner = creator.and_then(|sd| sd.owner.clone())
+        .or(owner_from_parent)
+        .unwrap_or_else(|| owner.clone());
+    let group_from_parent = if (auto_inherit_flags & SEF_DEFAULT_GROUP_FROM_PARENT) != 0 {
+        parent.and_then(|sd| sd.group.clone())
+    } else {
+        None
+    };
+    let group = creator.and_then(|sd| sd.group.clone())
+        .or(group_from_parent)
+        .or_else(|| group.cloned());
+
+    let ctx = InheritanceContext {
+        object_type,
+        is_container,
+        owner: &owner,
+        group: group.as_ref(),
+        creator_owner: Sid::try_from(CREATOR_OWNER_SID).expect("invalid CREATOR OWNER SID"),
+        creator_group: Sid::try_from(CREATOR_GROUP_SID).expect("invalid CREATOR GROUP SID"),
+    };
+    let (dacl, dacl_controls) = ctx.compute_acl(
+        parent.and_then(|sd| sd.dacl.as_ref()),
+        creator.map(|sd| (sd.dacl.as_ref(), sd.controls)),
+        &DACL_CONTROL_BITS,
+        auto_inherit_flags);
+    let (sacl, sacl_controls) = ctx.compute_acl(
+        parent.and_then(|sd| sd.sacl.as_ref()),
+        creator.map(|sd| (sd.sacl.as_ref(), sd.controls)),
+        &SACL_CONTROL_BITS,
+        auto_inherit_flags);
+
+    SecurityDescriptor {
+        revision: SECURITY_DESCRIPTOR_REVISION as u32,
+        controls: SE_SELF_RELATIVE | dacl_controls | sacl_controls,
+        owner: Some(owner),
+        group,
+        dacl,
+        sacl,
+    }
+}
+
+struct InheritanceContext<'a> {
+    object_type: Option<&'a Guid>,
+    is_container: bool,
+    owner: &'a Sid,
+    group: Option<&'a Sid>,
+    creator_owner: Sid,
+    creator_group: Sid,
+}
+
+impl<'a> InheritanceContext<'a> {
+    // Returns the new ACL (explicit ACEs from the creator first, then ACEs inherited from the
+    // parent) along with the control bits which apply to it
+    fn compute_acl(&self, parent_acl: Option<&Acl>, creator: Option<(Option<&Acl>, u16)>, bits: &AclControlBits, auto_inherit_flags: u32) -> (Option<Acl>, u16) {
+        let auto_inherit = (auto_inherit_flags & bits.auto_inherit_flag) != 0;
+        let (creator_present, creator_acl, creator_controls) = match creator {
+            Some((acl, controls)) => (acl.is_some() || (controls & bits.present) != 0, acl, controls),
+            None => (false, None, 0),
+        };
+        let protected = creator_present && (creator_controls & bits.protected) != 0;
+
+        let inherited = match parent_acl {
+            Some(acl) if !protected => self.inherit_from_parent(acl, auto_inherit),
+            _ => vec![],
+        };
+
+        let mut controls = bits.present;
+        if protected {
+            controls |= bits.protected;
+        }
+        if auto_inherit {
+            controls |= bits.auto_inherited;
+        }
+
+        let aces = if !creator_present {
+            if inherited.is_empty() {
+                return (None, 0);
+            }
+            inherited
+        } else if (creator_controls & bits.defaulted) != 0 && !inherited.is_empty() {
+            // A defaulted ACL from the creator is only used if nothing gets inherited
+            inherited
+        } else {
+            match creator_acl {
+                // A NULL ACL from the creator stays NULL
+                None => return (None, controls),
+                Some(acl) => {
+                    let mut aces = self.process_creator_aces(acl, auto_inherit);
+                    aces.extend(inherited);
+                    aces
+                },
+            }
+        };
+        (Some(Acl { aces }), controls)
+    }
+
+    fn process_creator_aces(&self, acl: &Acl, auto_inherit: bool) -> Vec<Ace> {
+        let mut res = vec![];
+        for ace in &acl.aces {
+            // Inherited ACEs in the creator descriptor get recomputed from the parent
+            if auto_inherit && ace.is_inherited() {
+                continue;
+            }
+            let mut flags = ace.flags;
+            if !self.is_container {
+                // Inheritance flags are meaningless on leaf objects
+                if ace.get_inherit_only() {
+                    continue;
+                }
+                flags &= !(OBJECT_INHERIT_ACE | CONTAINER_INHERIT_ACE | NO_PROPAGATE_INHERIT_ACE);
+            }
+            self.push_expanded(ace, flags, &mut res);
+        }
+        res
+    }
+
+    fn inherit_from_parent(&self, acl: &Acl, auto_inherit: bool) -> Vec<Ace> {
+        let mut res = vec![];
+        for ace in &acl.aces {
+            let container_inherit = (ace.flags & CONTAINER_INHERIT_ACE) != 0;
+            let object_inherit = (ace.flags & OBJECT_INHERIT_ACE) != 0;
+            let no_propagate = (ace.flags & NO_PROPAGATE_INHERIT_ACE) != 0;
+
+            let mut flags = ace.flags & !(INHERIT_ONLY_ACE | INHERITED_ACE);
+            let mut effective = if self.is_container {
+                if !container_inherit && !object_inherit {
+                    continue;
+                }
+                // ACEs only inheritable by leaf objects are passed down, but do not apply to
+                // containers themselves
+                container_inherit
+            } else {
+                if !object_inherit {
+                    continue;
+                }
+                true
+            };
+            if no_propagate || !self.is_container {
+                flags &= !(OBJECT_INHERIT_ACE | CONTAINER_INHERIT_ACE | NO_PROPAGATE_INHERIT_ACE);
+            }
+            // ACEs restricted to another class of objects are passed down without applying
+            if let Some(inherited_object_type) = ace.get_inherited_object_type() {
+                if self.object_type != Some(inherited_object_type) {
+                    effective = false;
+                }
+            }
+            if !effective {
+                if (flags & (OBJECT_INHERIT_ACE | CONTAINER_INHERIT_ACE)) == 0 {
+                    continue;
+                }
+                flags |= INHERIT_ONLY_ACE;
+            }
+            if auto_inherit {
+                flags |= INHERITED_ACE;
+            }
+            self.push_expanded(ace, flags, &mut res);
+        }
+        res
+    }
+
+    // Pushes an ACE with the given flags. If it applies to the object, CREATOR OWNER and CREATOR
+    // GROUP are replaced and generic rights are mapped. If it is also inheritable, the original
+    // ACE is kept as inherit-only so that it can be applied properly to children.
+    fn push_expanded(&self, ace: &Ace, flags: u8, res: &mut Vec<Ace>) {
+        if (flags & INHERIT_ONLY_ACE) != 0 {
+            res.push(Ace {
+                flags,
+                ..ace.clone()
+            });
+            return;
+        }
+        let trustee = if ace.trustee == self.creator_owner {
+            self.owner.clone()
+        } else if ace.trustee == self.creator_group && self.group.is_some() {
+            self.group.cloned().expect("assertion failed: group disappeared")
+        } else {
+            ace.trustee.clone()
+        };
+        let access_mask = ace.access_mask.map_generic();
+        let inheritable = (flags & (OBJECT_INHERIT_ACE | CONTAINER_INHERIT_ACE)) != 0;
+        if inheritable && (trustee != ace.trustee || access_mask != ace.access_mask) {
+            res.push(Ace {
+                trustee,
+                access_mask,
+                flags: flags & !(OBJECT_INHERIT_ACE | CONTAINER_INHERIT_ACE | NO_PROPAGATE_INHERIT_ACE),
+                type_specific: ace.type_specific.clone(),
+            });
+            res.push(Ace {
+                flags: flags | INHERIT_ONLY_ACE,
+                ..ace.clone()
+            });
+        } else {
+            res.push(Ace {
+                trustee,
+                access_mask,
+                flags,
+                type_specific: ace.type_specific.clone(),
+            });
+        }
+    }
+}
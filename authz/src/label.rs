@@ -0,0 +1,118 @@
+use core::fmt::{Display, Formatter};
+use crate::Sid;
+
+const SECURITY_MANDATORY_LABEL_AUTHORITY: u64 = 16;
+
+pub(crate) const SYSTEM_MANDATORY_LABEL_NO_WRITE_UP: u32 = 0x1;
+pub(crate) const SYSTEM_MANDATORY_LABEL_NO_READ_UP: u32 = 0x2;
+pub(crate) const SYSTEM_MANDATORY_LABEL_NO_EXECUTE_UP: u32 = 0x4;
+
+// Integrity levels are SIDs S-1-16-X, where X is the level (higher is more trusted)
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum IntegrityLevel {
+    Untrusted,
+    Low,
+    Medium,
+    MediumPlus,
+    High,
+    System,
+    ProtectedProcess,
+    Other(u32),
+}
+
+impl IntegrityLevel {
+    // Returns None if the given SID is not an integrity level
+    pub fn from_sid(sid: &Sid) -> Option<Self> {
+        let sub_authorities = sid.get_sub_authorities();
+        if sid.get_identifier_authority() != SECURITY_MANDATORY_LABEL_AUTHORITY || sub_authorities.len() != 1 {
+            return None;
+        }
+        Some(match sub_authorities[0] {
+            0x0000 => IntegrityLevel::Untrusted,
+            0x1000 => IntegrityLevel::Low,
+            0x2000 => IntegrityLevel::Medium,
+            0x2100 => IntegrityLevel::MediumPlus,
+            0x3000 => IntegrityLevel::High,
+            0x4000 => IntegrityLevel::System,
+            0x5000 => IntegrityLevel::ProtectedProcess,
+            level => IntegrityLevel::Other(level),
+        })
+    }
+
+    pub fn get_value(&self) -> u32 {
+        match self {
+            IntegrityLevel::Untrusted => 0x0000,
+            IntegrityLevel::Low => 0x1000,
+            IntegrityLevel::Medium => 0x2000,
+            IntegrityLevel::MediumPlus => 0x2100,
+            IntegrityLevel::High => 0x3000,
+            IntegrityLevel::System => 0x4000,
+            IntegrityLevel::ProtectedProcess => 0x5000,
+            IntegrityLevel::Other(level) => *level,
+        }
+    }
+
+    pub fn to_sid(&self) -> Sid {
+        Sid::from_parts(SECURITY_MANDATORY_LABEL_AUTHORITY, &[self.get_value()]).expect("invalid integrity level SID")
+    }
+}
+
+impl Display for IntegrityLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            IntegrityLevel::Untrusted => write!(f, "Untrusted"),
+            IntegrityLevel::Low => write!(f, "Low"),
+            IntegrityLevel::Medium => write!(f, "Medium"),
+            IntegrityLevel::MediumPlus => write!(f, "Medium Plus"),
+            IntegrityLevel::High => write!(f, "High"),
+            IntegrityLevel::System => write!(f, "System"),
+            IntegrityLevel::ProtectedProcess => write!(f, "Protected Process"),
+            IntegrityLevel::Other(level) => write!(f, "0x{:X}", level),
+        }
+    }
+}
+
+// Access policy of a mandatory label ACE, stored in its access mask: which kind of access is
+// denied to principals with a lower integrity level
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub struct MandatoryPolicy {
+    pub no_write_up: bool,
+    pub no_read_up: bool,
+    pub no_execute_up: bool,
+}
+
+impl MandatoryPolicy {
+    pub fn from_access_mask(mask: u32) -> Self {
+        Self {
+            no_write_up: (mask & SYSTEM_MANDATORY_LABEL_NO_WRITE_UP) != 0,
+            no_read_up: (mask & SYSTEM_MANDATORY_LABEL_NO_READ_UP) != 0,
+            no_execute_up: (mask & SYSTEM_MANDATORY_LABEL_NO_EXECUTE_UP) != 0,
+        }
+    }
+
+    pub fn to_access_mask(&self) -> u32 {
+        (if self.no_write_up { SYSTEM_MANDATORY_LABEL_NO_WRITE_UP } else { 0 }) |
+            (if self.no_read_up { SYSTEM_MANDATORY_LABEL_NO_READ_UP } else { 0 }) |
+            (if self.no_execute_up { SYSTEM_MANDATORY_LABEL_NO_EXECUTE_UP } else { 0 })
+    }
+}
+
+impl Display for MandatoryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mut policies = vec![];
+        if self.no_write_up {
+            policies.push("no_write_up");
+        }
+        if self.no_read_up {
+            policies.push("no_read_up");
+        }
+        if self.no_execute_up {
+            policies.push("no_execute_up");
+        }
+        if policies.is_empty() {
+            write!(f, "no_policy")
+        } else {
+            write!(f, "{}", policies.join(" "))
+        }
+    }
+}
@@ -4,13 +4,44 @@ mod acl;
 mod ace;
 mod sid;
 mod guid;
+mod access_mask;
+mod conditional;
+mod claims;
+mod label;
+mod well_known;
+mod sddl;
+mod access_check;
+mod inheritance;
+mod canonical;
+mod subsumption;
 mod utils;
 #[cfg(feature = "serial")]
 mod serial;
 
-pub use security_descriptor::SecurityDescriptor;
+pub use security_descriptor::{
+    SecurityDescriptor, SE_DACL_PRESENT, SE_DACL_DEFAULTED, SE_SACL_PRESENT, SE_SACL_DEFAULTED,
+    SE_DACL_AUTO_INHERIT_REQ, SE_SACL_AUTO_INHERIT_REQ, SE_DACL_AUTO_INHERITED, SE_SACL_AUTO_INHERITED,
+    SE_DACL_PROTECTED, SE_SACL_PROTECTED, SE_SELF_RELATIVE, OWNER_SECURITY_INFORMATION,
+    GROUP_SECURITY_INFORMATION, DACL_SECURITY_INFORMATION, SACL_SECURITY_INFORMATION,
+};
 pub use acl::Acl;
-pub use ace::{Ace, AceType};
+pub use ace::{
+    Ace, AceType, OBJECT_INHERIT_ACE, CONTAINER_INHERIT_ACE, NO_PROPAGATE_INHERIT_ACE, INHERIT_ONLY_ACE,
+    INHERITED_ACE, SUCCESSFUL_ACCESS_ACE_FLAG, FAILED_ACCESS_ACE_FLAG, ACE_OBJECT_TYPE_PRESENT,
+    ACE_INHERITED_OBJECT_TYPE_PRESENT,
+};
 pub use sid::Sid;
 pub use guid::Guid;
+pub use access_mask::AccessMask;
+pub use claims::{ClaimSecurityAttribute, ClaimValues};
+pub use label::{IntegrityLevel, MandatoryPolicy};
+pub use well_known::{lookup_well_known_sid, is_well_known_user_rid, WellKnownSidKind};
+pub use conditional::{ConditionalExpression, AttributeScope, IntegerSign, IntegerBase, UnaryOperator, BinaryOperator};
+pub use canonical::AceMove;
+pub use subsumption::RedundantAce;
+pub use access_check::{access_check, ObjectTypeNode, AccessCheckResult};
+pub use inheritance::{
+    create_private_object_security, SEF_DACL_AUTO_INHERIT, SEF_SACL_AUTO_INHERIT,
+    SEF_DEFAULT_OWNER_FROM_PARENT, SEF_DEFAULT_GROUP_FROM_PARENT,
+};
 pub use error::AuthzError;
\ No newline at end of file
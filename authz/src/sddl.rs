@@ -0,0 +1,1009 @@
+use std::convert::TryFrom;
+use crate::ace::{
+    ACCESS_ALLOWED_ACE_TYPE, ACCESS_DENIED_ACE_TYPE, SYSTEM_AUDIT_ACE_TYPE,
+    ACCESS_ALLOWED_OBJECT_ACE_TYPE, ACCESS_DENIED_OBJECT_ACE_TYPE, SYSTEM_AUDIT_OBJECT_ACE_TYPE,
+    ACCESS_ALLOWED_CALLBACK_ACE_TYPE, ACCESS_DENIED_CALLBACK_ACE_TYPE, ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE,
+    ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE, SYSTEM_AUDIT_CALLBACK_ACE_TYPE, SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE,
+    SYSTEM_MANDATORY_LABEL_ACE_TYPE, SYSTEM_RESOURCE_ATTRIBUTE_ACE_TYPE, SYSTEM_SCOPED_POLICY_ID_ACE_TYPE, OBJECT_INHERIT_ACE, CONTAINER_INHERIT_ACE, NO_PROPAGATE_INHERIT_ACE,
+    INHERIT_ONLY_ACE, INHERITED_ACE, SUCCESSFUL_ACCESS_ACE_FLAG, FAILED_ACCESS_ACE_FLAG,
+    ACE_OBJECT_TYPE_PRESENT, ACE_INHERITED_OBJECT_TYPE_PRESENT,
+};
+use crate::error::AuthzError;
+use crate::security_descriptor::{
+    SECURITY_DESCRIPTOR_REVISION, SE_DACL_PRESENT, SE_SACL_PRESENT, SE_SELF_RELATIVE,
+    SE_DACL_AUTO_INHERIT_REQ, SE_SACL_AUTO_INHERIT_REQ, SE_DACL_AUTO_INHERITED, SE_SACL_AUTO_INHERITED,
+    SE_DACL_PROTECTED, SE_SACL_PROTECTED,
+};
+use crate::conditional::{MAX_NESTING_DEPTH, UNARY_OPERATORS, BINARY_OPERATORS};
+use crate::{AccessMask, Ace, AceType, Acl, ClaimSecurityAttribute, ClaimValues, Guid, SecurityDescriptor, Sid};
+use crate::{ConditionalExpression, AttributeScope, IntegerSign, IntegerBase, UnaryOperator, BinaryOperator};
+
+// SDDL aliases for SIDs which do not depend on the domain
+const WELL_KNOWN_SID_ALIASES: &[(&str, &str)] = &[
+    ("AA", "S-1-5-32-579"), // Access Control Assistance Operators
+    ("AC", "S-1-15-2-1"), // All Application Packages
+    ("AN", "S-1-5-7"), // Anonymous Logon
+    ("AO", "S-1-5-32-548"), // Account Operators
+    ("AS", "S-1-18-1"), // Authentication Authority Asserted Identity
+    ("AU", "S-1-5-11"), // Authenticated Users
+    ("BA", "S-1-5-32-544"), // Builtin Administrators
+    ("BG", "S-1-5-32-546"), // Builtin Guests
+    ("BO", "S-1-5-32-551"), // Backup Operators
+    ("BU", "S-1-5-32-545"), // Builtin Users
+    ("CD", "S-1-5-32-574"), // Certificate Service DCOM Access
+    ("CG", "S-1-3-1"), // Creator Group
+    ("CO", "S-1-3-0"), // Creator Owner
+    ("CY", "S-1-5-32-569"), // Cryptographic Operators
+    ("ED", "S-1-5-9"), // Enterprise Domain Controllers
+    ("ER", "S-1-5-32-573"), // Event Log Readers
+    ("ES", "S-1-5-32-576"), // RDS Endpoint Servers
+    ("HA", "S-1-5-32-578"), // Hyper-V Administrators
+    ("HI", "S-1-16-12288"), // High Mandatory Level
+    ("IS", "S-1-5-32-568"), // IIS_IUSRS
+    ("IU", "S-1-5-4"), // Interactive
+    ("LS", "S-1-5-19"), // Local Service
+    ("LU", "S-1-5-32-559"), // Performance Log Users
+    ("LW", "S-1-16-4096"), // Low Mandatory Level
+    ("ME", "S-1-16-8192"), // Medium Mandatory Level
+    ("MP", "S-1-16-8448"), // Medium Plus Mandatory Level
+    ("MS", "S-1-5-32-577"), // RDS Management Servers
+    ("MU", "S-1-5-32-558"), // Performance Monitor Users
+    ("NO", "S-1-5-32-556"), // Network Configuration Operators
+    ("NS", "S-1-5-20"), // Network Service
+    ("NU", "S-1-5-2"), // Network
+    ("OW", "S-1-3-4"), // Owner Rights
+    ("PO", "S-1-5-32-550"), // Print Operators
+    ("PS", "S-1-5-10"), // Principal Self
+    ("PU", "S-1-5-32-547"), // Power Users
+    ("RA", "S-1-5-32-575"), // RDS Remote Access Servers
+    ("RC", "S-1-5-12"), // Restricted Code
+    ("RD", "S-1-5-32-555"), // Remote Desktop Users
+    ("RE", "S-1-5-32-552"), // Replicator
+    ("RM", "S-1-5-32-580"), // Remote Management Users
+    ("RU", "S-1-5-32-554"), // Pre-Windows 2000 Compatible Access
+    ("SI", "S-1-16-16384"), // System Mandatory Level
+    ("SO", "S-1-5-32-549"), // Server Operators
+    ("SS", "S-1-18-2"), // Service Asserted Identity
+    ("SU", "S-1-5-6"), // Service
+    ("SY", "S-1-5-18"), // Local System
+    ("UD", "S-1-5-84-0-0-0-0-0"), // User-Mode Drivers
+    ("WD", "S-1-1-0"), // Everyone
+    ("WR", "S-1-5-33"), // Write Restricted Code
+];
+
+// SDDL aliases for SIDs relative to the domain within which the SDDL applies
+const DOMAIN_SID_ALIASES: &[(&str, u32)] = &[
+    ("LA", 500), // Administrator
+    ("LG", 501), // Guest
+    ("DA", 512), // Domain Admins
+    ("DU", 513), // Domain Users
+    ("DG", 514), // Domain Guests
+    ("DC", 515), // Domain Computers
+    ("DD", 516), // Domain Controllers
+    ("CA", 517), // Certificate Publishers
+    ("PA", 520), // Group Policy Creator Owners
+    ("CN", 522), // Cloneable Domain Controllers
+    ("AP", 525), // Protected Users
+    ("KA", 526), // Key Admins
+    ("RS", 553), // RAS and IAS Servers
+];
+
+// SDDL aliases for SIDs relative to the root domain of the forest within which the SDDL applies
+const ROOT_DOMAIN_SID_ALIASES: &[(&str, u32)] = &[
+    ("RO", 498), // Enterprise Read-only Domain Controllers
+    ("SA", 518), // Schema Admins
+    ("EA", 519), // Enterprise Admins
+    ("EK", 527), // Enterprise Key Admins
+];
+
+const ACE_TYPE_NAMES: &[(&str, u8)] = &[
+    ("A", ACCESS_ALLOWED_ACE_TYPE),
+    ("D", ACCESS_DENIED_ACE_TYPE),
+    ("AU", SYSTEM_AUDIT_ACE_TYPE),
+    ("OA", ACCESS_ALLOWED_OBJECT_ACE_TYPE),
+    ("OD", ACCESS_DENIED_OBJECT_ACE_TYPE),
+    ("OU", SYSTEM_AUDIT_OBJECT_ACE_TYPE),
+    ("XA", ACCESS_ALLOWED_CALLBACK_ACE_TYPE),
+    ("XD", ACCESS_DENIED_CALLBACK_ACE_TYPE),
+    ("ZA", ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE),
+    ("XU", SYSTEM_AUDIT_CALLBACK_ACE_TYPE),
+    ("ML", SYSTEM_MANDATORY_LABEL_ACE_TYPE),
+    ("RA", SYSTEM_RESOURCE_ATTRIBUTE_ACE_TYPE),
+    ("SP", SYSTEM_SCOPED_POLICY_ID_ACE_TYPE),
+];
+
+const ACE_FLAG_NAMES: &[(&str, u8)] = &[
+    ("OI", OBJECT_INHERIT_ACE),
+    ("CI", CONTAINER_INHERIT_ACE),
+    ("NP", NO_PROPAGATE_INHERIT_ACE),
+    ("IO", INHERIT_ONLY_ACE),
+    ("ID", INHERITED_ACE),
+    ("SA", SUCCESSFUL_ACCESS_ACE_FLAG),
+    ("FA", FAILED_ACCESS_ACE_FLAG),
+];
+
+// Access rights used when serializing masks, in increasing bit order (same as Windows)
+const ACCESS_RIGHT_NAMES: &[(&str, u32)] = &[
+    ("CC", 0x0000_0001), // Create child
+    ("DC", 0x0000_0002), // Delete child
+    ("LC", 0x0000_0004), // List children
+    ("SW", 0x0000_0008), // Self write (validated write)
+    ("RP", 0x0000_0010), // Read property
+    ("WP", 0x0000_0020), // Write property
+    ("DT", 0x0000_0040), // Delete tree
+    ("LO", 0x0000_0080), // List object
+    ("CR", 0x0000_0100), // Control access
+    ("SD", 0x0001_0000), // Delete
+    ("RC", 0x0002_0000), // Read control
+    ("WD", 0x0004_0000), // Write DACL
+    ("WO", 0x0008_0000), // Write owner
+    ("GA", 0x1000_0000), // Generic all
+    ("GX", 0x2000_0000), // Generic execute
+    ("GW", 0x4000_0000), // Generic write
+    ("GR", 0x8000_0000), // Generic read
+];
+
+// Access rights only accepted when parsing (file and registry shortcuts)
+const EXTRA_ACCESS_RIGHT_NAMES: &[(&str, u32)] = &[
+    ("FA", 0x001F_01FF),
+    ("FR", 0x0012_0089),
+    ("FW", 0x0012_0116),
+    ("FX", 0x0012_00A0),
+    ("KA", 0x000F_003F),
+    ("KR", 0x0002_0019),
+    ("KW", 0x0002_0006),
+    ("KX", 0x0002_0019),
+];
+
+// Access rights of mandatory label ACEs
+const MANDATORY_LABEL_RIGHT_NAMES: &[(&str, u32)] = &[
+    ("NW", 0x1), // No write up
+    ("NR", 0x2), // No read up
+    ("NX", 0x4), // No execute up
+];
+
+impl SecurityDescriptor {
+    pub fn from_str(sddl: &str, domain_sid: &Sid, root_domain_sid: &Sid) -> Result<Self, AuthzError> {
+        let parser = SddlParser {
+            domain_sid,
+            root_domain_sid,
+        };
+        parser.parse_security_descriptor(sddl).map_err(|reason| AuthzError::InvalidStringSecurityDescriptor {
+            str: sddl.to_owned(),
+            reason,
+        })
+    }
+
+    pub fn to_sddl(&self) -> String {
+        let mut res = String::new();
+        if let Some(owner) = &self.owner {
+            res.push_str("O:");
+            res.push_str(&sid_to_sddl(owner));
+        }
+        if let Some(group) = &self.group {
+            res.push_str("G:");
+            res.push_str(&sid_to_sddl(group));
+        }
+        if self.dacl.is_some() || (self.controls & SE_DACL_PRESENT) != 0 {
+            res.push_str("D:");
+            res.push_str(&acl_flags_to_sddl(self.controls, SE_DACL_PROTECTED, SE_DACL_AUTO_INHERIT_REQ, SE_DACL_AUTO_INHERITED));
+            match &self.dacl {
+                Some(dacl) => res.push_str(&dacl.to_sddl()),
+                None => res.push_str("NO_ACCESS_CONTROL"),
+            }
+        }
+        if self.sacl.is_some() || (self.controls & SE_SACL_PRESENT) != 0 {
+            res.push_str("S:");
+            res.push_str(&acl_flags_to_sddl(self.controls, SE_SACL_PROTECTED, SE_SACL_AUTO_INHERIT_REQ, SE_SACL_AUTO_INHERITED));
+            match &self.sacl {
+                Some(sacl) => res.push_str(&sacl.to_sddl()),
+                None => res.push_str("NO_ACCESS_CONTROL"),
+            }
+        }
+        res
+    }
+}
+
+impl Acl {
+    pub fn to_sddl(&self) -> String {
+        self.aces.iter().map(|ace| ace.to_sddl()).collect()
+    }
+}
+
+impl Ace {
+    pub fn to_sddl(&self) -> String {
+        let ace_type = self.type_specific.get_type_code();
+        // Some ACE types have no SDDL abbreviation: they are written as their hexadecimal
+        // type code, which our parser also accepts
+        let ace_type_name = match ACE_TYPE_NAMES.iter().find(|(_, t)| *t == ace_type) {
+            Some((name, _)) => name.to_string(),
+            None => format!("0x{:X}", ace_type),
+        };
+        let flags: String = ACE_FLAG_NAMES.iter()
+            .filter(|(_, flag)| (self.flags & flag) != 0)
+            .map(|(name, _)| *name)
+            .collect();
+        let rights = if ace_type == SYSTEM_MANDATORY_LABEL_ACE_TYPE {
+            access_mask_to_sddl(self.access_mask.bits(), MANDATORY_LABEL_RIGHT_NAMES)
+        } else {
+            access_mask_to_sddl(self.access_mask.bits(), ACCESS_RIGHT_NAMES)
+        };
+        let object_type = self.get_object_type().map(guid_to_sddl).unwrap_or_default();
+        let inherited_object_type = self.get_inherited_object_type().map(guid_to_sddl).unwrap_or_default();
+        if let Some(attribute) = self.get_resource_attribute() {
+            return format!("({};{};{};{};{};{};{})", ace_type_name, flags, rights, object_type, inherited_object_type, sid_to_sddl(&self.trustee), attribute);
+        }
+        match self.get_condition() {
+            Ok(Some(condition)) => format!("({};{};{};{};{};{};{})", ace_type_name, flags, rights, object_type, inherited_object_type, sid_to_sddl(&self.trustee), condition.to_sddl_condition()),
+            _ => format!("({};{};{};{};{};{})", ace_type_name, flags, rights, object_type, inherited_object_type, sid_to_sddl(&self.trustee)),
+        }
+    }
+}
+
+pub(crate) fn sid_to_sddl(sid: &Sid) -> String {
+    let sid = sid.to_string();
+    match WELL_KNOWN_SID_ALIASES.iter().find(|(_, s)| *s == sid) {
+        Some((alias, _)) => alias.to_string(),
+        None => sid,
+    }
+}
+
+fn guid_to_sddl(guid: &Guid) -> String {
+    guid.to_string().to_lowercase()
+}
+
+fn access_mask_to_sddl(mask: u32, names: &[(&str, u32)]) -> String {
+    let known_bits = names.iter().fold(0, |acc, (_, bits)| acc | bits);
+    if mask == 0 || (mask & !known_bits) != 0 {
+        return format!("0x{:X}", mask);
+    }
+    names.iter()
+        .filter(|(_, bits)| (mask & bits) != 0)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+fn acl_flags_to_sddl(controls: u16, protected: u16, auto_inherit_req: u16, auto_inherited: u16) -> String {
+    let mut res = String::new();
+    if (controls & protected) != 0 {
+        res.push('P');
+    }
+    if (controls & auto_inherit_req) != 0 {
+        res.push_str("AR");
+    }
+    if (controls & auto_inherited) != 0 {
+        res.push_str("AI");
+    }
+    res
+}
+
+struct SddlParser<'a> {
+    domain_sid: &'a Sid,
+    root_domain_sid: &'a Sid,
+}
+
+impl<'a> SddlParser<'a> {
+    fn parse_security_descriptor(&self, sddl: &str) -> Result<SecurityDescriptor, String> {
+        let mut controls = SE_SELF_RELATIVE;
+        let mut owner = None;
+        let mut group = None;
+        let mut dacl = None;
+        let mut sacl = None;
+        let mut seen_components = vec![];
+
+        let mut rest = sddl.trim_start();
+        while !rest.is_empty() {
+            // Each component starts with a one-letter tag and a colon: O:, G:, D:, or S:
+            let tag = match rest.get(0..2) {
+                Some(tag) if tag.ends_with(':') => tag,
+                _ => return Err(format!("expected a component at \"{}\"", rest)),
+            };
+            if seen_components.contains(&tag) {
+                return Err(format!("duplicate component {}", tag));
+            }
+            seen_components.push(tag);
+            rest = &rest[2..];
+            match tag {
+                "O:" | "G:" => {
+                    let len = sid_token_len(rest);
+                    let sid = self.parse_sid(&rest[..len])?;
+                    if tag == "O:" {
+                        owner = Some(sid);
+                    } else {
+                        group = Some(sid);
+                    }
+                    rest = &rest[len..];
+                },
+                "D:" | "S:" => {
+                    let (flags, null_acl, remaining) = parse_acl_flags(rest);
+                    let (aces, remaining) = self.parse_aces(remaining)?;
+                    if null_acl && !aces.is_empty() {
+                        return Err(format!("{} cannot contain ACEs if it is NO_ACCESS_CONTROL", tag));
+                    }
+                    let acl = if null_acl {
+                        None
+                    } else {
+                        Some(Acl { aces })
+                    };
+                    if tag == "D:" {
+                        controls |= SE_DACL_PRESENT;
+                        controls |= map_acl_flags(flags, SE_DACL_PROTECTED, SE_DACL_AUTO_INHERIT_REQ, SE_DACL_AUTO_INHERITED);
+                        dacl = acl;
+                    } else {
+                        controls |= SE_SACL_PRESENT;
+                        controls |= map_acl_flags(flags, SE_SACL_PROTECTED, SE_SACL_AUTO_INHERIT_REQ, SE_SACL_AUTO_INHERITED);
+                        sacl = acl;
+                    }
+                    rest = remaining;
+                },
+                _ => return Err(format!("unknown component {}", tag)),
+            }
+            rest = rest.trim_start();
+        }
+
+        Ok(SecurityDescriptor {
+            revision: SECURITY_DESCRIPTOR_REVISION as u32,
+            controls,
+            owner,
+            group,
+            dacl,
+            sacl,
+        })
+    }
+
+    // Parses consecutive "(...)" ACE strings, returns them along with the rest of the string
+    fn parse_aces<'b>(&self, mut rest: &'b str) -> Result<(Vec<Ace>, &'b str), String> {
+        let mut aces = vec![];
+        loop {
+            rest = rest.trim_start();
+            if !rest.starts_with('(') {
+                break;
+            }
+            // Find the matching closing parenthesis, taking into account that conditional
+            // expressions can contain nested parentheses and quoted strings
+            let mut depth = 0;
+            let mut in_quotes = false;
+            let mut end = None;
+            for (i, c) in rest.char_indices() {
+                match c {
+                    '"' => in_quotes = !in_quotes,
+                    '(' if !in_quotes => depth += 1,
+                    ')' if !in_quotes => {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(i);
+                            break;
+                        }
+                    },
+                    _ => (),
+                }
+            }
+            let end = match end {
+                Some(end) => end,
+                None => return Err(format!("unterminated ACE \"{}\"", rest)),
+            };
+            aces.push(self.parse_ace(&rest[1..end])?);
+            rest = &rest[end + 1..];
+        }
+        Ok((aces, rest))
+    }
+
+    // Parses the inside of an ACE string: type;flags;rights;object_guid;inherit_object_guid;account_sid
+    fn parse_ace(&self, s: &str) -> Result<Ace, String> {
+        let fields: Vec<&str> = s.splitn(7, ';').map(|f| f.trim()).collect();
+        if fields.len() < 6 {
+            return Err(format!("expected 6 fields in ACE \"{}\"", s));
+        }
+
+        let ace_type = match ACE_TYPE_NAMES.iter().find(|(name, _)| *name == fields[0]) {
+            Some((_, t)) => *t,
+            None => match fields[0].strip_prefix("0x").or_else(|| fields[0].strip_prefix("0X")) {
+                Some(hex) => u8::from_str_radix(hex, 16).map_err(|_| format!("invalid ACE type \"{}\"", fields[0]))?,
+                None => return Err(format!("unsupported ACE type \"{}\"", fields[0])),
+            },
+        };
+        let flags = parse_ace_flags(fields[1])?;
+        let access_mask = AccessMask::from_bits_truncate(parse_access_mask(fields[2])?);
+        let object_type = parse_guid(fields[3])?;
+        let inherited_object_type = parse_guid(fields[4])?;
+        let trustee = self.parse_sid(fields[5])?;
+        let extra = if fields.len() == 7 && !fields[6].is_empty() { Some(fields[6]) } else { None };
+        if ace_type == SYSTEM_RESOURCE_ATTRIBUTE_ACE_TYPE {
+            let attribute = match extra {
+                Some(attribute) => self.parse_resource_attribute(attribute)?,
+                None => return Err(format!("missing resource attribute in ACE \"{}\"", s)),
+            };
+            return Ok(Ace {
+                trustee,
+                access_mask,
+                flags,
+                type_specific: AceType::ResourceAttribute { attribute },
+            });
+        }
+        let application_data = match extra {
+            Some(condition) if is_callback_ace_type(ace_type) => self.parse_condition(condition)?.to_bytes(),
+            Some(_) => return Err(format!("conditional expression in non-callback ACE \"{}\"", s)),
+            None => vec![],
+        };
+
+        let object_flags = if object_type.is_some() { ACE_OBJECT_TYPE_PRESENT } else { 0 } |
+            if inherited_object_type.is_some() { ACE_INHERITED_OBJECT_TYPE_PRESENT } else { 0 };
+        let type_specific = match ace_type {
+            ACCESS_ALLOWED_OBJECT_ACE_TYPE => AceType::AccessAllowedObject { flags: object_flags, object_type, inherited_object_type },
+            ACCESS_DENIED_OBJECT_ACE_TYPE => AceType::AccessDeniedObject { flags: object_flags, object_type, inherited_object_type },
+            SYSTEM_AUDIT_OBJECT_ACE_TYPE => AceType::AuditObject { flags: object_flags, object_type, inherited_object_type },
+            ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE => AceType::AccessAllowedCallbackObject { flags: object_flags, object_type, inherited_object_type, application_data },
+            ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE => AceType::AccessDeniedCallbackObject { flags: object_flags, object_type, inherited_object_type, application_data },
+            SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE => AceType::AuditCallbackObject { flags: object_flags, object_type, inherited_object_type, application_data },
+            _ if object_flags != 0 => return Err(format!("object GUIDs in non-object ACE \"{}\"", s)),
+            ACCESS_ALLOWED_ACE_TYPE => AceType::AccessAllowed,
+            ACCESS_DENIED_ACE_TYPE => AceType::AccessDenied,
+            SYSTEM_AUDIT_ACE_TYPE => AceType::Audit,
+            ACCESS_ALLOWED_CALLBACK_ACE_TYPE => AceType::AccessAllowedCallback { application_data },
+            ACCESS_DENIED_CALLBACK_ACE_TYPE => AceType::AccessDeniedCallback { application_data },
+            SYSTEM_AUDIT_CALLBACK_ACE_TYPE => AceType::AuditCallback { application_data },
+            SYSTEM_MANDATORY_LABEL_ACE_TYPE => AceType::MandatoryLabel,
+            SYSTEM_SCOPED_POLICY_ID_ACE_TYPE => AceType::ScopedPolicyId,
+            _ => return Err(format!("unsupported ACE type \"{}\"", fields[0])),
+        };
+
+        Ok(Ace {
+            trustee,
+            access_mask,
+            flags,
+            type_specific,
+        })
+    }
+
+    fn parse_sid(&self, s: &str) -> Result<Sid, String> {
+        if s.starts_with("S-") || s.starts_with("s-") {
+            return Sid::try_from(s).map_err(|_| format!("invalid SID \"{}\"", s));
+        }
+        if let Some((_, sid)) = WELL_KNOWN_SID_ALIASES.iter().find(|(alias, _)| *alias == s) {
+            return Ok(Sid::try_from(*sid).expect("invalid well-known SID alias"));
+        }
+        if let Some((_, rid)) = DOMAIN_SID_ALIASES.iter().find(|(alias, _)| *alias == s) {
+            return Ok(self.domain_sid.with_rid(*rid));
+        }
+        if let Some((_, rid)) = ROOT_DOMAIN_SID_ALIASES.iter().find(|(alias, _)| *alias == s) {
+            return Ok(self.root_domain_sid.with_rid(*rid));
+        }
+        Err(format!("unknown SID alias \"{}\"", s))
+    }
+
+    // Parses the last field of callback ACEs, a conditional expression such as
+    // (Member_of {SID(BA)}) or (@User.department == "IT" && Exists @Device.managed)
+    fn parse_condition(&self, s: &str) -> Result<ConditionalExpression, String> {
+        let mut parser = ConditionParser {
+            sddl: self,
+            s,
+            pos: 0,
+            level: 0,
+        };
+        let (expr, _) = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.pos != s.len() {
+            return Err(parser.error());
+        }
+        Ok(expr)
+    }
+
+    // Parses the last field of resource attribute ACEs: ("name",type,flags,value,...)
+    fn parse_resource_attribute(&self, s: &str) -> Result<ClaimSecurityAttribute, String> {
+        let inner = match s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+            Some(inner) => inner,
+            None => return Err(format!("resource attribute \"{}\" is not enclosed in parenthesis", s)),
+        };
+        let fields = split_attribute_fields(inner);
+        if fields.len() < 3 {
+            return Err(format!("expected name, type and flags in resource attribute \"{}\"", s));
+        }
+        let name = parse_quoted_string(fields[0])?;
+        let flags = match parse_integer(fields[2]).and_then(|i| u32::try_from(i).ok()) {
+            Some(flags) => flags,
+            None => return Err(format!("invalid resource attribute flags \"{}\"", fields[2])),
+        };
+        let values = &fields[3..];
+        let invalid = |v: &str| format!("invalid {} value \"{}\" in resource attribute \"{}\"", fields[1], v, s);
+        let values = match fields[1] {
+            "TI" => ClaimValues::Int64(values.iter()
+                .map(|v| parse_integer(v).and_then(|i| i64::try_from(i).ok()).ok_or_else(|| invalid(v)))
+                .collect::<Result<Vec<i64>, String>>()?),
+            "TU" => ClaimValues::Uint64(values.iter()
+                .map(|v| parse_integer(v).and_then(|i| u64::try_from(i).ok()).ok_or_else(|| invalid(v)))
+                .collect::<Result<Vec<u64>, String>>()?),
+            "TS" => ClaimValues::String(values.iter()
+                .map(|v| parse_quoted_string(v))
+                .collect::<Result<Vec<String>, String>>()?),
+            "TD" => ClaimValues::Sid(values.iter()
+                .map(|v| match v.strip_prefix("SID(").and_then(|v| v.strip_suffix(')')) {
+                    Some(sid) => self.parse_sid(sid.trim()),
+                    None => Err(invalid(v)),
+                })
+                .collect::<Result<Vec<Sid>, String>>()?),
+            "TB" => ClaimValues::Boolean(values.iter()
+                .map(|v| match *v {
+                    "0" => Ok(false),
+                    "1" => Ok(true),
+                    _ => Err(invalid(v)),
+                })
+                .collect::<Result<Vec<bool>, String>>()?),
+            "TX" => ClaimValues::OctetString(values.iter()
+                .map(|v| parse_hex_bytes(v.strip_prefix('#').unwrap_or(v)).ok_or_else(|| invalid(v)))
+                .collect::<Result<Vec<Vec<u8>>, String>>()?),
+            _ => return Err(format!("unsupported resource attribute type \"{}\"", fields[1])),
+        };
+        Ok(ClaimSecurityAttribute {
+            name,
+            flags,
+            values,
+        })
+    }
+}
+
+// Recursive descent parser for conditional expressions (see MS-DTYP 2.5.1.1), from the lowest
+// precedence (||) to the highest (literals and attributes). Each node is returned along with its
+// depth, which is capped like when parsing the binary form.
+struct ConditionParser<'a, 's> {
+    sddl: &'a SddlParser<'a>,
+    s: &'s str,
+    pos: usize,
+    // Number of parenthesis and ! currently open
+    level: usize,
+}
+
+impl<'a, 's> ConditionParser<'a, 's> {
+    fn rest(&self) -> &'s str {
+        &self.s[self.pos..]
+    }
+
+    fn error(&self) -> String {
+        format!("invalid conditional expression \"{}\" at \"{}\"", self.s, self.rest())
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns the keyword (operator name or attribute name) at the current position, without
+    // consuming it
+    fn peek_word(&mut self) -> &'s str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || "_:./".contains(c))).unwrap_or(rest.len());
+        &rest[..len]
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.level += 1;
+        if self.level > MAX_NESTING_DEPTH {
+            return Err(format!("conditional expression \"{}\" is nested too deeply", self.s));
+        }
+        Ok(())
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<usize, String> {
+        if depth > MAX_NESTING_DEPTH {
+            return Err(format!("conditional expression \"{}\" is nested too deeply", self.s));
+        }
+        Ok(depth)
+    }
+
+    fn binary(&self, operator: BinaryOperator, (left, left_depth): (ConditionalExpression, usize), (right, right_depth): (ConditionalExpression, usize)) -> Result<(ConditionalExpression, usize), String> {
+        let depth = self.check_depth(std::cmp::max(left_depth, right_depth) + 1)?;
+        Ok((ConditionalExpression::Binary { operator, left: Box::new(left), right: Box::new(right) }, depth))
+    }
+
+    fn unary(&self, operator: UnaryOperator, (operand, depth): (ConditionalExpression, usize)) -> Result<(ConditionalExpression, usize), String> {
+        let depth = self.check_depth(depth + 1)?;
+        Ok((ConditionalExpression::Unary { operator, operand: Box::new(operand) }, depth))
+    }
+
+    fn parse_or(&mut self) -> Result<(ConditionalExpression, usize), String> {
+        let mut left = self.parse_and()?;
+        while self.eat("||") {
+            let right = self.parse_and()?;
+            left = self.binary(BinaryOperator::Or, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<(ConditionalExpression, usize), String> {
+        let mut left = self.parse_not()?;
+        while self.eat("&&") {
+            let right = self.parse_not()?;
+            left = self.binary(BinaryOperator::And, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<(ConditionalExpression, usize), String> {
+        self.skip_whitespace();
+        if self.rest().starts_with('!') && !self.rest().starts_with("!=") {
+            self.pos += 1;
+            self.enter()?;
+            let operand = self.parse_not()?;
+            self.level -= 1;
+            return self.unary(UnaryOperator::Not, operand);
+        }
+        self.parse_relational()
+    }
+
+    fn parse_relational(&mut self) -> Result<(ConditionalExpression, usize), String> {
+        let word = self.peek_word();
+        if let Some((_, operator)) = UNARY_OPERATORS.iter().find(|(_, op)| op.get_name().eq_ignore_ascii_case(word)) {
+            self.pos += word.len();
+            let operand = self.parse_primary()?;
+            return self.unary(*operator, operand);
+        }
+        let left = self.parse_primary()?;
+        // Symbols are tried longest first, so that <= is not read as <
+        let mut operators: Vec<&BinaryOperator> = BINARY_OPERATORS.iter()
+            .map(|(_, op)| op)
+            .filter(|op| !matches!(op, BinaryOperator::And | BinaryOperator::Or))
+            .collect();
+        operators.sort_by_key(|op| std::cmp::Reverse(op.get_name().len()));
+        let word = self.peek_word();
+        let operator = operators.into_iter().find(|op| {
+            let name = op.get_name();
+            if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                name.eq_ignore_ascii_case(word)
+            } else {
+                self.rest().starts_with(name)
+            }
+        });
+        match operator {
+            Some(operator) => {
+                self.pos += operator.get_name().len();
+                let right = self.parse_primary()?;
+                self.binary(*operator, left, right)
+            },
+            None => Ok(left),
+        }
+    }
+
+    // Parses a parenthesized expression, an attribute, or a literal
+    fn parse_primary(&mut self) -> Result<(ConditionalExpression, usize), String> {
+        if self.eat("(") {
+            self.enter()?;
+            let expr = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(self.error());
+            }
+            self.level -= 1;
+            return Ok(expr);
+        }
+        let rest = self.rest();
+        let scopes = [("@User.", AttributeScope::User), ("@Device.", AttributeScope::Device), ("@Resource.", AttributeScope::Resource)];
+        if let Some((prefix, scope)) = scopes.iter().find(|(prefix, _)| rest.get(..prefix.len()).map(|p| p.eq_ignore_ascii_case(prefix)).unwrap_or(false)) {
+            self.pos += prefix.len();
+            let name = self.peek_word();
+            if name.is_empty() {
+                return Err(self.error());
+            }
+            self.pos += name.len();
+            return Ok((ConditionalExpression::Attribute { scope: *scope, name: name.to_owned() }, 0));
+        }
+        if rest.starts_with(|c: char| c.is_ascii_alphabetic()) && !rest.starts_with("SID(") {
+            let name = self.peek_word();
+            self.pos += name.len();
+            return Ok((ConditionalExpression::Attribute { scope: AttributeScope::Local, name: name.to_owned() }, 0));
+        }
+        self.parse_literal(0)
+    }
+
+    // Parses a string, integer, octet string, SID, or composite of these
+    fn parse_literal(&mut self, composite_depth: usize) -> Result<(ConditionalExpression, usize), String> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        if let Some(inner) = rest.strip_prefix('"') {
+            let len = inner.find('"').ok_or_else(|| self.error())?;
+            self.pos += len + 2;
+            return Ok((ConditionalExpression::String(inner[..len].to_owned()), 0));
+        }
+        if let Some(inner) = rest.strip_prefix('#') {
+            let len = inner.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(inner.len());
+            let bytes = parse_hex_bytes(&inner[..len]).ok_or_else(|| self.error())?;
+            self.pos += len + 1;
+            return Ok((ConditionalExpression::OctetString(bytes), 0));
+        }
+        if let Some(inner) = rest.strip_prefix("SID(") {
+            let len = inner.find(')').ok_or_else(|| self.error())?;
+            let sid = self.sddl.parse_sid(inner[..len].trim())?;
+            self.pos += len + 5;
+            return Ok((ConditionalExpression::Sid(sid), 0));
+        }
+        if rest.starts_with('{') {
+            if composite_depth >= MAX_NESTING_DEPTH {
+                return Err(format!("conditional expression \"{}\" is nested too deeply", self.s));
+            }
+            self.pos += 1;
+            let mut items = vec![];
+            if !self.eat("}") {
+                loop {
+                    let (item, _) = self.parse_literal(composite_depth + 1)?;
+                    items.push(item);
+                    if self.eat("}") {
+                        break;
+                    }
+                    if !self.eat(",") {
+                        return Err(self.error());
+                    }
+                }
+            }
+            return Ok((ConditionalExpression::Composite(items), 0));
+        }
+        let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '+' || c == '-')).unwrap_or(rest.len());
+        let token = &rest[..len];
+        let value = parse_integer(token).and_then(|i| i64::try_from(i).ok()).ok_or_else(|| self.error())?;
+        let (sign, digits) = match token.as_bytes()[0] {
+            b'+' => (IntegerSign::Positive, &token[1..]),
+            b'-' => (IntegerSign::Negative, &token[1..]),
+            _ => (IntegerSign::None, token),
+        };
+        let base = if digits.starts_with("0x") || digits.starts_with("0X") {
+            IntegerBase::Hexadecimal
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            IntegerBase::Octal
+        } else {
+            IntegerBase::Decimal
+        };
+        self.pos += len;
+        Ok((ConditionalExpression::Integer { value, sign, base, size: 8 }, 0))
+    }
+}
+
+fn is_callback_ace_type(ace_type: u8) -> bool {
+    [
+        ACCESS_ALLOWED_CALLBACK_ACE_TYPE,
+        ACCESS_DENIED_CALLBACK_ACE_TYPE,
+        SYSTEM_AUDIT_CALLBACK_ACE_TYPE,
+        ACCESS_ALLOWED_CALLBACK_OBJECT_ACE_TYPE,
+        ACCESS_DENIED_CALLBACK_OBJECT_ACE_TYPE,
+        SYSTEM_AUDIT_CALLBACK_OBJECT_ACE_TYPE,
+    ].contains(&ace_type)
+}
+
+// Splits the fields of a resource attribute on commas, except within quoted strings and SID()
+fn split_attribute_fields(s: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut in_quotes = false;
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => depth -= 1,
+            ',' if !in_quotes && depth == 0 => {
+                fields.push(s[start..i].trim());
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    fields.push(s[start..].trim());
+    fields
+}
+
+fn parse_quoted_string(s: &str) -> Result<String, String> {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) if !inner.contains('"') => Ok(inner.to_owned()),
+        _ => Err(format!("invalid quoted string {}", s)),
+    }
+}
+
+// Parses a signed decimal, hexadecimal (0x) or octal (leading 0) integer
+fn parse_integer(s: &str) -> Option<i128> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i128::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i128::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse::<i128>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 == 1 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+// Returns the length of the SID string or alias at the start of the given string (owner and
+// group components are not delimited, they stop where the next component starts)
+fn sid_token_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    if !(s.starts_with("S-") || s.starts_with("s-")) {
+        return std::cmp::min(2, s.len());
+    }
+    let mut len = 2;
+    let mut in_hex = false;
+    while len < bytes.len() {
+        let c = bytes[len];
+        if c == b'-' {
+            in_hex = false;
+        } else if (c == b'x' || c == b'X') && bytes[len - 1] == b'0' && bytes[len - 2] == b'-' {
+            in_hex = true;
+        } else if !(c.is_ascii_digit() || (in_hex && c.is_ascii_hexdigit())) {
+            break;
+        }
+        len += 1;
+    }
+    len
+}
+
+// Parses the flags before the ACEs of an ACL, returns (protected, auto_inherit_req,
+// auto_inherited), whether the ACL is NULL, and the rest of the string
+fn parse_acl_flags(mut rest: &str) -> ((bool, bool, bool), bool, &str) {
+    let mut flags = (false, false, false);
+    let mut null_acl = false;
+    loop {
+        if let Some(r) = rest.strip_prefix("NO_ACCESS_CONTROL") {
+            null_acl = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('P') {
+            flags.0 = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("AR") {
+            flags.1 = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("AI") {
+            flags.2 = true;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    (flags, null_acl, rest)
+}
+
+fn map_acl_flags(flags: (bool, bool, bool), protected: u16, auto_inherit_req: u16, auto_inherited: u16) -> u16 {
+    let mut controls = 0;
+    if flags.0 {
+        controls |= protected;
+    }
+    if flags.1 {
+        controls |= auto_inherit_req;
+    }
+    if flags.2 {
+        controls |= auto_inherited;
+    }
+    controls
+}
+
+fn parse_ace_flags(s: &str) -> Result<u8, String> {
+    let mut flags = 0;
+    for chunk in two_letter_chunks(s)? {
+        match ACE_FLAG_NAMES.iter().find(|(name, _)| *name == chunk) {
+            Some((_, flag)) => flags |= flag,
+            None => return Err(format!("unknown ACE flag \"{}\"", chunk)),
+        }
+    }
+    Ok(flags)
+}
+
+fn parse_access_mask(s: &str) -> Result<u32, String> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return u32::from_str_radix(hex, 16).map_err(|_| format!("invalid access mask \"{}\"", s));
+    }
+    if !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit()) {
+        return s.parse::<u32>().map_err(|_| format!("invalid access mask \"{}\"", s));
+    }
+    let mut mask = 0;
+    for chunk in two_letter_chunks(s)? {
+        match ACCESS_RIGHT_NAMES.iter()
+                .chain(EXTRA_ACCESS_RIGHT_NAMES.iter())
+                .chain(MANDATORY_LABEL_RIGHT_NAMES.iter())
+                .find(|(name, _)| *name == chunk) {
+            Some((_, bits)) => mask |= bits,
+            None => return Err(format!("unknown access right \"{}\"", chunk)),
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_guid(s: &str) -> Result<Option<Guid>, String> {
+    if s.is_empty() {
+        return Ok(None);
+    }
+    match Guid::try_from(s) {
+        Ok(guid) => Ok(Some(guid)),
+        Err(_) => Err(format!("invalid GUID \"{}\"", s)),
+    }
+}
+
+fn two_letter_chunks(s: &str) -> Result<Vec<&str>, String> {
+    if s.len() % 2 == 1 || !s.is_ascii() {
+        return Err(format!("expected a list of two-letter abbreviations, got \"{}\"", s));
+    }
+    Ok((0..s.len()).step_by(2).map(|i| &s[i..i + 2]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(sddl: &str) -> Result<SecurityDescriptor, AuthzError> {
+        let domain_sid = Sid::try_from("S-1-5-21-1-2-3").expect("invalid SID");
+        SecurityDescriptor::from_str(sddl, &domain_sid, &domain_sid)
+    }
+
+    fn get_condition(sddl: &str) -> ConditionalExpression {
+        let sd = parse(sddl).expect("unable to parse SDDL");
+        sd.dacl.expect("no DACL").aces[0].get_condition().expect("invalid condition").expect("no condition")
+    }
+
+    #[test]
+    fn conditions_are_parsed() {
+        let condition = get_condition("O:BAD:(XA;;CR;;;WD;(Member_of {SID(BA)}))");
+        assert_eq!(condition, ConditionalExpression::Unary {
+            operator: UnaryOperator::MemberOf,
+            operand: Box::new(ConditionalExpression::Composite(vec![
+                ConditionalExpression::Sid(Sid::try_from("S-1-5-32-544").expect("invalid SID")),
+            ])),
+        });
+
+        let condition = get_condition("D:(XD;;FA;;;WD;(@User.clearance < 3 || !(Member_of_any {SID(DA), SID(S-1-5-21-1-2-3-1104)}) && title == \"x\"))");
+        assert_eq!(condition.to_string(), "((@User.clearance < 3) || ((!(Member_of_Any {SID(S-1-5-21-1-2-3-512), SID(S-1-5-21-1-2-3-1104)})) && (title == \"x\")))");
+
+        let condition = get_condition("D:(ZA;;CR;;;WD;(Exists @Resource.Project))");
+        assert_eq!(condition.get_attributes(), vec![(AttributeScope::Resource, "Project")]);
+
+        let condition = get_condition("D:(XA;;FA;;;WD;((@Device.level >= -0x10) && (@User.tags Any_of {\"a\", #00ff, 017})))");
+        assert_eq!(condition.to_string(), "((@Device.level >= -0x10) && (@User.tags Any_of {\"a\", #00ff, 017}))");
+    }
+
+    #[test]
+    fn conditions_round_trip() {
+        for sddl in [
+            "O:BAD:(XA;;CR;;;WD;(Member_of {SID(BA)}))",
+            "D:(XD;OICI;FA;;;WD;(!(@User.clearance <= +2)))",
+            "D:(XA;;FA;;;AU;((Not_Member_of {SID(DA)}) || (@User.dept Contains {\"IT\", \"HR\"})))",
+            "D:(ZA;;RP;bf967aba-0de6-11d0-a285-00aa003049e2;;WD;(Device_Member_of_Any {SID(S-1-5-21-1-2-3-1104)}))",
+            "S:(XU;SA;FA;;;WD;(@Resource.Secret == 1))",
+        ] {
+            let sd = parse(sddl).expect("unable to parse SDDL");
+            let reparsed = parse(&sd.to_sddl()).expect("unable to parse generated SDDL");
+            assert_eq!(sd.to_bytes(), reparsed.to_bytes(), "{} -> {}", sddl, sd.to_sddl());
+        }
+    }
+
+    #[test]
+    fn invalid_conditions_are_rejected() {
+        for sddl in [
+            "D:(A;;FA;;;WD;(Member_of {SID(BA)}))",
+            "D:(XA;;FA;;;WD;(Member_of {SID(BA)})",
+            "D:(XA;;FA;;;WD;(Member_of {SID(BA)})))",
+            "D:(XA;;FA;;;WD;(@User.x == ))",
+            "D:(XA;;FA;;;WD;(@User.x == \"a))",
+            "D:(XA;;FA;;;WD;(Member_of {SID(XX)}))",
+            "D:(XA;;FA;;;WD;(x == 99999999999999999999))",
+        ] {
+            assert!(parse(sddl).is_err(), "{}", sddl);
+        }
+        let nested = format!("D:(XA;;FA;;;WD;({}x{}))", "(".repeat(MAX_NESTING_DEPTH + 1), ")".repeat(MAX_NESTING_DEPTH + 1));
+        assert!(parse(&nested).is_err());
+        let chained = format!("D:(XA;;FA;;;WD;(x{}))", " && x".repeat(MAX_NESTING_DEPTH + 1));
+        assert!(parse(&chained).is_err());
+        let negated = format!("D:(XA;;FA;;;WD;({}x))", "!".repeat(60000));
+        assert!(parse(&negated).is_err());
+    }
+}
@@ -1,13 +1,29 @@
-use windows::Win32::Security::{IsValidSecurityDescriptor, GetSecurityDescriptorControl, GetSecurityDescriptorOwner, GetLengthSid, GetSecurityDescriptorGroup, GetSecurityDescriptorDacl, GetAclInformation, ACL_SIZE_INFORMATION, AclSizeInformation, ACL, GetSecurityDescriptorSacl, SECURITY_DESCRIPTOR};
-use std::ptr::null_mut;
-use windows::Win32::Foundation::PSID;
 use crate::error::AuthzError;
-use crate::utils::get_last_error;
+use crate::utils::{read_u16, read_u32};
 use crate::{Sid, Acl};
-use windows::Win32::Security::Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
-use windows::Win32::System::Memory::LocalFree;
 
-#[derive(Debug)]
+pub(crate) const SECURITY_DESCRIPTOR_REVISION: u8 = 1;
+
+pub const SE_DACL_PRESENT: u16 = 0x0004;
+pub const SE_DACL_DEFAULTED: u16 = 0x0008;
+pub const SE_SACL_PRESENT: u16 = 0x0010;
+pub const SE_SACL_DEFAULTED: u16 = 0x0020;
+pub const SE_DACL_AUTO_INHERIT_REQ: u16 = 0x0100;
+pub const SE_SACL_AUTO_INHERIT_REQ: u16 = 0x0200;
+pub const SE_DACL_AUTO_INHERITED: u16 = 0x0400;
+pub const SE_SACL_AUTO_INHERITED: u16 = 0x0800;
+pub const SE_DACL_PROTECTED: u16 = 0x1000;
+pub const SE_SACL_PROTECTED: u16 = 0x2000;
+pub const SE_SELF_RELATIVE: u16 = 0x8000;
+
+// Parts of a security descriptor to query or set (e.g. with the LDAP_SERVER_SD_FLAGS_OID control)
+pub const OWNER_SECURITY_INFORMATION: u32 = 0x1;
+pub const GROUP_SECURITY_INFORMATION: u32 = 0x2;
+pub const DACL_SECURITY_INFORMATION: u32 = 0x4;
+pub const SACL_SECURITY_INFORMATION: u32 = 0x8;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
 pub struct SecurityDescriptor {
     pub revision: u32,
     pub controls: u16,
@@ -19,132 +35,49 @@ pub struct SecurityDescriptor {
 
 impl SecurityDescriptor {
     pub fn from_bytes(slice: &[u8]) -> Result<Self, AuthzError> {
-        let is_valid = unsafe { IsValidSecurityDescriptor(slice.as_ptr() as *const _) };
-        if !is_valid.as_bool() {
+        // Only self-relative security descriptors can be parsed from a buffer (absolute ones
+        // contain pointers). Header: Revision (u8), Sbz1 (u8), Control (u16), then offsets
+        // (u32) to the owner, the group, the SACL, and the DACL. An offset of 0 means absent.
+        if slice.len() < 20 || slice[0] != SECURITY_DESCRIPTOR_REVISION {
             return Err(AuthzError::InvalidSecurityDescriptor(slice.to_vec()));
         }
-
-        let mut controls: u16 = 0;
-        let mut revision: u32 = 0;
-        let succeeded = unsafe { GetSecurityDescriptorControl(slice.as_ptr() as *const _, &mut controls as *mut _, &mut revision as *mut _) };
-        if !succeeded.as_bool() {
-            return Err(AuthzError::GetSecurityDescriptorControlFailed { code: get_last_error(), bytes: slice.to_vec() });
+        let revision = slice[0] as u32;
+        let controls = read_u16(slice, 2).expect("assertion failed: security descriptor shorter than its header");
+        if (controls & SE_SELF_RELATIVE) == 0 {
+            return Err(AuthzError::InvalidSecurityDescriptor(slice.to_vec()));
         }
+        let offset_owner = read_u32(slice, 4).expect("assertion failed: security descriptor shorter than its header") as usize;
+        let offset_group = read_u32(slice, 8).expect("assertion failed: security descriptor shorter than its header") as usize;
+        let offset_sacl = read_u32(slice, 12).expect("assertion failed: security descriptor shorter than its header") as usize;
+        let offset_dacl = read_u32(slice, 16).expect("assertion failed: security descriptor shorter than its header") as usize;
 
-        // Parse the owner from the SD
-        let owner = {
-            let mut psid = PSID(0);
-            let mut defaulted: i32 = 0;
-            let succeeded = unsafe { GetSecurityDescriptorOwner(slice.as_ptr() as *const _, &mut psid as *mut _, &mut defaulted as *mut _) };
-            if !succeeded.as_bool() {
-                return Err(AuthzError::GetSecurityDescriptorOwnerFailed { code: get_last_error(), ptr: null_mut(), bytes: slice.to_vec() });
-            }
-            else if psid.0 == 0 {
-                None // a NULL pointer is returned if there is no owner in this SD
-            }
-            else if (psid.0 as usize) < (slice.as_ptr() as usize) || (psid.0 as usize) >= (slice.as_ptr() as usize + slice.len()) {
-                return Err(AuthzError::GetSecurityDescriptorOwnerFailed { code: u32::MAX, ptr: psid.0 as *const u8, bytes: slice.to_vec() });
-            }
-            else {
-                let expected_size = unsafe { GetLengthSid(psid) } as usize;
-                if (psid.0 as usize + expected_size) > (slice.as_ptr() as usize + slice.len()) {
-                    return Err(AuthzError::GetSecurityDescriptorOwnerFailed { code: u32::MAX, ptr: psid.0 as *const u8, bytes: slice.to_vec() });
-                }
-                let offset = (psid.0 as usize) - (slice.as_ptr() as usize);
-                Some(Sid::from_bytes(&slice[offset..offset+expected_size])?)
-            }
+        // Parse the owner, if any
+        let owner = if offset_owner == 0 {
+            None
+        } else {
+            Some(Self::parse_sid_at(slice, "owner", offset_owner)?)
         };
 
         // Parse the primary group, if any
-        let group = {
-            let mut psid = PSID(0);
-            let mut defaulted: i32 = 0;
-            let succeeded = unsafe { GetSecurityDescriptorGroup(slice.as_ptr() as *const _, &mut psid as *mut _, &mut defaulted as *mut _) };
-            if !succeeded.as_bool() {
-                return Err(AuthzError::GetSecurityDescriptorGroupFailed { code: get_last_error(), ptr: null_mut(), bytes: slice.to_vec() });
-            }
-            else if psid.0 == 0 {
-                None // a NULL pointer is returned if there is no primary group in this SD
-            }
-            else if (psid.0 as usize) < (slice.as_ptr() as usize) || (psid.0 as usize) >= (slice.as_ptr() as usize + slice.len()) {
-                return Err(AuthzError::GetSecurityDescriptorGroupFailed { code: u32::MAX, ptr: psid.0 as *const u8, bytes: slice.to_vec() });
-            }
-            else {
-                let expected_size = unsafe { GetLengthSid(psid) } as usize;
-                let offset = (psid.0 as usize) - (slice.as_ptr() as usize);
-                if (offset + expected_size) > slice.len() {
-                    return Err(AuthzError::GetSecurityDescriptorGroupFailed { code: u32::MAX, ptr: psid.0 as *const u8, bytes: slice.to_vec() });
-                }
-                Some(Sid::from_bytes(&slice[offset..offset+expected_size])?)
-            }
+        let group = if offset_group == 0 {
+            None
+        } else {
+            Some(Self::parse_sid_at(slice, "group", offset_group)?)
         };
 
-        // Parse the DACL, if any
-        let dacl = {
-            let mut acl = null_mut() as *mut ACL;
-            let mut present: i32 = 0;
-            let mut defaulted: i32 = 0;
-            let succeeded = unsafe { GetSecurityDescriptorDacl(slice.as_ptr() as *const _, &mut present as *mut _, &mut acl as *mut _, &mut defaulted as *mut _) };
-            if !succeeded.as_bool() {
-                return Err(AuthzError::GetSecurityDescriptorDaclFailed { code: get_last_error(), ptr: null_mut(), bytes: slice.to_vec() });
-            }
-            else if acl.is_null() {
-                None // a NULL pointer is returned if there is no DACL in this SD
-            }
-            else if (acl as usize) < (slice.as_ptr() as usize) || (acl as usize) >= (slice.as_ptr() as usize + slice.len()) {
-                return Err(AuthzError::GetSecurityDescriptorDaclFailed { code: u32::MAX, ptr: acl as *const u8, bytes: slice.to_vec() });
-            }
-            else {
-                let mut size_info = ACL_SIZE_INFORMATION {
-                    AceCount: 0,
-                    AclBytesInUse: 0,
-                    AclBytesFree: 0
-                };
-                let succeeded = unsafe { GetAclInformation(acl, &mut size_info as *mut _ as *mut _, std::mem::size_of_val(&size_info) as u32, AclSizeInformation) };
-                if !succeeded.as_bool() {
-                    return Err(AuthzError::GetAclInformationFailed { bytes: slice.to_vec(), code: get_last_error() });
-                }
-                let expected_size = (size_info.AclBytesInUse + size_info.AclBytesFree) as usize;
-                let offset = (acl as usize) - (slice.as_ptr() as usize);
-                if (offset + expected_size) > slice.len() {
-                    return Err(AuthzError::UnexpectedAclSize { bytes: slice.to_vec(), expected_size });
-                }
-                Some(Acl::from(&slice[offset..offset+expected_size])?)
-            }
+        // Parse the DACL, if any (a present DACL with a 0 offset is a NULL DACL, which we
+        // represent the same way as an absent DACL)
+        let dacl = if (controls & SE_DACL_PRESENT) == 0 || offset_dacl == 0 {
+            None
+        } else {
+            Some(Self::parse_acl_at(slice, "DACL", offset_dacl)?)
         };
 
         // Parse the SACL, if any
-        let sacl = {
-            let mut acl = null_mut() as *mut ACL;
-            let mut present: i32 = 0;
-            let mut defaulted: i32 = 0;
-            let succeeded = unsafe { GetSecurityDescriptorSacl(slice.as_ptr() as *const _, &mut present as *mut _, &mut acl as *mut _, &mut defaulted as *mut _) };
-            if !succeeded.as_bool() {
-                return Err(AuthzError::GetSecurityDescriptorSaclFailed { code: get_last_error(), ptr: null_mut(), bytes: slice.to_vec() });
-            }
-            else if acl.is_null() {
-                None // a NULL pointer is returned if there is no SACL in this SD
-            }
-            else if (acl as usize) < (slice.as_ptr() as usize) || (acl as usize) >= (slice.as_ptr() as usize + slice.len()) {
-                return Err(AuthzError::GetSecurityDescriptorSaclFailed { code: 0, ptr: acl as *const u8, bytes: slice.to_vec() });
-            }
-            else {
-                let mut size_info = ACL_SIZE_INFORMATION {
-                    AceCount: 0,
-                    AclBytesInUse: 0,
-                    AclBytesFree: 0
-                };
-                let succeeded = unsafe { GetAclInformation(acl, &mut size_info as *mut _ as *mut _, std::mem::size_of_val(&size_info) as u32, AclSizeInformation) };
-                if !succeeded.as_bool() {
-                    return Err(AuthzError::GetAclInformationFailed { bytes: slice.to_vec(), code: get_last_error() });
-                }
-                let expected_size = (size_info.AclBytesInUse + size_info.AclBytesFree) as usize;
-                let offset = (acl as usize) - (slice.as_ptr() as usize);
-                if (offset + expected_size) > slice.len() {
-                    return Err(AuthzError::UnexpectedAclSize { bytes: slice.to_vec(), expected_size });
-                }
-                Some(Acl::from(&slice[offset..offset+expected_size])?)
-            }
+        let sacl = if (controls & SE_SACL_PRESENT) == 0 || offset_sacl == 0 {
+            None
+        } else {
+            Some(Self::parse_acl_at(slice, "SACL", offset_sacl)?)
         };
 
         Ok(SecurityDescriptor {
@@ -157,57 +90,152 @@ impl SecurityDescriptor {
         })
     }
 
-    pub fn from_str(sddl: &str, domain_sid: &Sid, root_domain_sid: &Sid) -> Result<Self, AuthzError> {
-        // First, we need to normalize the SDDL, in case it contains abbreviated
-        // principals which depend on the domain within which the SDDL applies, and
-        // depends on the root domain of the forest within which the SDDL applies.
-        let sddl = sddl.replace( // Account Operators
-            ";AO)",
-            ";S-1-5-32-548)"
-        ).replace( // Domain Admins
-            ";DA)",
-            &format!(";{}-512)", domain_sid.to_string())
-        ).replace( // Domain Users
-            ";DU)",
-            &format!(";{}-513)", domain_sid.to_string())
-        ).replace( // Domain Guests
-            ";DG)",
-            &format!(";{}-514)", domain_sid.to_string())
-        ).replace( // Domain Computers
-            ";DC)",
-            &format!(";{}-515)", domain_sid.to_string())
-        ).replace( // Domain Controllers
-            ";DD)",
-            &format!(";{}-516)", domain_sid.to_string())
-        ).replace( // Certificate Publishers
-            ";CA)",
-            &format!(";{}-517)", domain_sid.to_string())
-        ).replace( // RAS and IAS Servers
-            ";RS)",
-            &format!(";{}-553)", domain_sid.to_string())
-        ).replace( // Group Policy Admins / Creator Owner
-            ";PA)",
-            &format!(";{}-520)", domain_sid.to_string())
-        ).replace( // Enterprise Read-only Domain Controllers
-            ";RO)",
-            &format!(";{}-498)", root_domain_sid.to_string())
-        ).replace( // Schema Admins
-            ";SA)",
-            &format!(";{}-518)", root_domain_sid.to_string())
-        ).replace( // Enterprise Admins
-            ";EA)",
-            &format!(";{}-519)", root_domain_sid.to_string())
-        );
-
-        let mut psd: *mut SECURITY_DESCRIPTOR = null_mut();
-        let mut sd_size: u32 = 0;
-        let succeeded = unsafe { ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl.as_str(), SDDL_REVISION_1, &mut psd as *mut _, &mut sd_size as *mut _) };
-        if !succeeded.as_bool() {
-            return Err(AuthzError::InvalidStringSecurityDescriptor { code: get_last_error(), str: sddl });
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Emits a self-relative security descriptor in the same layout as the one produced by
+        // Windows: header, then SACL, DACL, owner, and group. A NULL DACL (present flag without
+        // an ACL) is kept as-is, with a 0 offset.
+        let mut controls = self.controls | SE_SELF_RELATIVE;
+        if self.dacl.is_some() {
+            controls |= SE_DACL_PRESENT;
+        }
+        if self.sacl.is_some() {
+            controls |= SE_SACL_PRESENT;
+        }
+        let mut res = vec![SECURITY_DESCRIPTOR_REVISION, 0];
+        res.extend_from_slice(&controls.to_le_bytes());
+        res.resize(20, 0);
+
+        let mut offset_sacl = 0;
+        if let Some(sacl) = &self.sacl {
+            offset_sacl = res.len() as u32;
+            res.extend_from_slice(&sacl.to_bytes());
+        }
+        let mut offset_dacl = 0;
+        if let Some(dacl) = &self.dacl {
+            offset_dacl = res.len() as u32;
+            res.extend_from_slice(&dacl.to_bytes());
         }
-        let slice = unsafe { &*std::ptr::slice_from_raw_parts(psd as *const u8, sd_size as usize) };
-        let res = SecurityDescriptor::from_bytes(slice);
-        unsafe { LocalFree(psd as isize); }
+        let mut offset_owner = 0;
+        if let Some(owner) = &self.owner {
+            offset_owner = res.len() as u32;
+            res.extend_from_slice(owner.as_bytes());
+        }
+        let mut offset_group = 0;
+        if let Some(group) = &self.group {
+            offset_group = res.len() as u32;
+            res.extend_from_slice(group.as_bytes());
+        }
+
+        res[4..8].copy_from_slice(&offset_owner.to_le_bytes());
+        res[8..12].copy_from_slice(&offset_group.to_le_bytes());
+        res[12..16].copy_from_slice(&offset_sacl.to_le_bytes());
+        res[16..20].copy_from_slice(&offset_dacl.to_le_bytes());
         res
     }
-}
\ No newline at end of file
+
+    fn parse_sid_at(slice: &[u8], field: &'static str, offset: usize) -> Result<Sid, AuthzError> {
+        if offset < 20 || offset >= slice.len() {
+            return Err(AuthzError::SecurityDescriptorOffsetOutOfBounds { bytes: slice.to_vec(), field, offset });
+        }
+        Sid::from_bytes_prefix(&slice[offset..])
+    }
+
+    fn parse_acl_at(slice: &[u8], field: &'static str, offset: usize) -> Result<Acl, AuthzError> {
+        if offset < 20 || offset >= slice.len() {
+            return Err(AuthzError::SecurityDescriptorOffsetOutOfBounds { bytes: slice.to_vec(), field, offset });
+        }
+        let expected_size = match read_u16(slice, offset + 2) {
+            Some(size) => size as usize,
+            None => return Err(AuthzError::InvalidAcl(slice[offset..].to_vec())),
+        };
+        if (offset + expected_size) > slice.len() {
+            return Err(AuthzError::UnexpectedAclSize { bytes: slice.to_vec(), expected_size });
+        }
+        Acl::from(&slice[offset..offset+expected_size])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::{Ace, AceType};
+    use crate::ace::tests::sample_aces;
+
+    fn sample_security_descriptors() -> Vec<SecurityDescriptor> {
+        let domain_sid = Sid::try_from("S-1-5-21-1-2-3").expect("invalid SID");
+        let sddl = "O:DAG:DUD:PAI(A;;GA;;;DA)(OA;CIIO;RPWP;bf967a86-0de6-11d0-a285-00aa003049e2;bf967aba-0de6-11d0-a285-00aa003049e2;AU)S:(AU;SAFA;WDWO;;;WD)(ML;;NW;;;LW)";
+        let parsed = SecurityDescriptor::from_str(sddl, &domain_sid, &domain_sid).expect("unable to parse SDDL");
+        let (sacl_aces, dacl_aces): (Vec<Ace>, Vec<Ace>) = sample_aces().into_iter()
+            .partition(|ace| matches!(ace.type_specific, AceType::Audit | AceType::AuditObject { .. } | AceType::AuditCallback { .. } |
+                AceType::AuditCallbackObject { .. } | AceType::MandatoryLabel | AceType::ResourceAttribute { .. } | AceType::ScopedPolicyId));
+        vec![
+            parsed,
+            SecurityDescriptor {
+                revision: SECURITY_DESCRIPTOR_REVISION as u32,
+                controls: SE_SELF_RELATIVE | SE_DACL_PRESENT | SE_SACL_PRESENT | SE_SACL_AUTO_INHERITED,
+                owner: Some(domain_sid.with_rid(500)),
+                group: None,
+                dacl: Some(Acl { aces: dacl_aces }),
+                sacl: Some(Acl { aces: sacl_aces }),
+            },
+            // Empty DACL (which denies everything), and a NULL SACL
+            SecurityDescriptor {
+                revision: SECURITY_DESCRIPTOR_REVISION as u32,
+                controls: SE_SELF_RELATIVE | SE_DACL_PRESENT | SE_SACL_PRESENT,
+                owner: None,
+                group: Some(domain_sid.with_rid(513)),
+                dacl: Some(Acl { aces: vec![] }),
+                sacl: None,
+            },
+            // Nothing at all
+            SecurityDescriptor {
+                revision: SECURITY_DESCRIPTOR_REVISION as u32,
+                controls: SE_SELF_RELATIVE,
+                owner: None,
+                group: None,
+                dacl: None,
+                sacl: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn acl_bytes_round_trip() {
+        for sd in sample_security_descriptors() {
+            for acl in sd.dacl.iter().chain(sd.sacl.iter()) {
+                let bytes = acl.to_bytes();
+                let parsed = Acl::from(&bytes).expect("unable to parse ACL");
+                assert_eq!(&parsed, acl);
+                assert_eq!(parsed.to_bytes(), bytes);
+            }
+        }
+        assert!(Acl::from(&Acl { aces: vec![] }.to_bytes()[..7]).is_err());
+        let mut bytes = Acl { aces: sample_aces() }.to_bytes();
+        bytes.push(0);
+        assert!(Acl::from(&bytes).is_err());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        for sd in sample_security_descriptors() {
+            let bytes = sd.to_bytes();
+            let parsed = SecurityDescriptor::from_bytes(&bytes).expect("unable to parse security descriptor");
+            assert_eq!(parsed, sd);
+            assert_eq!(parsed.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn invalid_bytes_are_rejected() {
+        let bytes = sample_security_descriptors()[0].to_bytes();
+        assert!(SecurityDescriptor::from_bytes(&bytes[..19]).is_err());
+        assert!(SecurityDescriptor::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut absolute = bytes.clone();
+        absolute[3] &= !((SE_SELF_RELATIVE >> 8) as u8);
+        assert!(SecurityDescriptor::from_bytes(&absolute).is_err());
+        let mut out_of_bounds = bytes;
+        out_of_bounds[4..8].copy_from_slice(&0xFFFFu32.to_le_bytes());
+        assert!(SecurityDescriptor::from_bytes(&out_of_bounds).is_err());
+    }
+}
@@ -2,6 +2,7 @@ use std::convert::TryFrom;
 use serde::de::Visitor;
 use crate::Sid;
 use crate::Guid;
+use crate::AccessMask;
 
 impl serde::Serialize for Sid {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
@@ -60,4 +61,98 @@ impl<'de> serde::Deserialize<'de> for Guid {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
         deserializer.deserialize_str(GuidStrVisitor)
     }
-}
\ No newline at end of file
+}
+// Access masks are serialized as numbers, and can be deserialized from either numbers or
+// strings of rights names (e.g. "GENERIC_ALL", "READ_PROP | WRITE_PROP")
+impl serde::Serialize for AccessMask {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+        serializer.serialize_u32(self.bits())
+    }
+}
+
+struct AccessMaskVisitor;
+
+impl<'de> Visitor<'de> for AccessMaskVisitor {
+    type Value = AccessMask;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("expected an access mask as number, or as string like \"READ_PROP | WRITE_PROP\"")
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> where E: serde::de::Error,
+    {
+        // Undefined bits are part of the mask too, any 32-bit number is accepted
+        match u32::try_from(v).ok().and_then(AccessMask::from_bits) {
+            Some(m) => Ok(m),
+            None => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(v), &self))
+        }
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: serde::de::Error,
+    {
+        match AccessMask::try_from(v) {
+            Ok(m) => Ok(m),
+            _ => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Str(v), &self))
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AccessMask {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+        deserializer.deserialize_any(AccessMaskVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Acl, SecurityDescriptor};
+    use crate::ace::tests::sample_aces;
+
+    fn round_trip<T>(value: &T) -> T where T: serde::Serialize + serde::de::DeserializeOwned {
+        let json = serde_json::to_string(value).expect("unable to serialize");
+        serde_json::from_str(&json).unwrap_or_else(|e| panic!("unable to deserialize {}: {}", json, e))
+    }
+
+    #[test]
+    fn sids_and_guids_are_strings() {
+        let sid = Sid::try_from("S-1-5-21-1-2-3-512").expect("invalid SID");
+        assert_eq!(serde_json::to_string(&sid).expect("unable to serialize"), "\"S-1-5-21-1-2-3-512\"");
+        assert_eq!(round_trip(&sid), sid);
+        let guid = Guid::try_from("bf967aba-0de6-11d0-a285-00aa003049e2").expect("invalid GUID");
+        assert_eq!(round_trip(&guid), guid);
+        assert_eq!(serde_json::from_str::<Guid>("\"{BF967ABA-0DE6-11D0-A285-00AA003049E2}\"").expect("unable to deserialize"), guid);
+        assert!(serde_json::from_str::<Sid>("\"S-1-X\"").is_err());
+        assert!(serde_json::from_str::<Sid>("42").is_err());
+    }
+
+    #[test]
+    fn access_masks() {
+        let mask = AccessMask::READ_PROP | AccessMask::WRITE_PROP;
+        assert_eq!(serde_json::to_string(&mask).expect("unable to serialize"), "48");
+        assert_eq!(round_trip(&mask), mask);
+        assert_eq!(serde_json::from_str::<AccessMask>("\"READ_PROP | WRITE_PROP\"").expect("unable to deserialize"), mask);
+        assert!(serde_json::from_str::<AccessMask>("\"READ_PROP | NOT_A_RIGHT\"").is_err());
+        assert!(serde_json::from_str::<AccessMask>("-1").is_err());
+        assert!(serde_json::from_str::<AccessMask>("4294967296").is_err());
+        let undefined = serde_json::from_str::<AccessMask>("4294967295").expect("unable to deserialize");
+        assert_eq!(undefined.bits(), u32::MAX);
+        assert_eq!(round_trip(&undefined), undefined);
+        let undefined = serde_json::from_str::<AccessMask>("\"WRITE_PROP | 0x200\"").expect("unable to deserialize");
+        assert_eq!(undefined.bits(), 0x220);
+        assert_eq!(undefined.to_string(), "WRITE_PROP | 0x200");
+    }
+
+    #[test]
+    fn security_descriptors_round_trip() {
+        for ace in sample_aces() {
+            assert_eq!(round_trip(&ace), ace);
+        }
+        let acl = Acl { aces: sample_aces() };
+        assert_eq!(round_trip(&acl), acl);
+        let domain_sid = Sid::try_from("S-1-5-21-1-2-3").expect("invalid SID");
+        let sd = SecurityDescriptor::from_str("O:DAG:DUD:PAI(A;;GA;;;DA)(XA;;CR;;;WD;(Member_of {SID(BA)}))S:(ML;;NW;;;LW)", &domain_sid, &domain_sid)
+            .expect("unable to parse SDDL");
+        assert_eq!(round_trip(&sd), sd);
+    }
+}
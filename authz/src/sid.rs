@@ -1,18 +1,11 @@
-use core::fmt::Debug;
+use core::fmt::{Debug, Display, Formatter};
 use std::convert::TryFrom;
-use windows::Win32::Security::EqualPrefixSid;
-use windows::Win32::Security::GetSidIdentifierAuthority;
-use windows::Win32::Security::GetSidSubAuthority;
-use windows::Win32::Security::GetSidSubAuthorityCount;
-use core::ptr::null_mut;
-use core::fmt::Display;
 use crate::error::AuthzError;
-use windows::Win32::Security::{IsValidSid, GetLengthSid};
-use windows::Win32::Security::Authorization::{ConvertSidToStringSidW, ConvertStringSidToSidW};
-use windows::Win32::Foundation::{PSID, PWSTR};
-use windows::Win32::System::Memory::LocalFree;
-use windows::core::alloc::fmt::Formatter;
-use crate::utils::{pwstr_to_str, get_last_error};
+use crate::utils::read_u32;
+
+const SID_REVISION: u8 = 1;
+const SID_MAX_SUB_AUTHORITIES: usize = 15;
+const SECURITY_NT_AUTHORITY: u64 = 5;
 
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct Sid {
@@ -21,12 +14,7 @@ pub struct Sid {
 
 impl Sid {
     pub fn from_bytes(slice: &[u8]) -> Result<Self, AuthzError> {
-        let is_valid = unsafe { IsValidSid(PSID(slice.as_ptr() as isize)) };
-        if !is_valid.as_bool() {
-            return Err(AuthzError::InvalidSidBytes(slice.to_vec()));
-        }
-
-        let expected_size = unsafe { GetLengthSid(PSID(slice.as_ptr() as isize)) } as usize;
+        let expected_size = Self::get_expected_size(slice)?;
         if expected_size != slice.len() {
             return Err(AuthzError::UnexpectedSidSize { bytes: slice.to_vec(), expected_size });
         }
@@ -37,91 +25,137 @@ impl Sid {
         })
     }
 
-    pub(crate) unsafe fn from_ptr(sid: PSID) -> Result<Self, AuthzError> {
-        let is_valid = IsValidSid(sid);
-        if !is_valid.as_bool() {
-            return Err(AuthzError::InvalidSidPointer(sid.0 as *const u8));
+    // Parses a SID at the start of the given buffer, ignoring any data which might follow it
+    // (e.g. application data after the SID in callback ACEs)
+    pub(crate) fn from_bytes_prefix(slice: &[u8]) -> Result<Self, AuthzError> {
+        let expected_size = Self::get_expected_size(slice)?;
+        if expected_size > slice.len() {
+            return Err(AuthzError::UnexpectedSidSize { bytes: slice.to_vec(), expected_size });
         }
+        Ok(Sid {
+            bytes: slice[..expected_size].to_vec(),
+        })
+    }
 
-        let size = GetLengthSid(sid);
-        if size == 0 || size > 1024 {
-            return Err(AuthzError::InvalidSidPointer(sid.0 as *const u8));
+    fn get_expected_size(slice: &[u8]) -> Result<usize, AuthzError> {
+        if slice.len() < 8 || slice[0] != SID_REVISION || slice[1] as usize > SID_MAX_SUB_AUTHORITIES {
+            return Err(AuthzError::InvalidSidBytes(slice.to_vec()));
+        }
+        Ok(8 + 4 * (slice[1] as usize))
+    }
+
+    pub fn from_parts(identifier_authority: u64, sub_authorities: &[u32]) -> Result<Self, AuthzError> {
+        if sub_authorities.len() > SID_MAX_SUB_AUTHORITIES || identifier_authority >= (1 << 48) {
+            return Err(AuthzError::InvalidSidString(format!("S-1-{}-{:?}", identifier_authority, sub_authorities)));
+        }
+        let mut bytes = Vec::with_capacity(8 + 4 * sub_authorities.len());
+        bytes.push(SID_REVISION);
+        bytes.push(sub_authorities.len() as u8);
+        bytes.extend_from_slice(&identifier_authority.to_be_bytes()[2..]);
+        for sub_authority in sub_authorities {
+            bytes.extend_from_slice(&sub_authority.to_le_bytes());
         }
-        let slice = std::ptr::slice_from_raw_parts(sid.0 as *const u8, size as usize);
-        let bytes = (*slice).to_vec();
         Ok(Sid {
             bytes,
         })
     }
 
+    pub fn get_identifier_authority(&self) -> u64 {
+        let mut authority = [0u8; 8];
+        authority[2..].copy_from_slice(&self.bytes[2..8]);
+        u64::from_be_bytes(authority)
+    }
+
+    pub fn get_sub_authorities(&self) -> Vec<u32> {
+        (0..self.bytes[1] as usize)
+            .map(|i| read_u32(&self.bytes, 8 + 4 * i).expect("assertion failed: SID shorter than its sub authority count"))
+            .collect()
+    }
+
     pub fn get_rid(&self) -> u32 {
-        unsafe {
-            let sub_auth_count =  *(GetSidSubAuthorityCount(PSID(self.bytes.as_ptr() as isize)));
-            *(GetSidSubAuthority(PSID(self.bytes.as_ptr() as isize), (sub_auth_count - 1).into()))
-        }
+        self.get_sub_authorities().last().copied().unwrap_or(0)
     }
 
+    // Returns true if both SIDs have the same number of sub authorities, and only differ by
+    // their last sub authority (same semantics as EqualPrefixSid())
     pub fn shares_prefix_with(&self, other: &Sid) -> bool {
-        unsafe {
-            EqualPrefixSid(PSID(self.as_bytes().as_ptr() as isize), PSID(other.as_bytes().as_ptr() as isize))
-        }.as_bool()
+        if self.bytes.len() != other.bytes.len() || self.bytes.len() < 12 {
+            return false;
+        }
+        self.bytes[..self.bytes.len() - 4] == other.bytes[..other.bytes.len() - 4]
     }
 
     // Returns true if and only if the SID starts with S-1-5-21-X-Y-Z
     pub fn is_domain_specific(&self) -> bool {
-        unsafe {
-            let sub_auth_count =  *(GetSidSubAuthorityCount(PSID(self.bytes.as_ptr() as isize)));
-            if sub_auth_count < 4 {
-                return false;
-            }
-            if (*GetSidIdentifierAuthority(PSID(self.bytes.as_ptr() as isize))).Value != [0, 0, 0, 0, 0, 5] { // SECURITY_NT_AUTHORITY
-                return false;
-            }
-            if *(GetSidSubAuthority(PSID(self.bytes.as_ptr() as isize), 0)) != 21 {
-                return false;
-            }
+        let sub_authorities = self.get_sub_authorities();
+        if sub_authorities.len() < 4 {
+            return false;
         }
-        true
+        if self.get_identifier_authority() != SECURITY_NT_AUTHORITY {
+            return false;
+        }
+        sub_authorities[0] == 21
     }
 
     pub fn with_rid(&self, rid: u32) -> Self {
-        let s = format!("{}-{}", self, rid);
-        Self::try_from(s.as_ref()).expect("invalid RID concatenation")
+        let mut sub_authorities = self.get_sub_authorities();
+        sub_authorities.push(rid);
+        Self::from_parts(self.get_identifier_authority(), &sub_authorities).expect("invalid RID concatenation")
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         self.bytes.as_slice()
     }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
 }
 
 impl TryFrom<&str> for Sid {
     type Error = AuthzError;
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        let mut psid = PSID(0);
-        let succeeded = unsafe { ConvertStringSidToSidW(s, &mut psid as *mut _) };
-        if !succeeded.as_bool() {
-            return Err(AuthzError::InvalidSidString { code: get_last_error(), str: s.to_owned() });
+        let mut parts = s.split('-');
+        match (parts.next(), parts.next()) {
+            (Some(prefix), Some("1")) if prefix.eq_ignore_ascii_case("S") => (),
+            _ => return Err(AuthzError::InvalidSidString(s.to_owned())),
+        }
+        // Identifier authorities are written in decimal, unless they do not fit in 32 bits,
+        // in which case they are written in hexadecimal with a 0x prefix
+        let identifier_authority = match parts.next() {
+            Some(auth) if auth.starts_with("0x") || auth.starts_with("0X") => u64::from_str_radix(&auth[2..], 16),
+            Some(auth) => auth.parse::<u64>(),
+            None => return Err(AuthzError::InvalidSidString(s.to_owned())),
+        };
+        let identifier_authority = match identifier_authority {
+            Ok(n) => n,
+            Err(_) => return Err(AuthzError::InvalidSidString(s.to_owned())),
+        };
+        let mut sub_authorities = vec![];
+        for part in parts {
+            match part.parse::<u32>() {
+                Ok(n) => sub_authorities.push(n),
+                Err(_) => return Err(AuthzError::InvalidSidString(s.to_owned())),
+            }
         }
-        let res = unsafe { Self::from_ptr(psid) };
-        unsafe { LocalFree(psid.0); }
-        res
+        Self::from_parts(identifier_authority, &sub_authorities)
+            .map_err(|_| AuthzError::InvalidSidString(s.to_owned()))
     }
 }
 
 impl Display for Sid {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
-        unsafe {
-            let mut str = PWSTR(null_mut());
-            let succeeded = ConvertSidToStringSidW(PSID(self.bytes.as_ptr() as isize), &mut str);
-            if succeeded.as_bool() {
-                let res = pwstr_to_str(str.0);
-                LocalFree(str.0 as isize);
-                f.write_str(&res)
-            } else {
-                write!(f, "SID={:?}", self)
-            }
+        let identifier_authority = self.get_identifier_authority();
+        if identifier_authority >= (1 << 32) {
+            write!(f, "S-1-0x{:012X}", identifier_authority)?;
+        } else {
+            write!(f, "S-1-{}", identifier_authority)?;
         }
+        for sub_authority in self.get_sub_authorities() {
+            write!(f, "-{}", sub_authority)?;
+        }
+        Ok(())
     }
 }
 
@@ -130,3 +164,31 @@ impl Debug for Sid {
         f.write_str(&self.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let administrators = [1, 2, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0, 32, 2, 0, 0];
+        let sid = Sid::from_bytes(&administrators).expect("unable to parse SID");
+        assert_eq!(sid.to_string(), "S-1-5-32-544");
+        assert_eq!(sid.to_bytes(), administrators);
+
+        for str in ["S-1-0-0", "S-1-5-21-1004336348-1177238915-682003330-512", "S-1-16-12288", "S-1-5-21-1-2-3-4-5-6-7-8-9-10-11-12-13-14"] {
+            let sid = Sid::try_from(str).expect("invalid SID string");
+            assert_eq!(Sid::from_bytes(&sid.to_bytes()).expect("unable to parse SID"), sid);
+            assert_eq!(sid.to_string(), str);
+        }
+    }
+
+    #[test]
+    fn invalid_bytes_are_rejected() {
+        assert!(Sid::from_bytes(&[]).is_err());
+        assert!(Sid::from_bytes(&[1, 2, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0]).is_err());
+        assert!(Sid::from_bytes(&[1, 1, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0, 0]).is_err());
+        assert!(Sid::try_from("S-1-5-21-1-2-3-4-5-6-7-8-9-10-11-12-13-14-15").is_err());
+    }
+}
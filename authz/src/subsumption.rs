@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use crate::ace::{CONTAINER_INHERIT_ACE, FAILED_ACCESS_ACE_FLAG, OBJECT_INHERIT_ACE, SUCCESSFUL_ACCESS_ACE_FLAG};
+use crate::{Ace, AceType, Acl, Guid};
+
+// One ACE which can be removed without changing the effective ACL, since another ACE evaluated
+// before it in the same ACL covers everything it does
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serial", derive(serde::Serialize, serde::Deserialize))]
+pub struct RedundantAce {
+    pub ace: Ace,
+    pub index: usize,
+    pub covered_by: Ace,
+    pub covered_by_index: usize,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum AceKind {
+    Allow,
+    Deny,
+    Audit,
+    Other,
+}
+
+fn get_ace_kind(ace: &Ace) -> AceKind {
+    match ace.type_specific {
+        AceType::AccessAllowed | AceType::AccessAllowedObject { .. } |
+            AceType::AccessAllowedCallback { .. } | AceType::AccessAllowedCallbackObject { .. } => AceKind::Allow,
+        AceType::AccessDenied | AceType::AccessDeniedObject { .. } |
+            AceType::AccessDeniedCallback { .. } | AceType::AccessDeniedCallbackObject { .. } => AceKind::Deny,
+        AceType::Audit | AceType::AuditObject { .. } |
+            AceType::AuditCallback { .. } | AceType::AuditCallbackObject { .. } => AceKind::Audit,
+        AceType::MandatoryLabel | AceType::ResourceAttribute { .. } | AceType::ScopedPolicyId => AceKind::Other,
+    }
+}
+
+impl Ace {
+    // Returns true if everything this ACE grants (or denies, or audits) is also granted (or
+    // denied, or audited) by `other`, on every object this ACE applies to. `property_sets` maps
+    // attribute GUIDs to the GUID of the property set they belong to, if any.
+    pub fn is_covered_by(&self, other: &Ace, property_sets: &HashMap<Guid, Guid>) -> bool {
+        if self.trustee != other.trustee {
+            return false;
+        }
+        let kind = get_ace_kind(self);
+        if kind != get_ace_kind(other) {
+            return false;
+        }
+        if kind == AceKind::Other {
+            return self.type_specific == other.type_specific && self.access_mask == other.access_mask;
+        }
+        if kind == AceKind::Audit {
+            let audit_flags = SUCCESSFUL_ACCESS_ACE_FLAG | FAILED_ACCESS_ACE_FLAG;
+            if (self.flags & audit_flags & !other.flags) != 0 {
+                return false;
+            }
+        }
+
+        // An unconditional ACE covers a conditional one, but conditions cannot be compared
+        // otherwise (they might be evaluated differently)
+        if let Some(other_data) = other.get_application_data() {
+            if self.get_application_data() != Some(other_data) {
+                return false;
+            }
+        }
+
+        if !other.access_mask.map_generic().contains(self.access_mask.map_generic()) {
+            return false;
+        }
+        // Object ACEs only grant their rights on one node of the object type tree (and the nodes
+        // below it), never on the object as a whole
+        if let Some(other_object_type) = other.get_object_type() {
+            match self.get_object_type() {
+                Some(object_type) if object_type == other_object_type => (),
+                Some(object_type) if property_sets.get(object_type) == Some(other_object_type) => (),
+                _ => return false,
+            }
+        }
+
+        // The other ACE must apply to the object itself if this one does
+        if !self.get_inherit_only() && other.get_inherit_only() {
+            return false;
+        }
+        // And be inherited by at least the same children
+        let inherit_flags = self.flags & (CONTAINER_INHERIT_ACE | OBJECT_INHERIT_ACE);
+        if inherit_flags != 0 {
+            if (inherit_flags & !other.flags) != 0 {
+                return false;
+            }
+            if other.get_no_propagate() && !self.get_no_propagate() {
+                return false;
+            }
+            if let Some(other_inherited_object_type) = other.get_inherited_object_type() {
+                if self.get_inherited_object_type() != Some(other_inherited_object_type) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Acl {
+    // Returns the ACEs covered by an ACE evaluated before them, each with an ACE covering it which
+    // is not redundant itself. To stay on the safe side, allow ACEs are not reported either if a
+    // deny ACE in between could apply to their trustee (e.g. through a group membership). When
+    // several ACEs are equivalent, the first one is kept and the others are reported as redundant.
+    pub fn get_redundant_aces(&self, property_sets: &HashMap<Guid, Guid>) -> Vec<RedundantAce> {
+        let covers = |i: usize, j: usize| j < i &&
+            self.aces[i].is_covered_by(&self.aces[j], property_sets) &&
+            !self.aces[j + 1..i].iter().any(|ace| could_deny_first(ace, &self.aces[i]));
+        let redundant: Vec<bool> = (0..self.aces.len())
+            .map(|i| (0..i).any(|j| covers(i, j)))
+            .collect();
+        let mut res = vec![];
+        for index in (0..self.aces.len()).filter(|i| redundant[*i]) {
+            let covered_by_index = (0..index)
+                .find(|j| !redundant[*j] && covers(index, *j))
+                .or_else(|| (0..index).find(|j| covers(index, *j)))
+                .expect("assertion failed: redundant ACE not covered");
+            res.push(RedundantAce {
+                ace: self.aces[index].clone(),
+                index,
+                covered_by: self.aces[covered_by_index].clone(),
+                covered_by_index,
+            });
+        }
+        res
+    }
+}
+
+// Returns true if `deny` is a deny ACE which could deny some of the rights `allow` grants. Group
+// memberships are unknown here, so any trustee could be a group `allow`'s trustee is a member of.
+fn could_deny_first(deny: &Ace, allow: &Ace) -> bool {
+    get_ace_kind(allow) == AceKind::Allow &&
+        get_ace_kind(deny) == AceKind::Deny &&
+        deny.access_mask.map_generic().intersects(allow.access_mask.map_generic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use crate::{SecurityDescriptor, Sid};
+
+    fn get_redundant_indexes(sddl: &str) -> Vec<(usize, usize)> {
+        let domain_sid = Sid::try_from("S-1-5-21-1-2-3").expect("invalid SID");
+        let sd = SecurityDescriptor::from_str(sddl, &domain_sid, &domain_sid).expect("unable to parse SDDL");
+        sd.dacl.expect("no DACL")
+            .get_redundant_aces(&HashMap::new())
+            .into_iter()
+            .map(|r| (r.index, r.covered_by_index))
+            .collect()
+    }
+
+    #[test]
+    fn covering_aces_come_first() {
+        assert_eq!(get_redundant_indexes("D:(A;;GA;;;S-1-5-21-1-2-3-1000)(A;;WP;;;S-1-5-21-1-2-3-1000)"), vec![(1, 0)]);
+        assert_eq!(get_redundant_indexes("D:(A;;WP;;;S-1-5-21-1-2-3-1000)(A;;GA;;;S-1-5-21-1-2-3-1000)"), vec![]);
+        assert_eq!(get_redundant_indexes("D:(A;;RPWP;;;S-1-5-21-1-2-3-1000)(A;;RPWP;;;S-1-5-21-1-2-3-1000)"), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn object_aces_do_not_cover_the_whole_object() {
+        // Rights of an object ACE, whichever they are, only apply to its object type
+        assert_eq!(get_redundant_indexes("D:(OA;;WD;bf9679c0-0de6-11d0-a285-00aa003049e2;;S-1-5-21-1-2-3-1000)(A;;WD;;;S-1-5-21-1-2-3-1000)"), vec![]);
+        assert_eq!(get_redundant_indexes("D:(OA;;SDWO;bf9679c0-0de6-11d0-a285-00aa003049e2;;S-1-5-21-1-2-3-1000)(A;;SD;;;S-1-5-21-1-2-3-1000)"), vec![]);
+        assert_eq!(get_redundant_indexes("D:(OA;;RPWP;bf9679c0-0de6-11d0-a285-00aa003049e2;;S-1-5-21-1-2-3-1000)(A;;WP;;;S-1-5-21-1-2-3-1000)"), vec![]);
+        assert_eq!(get_redundant_indexes("D:(OA;;RPWP;bf9679c0-0de6-11d0-a285-00aa003049e2;;S-1-5-21-1-2-3-1000)(OA;;WP;bf9679c0-0de6-11d0-a285-00aa003049e2;;S-1-5-21-1-2-3-1000)"), vec![(1, 0)]);
+        // An ACE for the whole object still covers object ACEs
+        assert_eq!(get_redundant_indexes("D:(A;;GA;;;S-1-5-21-1-2-3-1000)(OA;;SDWP;bf9679c0-0de6-11d0-a285-00aa003049e2;;S-1-5-21-1-2-3-1000)"), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn property_sets_cover_their_attributes() {
+        let member = Guid::try_from("bf9679c0-0de6-11d0-a285-00aa003049e2").expect("invalid GUID");
+        let membership = Guid::try_from("bc0ac240-79a9-11d0-9020-00c04fc2d4cf").expect("invalid GUID");
+        let property_sets = HashMap::from([(member, membership)]);
+        let domain_sid = Sid::try_from("S-1-5-21-1-2-3").expect("invalid SID");
+        let sd = SecurityDescriptor::from_str("D:(OA;;WP;bc0ac240-79a9-11d0-9020-00c04fc2d4cf;;S-1-5-21-1-2-3-1000)(OA;;WP;bf9679c0-0de6-11d0-a285-00aa003049e2;;S-1-5-21-1-2-3-1000)", &domain_sid, &domain_sid)
+            .expect("unable to parse SDDL");
+        let redundant = sd.dacl.expect("no DACL").get_redundant_aces(&property_sets);
+        assert_eq!(redundant.iter().map(|r| (r.index, r.covered_by_index)).collect::<Vec<_>>(), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn deny_aces_in_between_are_respected() {
+        // Removing the explicit ACE would let the inherited deny ACE apply to members of the group
+        assert_eq!(get_redundant_indexes("D:AI(A;;WP;;;S-1-5-21-1-2-3-1000)(D;ID;WP;;;S-1-5-21-1-2-3-1001)(A;ID;GA;;;S-1-5-21-1-2-3-1000)"), vec![]);
+        assert_eq!(get_redundant_indexes("D:(A;;GA;;;S-1-5-21-1-2-3-1000)(D;;WP;;;S-1-5-21-1-2-3-1001)(A;;WP;;;S-1-5-21-1-2-3-1000)"), vec![]);
+        // Deny ACEs which cannot deny these rights do not matter
+        assert_eq!(get_redundant_indexes("D:(A;;GA;;;S-1-5-21-1-2-3-1000)(D;;SD;;;S-1-5-21-1-2-3-1001)(A;;WP;;;S-1-5-21-1-2-3-1000)"), vec![(2, 0)]);
+        // Deny ACEs are not affected by allow ACEs in between
+        assert_eq!(get_redundant_indexes("D:(D;;GA;;;S-1-5-21-1-2-3-1000)(A;;WP;;;S-1-5-21-1-2-3-1001)(D;;WP;;;S-1-5-21-1-2-3-1000)"), vec![(2, 0)]);
+    }
+}
@@ -1,16 +1,9 @@
-use windows::Win32::Foundation::GetLastError;
-
-pub(crate) fn get_last_error() -> u32 {
-    unsafe { GetLastError().0 }
+pub(crate) fn read_u16(slice: &[u8], offset: usize) -> Option<u16> {
+    let bytes = slice.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
 }
 
-pub(crate) fn pwstr_to_str(ptr: *const u16) -> String {
-    let mut len = 0;
-    unsafe {
-        while *(ptr.add(len)) != 0 {
-            len += 1;
-        }
-    }
-    let slice = unsafe { &*(std::ptr::slice_from_raw_parts(ptr, len)) };
-    String::from_utf16_lossy(slice)
-}
\ No newline at end of file
+pub(crate) fn read_u32(slice: &[u8], offset: usize) -> Option<u32> {
+    let bytes = slice.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
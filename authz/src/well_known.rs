@@ -0,0 +1,158 @@
+use core::fmt::{Display, Formatter};
+use crate::Sid;
+
+// Which kind of principal a well-known SID designates
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum WellKnownSidKind {
+    // Groups in the BUILTIN domain (S-1-5-32-X)
+    BuiltinGroup,
+    // Identities which are not accounts, computed at logon or access check time (e.g. Everyone,
+    // CREATOR OWNER, Authenticated Users)
+    SpecialIdentity,
+    // Accounts used by the operating system and services (e.g. SYSTEM, NETWORK SERVICE)
+    ServiceIdentity,
+    // Accounts and groups with a fixed RID in each domain (e.g. Domain Admins, Key Admins)
+    DomainRelative,
+}
+
+impl Display for WellKnownSidKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WellKnownSidKind::BuiltinGroup => write!(f, "Builtin group"),
+            WellKnownSidKind::SpecialIdentity => write!(f, "Special identity"),
+            WellKnownSidKind::ServiceIdentity => write!(f, "Service identity"),
+            WellKnownSidKind::DomainRelative => write!(f, "Domain-relative"),
+        }
+    }
+}
+
+// Well-known SIDs which are the same on every machine. Names are the English names returned
+// by LookupAccountSid(), prefixed by their authority where Windows does so.
+const WELL_KNOWN_SIDS: &[(&str, &str, WellKnownSidKind)] = &[
+    ("S-1-0-0", "NULL SID", WellKnownSidKind::SpecialIdentity),
+    ("S-1-1-0", "Everyone", WellKnownSidKind::SpecialIdentity),
+    ("S-1-2-0", "LOCAL", WellKnownSidKind::SpecialIdentity),
+    ("S-1-2-1", "CONSOLE LOGON", WellKnownSidKind::SpecialIdentity),
+    ("S-1-3-0", "CREATOR OWNER", WellKnownSidKind::SpecialIdentity),
+    ("S-1-3-1", "CREATOR GROUP", WellKnownSidKind::SpecialIdentity),
+    ("S-1-3-2", "CREATOR OWNER SERVER", WellKnownSidKind::SpecialIdentity),
+    ("S-1-3-3", "CREATOR GROUP SERVER", WellKnownSidKind::SpecialIdentity),
+    ("S-1-3-4", "OWNER RIGHTS", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-1", "NT AUTHORITY\\DIALUP", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-2", "NT AUTHORITY\\NETWORK", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-3", "NT AUTHORITY\\BATCH", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-4", "NT AUTHORITY\\INTERACTIVE", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-6", "NT AUTHORITY\\SERVICE", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-7", "NT AUTHORITY\\ANONYMOUS LOGON", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-8", "NT AUTHORITY\\PROXY", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-9", "NT AUTHORITY\\ENTERPRISE DOMAIN CONTROLLERS", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-10", "NT AUTHORITY\\SELF", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-11", "NT AUTHORITY\\Authenticated Users", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-12", "NT AUTHORITY\\RESTRICTED", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-13", "NT AUTHORITY\\TERMINAL SERVER USER", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-14", "NT AUTHORITY\\REMOTE INTERACTIVE LOGON", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-15", "NT AUTHORITY\\This Organization", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-17", "NT AUTHORITY\\IUSR", WellKnownSidKind::ServiceIdentity),
+    ("S-1-5-18", "NT AUTHORITY\\SYSTEM", WellKnownSidKind::ServiceIdentity),
+    ("S-1-5-19", "NT AUTHORITY\\LOCAL SERVICE", WellKnownSidKind::ServiceIdentity),
+    ("S-1-5-20", "NT AUTHORITY\\NETWORK SERVICE", WellKnownSidKind::ServiceIdentity),
+    ("S-1-5-33", "NT AUTHORITY\\WRITE RESTRICTED", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-64-10", "NT AUTHORITY\\NTLM Authentication", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-64-14", "NT AUTHORITY\\SChannel Authentication", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-64-21", "NT AUTHORITY\\Digest Authentication", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-80-0", "NT SERVICE\\ALL SERVICES", WellKnownSidKind::ServiceIdentity),
+    ("S-1-5-113", "NT AUTHORITY\\Local account", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-114", "NT AUTHORITY\\Local account and member of Administrators group", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-1000", "NT AUTHORITY\\Other Organization", WellKnownSidKind::SpecialIdentity),
+    ("S-1-5-32-544", "BUILTIN\\Administrators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-545", "BUILTIN\\Users", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-546", "BUILTIN\\Guests", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-547", "BUILTIN\\Power Users", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-548", "BUILTIN\\Account Operators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-549", "BUILTIN\\Server Operators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-550", "BUILTIN\\Print Operators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-551", "BUILTIN\\Backup Operators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-552", "BUILTIN\\Replicator", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-554", "BUILTIN\\Pre-Windows 2000 Compatible Access", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-555", "BUILTIN\\Remote Desktop Users", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-556", "BUILTIN\\Network Configuration Operators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-557", "BUILTIN\\Incoming Forest Trust Builders", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-558", "BUILTIN\\Performance Monitor Users", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-559", "BUILTIN\\Performance Log Users", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-560", "BUILTIN\\Windows Authorization Access Group", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-561", "BUILTIN\\Terminal Server License Servers", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-562", "BUILTIN\\Distributed COM Users", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-568", "BUILTIN\\IIS_IUSRS", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-569", "BUILTIN\\Cryptographic Operators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-573", "BUILTIN\\Event Log Readers", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-574", "BUILTIN\\Certificate Service DCOM Access", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-575", "BUILTIN\\RDS Remote Access Servers", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-576", "BUILTIN\\RDS Endpoint Servers", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-577", "BUILTIN\\RDS Management Servers", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-578", "BUILTIN\\Hyper-V Administrators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-579", "BUILTIN\\Access Control Assistance Operators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-580", "BUILTIN\\Remote Management Users", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-582", "BUILTIN\\Storage Replica Administrators", WellKnownSidKind::BuiltinGroup),
+    ("S-1-5-32-583", "BUILTIN\\Device Owners", WellKnownSidKind::BuiltinGroup),
+    ("S-1-15-2-1", "APPLICATION PACKAGE AUTHORITY\\ALL APPLICATION PACKAGES", WellKnownSidKind::SpecialIdentity),
+    ("S-1-15-2-2", "APPLICATION PACKAGE AUTHORITY\\ALL RESTRICTED APPLICATION PACKAGES", WellKnownSidKind::SpecialIdentity),
+    ("S-1-18-1", "Authentication authority asserted identity", WellKnownSidKind::SpecialIdentity),
+    ("S-1-18-2", "Service asserted identity", WellKnownSidKind::SpecialIdentity),
+    ("S-1-18-3", "Fresh public key identity", WellKnownSidKind::SpecialIdentity),
+    ("S-1-18-4", "Key trust identity", WellKnownSidKind::SpecialIdentity),
+    ("S-1-18-5", "Key property MFA", WellKnownSidKind::SpecialIdentity),
+    ("S-1-18-6", "Key property attestation", WellKnownSidKind::SpecialIdentity),
+];
+
+// Accounts and groups created with a fixed RID in each domain (S-1-5-21-X-Y-Z-RID). Some of
+// them only exist in the forest root domain (e.g. Schema Admins).
+const DOMAIN_RELATIVE_RIDS: &[(u32, &str)] = &[
+    (498, "Enterprise Read-only Domain Controllers"),
+    (500, "Administrator"),
+    (501, "Guest"),
+    (502, "krbtgt"),
+    (503, "DefaultAccount"),
+    (504, "WDAGUtilityAccount"),
+    (512, "Domain Admins"),
+    (513, "Domain Users"),
+    (514, "Domain Guests"),
+    (515, "Domain Computers"),
+    (516, "Domain Controllers"),
+    (517, "Cert Publishers"),
+    (518, "Schema Admins"),
+    (519, "Enterprise Admins"),
+    (520, "Group Policy Creator Owners"),
+    (521, "Read-only Domain Controllers"),
+    (522, "Cloneable Domain Controllers"),
+    (525, "Protected Users"),
+    (526, "Key Admins"),
+    (527, "Enterprise Key Admins"),
+    (553, "RAS and IAS Servers"),
+    (571, "Allowed RODC Password Replication Group"),
+    (572, "Denied RODC Password Replication Group"),
+];
+
+// Returns the stable English name of the given SID and its kind, without querying any
+// directory or the local machine. Domain-relative SIDs are named without their domain.
+pub fn lookup_well_known_sid(sid: &Sid) -> Option<(&'static str, WellKnownSidKind)> {
+    if sid.is_domain_specific() {
+        // S-1-5-21-X-Y-Z-RID, the domain part being exactly three sub authorities
+        if sid.get_sub_authorities().len() != 5 {
+            return None;
+        }
+        let rid = sid.get_rid();
+        return DOMAIN_RELATIVE_RIDS.iter()
+            .find(|(r, _)| *r == rid)
+            .map(|(_, name)| (*name, WellKnownSidKind::DomainRelative));
+    }
+    let sid = sid.to_string();
+    WELL_KNOWN_SIDS.iter()
+        .find(|(s, _, _)| *s == sid)
+        .map(|(_, name, kind)| (*name, *kind))
+}
+
+// Returns true if the given RID is assigned to a user account (as opposed to a group) in every
+// domain
+pub fn is_well_known_user_rid(rid: u32) -> bool {
+    (500..=504).contains(&rid)
+}
@@ -1,4 +1,6 @@
+#[cfg(windows)]
 pub mod connection;
+#[cfg(windows)]
 pub mod control;
 pub mod error;
 pub mod search;
@@ -1,12 +1,20 @@
+#[cfg(windows)]
 use crate::connection::LdapConnection;
+#[cfg(windows)]
 use crate::error::LdapError;
+#[cfg(windows)]
 use windows::Win32::Networking::Ldap::{LDAP_SUCCESS, LDAPMessage, ldap_first_entry, ldap_next_entry, ldap_memfree, ldap_get_dnW, ldap_msgfree, ldap_first_attributeW, ldap_get_values_lenW, ldap_next_attributeW, ldap_search_ext_sW, ldapcontrolW, LDAP_BERVAL, ldap_create_page_controlW, ldap_control_freeW, ldap_parse_resultW, ldap_parse_page_controlW, ber_bvfree, ldap_controls_freeW, LDAP_CONTROL_NOT_FOUND};
+#[cfg(windows)]
 use windows::Win32::Foundation::{PSTR, PWSTR, BOOLEAN};
+#[cfg(windows)]
 use std::ptr::{null_mut, null};
 use std::collections::HashMap;
+#[cfg(windows)]
 use crate::utils::{pwstr_to_str, str_to_wstr};
+#[cfg(windows)]
 use crate::control::LdapControl;
 
+#[cfg(windows)]
 #[derive(Debug)]
 pub struct LdapSearch<'a> {
     connection: &'a LdapConnection,
@@ -32,6 +40,7 @@ pub struct LdapEntry {
     pub attrs: HashMap<String, Vec<Vec<u8>>>,
 }
 
+#[cfg(windows)]
 impl<'a> LdapSearch<'a> {
     pub fn new(connection: &'a LdapConnection,
                base: Option<&str>,
@@ -63,6 +72,7 @@ impl<'a> LdapSearch<'a> {
     }
 }
 
+#[cfg(windows)]
 impl Drop for LdapSearch<'_> {
     fn drop(&mut self) {
         if !self.result_page.is_null() {
@@ -72,6 +82,7 @@ impl Drop for LdapSearch<'_> {
     }
 }
 
+#[cfg(windows)]
 impl<'a> Iterator for LdapSearch<'a> {
     type Item = Result<LdapEntry, LdapError>;
 
@@ -1,12 +1,15 @@
 use core::borrow::Borrow;
+#[cfg(windows)]
 use windows::Win32::Networking::Ldap::{LdapGetLastError, ldap_err2stringW};
 use crate::error::LdapError;
 use crate::search::LdapEntry;
 
+#[cfg(windows)]
 pub(crate) fn str_to_wstr(s: &str) -> Vec<u16> {
     s.encode_utf16().chain(std::iter::once(0)).collect()
 }
 
+#[cfg(windows)]
 pub(crate) fn pwstr_to_str(ptr: *const u16) -> String {
     let mut len = 0;
     unsafe {
@@ -18,10 +21,12 @@ pub(crate) fn pwstr_to_str(ptr: *const u16) -> String {
     String::from_utf16_lossy(slice)
 }
 
+#[cfg(windows)]
 pub(crate) fn get_ldap_errcode() -> u32 {
     unsafe { LdapGetLastError() }
 }
 
+#[cfg(windows)]
 pub(crate) fn get_ldap_errmsg(code: u32) -> String {
     let res = unsafe { ldap_err2stringW(code) };
     if res.is_null() {
@@ -31,10 +36,16 @@ pub(crate) fn get_ldap_errmsg(code: u32) -> String {
     }
 }
 
+// Error codes only come from the Windows LDAP client, which is not there to describe them elsewhere
+#[cfg(not(windows))]
+pub(crate) fn get_ldap_errmsg(code: u32) -> String {
+    format!("error code {}", code)
+}
+
 pub fn get_attr_strs<T: Borrow<LdapEntry>>(search_results: &[T], base: &str, attr_name: &str) -> Result<Vec<String>, LdapError> {
     let (dn, attrs) = if search_results.len() > 1 {
         return Err(LdapError::RequiredObjectCollision { dn: base.to_owned() });
-    } else if search_results.len() == 0 {
+    } else if search_results.is_empty() {
         return Err(LdapError::RequiredObjectMissing { dn: base.to_owned() });
     } else {
         (&search_results[0].borrow().dn, &search_results[0].borrow().attrs)